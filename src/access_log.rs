@@ -0,0 +1,99 @@
+//! Access log of the gateway (`--access-log`), so that developers can see what their frontend
+//! actually requests.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! appends a line per request in the Combined Log Format, followed by the canister the request is
+//! for, from its path or host name, and how long the response took, e.g.
+//!
+//! ```text
+//! 127.0.0.1 - - [10/Oct/2026:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 "-" "Mozilla/5.0" canister=bkyz2-fmaaa-aaaaa-qaaaq-cai 12ms
+//! ```
+//!
+//! An `access_token` query parameter is left out of the logged path, so that the log doesn't
+//! hand out access to the network.
+
+use std::{
+    fs::File,
+    io::Write,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use candid::Principal;
+use hyper::{
+    HeaderMap,
+    header::{HOST, REFERER, USER_AGENT},
+    http::request,
+};
+
+use crate::{access_token, timestamp};
+
+/// The access log file.
+pub struct AccessLog {
+    file: Mutex<File>,
+}
+
+impl AccessLog {
+    pub fn new(file: File) -> Self {
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Logs the request described by `parts` from `peer`, received at `received`, which was
+    /// answered with `status` and a body of `size` bytes after `elapsed`.
+    pub fn log(
+        &self,
+        parts: &request::Parts,
+        peer: Option<IpAddr>,
+        received: SystemTime,
+        status: u16,
+        size: usize,
+        elapsed: Duration,
+    ) {
+        let header = |headers: &HeaderMap, name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("-")
+                .replace('"', "\\\"")
+        };
+        let canister = canister_id(parts)
+            .map(|canister_id| canister_id.to_text())
+            .unwrap_or_else(|| "-".to_string());
+        let line = format!(
+            "{} - - [{}] \"{} {} {:?}\" {status} {size} \"{}\" \"{}\" canister={canister} {}ms",
+            peer.map_or_else(|| "-".to_string(), |peer| peer.to_string()),
+            timestamp::format_log_timestamp(
+                received
+                    .duration_since(UNIX_EPOCH)
+                    .expect("system clock should be after the unix epoch")
+                    .as_nanos() as u64
+            ),
+            parts.method,
+            access_token::path_and_query(&parts.uri),
+            parts.version,
+            header(&parts.headers, REFERER),
+            header(&parts.headers, USER_AGENT),
+            elapsed.as_millis(),
+        );
+        let mut file = self.file.lock().expect("access log lock poisoned");
+        if let Err(e) = writeln!(file, "{line}") {
+            eprintln!("Warning: failed to write to the access log: {e:#}");
+        }
+    }
+}
+
+/// Returns the canister a request is for: the one in its API path, or the one whose subdomain it
+/// is sent to.
+fn canister_id(parts: &request::Parts) -> Option<Principal> {
+    if let ["", "api", _, "canister", canister_id, ..] =
+        parts.uri.path().split('/').collect::<Vec<_>>()[..]
+    {
+        return Principal::from_text(canister_id).ok();
+    }
+    let host = parts.headers.get(HOST)?.to_str().ok()?;
+    let (subdomain, _) = host.split_once('.')?;
+    Principal::from_text(subdomain).ok()
+}
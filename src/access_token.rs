@@ -0,0 +1,360 @@
+//! Access tokens for networks exposed beyond the machine (`--access-token`), so that binding to
+//! an office LAN doesn't leave PocketIC's admin API open to anyone on it.
+//!
+//! The gateway (see [`crate::gateway`]) and the admin API both require the token from every
+//! client, including those on the machine itself, as tunnels and reverse proxies connect from it.
+//! Only clients of the gateway's Unix socket can be let in without it, with
+//! `--trust-gateway-socket`. PocketIC's server is then bound to loopback, and the launcher serves
+//! the admin API on the bound address in front of it. The token is accepted as a bearer token, or
+//! as an `access_token` query parameter, in which case the gateway also sets it as a cookie so
+//! that a browser opening a dapp with it keeps access to the dapp's assets. The gateway removes
+//! the token from requests before forwarding them, so that canisters never see it.
+//!
+//! `--config-token` guards the admin API on the config port the same way, even when it isn't
+//! exposed. PocketIC's own server keeps listening without a token on a random loopback port, which
+//! the launcher's own requests go to; the token keeps out tools that are only given the config
+//! port, not every process on the machine.
+
+use std::{convert::Infallible, net::IpAddr, sync::Arc};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    HeaderMap, Request, Response, StatusCode, Uri,
+    body::{Bytes, Incoming},
+    header::{AUTHORIZATION, COOKIE, HeaderValue, WWW_AUTHENTICATE},
+    http::{request, uri::PathAndQuery},
+    service::service_fn,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use crate::{control::ACCEPT_BACKOFF, gateway, tls};
+
+/// Query parameter and cookie the token can be given in.
+const PARAMETER: &str = "access_token";
+
+/// The token clients must present.
+#[derive(Clone)]
+pub struct AccessToken {
+    token: String,
+    /// Whether requests on the gateway's Unix socket need no token.
+    trust_socket: bool,
+    /// Tokens are compared by digest, so that comparisons take the same time however much of the
+    /// token a client guessed.
+    digest: [u8; 32],
+}
+
+/// Whether a request may go through.
+#[derive(PartialEq)]
+pub enum Access {
+    Granted,
+    /// Granted by the query parameter, which the response should turn into a cookie.
+    GrantedByQuery,
+    Denied,
+}
+
+impl AccessToken {
+    /// A token that all clients need, except those on the gateway's Unix socket if `trust_socket`.
+    pub fn new(token: String, trust_socket: bool) -> Self {
+        let digest = Sha256::digest(&token).into();
+        Self {
+            token,
+            trust_socket,
+            digest,
+        }
+    }
+
+    /// Checks a request with `headers` to `uri` from `peer`, which is `None` for Unix sockets.
+    pub fn check(&self, headers: &HeaderMap, uri: &Uri, peer: Option<IpAddr>) -> Access {
+        if self.trust_socket && peer.is_none() {
+            return Access::Granted;
+        }
+        let cookies = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(cookie_token);
+        if bearer_tokens(headers)
+            .chain(cookies)
+            .any(|token| self.matches(token))
+        {
+            return Access::Granted;
+        }
+        let query = uri.query().unwrap_or_default().split('&');
+        if query
+            .filter_map(query_token)
+            .any(|token| self.matches(token))
+        {
+            return Access::GrantedByQuery;
+        }
+        Access::Denied
+    }
+
+    fn matches(&self, token: &str) -> bool {
+        <[u8; 32]>::from(Sha256::digest(token.trim())) == self.digest
+    }
+
+    /// Removes the token from the request described by `parts`, wherever it was given, so that
+    /// it isn't forwarded: bearer tokens that match it, and the `access_token` cookie and query
+    /// parameter.
+    pub fn strip(&self, parts: &mut request::Parts) {
+        let authorization = parts
+            .headers
+            .get_all(AUTHORIZATION)
+            .iter()
+            .filter(|value| {
+                !value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .is_some_and(|token| self.matches(token))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let cookies = parts
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| {
+                let Ok(value) = value.to_str() else {
+                    return Some(value.clone());
+                };
+                let kept = value
+                    .split(';')
+                    .filter(|cookie| cookie_token(cookie).is_none())
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                if kept.is_empty() {
+                    return None;
+                }
+                HeaderValue::from_str(&kept).ok()
+            })
+            .collect::<Vec<_>>();
+        for (name, values) in [(AUTHORIZATION, authorization), (COOKIE, cookies)] {
+            parts.headers.remove(&name);
+            for value in values {
+                parts.headers.append(&name, value);
+            }
+        }
+        if parts
+            .uri
+            .query()
+            .is_some_and(|query| query.split('&').any(|param| query_token(param).is_some()))
+        {
+            let mut uri = parts.uri.clone().into_parts();
+            uri.path_and_query = PathAndQuery::try_from(path_and_query(&parts.uri)).ok();
+            if let Ok(stripped) = Uri::from_parts(uri) {
+                parts.uri = stripped;
+            }
+        }
+    }
+
+    /// The `Set-Cookie` value that grants access to later requests to the same host.
+    pub fn cookie(&self) -> Option<HeaderValue> {
+        HeaderValue::from_str(&format!(
+            "{PARAMETER}={}; Path=/; HttpOnly; SameSite=Lax",
+            self.token
+        ))
+        .ok()
+    }
+}
+
+/// Returns the path and query of `uri` without the `access_token` query parameter.
+pub fn path_and_query(uri: &Uri) -> String {
+    let query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| query_token(param).is_none())
+        .collect::<Vec<_>>()
+        .join("&");
+    match query.as_str() {
+        "" => uri.path().to_string(),
+        query => format!("{}?{query}", uri.path()),
+    }
+}
+
+/// The tokens given as bearer tokens in `headers`.
+fn bearer_tokens(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(AUTHORIZATION)
+        .iter()
+        .filter_map(|value| value.to_str().ok()?.strip_prefix("Bearer "))
+}
+
+/// The token in `cookie`, one of a `Cookie` header's, if it is the `access_token` cookie.
+fn cookie_token(cookie: &str) -> Option<&str> {
+    cookie.trim().strip_prefix(PARAMETER)?.strip_prefix('=')
+}
+
+/// The token in `param`, one of a query's, if it is the `access_token` parameter.
+fn query_token(param: &str) -> Option<&str> {
+    param.strip_prefix(PARAMETER)?.strip_prefix('=')
+}
+
+/// The response to a request without the token.
+pub fn denied() -> Response<Full<Bytes>> {
+    let mut response = gateway::plain_response(
+        StatusCode::UNAUTHORIZED,
+        format!(
+            "this network requires an access token (--access-token), as a bearer token or an \
+             `{PARAMETER}` query parameter"
+        ),
+    );
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Serves PocketIC's admin API at `upstream` on `listener`, also over TLS if there is an
+/// `acceptor`, to clients with `token` if there is one, until the task is aborted.
+pub async fn serve_admin(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    upstream: String,
+    token: Option<AccessToken>,
+) {
+    let client = reqwest::Client::new();
+    let state = Arc::new((upstream, client, token));
+    loop {
+        let Ok((stream, peer)) = listener.accept().await else {
+            tokio::time::sleep(ACCEPT_BACKOFF).await;
+            continue;
+        };
+        let state = Arc::clone(&state);
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let Some(stream) = tls::accept(acceptor.as_ref(), stream).await else {
+                return;
+            };
+            let service = service_fn(move |req| {
+                let state = Arc::clone(&state);
+                async move {
+                    let (upstream, client, token) = &*state;
+                    forward_admin(upstream, client, token, req, peer.ip()).await
+                }
+            });
+            _ = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn forward_admin(
+    upstream: &str,
+    client: &reqwest::Client,
+    token: &Option<AccessToken>,
+    req: Request<Incoming>,
+    peer: IpAddr,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if let Some(token) = token
+        && token.check(req.headers(), req.uri(), Some(peer)) == Access::Denied
+    {
+        return Ok(denied());
+    }
+    let (mut parts, body) = req.into_parts();
+    if let Some(token) = token {
+        token.strip(&mut parts);
+    }
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            return Ok(gateway::plain_response(
+                StatusCode::BAD_REQUEST,
+                format!("failed to read the request body: {e}"),
+            ));
+        }
+    };
+    let path_and_query = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let mut request = client
+        .request(parts.method, format!("{upstream}{path_and_query}"))
+        .body(body);
+    for (name, value) in &parts.headers {
+        if !gateway::is_hop_by_hop(name) {
+            request = request.header(name, value);
+        }
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(gateway::plain_response(
+                StatusCode::BAD_GATEWAY,
+                format!("failed to reach PocketIC: {e}"),
+            ));
+        }
+    };
+    let mut builder = Response::builder().status(response.status());
+    for (name, value) in response.headers() {
+        if !gateway::is_hop_by_hop(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            return Ok(gateway::plain_response(
+                StatusCode::BAD_GATEWAY,
+                format!("failed to read PocketIC's response: {e}"),
+            ));
+        }
+    };
+    Ok(builder.body(Full::new(body)).expect("valid response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_requires_the_token_on_loopback() {
+        let token = AccessToken::new("secret".to_string(), false);
+        let uri = Uri::from_static("/");
+        let loopback = Some(IpAddr::from([127, 0, 0, 1]));
+        assert!(token.check(&HeaderMap::new(), &uri, loopback) == Access::Denied);
+        assert!(token.check(&HeaderMap::new(), &uri, None) == Access::Denied);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert!(token.check(&headers, &uri, loopback) == Access::Granted);
+        let uri = Uri::from_static("/?access_token=secret");
+        assert!(token.check(&HeaderMap::new(), &uri, loopback) == Access::GrantedByQuery);
+        let trusting = AccessToken::new("secret".to_string(), true);
+        assert!(trusting.check(&HeaderMap::new(), &Uri::from_static("/"), None) == Access::Granted);
+    }
+
+    #[test]
+    fn strip_removes_the_token() {
+        let token = AccessToken::new("secret".to_string(), false);
+        let (mut parts, ()) = Request::builder()
+            .uri("/index.html?a=1&access_token=secret&b=2")
+            .header(AUTHORIZATION, "Bearer secret")
+            .header(COOKIE, "theme=dark; access_token=secret")
+            .body(())
+            .unwrap()
+            .into_parts();
+        token.strip(&mut parts);
+        assert_eq!(parts.uri, "/index.html?a=1&b=2");
+        assert!(!parts.headers.contains_key(AUTHORIZATION));
+        assert_eq!(parts.headers[COOKIE], "theme=dark");
+
+        let (mut parts, ()) = Request::builder()
+            .uri("/?access_token=secret")
+            .header(AUTHORIZATION, "Bearer the-canister's")
+            .header(COOKIE, "access_token=secret")
+            .body(())
+            .unwrap()
+            .into_parts();
+        token.strip(&mut parts);
+        assert_eq!(parts.uri, "/");
+        assert_eq!(parts.headers[AUTHORIZATION], "Bearer the-canister's");
+        assert!(!parts.headers.contains_key(COOKIE));
+    }
+}
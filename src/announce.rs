@@ -0,0 +1,183 @@
+//! Announcement of canisters' gateway URLs (`--announce-canisters`), so that developers don't
+//! assemble `http://<id>.localhost:<port>` by hand after each deploy.
+//!
+//! PocketIC has no notion of "a canister was created", so the launcher polls for new canisters:
+//! those added to the canister registry, and those created in the subnets' canister ID ranges,
+//! which are allocated in order from the start of each range. A canister is announced once it
+//! answers `http_request`, which is usually after its code is installed rather than when it is
+//! created, and its URL is recorded in the registry if the network has a status directory.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use candid::{CandidType, Encode, Principal};
+
+use crate::{control::Control, registry};
+
+/// How often the network is checked for new canisters.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(CandidType)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    certificate_version: Option<u16>,
+}
+
+/// Watches the network of `control` for new canisters, printing the URL on the gateway at
+/// `gateway_port` of each that serves HTTP and recording it in the registry in `status_dir`.
+pub async fn run(control: Arc<Control>, status_dir: Option<PathBuf>, gateway_port: u16) {
+    let pic = &control.pic;
+    // the next ID to check and the last ID of each range
+    let mut ranges: Vec<(u64, u64)> = pic
+        .topology()
+        .await
+        .subnet_configs
+        .values()
+        .flat_map(|subnet| &subnet.canister_ranges)
+        .filter_map(|range| {
+            Some((
+                index(&range.start.canister_id)?,
+                index(&range.end.canister_id)?,
+            ))
+        })
+        .collect();
+    // canisters that exist at startup were announced then, if at all
+    let mut known = registered(status_dir.as_deref())
+        .into_values()
+        .collect::<BTreeSet<_>>();
+    for (next, end) in &mut ranges {
+        while *next <= *end && pic.canister_exists(canister_id(*next)).await {
+            known.insert(canister_id(*next));
+            *next += 1;
+        }
+    }
+    // canisters that have yet to serve HTTP, with their names in the registry
+    let mut pending = BTreeMap::<Principal, Option<String>>::new();
+    let mut ticks =
+        tokio::time::interval_at(tokio::time::Instant::now() + POLL_INTERVAL, POLL_INTERVAL);
+    loop {
+        ticks.tick().await;
+        for (name, canister_id) in registered(status_dir.as_deref()) {
+            if known.insert(canister_id) || pending.contains_key(&canister_id) {
+                pending.insert(canister_id, Some(name));
+            }
+        }
+        for (next, end) in &mut ranges {
+            while *next <= *end && pic.canister_exists(canister_id(*next)).await {
+                if known.insert(canister_id(*next)) {
+                    pending.insert(canister_id(*next), None);
+                }
+                *next += 1;
+            }
+        }
+        let mut announced = BTreeMap::new();
+        for (&canister_id, name) in &pending {
+            if !pic.canister_exists(canister_id).await {
+                // deleted before it served HTTP
+                announced.insert(canister_id, None);
+                continue;
+            }
+            if !serves_http(&control, canister_id).await {
+                continue;
+            }
+            let url = format!("http://{canister_id}.localhost:{gateway_port}");
+            match name {
+                Some(name) => eprintln!("{name} ({canister_id}) available at {url}"),
+                None => eprintln!("{canister_id} available at {url}"),
+            }
+            announced.insert(canister_id, Some(url));
+        }
+        pending.retain(|canister_id, _| !announced.contains_key(canister_id));
+        if let Some(status_dir) = &status_dir
+            && announced.values().any(Option::is_some)
+            && let Err(e) = record(status_dir, &announced)
+        {
+            eprintln!("Warning: failed to record canister URLs: {e:#}");
+        }
+    }
+}
+
+/// Returns the canisters in the registry in `status_dir`, by name.
+fn registered(status_dir: Option<&Path>) -> BTreeMap<String, Principal> {
+    let Some(status_dir) = status_dir else {
+        return BTreeMap::new();
+    };
+    match registry::read(status_dir) {
+        Ok(registry) => registry
+            .canisters
+            .into_iter()
+            .map(|(name, entry)| (name, entry.canister_id))
+            .collect(),
+        Err(e) => {
+            eprintln!("Warning: failed to read canister registry: {e:#}");
+            BTreeMap::new()
+        }
+    }
+}
+
+/// Records the URLs of the `announced` canisters in the registry in `status_dir`: on their
+/// entries if they have one, and by ID otherwise.
+fn record(
+    status_dir: &Path,
+    announced: &BTreeMap<Principal, Option<String>>,
+) -> anyhow::Result<()> {
+    let mut registry = registry::read(status_dir)?;
+    for (&canister_id, url) in announced {
+        let Some(url) = url else {
+            continue;
+        };
+        match registry
+            .canisters
+            .values_mut()
+            .find(|entry| entry.canister_id == canister_id)
+        {
+            Some(entry) => entry.url = Some(url.clone()),
+            None => {
+                registry.announced.insert(canister_id, url.clone());
+            }
+        }
+    }
+    registry::write(status_dir, &registry)
+}
+
+/// Returns whether `canister_id` answers `http_request`, i.e. can be browsed through the gateway.
+async fn serves_http(control: &Control, canister_id: Principal) -> bool {
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        url: "/".to_string(),
+        headers: vec![],
+        body: vec![],
+        certificate_version: Some(2),
+    };
+    let Ok(arg) = Encode!(&request) else {
+        return false;
+    };
+    control
+        .pic
+        .query_call(canister_id, Principal::anonymous(), "http_request", arg)
+        .await
+        .is_ok()
+}
+
+/// Returns the index of the canister ID `bytes` within the canister ID space.
+fn index(bytes: &[u8]) -> Option<u64> {
+    // canister IDs are a big-endian index followed by the opaque ID class
+    match bytes {
+        [index @ .., 1, 1] => Some(u64::from_be_bytes(index.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Returns the canister ID at `index` within the canister ID space.
+fn canister_id(index: u64) -> Principal {
+    let mut bytes = index.to_be_bytes().to_vec();
+    bytes.extend([1, 1]);
+    Principal::from_slice(&bytes)
+}
@@ -0,0 +1,164 @@
+//! Portable `.tar.zst` archives of a network: the state directory plus a manifest describing it.
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::crypt::{self, Passphrase};
+
+const MANIFEST: &str = "manifest.json";
+const STATE: &str = "state";
+
+/// Describes the network an archive was exported from.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub v: String,
+    /// Version of the launcher (and thereby pocket-ic) that produced the state.
+    pub launcher_version: String,
+    /// Unix timestamp of the export, in seconds.
+    pub created_at: u64,
+    /// The instance topology, if the network's status directory was available during export.
+    pub topology: Option<serde_json::Value>,
+}
+
+/// Writes `state_dir` and a manifest to a zstd-compressed tarball at `output`, encrypted with
+/// `passphrase` if given. `status_dir` is consulted for the topology of the network that last
+/// used `state_dir`.
+pub fn export(
+    state_dir: &Path,
+    status_dir: Option<&Path>,
+    output: &Path,
+    passphrase: Option<&Passphrase>,
+) -> anyhow::Result<()> {
+    if !state_dir.is_dir() {
+        bail!("state directory {} does not exist", state_dir.display());
+    }
+    let topology = match status_dir {
+        Some(status_dir) => {
+            let topology_file = status_dir.join("topology.json");
+            let contents = fs::read_to_string(&topology_file)
+                .with_context(|| format!("failed to read {}", topology_file.display()))?;
+            Some(serde_json::from_str(&contents).context("failed to parse topology file")?)
+        }
+        None => None,
+    };
+    let manifest = Manifest {
+        v: "1".to_string(),
+        launcher_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock should be after the unix epoch")
+            .as_secs(),
+        topology,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).expect("infallible serialization");
+    let file =
+        File::create(output).with_context(|| format!("failed to create {}", output.display()))?;
+    match passphrase {
+        Some(passphrase) => {
+            write_archive(
+                crypt::Encryptor::new(file, passphrase)?,
+                &manifest,
+                state_dir,
+            )?
+            .finish()
+            .context("failed to finish encryption")?;
+        }
+        None => {
+            write_archive(file, &manifest, state_dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_archive<W: Write>(writer: W, manifest: &[u8], state_dir: &Path) -> anyhow::Result<W> {
+    let encoder = zstd::Encoder::new(writer, 0).context("failed to start compression")?;
+    let mut builder = tar::Builder::new(encoder);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder
+        .append_data(&mut header, MANIFEST, manifest)
+        .context("failed to write manifest to archive")?;
+    builder
+        .append_dir_all(STATE, state_dir)
+        .context("failed to write state directory to archive")?;
+    builder
+        .into_inner()
+        .context("failed to finish archive")?
+        .finish()
+        .context("failed to finish compression")
+}
+
+/// Unpacks an archive created by [`export`] into `state_dir`, returning its manifest.
+/// Encrypted archives are decrypted with the passphrase from [`crypt::load_passphrase`].
+/// Fails if `state_dir` is not empty, unless `force` is set, in which case it is replaced.
+pub fn import(archive: &Path, state_dir: &Path, force: bool) -> anyhow::Result<Manifest> {
+    let occupied = state_dir.exists()
+        && fs::read_dir(state_dir)
+            .context("failed to read state directory")?
+            .next()
+            .is_some();
+    if occupied && !force {
+        bail!(
+            "state directory {} is not empty (use --force to replace it)",
+            state_dir.display()
+        );
+    }
+    let parent = std::path::absolute(state_dir)
+        .context("failed to resolve state directory")?
+        .parent()
+        .context("state directory has no parent")?
+        .to_path_buf();
+    fs::create_dir_all(&parent).context("failed to create state directory parent")?;
+    // unpack next to the destination so the final move is a cheap rename
+    let tmp = TempDir::new_in(&parent).context("failed to create temporary directory")?;
+    let file =
+        File::open(archive).with_context(|| format!("failed to open {}", archive.display()))?;
+    let mut file = BufReader::new(file);
+    let header = file.fill_buf().context("failed to read archive")?;
+    let reader: Box<dyn Read> = if crypt::is_encrypted(header) {
+        let passphrase = crypt::load_passphrase().context("archive is encrypted")?;
+        Box::new(crypt::Decryptor::new(file, &passphrase)?)
+    } else {
+        Box::new(file)
+    };
+    let decoder = zstd::Decoder::new(reader).context("failed to start decompression")?;
+    tar::Archive::new(decoder)
+        .unpack(tmp.path())
+        .context("failed to unpack archive")?;
+    let manifest =
+        fs::read_to_string(tmp.path().join(MANIFEST)).context("archive is missing its manifest")?;
+    let manifest: Manifest = serde_json::from_str(&manifest).context("failed to parse manifest")?;
+    if manifest.v != "1" {
+        bail!("unsupported archive manifest version {}", manifest.v);
+    }
+    let unpacked_state = tmp.path().join(STATE);
+    if !unpacked_state.is_dir() {
+        bail!("archive does not contain a state directory");
+    }
+    if state_dir.exists() {
+        fs::remove_dir_all(state_dir).context("failed to clear state directory")?;
+    }
+    fs::rename(&unpacked_state, state_dir).context("failed to move unpacked state into place")?;
+    Ok(manifest)
+}
+
+/// Warns if `manifest` was written by another version of the launcher.
+pub fn warn_on_version_mismatch(manifest: &Manifest) {
+    if manifest.launcher_version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "Warning: archive was exported by launcher version {}; this is version {}",
+            manifest.launcher_version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+}
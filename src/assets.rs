@@ -0,0 +1,282 @@
+//! Asset canisters serving local directories (`--asset-canister`), so that a frontend is served and
+//! certified by the local network without a separate deploy tool.
+//!
+//! The launcher ships no asset canister Wasm, so the module is taken from the command line (e.g.
+//! `assetstorage.wasm.gz` from an SDK release). Like the Candid UI, each asset canister gets a
+//! fresh ID that is recorded in the canister registry to find it again when the network resumes.
+//!
+//! A sync replaces all of a canister's assets with the directory's files in one batch, so the
+//! canister never serves a half-uploaded directory. Files and directories whose names start with
+//! `.` are skipped.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher, recommended_watcher};
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::canister;
+
+/// Cycles given to each asset canister.
+const ASSET_CANISTER_CYCLES: u128 = 100_000_000_000_000;
+/// Size of the chunks files are uploaded in, below the ingress message limit.
+const CHUNK_SIZE: usize = 1_900_000;
+/// How long to wait for more changes after a file changes before syncing.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Parses a `NAME=DIR` pair.
+pub fn parse_asset_canister(s: &str) -> Result<(String, PathBuf), String> {
+    let (name, dir) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=DIR`, got `{s}`"))?;
+    if name.is_empty() {
+        return Err(format!("missing canister name in `{s}`"));
+    }
+    Ok((name.to_string(), PathBuf::from(dir)))
+}
+
+/// Installs the asset canister Wasm module `wasm`, returning the canister's ID. If `existing`
+/// names a canister that still exists, it is assumed to be the asset canister and returned as is.
+pub async fn install(
+    pic: &PocketIc,
+    existing: Option<Principal>,
+    wasm: Vec<u8>,
+) -> anyhow::Result<Principal> {
+    if let Some(canister_id) = existing
+        && pic.canister_exists(canister_id).await
+    {
+        return Ok(canister_id);
+    }
+    let canister_id = pic.create_canister().await;
+    pic.add_cycles(canister_id, ASSET_CANISTER_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, Encode!()?).await?;
+    Ok(canister_id)
+}
+
+#[derive(CandidType)]
+struct ListArguments {}
+
+#[derive(CandidType, Deserialize)]
+struct AssetDetails {
+    key: String,
+}
+
+#[derive(CandidType)]
+struct CreateBatchArguments {}
+
+#[derive(CandidType, Deserialize)]
+struct CreateBatchResponse {
+    batch_id: Nat,
+}
+
+#[derive(CandidType)]
+struct CreateChunkArguments {
+    batch_id: Nat,
+    content: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct CreateChunkResponse {
+    chunk_id: Nat,
+}
+
+#[derive(CandidType)]
+struct CommitBatchArguments {
+    batch_id: Nat,
+    operations: Vec<BatchOperationKind>,
+}
+
+#[derive(CandidType)]
+enum BatchOperationKind {
+    CreateAsset(CreateAssetArguments),
+    SetAssetContent(SetAssetContentArguments),
+    DeleteAsset(DeleteAssetArguments),
+}
+
+#[derive(CandidType)]
+struct DeleteAssetArguments {
+    key: String,
+}
+
+#[derive(CandidType)]
+struct CreateAssetArguments {
+    key: String,
+    content_type: String,
+}
+
+#[derive(CandidType)]
+struct SetAssetContentArguments {
+    key: String,
+    content_encoding: String,
+    chunk_ids: Vec<Nat>,
+    sha256: Option<Vec<u8>>,
+}
+
+/// Replaces the assets of `canister_id` with the files in `dir`, returning the number of files.
+pub async fn sync(pic: &PocketIc, canister_id: Principal, dir: &Path) -> anyhow::Result<usize> {
+    let mut files = vec![];
+    collect_files(dir, "", &mut files)
+        .with_context(|| format!("failed to read {}", dir.display()))?;
+    let call = async |method: &str, arg: Vec<u8>| {
+        pic.update_call(canister_id, Principal::anonymous(), method, arg)
+            .await
+            .map_err(|e| anyhow!("{method} failed for asset canister {canister_id}: {e}"))
+    };
+    let response = pic
+        .query_call(
+            canister_id,
+            Principal::anonymous(),
+            "list",
+            Encode!(&ListArguments {})?,
+        )
+        .await
+        .map_err(|e| anyhow!("list failed for asset canister {canister_id}: {e}"))?;
+    // the batch's `Clear` operation would also discard its chunks, so assets are deleted one by one
+    let mut operations: Vec<_> = Decode!(&response, Vec<AssetDetails>)?
+        .into_iter()
+        .map(|asset| BatchOperationKind::DeleteAsset(DeleteAssetArguments { key: asset.key }))
+        .collect();
+    let response = call("create_batch", Encode!(&CreateBatchArguments {})?).await?;
+    let batch_id = Decode!(&response, CreateBatchResponse)?.batch_id;
+    for (key, path) in &files {
+        let content =
+            fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut chunk_ids = vec![];
+        // an empty file is still uploaded as one (empty) chunk
+        for chunk in content
+            .chunks(CHUNK_SIZE)
+            .chain(content.is_empty().then_some(&[][..]))
+        {
+            let response = call(
+                "create_chunk",
+                Encode!(&CreateChunkArguments {
+                    batch_id: batch_id.clone(),
+                    content: chunk.to_vec(),
+                })?,
+            )
+            .await?;
+            chunk_ids.push(Decode!(&response, CreateChunkResponse)?.chunk_id);
+        }
+        operations.push(BatchOperationKind::CreateAsset(CreateAssetArguments {
+            key: key.clone(),
+            content_type: content_type(key).to_string(),
+        }));
+        operations.push(BatchOperationKind::SetAssetContent(
+            SetAssetContentArguments {
+                key: key.clone(),
+                content_encoding: "identity".to_string(),
+                chunk_ids,
+                sha256: Some(Sha256::digest(&content).to_vec()),
+            },
+        ));
+    }
+    call(
+        "commit_batch",
+        Encode!(&CommitBatchArguments {
+            batch_id,
+            operations,
+        })?,
+    )
+    .await?;
+    Ok(files.len())
+}
+
+/// Appends the files under `dir` to `files` as `(key, path)` pairs, where keys are paths relative
+/// to the synced directory, starting with `/`.
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let key = format!("{prefix}/{name}");
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, &key, files)?;
+        } else {
+            files.push((key, path));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the content type of an asset, by its extension.
+pub fn content_type(key: &str) -> &'static str {
+    let extension = key
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "js" | "mjs" => "text/javascript",
+        "css" => "text/css",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Re-syncs `canister_id` whenever a file under `dir` changes. The watcher stops when the
+/// returned watcher is dropped, and the task is aborted.
+pub fn watch(
+    pic: PocketIc,
+    name: String,
+    canister_id: Principal,
+    dir: PathBuf,
+) -> anyhow::Result<(RecommendedWatcher, JoinHandle<()>)> {
+    let (tx, mut rx) = mpsc::channel(1);
+    let mut watcher = recommended_watcher(move |event: Result<Event, notify::Error>| {
+        if event.is_ok() {
+            // a full channel already has a sync pending
+            _ = tx.try_send(());
+        }
+    })
+    .context("failed to create file watcher")?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .with_context(|| format!("failed to watch {}", dir.display()))?;
+    let task = tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            // editors and bundlers write several files at once, so wait for them to finish
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            match sync(&pic, canister_id, &dir).await {
+                Ok(count) => eprintln!("synced {count} files from {} to {name}", dir.display()),
+                Err(e) => eprintln!("Warning: failed to sync {}: {e:#}", dir.display()),
+            }
+        }
+    });
+    Ok((watcher, task))
+}
@@ -0,0 +1,208 @@
+//! A quick cost profiler for canister methods (`bench`).
+//!
+//! The calls are listed in a TOML manifest:
+//!
+//! ```toml
+//! [[calls]]
+//! name = "transfer"                 # optional, defaults to `<canister>.<method>`
+//! canister = "backend"              # registry name or canister ID
+//! method = "transfer"
+//! arg = '(record { amount = 100 })' # Candid text, optional
+//! sender = "2vxsx-fae"              # optional, anonymous by default
+//! query = false                     # optional
+//! repeat = 10                       # optional, 1 by default
+//! ```
+//!
+//! Calls run while the network's rounds are paused, so that no other activity is billed to the
+//! canister. The replica doesn't report instructions to callers, so they are estimated from the
+//! cycles an update burns, using the fees of an application subnet. Queries burn no cycles, so
+//! their instructions are unknown.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, anyhow};
+use candid::{Encode, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::control::Control;
+
+/// Fee for receiving an ingress message, and per byte of it.
+const INGRESS_MESSAGE_FEE: u128 = 1_200_000;
+const INGRESS_BYTE_FEE: u128 = 2_000;
+/// Fee for executing an update, and per ten instructions.
+const UPDATE_EXECUTION_FEE: u128 = 5_000_000;
+const TEN_INSTRUCTIONS_FEE: u128 = 4;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    calls: Vec<ManifestCall>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestCall {
+    name: Option<String>,
+    canister: String,
+    method: String,
+    arg: Option<String>,
+    sender: Option<Principal>,
+    #[serde(default)]
+    query: bool,
+    repeat: Option<u32>,
+}
+
+/// A call to benchmark, resolved against the canister registry.
+#[derive(Serialize, Deserialize)]
+pub struct BenchCall {
+    pub name: String,
+    pub canister_id: Principal,
+    pub method: String,
+    pub arg: Vec<u8>,
+    pub sender: Principal,
+    pub query: bool,
+    pub repeat: u32,
+}
+
+/// Averages over the repetitions of a call.
+#[derive(Serialize, Deserialize)]
+pub struct BenchResult {
+    pub name: String,
+    pub calls: u32,
+    pub cycles: u128,
+    /// Estimated from `cycles`, for updates that burned any.
+    pub instructions: Option<u64>,
+    pub wall_time: Duration,
+}
+
+/// Reads the manifest at `path`, resolving canister names with `canisters`.
+pub fn load(
+    path: &Path,
+    canisters: &BTreeMap<String, Principal>,
+) -> anyhow::Result<Vec<BenchCall>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest: Manifest =
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    let mut calls = vec![];
+    for call in manifest.calls {
+        let name = call
+            .name
+            .unwrap_or_else(|| format!("{}.{}", call.canister, call.method));
+        let canister_id = match canisters.get(&call.canister) {
+            Some(&canister_id) => canister_id,
+            None => Principal::from_text(&call.canister)
+                .with_context(|| format!("unknown canister `{}` in {name}", call.canister))?,
+        };
+        let arg = match &call.arg {
+            Some(text) => candid_parser::parse_idl_args(text)
+                .with_context(|| format!("invalid arg for {name}"))?
+                .to_bytes()
+                .with_context(|| format!("invalid arg for {name}"))?,
+            None => Encode!()?,
+        };
+        calls.push(BenchCall {
+            name,
+            canister_id,
+            method: call.method,
+            arg,
+            sender: call.sender.unwrap_or(Principal::anonymous()),
+            query: call.query,
+            repeat: call.repeat.unwrap_or(1).max(1),
+        });
+    }
+    Ok(calls)
+}
+
+/// Runs `calls` on the network of `control`, with rounds paused.
+pub async fn run(control: &Control, calls: Vec<BenchCall>) -> anyhow::Result<Vec<BenchResult>> {
+    let pic = &control.pic;
+    let paused = control.progress.lock().await;
+    pic.stop_progress().await;
+    let mut results = vec![];
+    let mut outcome = Ok(());
+    for call in calls {
+        let mut cycles = 0;
+        let mut wall_time = Duration::ZERO;
+        for _ in 0..call.repeat {
+            let before = pic.cycle_balance(call.canister_id).await;
+            let start = Instant::now();
+            let response = if call.query {
+                pic.query_call(
+                    call.canister_id,
+                    call.sender,
+                    &call.method,
+                    call.arg.clone(),
+                )
+                .await
+            } else {
+                pic.update_call(
+                    call.canister_id,
+                    call.sender,
+                    &call.method,
+                    call.arg.clone(),
+                )
+                .await
+            };
+            wall_time += start.elapsed();
+            if let Err(e) = response {
+                outcome = Err(anyhow!("{} failed: {e}", call.name));
+                break;
+            }
+            cycles += before.saturating_sub(pic.cycle_balance(call.canister_id).await);
+        }
+        if outcome.is_err() {
+            break;
+        }
+        let cycles = cycles / u128::from(call.repeat);
+        let instructions = (!call.query && cycles > 0).then(|| {
+            let ingress_bytes = (call.method.len() + call.arg.len()) as u128;
+            let execution = cycles.saturating_sub(
+                INGRESS_MESSAGE_FEE + INGRESS_BYTE_FEE * ingress_bytes + UPDATE_EXECUTION_FEE,
+            );
+            (execution * 10 / TEN_INSTRUCTIONS_FEE) as u64
+        });
+        results.push(BenchResult {
+            name: call.name,
+            calls: call.repeat,
+            cycles,
+            instructions,
+            wall_time: wall_time / call.repeat,
+        });
+    }
+    control.resume_progress().await?;
+    drop(paused);
+    outcome.map(|()| results)
+}
+
+/// Prints `results` as a table.
+pub fn print(results: &[BenchResult]) {
+    let width = results
+        .iter()
+        .map(|result| result.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{:<width$}  {:>5}  {:>15}  {:>15}  {:>10}",
+        "CALL", "CALLS", "~INSTRUCTIONS", "CYCLES", "WALL TIME"
+    );
+    for result in results {
+        let instructions = result
+            .instructions
+            .map_or_else(|| "-".to_string(), |instructions| instructions.to_string());
+        println!(
+            "{:<width$}  {:>5}  {:>15}  {:>15}  {:>8.1}ms",
+            result.name,
+            result.calls,
+            instructions,
+            result.cycles,
+            result.wall_time.as_secs_f64() * 1000.0
+        );
+    }
+}
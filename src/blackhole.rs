@@ -0,0 +1,35 @@
+//! The blackhole canister (`--blackhole`), a canister controlled only by itself that reports the
+//! status of any canister it controls. Monitoring tools expect it as a controller of the canisters
+//! they watch.
+//!
+//! The blackhole's mainnet ID is outside the ranges of the local subnets, so it is installed at a
+//! fixed ID at the end of the NNS subnet's range instead, which stays the same across networks.
+
+use anyhow::anyhow;
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::canister;
+
+/// ID of the blackhole canister: the last in the NNS subnet's range, which no NNS canister uses.
+pub const BLACKHOLE_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 15, 255, 255, 1, 1]);
+/// Cycles given to the blackhole canister.
+const BLACKHOLE_CYCLES: u128 = 100_000_000_000_000;
+
+/// Installs the blackhole Wasm module `wasm` and makes the canister its own only controller,
+/// unless it is already installed.
+pub async fn install(pic: &PocketIc, wasm: Vec<u8>) -> anyhow::Result<()> {
+    let canister_id = BLACKHOLE_CANISTER_ID;
+    if pic.canister_exists(canister_id).await {
+        return Ok(());
+    }
+    pic.create_canister_with_id(None, None, canister_id)
+        .await
+        .map_err(|e| anyhow!("failed to create canister {canister_id}: {e}"))?;
+    pic.add_cycles(canister_id, BLACKHOLE_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, Encode!()?).await?;
+    pic.set_controllers(canister_id, None, vec![canister_id])
+        .await
+        .map_err(|e| anyhow!("failed to set controllers of the blackhole: {e}"))
+}
@@ -0,0 +1,52 @@
+//! Bounded runs (`--max-rounds`, `--max-duration`), for benchmark scripts and CI smoke tests that
+//! must never run unbounded: the network shuts down once it has executed a number of rounds or
+//! been up for a while.
+//!
+//! PocketIC doesn't report how many rounds auto-progress has executed, so with `--max-rounds` the
+//! launcher executes the rounds itself, keeping the network's time in step with the host's clock
+//! as auto-progress does.
+
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pocket_ic::Time;
+
+use crate::control::Control;
+
+/// How long to wait before checking again while the network is paused for exceeding its quota.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Executes `rounds` rounds on the network of `control`, then shuts it down.
+pub async fn run(control: Arc<Control>, rounds: u64) {
+    let mut executed = 0;
+    while executed < rounds {
+        {
+            // operations that pause rounds hold the lock until they resume them
+            let executing = control.progress.lock().await;
+            if control.quota_paused.load(Ordering::Relaxed) {
+                drop(executing);
+                tokio::time::sleep(PAUSED_POLL_INTERVAL).await;
+                continue;
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system clock should be after the unix epoch")
+                .as_nanos() as u64;
+            if now > control.pic.get_time().await.as_nanos_since_unix_epoch() {
+                control
+                    .pic
+                    .set_time(Time::from_nanos_since_unix_epoch(now))
+                    .await;
+            }
+            control.pic.tick().await;
+            executed += 1;
+        }
+        let delay = control.artificial_delay_ms.lock().await.unwrap_or(0);
+        // sleeping even for no delay lets operations waiting for the lock take it
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+    eprintln!("executed {rounds} rounds (--max-rounds); shutting down");
+    control.shutdown.notify_one();
+}
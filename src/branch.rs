@@ -0,0 +1,138 @@
+//! Named branches of a state directory, stored next to it in `<state-dir>.branches`.
+//!
+//! The state directory always holds the current branch; the others are kept in the branches
+//! directory, so switching branches is a pair of renames.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use clap::Subcommand;
+
+use crate::snapshot;
+
+/// File in the branches directory recording the name of the current branch.
+const CURRENT_FILE: &str = "CURRENT";
+/// Name of the current branch if none has been recorded.
+const DEFAULT_BRANCH: &str = "main";
+
+fn branches_dir(state_dir: &Path) -> anyhow::Result<PathBuf> {
+    snapshot::sibling_dir(state_dir, ".branches")
+}
+
+/// Returns the name of the branch currently in `state_dir`.
+pub fn current(state_dir: &Path) -> anyhow::Result<String> {
+    let file = branches_dir(state_dir)?.join(CURRENT_FILE);
+    match fs::read_to_string(&file) {
+        Ok(name) => Ok(name.trim().to_string()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(DEFAULT_BRANCH.to_string()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", file.display())),
+    }
+}
+
+/// Creates a branch called `name` from the current contents of `state_dir`.
+pub fn create(state_dir: &Path, name: &str) -> anyhow::Result<()> {
+    snapshot::validate_file_name(name).map_err(anyhow::Error::msg)?;
+    if name == CURRENT_FILE {
+        bail!("`{CURRENT_FILE}` cannot be used as a branch name");
+    }
+    let dest = branches_dir(state_dir)?.join(name);
+    if dest.exists() || name == current(state_dir)? {
+        bail!("branch {name} already exists");
+    }
+    if !state_dir.is_dir() {
+        bail!("state directory {} does not exist", state_dir.display());
+    }
+    snapshot::copy_dir(state_dir, &dest)
+        .with_context(|| format!("failed to copy state directory to branch {name}"))
+}
+
+/// Stores the current branch away and moves the branch called `name` into `state_dir`.
+/// The network using `state_dir` must not be running.
+pub fn switch(state_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let current = current(state_dir)?;
+    if name == current {
+        return Ok(());
+    }
+    let dir = branches_dir(state_dir)?;
+    let target = dir.join(name);
+    if name == CURRENT_FILE || !target.is_dir() {
+        bail!("no branch named {name}");
+    }
+    if state_dir.exists() {
+        fs::rename(state_dir, dir.join(&current))
+            .with_context(|| format!("failed to store branch {current}"))?;
+    }
+    fs::rename(&target, state_dir).with_context(|| format!("failed to check out branch {name}"))?;
+    fs::write(dir.join(CURRENT_FILE), name).context("failed to record current branch")
+}
+
+/// Lists the names of all branches of `state_dir`, including the current one.
+pub fn list(state_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let dir = branches_dir(state_dir)?;
+    let mut branches = vec![current(state_dir)?];
+    if dir.exists() {
+        for entry in fs::read_dir(&dir).context("failed to read branches directory")? {
+            let entry = entry.context("failed to read branches directory")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() && !name.starts_with('.') {
+                branches.push(name);
+            }
+        }
+    }
+    branches.sort();
+    Ok(branches)
+}
+
+/// Deletes the branch called `name`, which must not be the current branch.
+pub fn delete(state_dir: &Path, name: &str) -> anyhow::Result<()> {
+    if name == current(state_dir)? {
+        bail!("cannot delete the current branch {name}");
+    }
+    let target = branches_dir(state_dir)?.join(name);
+    if name == CURRENT_FILE || !target.is_dir() {
+        bail!("no branch named {name}");
+    }
+    fs::remove_dir_all(&target).with_context(|| format!("failed to delete branch {name}"))
+}
+
+#[derive(Subcommand)]
+pub enum StateCommand {
+    /// Creates a branch from the current contents of `--state-dir`.
+    Branch {
+        /// Name of the new branch.
+        name: String,
+    },
+    /// Switches `--state-dir` to another branch. The network must be stopped.
+    Switch {
+        /// Name of the branch to switch to.
+        name: String,
+    },
+    /// Lists the branches of `--state-dir`, marking the current one.
+    Branches,
+    /// Deletes a branch other than the current one.
+    DeleteBranch {
+        /// Name of the branch to delete.
+        name: String,
+    },
+}
+
+/// Runs `command` on the branches of `state_dir`.
+pub fn run_command(state_dir: &Path, command: StateCommand) -> anyhow::Result<()> {
+    match command {
+        StateCommand::Branch { name } => create(state_dir, &name),
+        StateCommand::Switch { name } => switch(state_dir, &name),
+        StateCommand::Branches => {
+            let current = current(state_dir)?;
+            for name in list(state_dir)? {
+                let marker = if name == current { "*" } else { " " };
+                println!("{marker} {name}");
+            }
+            Ok(())
+        }
+        StateCommand::DeleteBranch { name } => delete(state_dir, &name),
+    }
+}
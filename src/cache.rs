@@ -0,0 +1,184 @@
+//! Caching of certified responses on the gateway (`--gateway-cache`), so that frontend reloads are
+//! fast and bugs that only appear behind the boundary nodes' caches, such as stale assets after an
+//! upgrade, surface locally.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! keeps the certified responses to `GET` requests, by host, path and accepted encodings, like a
+//! boundary node: for the TTL, or less if the response's `Cache-Control` says so, and not at all
+//! if it forbids caching. Requests with `Cache-Control: no-cache` bypass the cache and refresh it,
+//! and the `purge-gateway-cache` subcommand empties it. Responses say whether they came from the
+//! cache in `x-cache-status`, as boundary nodes do.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http_body_util::Full;
+use hyper::{
+    HeaderMap, Method, Response, StatusCode,
+    body::Bytes,
+    header::{ACCEPT_ENCODING, CACHE_CONTROL, HOST, HeaderName, HeaderValue, PRAGMA},
+    http::{request, response},
+};
+
+/// Header that tells whether a response came from the cache.
+pub const CACHE_STATUS: HeaderName = HeaderName::from_static("x-cache-status");
+/// Header of certified responses.
+const IC_CERTIFICATE: HeaderName = HeaderName::from_static("ic-certificate");
+/// Largest response body that is cached.
+const MAX_ENTRY_SIZE: usize = 10 << 20;
+
+/// The gateway's cache.
+pub struct GatewayCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    expires: Instant,
+}
+
+/// What the cache did with a request.
+pub enum Lookup {
+    Hit(Response<Full<Bytes>>),
+    /// Not in the cache; the response can be stored.
+    Miss,
+    /// Not cacheable, or bypassed by the client; the response can be stored if it is cacheable.
+    Bypass,
+    /// Not cacheable at all.
+    Uncacheable,
+}
+
+impl GatewayCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: <_>::default(),
+        }
+    }
+
+    /// Looks up the request described by `parts`.
+    pub fn lookup(&self, parts: &request::Parts) -> Lookup {
+        let Some(key) = key(parts) else {
+            return Lookup::Uncacheable;
+        };
+        let bypass = [CACHE_CONTROL, PRAGMA].iter().any(|name| {
+            parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| value.to_ascii_lowercase().contains("no-cache"))
+        });
+        if bypass {
+            return Lookup::Bypass;
+        }
+        let entries = self.entries.lock().expect("cache lock poisoned");
+        match entries.get(&key) {
+            Some(entry) if entry.expires > Instant::now() => {
+                let mut response = Response::new(Full::new(entry.body.clone()));
+                *response.status_mut() = entry.status;
+                *response.headers_mut() = entry.headers.clone();
+                response
+                    .headers_mut()
+                    .insert(CACHE_STATUS, HeaderValue::from_static("HIT"));
+                Lookup::Hit(response)
+            }
+            _ => Lookup::Miss,
+        }
+    }
+
+    /// Whether a response with the head `response` and a body of at least `size` bytes can be
+    /// stored, so that the gateway only reads the bodies it may keep.
+    pub fn admits(&self, response: &response::Parts, size: u64) -> bool {
+        response.status == StatusCode::OK
+            && response.headers.contains_key(IC_CERTIFICATE)
+            && size <= MAX_ENTRY_SIZE as u64
+    }
+
+    /// Stores the response with the head `response` and `body` to the request described by
+    /// `parts` if it is cacheable.
+    pub fn store(&self, parts: &request::Parts, response: &response::Parts, body: &Bytes) {
+        let Some(key) = key(parts) else {
+            return;
+        };
+        if !self.admits(response, body.len() as u64) {
+            return;
+        }
+        let Some(ttl) = self.ttl_of(&response.headers) else {
+            return;
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        entries.retain(|_, entry| entry.expires > now);
+        entries.insert(
+            key,
+            Entry {
+                status: response.status,
+                headers: response.headers.clone(),
+                body: body.clone(),
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Empties the cache, returning how many responses it held.
+    pub fn purge(&self) -> usize {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        let now = Instant::now();
+        let purged = entries.values().filter(|entry| entry.expires > now).count();
+        entries.clear();
+        purged
+    }
+
+    /// How long a response with `headers` may be cached, if at all.
+    fn ttl_of(&self, headers: &HeaderMap) -> Option<Duration> {
+        let mut ttl = self.ttl;
+        for directive in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let directive = directive.trim().to_ascii_lowercase();
+            if matches!(directive.as_str(), "no-store" | "no-cache" | "private") {
+                return None;
+            }
+            if let Some(max_age) = directive
+                .strip_prefix("s-maxage=")
+                .or_else(|| directive.strip_prefix("max-age="))
+                .and_then(|seconds| seconds.parse().ok())
+            {
+                ttl = ttl.min(Duration::from_secs(max_age));
+            }
+        }
+        (!ttl.is_zero()).then_some(ttl)
+    }
+}
+
+/// Returns the key the response to the request described by `parts` is cached under, if it can
+/// be cached.
+fn key(parts: &request::Parts) -> Option<String> {
+    if parts.method != Method::GET || parts.uri.path().starts_with("/api/") {
+        return None;
+    }
+    // HTTP/2 requests carry the host in the URI's authority rather than a Host header
+    let host = match parts.headers.get(HOST) {
+        Some(host) => host.to_str().ok()?,
+        None => parts.uri.authority()?.as_str(),
+    };
+    let accept_encoding = parts
+        .headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    Some(format!(
+        "{host} {} {accept_encoding}",
+        parts.uri.path_and_query().map_or("/", |p| p.as_str())
+    ))
+}
@@ -0,0 +1,55 @@
+//! The Candid UI canister (`--candid-ui`), a web frontend for calling any canister's methods from
+//! its Candid interface.
+//!
+//! The launcher ships no Candid UI Wasm, so the module is taken from the command line (e.g.
+//! `candid_ui.wasm` from a Candid release). The canister has no mainnet ID to reuse, so it gets a
+//! fresh ID, which is recorded in the canister registry to find it again when the network resumes.
+
+use std::collections::BTreeMap;
+
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::canister;
+
+/// Name of the Candid UI canister in the registry.
+pub const REGISTRY_NAME: &str = "candid-ui";
+/// Cycles given to the Candid UI canister.
+const CANDID_UI_CYCLES: u128 = 100_000_000_000_000;
+
+/// Installs the Candid UI Wasm module `wasm`, returning the canister's ID. If `existing` names a
+/// canister that still exists, it is assumed to be the Candid UI and returned as is.
+pub async fn install(
+    pic: &PocketIc,
+    existing: Option<Principal>,
+    wasm: Vec<u8>,
+) -> anyhow::Result<Principal> {
+    if let Some(canister_id) = existing
+        && pic.canister_exists(canister_id).await
+    {
+        return Ok(canister_id);
+    }
+    let canister_id = pic.create_canister().await;
+    pic.add_cycles(canister_id, CANDID_UI_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, Encode!()?).await?;
+    Ok(canister_id)
+}
+
+/// Returns the URL of the Candid UI served through the gateway on `gateway_port`.
+pub fn url(candid_ui: Principal, gateway_port: u16) -> String {
+    format!("http://{candid_ui}.localhost:{gateway_port}")
+}
+
+/// Returns the Candid UI URL of each of `canisters`, by name.
+pub fn canister_urls(
+    candid_ui: Principal,
+    gateway_port: u16,
+    canisters: &BTreeMap<String, Principal>,
+) -> Vec<(String, String)> {
+    let base = url(candid_ui, gateway_port);
+    canisters
+        .iter()
+        .filter(|(_, canister_id)| **canister_id != candid_ui)
+        .map(|(name, canister_id)| (name.clone(), format!("{base}/?id={canister_id}")))
+        .collect()
+}
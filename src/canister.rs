@@ -0,0 +1,467 @@
+//! Operations on individual canisters of a network, through the management canister.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use candid::{CandidType, Decode, Encode, Principal};
+use clap::Subcommand;
+use pocket_ic::{
+    common::rest::RawEffectivePrincipal,
+    management_canister::{CanisterSettings, CanisterStatusResult},
+    nonblocking::PocketIc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::control;
+
+/// Snapshot data is read in chunks that fit comfortably in a single response.
+const CHUNK_SIZE: u64 = 2_000_000;
+
+/// The code and data of a canister snapshot.
+pub struct SnapshotContents {
+    pub wasm_module: Vec<u8>,
+    pub stable_memory: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct ReadCanisterSnapshotMetadataArgs {
+    canister_id: Principal,
+    snapshot_id: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ReadCanisterSnapshotMetadataResponse {
+    wasm_module_size: u64,
+    stable_memory_size: u64,
+}
+
+#[derive(CandidType)]
+struct ReadCanisterSnapshotDataArgs {
+    canister_id: Principal,
+    snapshot_id: Vec<u8>,
+    kind: SnapshotDataKind,
+}
+
+#[derive(CandidType, Deserialize)]
+enum SnapshotDataKind {
+    #[serde(rename = "wasm_module")]
+    WasmModule { offset: u64, size: u64 },
+    #[serde(rename = "stable_memory")]
+    StableMemory { offset: u64, size: u64 },
+}
+
+#[derive(CandidType, Deserialize)]
+struct ReadCanisterSnapshotDataResponse {
+    chunk: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct InstallCodeArgs {
+    mode: InstallMode,
+    canister_id: Principal,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum InstallMode {
+    #[serde(rename = "install")]
+    Install,
+    #[serde(rename = "reinstall")]
+    Reinstall,
+    #[serde(rename = "upgrade")]
+    Upgrade(Option<UpgradeFlags>),
+}
+
+#[derive(CandidType, Deserialize)]
+struct UpgradeFlags {
+    skip_pre_upgrade: Option<bool>,
+}
+
+/// Describes a canister exported by [`export`].
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+    pub v: String,
+    pub canister_id: Principal,
+    /// Version of the launcher that exported the canister.
+    pub launcher_version: String,
+    /// Unix timestamp of the export, in seconds.
+    pub exported_at: u64,
+    /// Hex-encoded SHA-256 hash of the Wasm module.
+    pub module_hash: Option<String>,
+    pub controllers: Vec<Principal>,
+    pub cycles: String,
+    pub memory_size: String,
+}
+
+/// Reads the Wasm module and stable memory of a canister snapshot. `call` makes a call to the
+/// management canister on behalf of a controller of `canister_id`.
+pub async fn read_snapshot<F: Future<Output = anyhow::Result<Vec<u8>>>>(
+    call: impl Fn(&'static str, Vec<u8>) -> F,
+    canister_id: Principal,
+    snapshot_id: &[u8],
+) -> anyhow::Result<SnapshotContents> {
+    let metadata = call(
+        "read_canister_snapshot_metadata",
+        Encode!(&ReadCanisterSnapshotMetadataArgs {
+            canister_id,
+            snapshot_id: snapshot_id.to_vec(),
+        })?,
+    )
+    .await?;
+    let metadata = Decode!(&metadata, ReadCanisterSnapshotMetadataResponse)?;
+    let wasm_module = read_snapshot_data(
+        &call,
+        canister_id,
+        snapshot_id,
+        metadata.wasm_module_size,
+        |offset, size| SnapshotDataKind::WasmModule { offset, size },
+    )
+    .await
+    .context("failed to read Wasm module")?;
+    let stable_memory = read_snapshot_data(
+        &call,
+        canister_id,
+        snapshot_id,
+        metadata.stable_memory_size,
+        |offset, size| SnapshotDataKind::StableMemory { offset, size },
+    )
+    .await
+    .context("failed to read stable memory")?;
+    Ok(SnapshotContents {
+        wasm_module,
+        stable_memory,
+    })
+}
+
+async fn read_snapshot_data<F: Future<Output = anyhow::Result<Vec<u8>>>>(
+    call: &impl Fn(&'static str, Vec<u8>) -> F,
+    canister_id: Principal,
+    snapshot_id: &[u8],
+    size: u64,
+    kind: impl Fn(u64, u64) -> SnapshotDataKind,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size as usize);
+    let mut offset = 0;
+    while offset < size {
+        let chunk_size = CHUNK_SIZE.min(size - offset);
+        let response = call(
+            "read_canister_snapshot_data",
+            Encode!(&ReadCanisterSnapshotDataArgs {
+                canister_id,
+                snapshot_id: snapshot_id.to_vec(),
+                kind: kind(offset, chunk_size),
+            })?,
+        )
+        .await?;
+        let response = Decode!(&response, ReadCanisterSnapshotDataResponse)?;
+        data.extend_from_slice(&response.chunk);
+        offset += chunk_size;
+    }
+    Ok(data)
+}
+
+/// Installs `wasm_module` (optionally gzipped) into the empty canister `canister_id` on behalf
+/// of `sender`, which must be a controller. Unlike `PocketIc::install_canister`, failures such as
+/// a trapping `init` are returned rather than panicking.
+pub async fn install_code(
+    pic: &PocketIc,
+    canister_id: Principal,
+    sender: Principal,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    call_install_code(
+        pic,
+        sender,
+        InstallCodeArgs {
+            mode: InstallMode::Install,
+            canister_id,
+            wasm_module,
+            arg,
+        },
+    )
+    .await
+    .with_context(|| format!("failed to install code in canister {canister_id}"))
+}
+
+/// Replaces the code of `canister_id` with `wasm_module`, discarding its state, on behalf of
+/// `sender`, which must be a controller.
+pub async fn reinstall_code(
+    pic: &PocketIc,
+    canister_id: Principal,
+    sender: Principal,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    call_install_code(
+        pic,
+        sender,
+        InstallCodeArgs {
+            mode: InstallMode::Reinstall,
+            canister_id,
+            wasm_module,
+            arg,
+        },
+    )
+    .await
+    .with_context(|| format!("failed to reinstall code in canister {canister_id}"))
+}
+
+/// Adds `controllers` to the controllers of `canister_id`, on behalf of its first controller.
+pub async fn add_controllers(
+    pic: &PocketIc,
+    canister_id: Principal,
+    controllers: &[Principal],
+) -> anyhow::Result<()> {
+    let mut current = pic.get_controllers(canister_id).await;
+    let Some(&controller) = current.first() else {
+        bail!("canister {canister_id} has no controllers, so its controllers cannot be changed");
+    };
+    for &new in controllers {
+        if !current.contains(&new) {
+            current.push(new);
+        }
+    }
+    pic.set_controllers(canister_id, Some(controller), current)
+        .await
+        .map_err(|e| anyhow!("failed to set controllers of canister {canister_id}: {e}"))
+}
+
+/// Updates the settings of `canister_id` to `settings`, on behalf of its first controller.
+pub async fn update_settings(
+    pic: &PocketIc,
+    canister_id: Principal,
+    settings: CanisterSettings,
+) -> anyhow::Result<()> {
+    let Some(&controller) = pic.get_controllers(canister_id).await.first() else {
+        bail!("canister {canister_id} has no controllers, so its settings cannot be changed");
+    };
+    pic.update_canister_settings(canister_id, Some(controller), settings)
+        .await
+        .map_err(|e| anyhow!("failed to update settings of canister {canister_id}: {e}"))
+}
+
+/// Upgrades `canister_id` to `wasm_module`, passing `arg` to its `post_upgrade`, on behalf of its
+/// first controller.
+pub async fn upgrade_code(
+    pic: &PocketIc,
+    canister_id: Principal,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    let Some(&controller) = pic.get_controllers(canister_id).await.first() else {
+        bail!("canister {canister_id} has no controllers, so it cannot be upgraded");
+    };
+    call_install_code(
+        pic,
+        controller,
+        InstallCodeArgs {
+            mode: InstallMode::Upgrade(None),
+            canister_id,
+            wasm_module,
+            arg,
+        },
+    )
+    .await
+    .with_context(|| format!("failed to upgrade canister {canister_id}"))
+}
+
+/// Upgrades `canister_id` to the Wasm module it already runs, passing `arg` to its
+/// `post_upgrade`. This is how canisters that take their configuration as an upgrade argument are
+/// reconfigured. Like [`export`], this needs a temporary canister snapshot.
+pub async fn reconfigure(
+    pic: &PocketIc,
+    canister_id: Principal,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    let Some(&controller) = pic.get_controllers(canister_id).await.first() else {
+        bail!("canister {canister_id} has no controllers, so it cannot be upgraded");
+    };
+    let SnapshotContents { wasm_module, .. } =
+        read_via_snapshot(pic, canister_id, controller).await?;
+    call_install_code(
+        pic,
+        controller,
+        InstallCodeArgs {
+            mode: InstallMode::Upgrade(None),
+            canister_id,
+            wasm_module,
+            arg,
+        },
+    )
+    .await
+    .with_context(|| format!("failed to upgrade canister {canister_id}"))
+}
+
+async fn call_install_code(
+    pic: &PocketIc,
+    sender: Principal,
+    args: InstallCodeArgs,
+) -> anyhow::Result<()> {
+    pic.update_call_with_effective_principal(
+        Principal::management_canister(),
+        RawEffectivePrincipal::CanisterId(args.canister_id.as_slice().to_vec()),
+        sender,
+        "install_code",
+        Encode!(&args)?,
+    )
+    .await
+    .map_err(|e| anyhow!("{e}"))?;
+    Ok(())
+}
+
+/// Reads the Wasm module and stable memory of `canister_id` through a temporary snapshot taken on
+/// behalf of `controller`.
+async fn read_via_snapshot(
+    pic: &PocketIc,
+    canister_id: Principal,
+    controller: Principal,
+) -> anyhow::Result<SnapshotContents> {
+    let snapshot = pic
+        .take_canister_snapshot(canister_id, Some(controller), None)
+        .await
+        .map_err(|e| anyhow!("failed to snapshot canister {canister_id}: {e}"))?;
+    let contents = read_snapshot(
+        |method, arg| async move {
+            pic.update_call_with_effective_principal(
+                Principal::management_canister(),
+                RawEffectivePrincipal::CanisterId(canister_id.as_slice().to_vec()),
+                controller,
+                method,
+                arg,
+            )
+            .await
+            .map_err(|e| anyhow!("{method} failed for canister {canister_id}: {e}"))
+        },
+        canister_id,
+        &snapshot.id,
+    )
+    .await;
+    pic.delete_canister_snapshot(canister_id, Some(controller), snapshot.id)
+        .await
+        .map_err(|e| anyhow!("failed to delete temporary snapshot of {canister_id}: {e}"))?;
+    contents
+}
+
+/// Reads the Wasm module, stable memory and metadata of `canister_id`, to be written by [`write`].
+///
+/// The canister's code is only readable through a canister snapshot, so a temporary snapshot is
+/// taken on behalf of one of its controllers and deleted afterwards. This fails if the canister
+/// already has the maximum number of snapshots.
+pub async fn export(
+    pic: &PocketIc,
+    canister_id: Principal,
+) -> anyhow::Result<(Metadata, SnapshotContents)> {
+    if !pic.canister_exists(canister_id).await {
+        bail!("canister {canister_id} does not exist");
+    }
+    let controllers = pic.get_controllers(canister_id).await;
+    let Some(&controller) = controllers.first() else {
+        bail!("canister {canister_id} has no controllers, so its Wasm module cannot be read");
+    };
+    let status = pic
+        .canister_status(canister_id, Some(controller))
+        .await
+        .map_err(|e| anyhow!("failed to get status of canister {canister_id}: {e}"))?;
+    let SnapshotContents {
+        wasm_module,
+        stable_memory,
+    } = read_via_snapshot(pic, canister_id, controller).await?;
+    let metadata = Metadata {
+        v: "1".to_string(),
+        canister_id,
+        launcher_version: env!("CARGO_PKG_VERSION").to_string(),
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock should be after the unix epoch")
+            .as_secs(),
+        module_hash: status.module_hash.map(hex::encode),
+        controllers,
+        cycles: status.cycles.0.to_string(),
+        memory_size: status.memory_size.0.to_string(),
+    };
+    Ok((
+        metadata,
+        SnapshotContents {
+            wasm_module,
+            stable_memory,
+        },
+    ))
+}
+
+/// Writes a canister read by [`export`] to the directory `output`, as `canister.wasm`,
+/// `stable_memory.bin` and `metadata.json`.
+pub fn write(
+    output: &Path,
+    metadata: &Metadata,
+    SnapshotContents {
+        wasm_module,
+        stable_memory,
+    }: &SnapshotContents,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(output).with_context(|| format!("failed to create {}", output.display()))?;
+    fs::write(output.join("canister.wasm"), wasm_module).context("failed to write Wasm module")?;
+    fs::write(output.join("stable_memory.bin"), stable_memory)
+        .context("failed to write stable memory")?;
+    fs::write(
+        output.join("metadata.json"),
+        serde_json::to_vec_pretty(metadata).expect("infallible serialization"),
+    )
+    .context("failed to write metadata")?;
+    Ok(())
+}
+
+/// Returns the status of `canister_id`, asked for on behalf of one of its controllers, or `None`
+/// if it doesn't exist or has no controllers.
+pub async fn status(pic: &PocketIc, canister_id: Principal) -> Option<CanisterStatusResult> {
+    if !pic.canister_exists(canister_id).await {
+        return None;
+    }
+    let controller = *pic.get_controllers(canister_id).await.first()?;
+    pic.canister_status(canister_id, Some(controller))
+        .await
+        .ok()
+}
+
+#[derive(Subcommand)]
+pub enum CanisterCommand {
+    /// Writes a canister's Wasm module, stable memory and metadata to a directory.
+    /// Requires `--status-dir`.
+    Export {
+        /// ID of the canister to export.
+        id: Principal,
+        /// Directory to write the canister's files to.
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: CanisterCommand) -> anyhow::Result<()> {
+    match command {
+        CanisterCommand::Export { id, output } => {
+            let response: control::CanisterExportResponse = control::request(
+                status_dir,
+                "/canister/export",
+                &control::CanisterExportRequest { canister_id: id },
+            )
+            .await?;
+            let contents = SnapshotContents {
+                wasm_module: STANDARD
+                    .decode(&response.wasm_module)
+                    .context("invalid Wasm module in control response")?,
+                stable_memory: STANDARD
+                    .decode(&response.stable_memory)
+                    .context("invalid stable memory in control response")?,
+            };
+            write(&output, &response.metadata, &contents)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+//! Chaos testing: requests for a subnet's canisters are held back for a while (`stall-subnet`), so
+//! that developers can check that multi-subnet dapps and their retry logic cope when one subnet
+//! lags, and a share of ingress messages or of all responses are delayed or failed
+//! (`--ingress-delay`, `--ingress-reject-rate`, `--inject-latency`, `--inject-error-rate`), so that
+//! frontends can test their retry and timeout UX, loading states and error handling. Canister
+//! creation can be throttled (`--max-canister-creations`), as a busy subnet does, so that
+//! factory-pattern dapps can test their backoff, and so can gateway requests in general
+//! (`--rate-limit`), as boundary nodes do.
+//!
+//! PocketIC executes the rounds of all subnets together and cannot hold one back, so faults are
+//! injected in front of it, by the launcher's gateway (see [`crate::gateway`]): requests to a
+//! stalled subnet or its canisters wait until the stall ends. Calls between canisters are not
+//! affected, so only canisters created by ingress messages count towards the creation limit: calls
+//! to the management canister, the cycles ledger or the cycles minting canister that create them.
+//!
+//! `upgrade-subnet` simulates a subnet's replica upgrade on mainnet, so that canister authors can
+//! check that their clients and in-flight calls survive maintenance: the subnet is stalled at the
+//! gateway for the downtime while the other subnets keep serving requests. PocketIC executes rounds
+//! for all subnets together and can't checkpoint or restart a single subnet, so the subnet's
+//! canisters keep running their timers and handling calls from other subnets meanwhile, and no
+//! restart from a checkpoint is simulated.
+
+use std::{collections::BTreeMap, net::IpAddr, sync::Mutex, time::Duration};
+
+use ic_principal::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::time::Instant;
+
+use crate::{cycles, index, ingress};
+
+/// Window in which at most `--rate-limit` requests are served.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+/// Window in which at most `--max-canister-creations` canisters are created, about a mainnet
+/// round.
+const CREATION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Subnets currently stalled, and until when.
+#[derive(Default)]
+pub struct Stalls {
+    until: Mutex<BTreeMap<Principal, Instant>>,
+}
+
+impl Stalls {
+    /// Stalls `subnet_id` for `duration` from now, replacing any stall of it in progress.
+    pub fn stall(&self, subnet_id: Principal, duration: Duration) {
+        self.until
+            .lock()
+            .expect("stalls lock poisoned")
+            .insert(subnet_id, Instant::now() + duration);
+    }
+
+    /// Waits until the subnet a gateway request to `path` is for is no longer stalled.
+    pub async fn wait(&self, pic: &PocketIc, path: &str) {
+        // only look up the subnet while some subnet is stalled
+        if self.until.lock().expect("stalls lock poisoned").is_empty() {
+            return;
+        }
+        let subnet_id = match target(path) {
+            Some(Target::Subnet(subnet_id)) => subnet_id,
+            Some(Target::Canister(canister_id)) => match pic.get_subnet(canister_id).await {
+                Some(subnet_id) => subnet_id,
+                None => return,
+            },
+            None => return,
+        };
+        // the stall may be extended while waiting, so check again after each wait
+        while let Some(until) = self.stalled_until(subnet_id) {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+
+    /// Returns when the stall of `subnet_id` ends, if it is stalled, forgetting stalls that ended.
+    fn stalled_until(&self, subnet_id: Principal) -> Option<Instant> {
+        let mut stalls = self.until.lock().expect("stalls lock poisoned");
+        let now = Instant::now();
+        stalls.retain(|_, until| *until > now);
+        stalls.get(&subnet_id).copied()
+    }
+}
+
+enum Target {
+    Canister(Principal),
+    Subnet(Principal),
+}
+
+/// Returns the canister or subnet a gateway request to `path` is for.
+fn target(path: &str) -> Option<Target> {
+    match path.split('/').collect::<Vec<_>>()[..] {
+        ["", "api", _, "canister", id, ..] => Principal::from_text(id).ok().map(Target::Canister),
+        ["", "api", _, "subnet", id, ..] => Principal::from_text(id).ok().map(Target::Subnet),
+        _ => None,
+    }
+}
+
+/// Resolves `subnet` on the network of `pic`: a subnet ID, or a kind of subnet such as
+/// `application` or `fiduciary` for the first subnet of that kind.
+pub async fn resolve_subnet(pic: &PocketIc, subnet: &str) -> Result<Principal, String> {
+    let topology = pic.topology().await;
+    if let Ok(subnet_id) = Principal::from_text(subnet) {
+        return if topology.subnet_configs.contains_key(&subnet_id) {
+            Ok(subnet_id)
+        } else {
+            Err(format!("the network has no subnet {subnet_id}"))
+        };
+    }
+    let kind = subnet.replace('-', "").to_lowercase();
+    topology
+        .subnet_configs
+        .iter()
+        .find(|(_, config)| format!("{:?}", config.subnet_kind).to_lowercase() == kind)
+        .map(|(&subnet_id, _)| subnet_id)
+        .ok_or_else(|| format!("the network has no {subnet} subnet"))
+}
+
+/// Faults injected into gateway requests: ingress messages, i.e. update calls, or all responses.
+pub struct Faults {
+    /// Share of requests failed, from 0 to 1.
+    reject_rate: f64,
+    /// Share of requests delayed, from 0 to 1.
+    delay_rate: f64,
+    delay: Duration,
+    rng: SystemRandom,
+}
+
+impl Faults {
+    /// Returns faults delaying a `delay_rate` share of requests by `delay` and failing a
+    /// `reject_rate` share, or `None` if there are neither delays nor failures.
+    pub fn new(delay: Option<Duration>, delay_rate: f64, reject_rate: Option<f64>) -> Option<Self> {
+        (delay.is_some() || reject_rate.is_some()).then(|| Self {
+            reject_rate: reject_rate.unwrap_or(0.0),
+            delay_rate: if delay.is_some() { delay_rate } else { 0.0 },
+            delay: delay.unwrap_or_default(),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Waits out the delay of a request, if it is chosen to be delayed, and returns whether it is
+    /// to be failed.
+    pub async fn inject(&self) -> bool {
+        if self.roll() < self.delay_rate {
+            tokio::time::sleep(self.delay).await;
+        }
+        self.roll() < self.reject_rate
+    }
+
+    /// Returns a uniformly random number in `[0, 1)`.
+    fn roll(&self) -> f64 {
+        let mut bytes = [0; 4];
+        self.rng
+            .fill(&mut bytes)
+            .expect("system randomness should be available");
+        f64::from(u32::from_le_bytes(bytes)) / (f64::from(u32::MAX) + 1.0)
+    }
+}
+
+/// Parses a percentage from 0 to 100 into a share from 0 to 1.
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err(format!("expected a percentage from 0 to 100, got `{s}`")),
+    }
+}
+
+/// Limit on the canisters created through the gateway (`--max-canister-creations`).
+pub struct CreationThrottle {
+    pub limit: u32,
+    /// Start of the current window, and the canisters created in it.
+    window: Mutex<(Instant, u32)>,
+}
+
+impl CreationThrottle {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Returns whether the call in `envelope` goes ahead: it doesn't create a canister, or the
+    /// limit of the current window isn't reached yet.
+    pub fn admit(&self, envelope: &[u8]) -> bool {
+        if !ingress::call_content(envelope).is_some_and(|content| creates_canister(&content)) {
+            return true;
+        }
+        let mut window = self.window.lock().expect("creation window lock poisoned");
+        let (start, created) = &mut *window;
+        if start.elapsed() >= CREATION_WINDOW {
+            *start = Instant::now();
+            *created = 0;
+        }
+        if *created >= self.limit {
+            return false;
+        }
+        *created += 1;
+        true
+    }
+}
+
+/// Whether a call with `content` creates a canister.
+fn creates_canister(content: &ingress::CallContent) -> bool {
+    let method = content.method_name.as_str();
+    if content.canister_id == Principal::management_canister() {
+        matches!(
+            method,
+            "create_canister" | "provisional_create_canister_with_cycles"
+        )
+    } else if content.canister_id == index::CYCLES_LEDGER_CANISTER_ID {
+        method == "create_canister"
+    } else if content.canister_id == cycles::CMC_CANISTER_ID {
+        method == "notify_create_canister"
+    } else {
+        false
+    }
+}
+
+/// Limit on the requests the gateway serves (`--rate-limit`).
+pub struct RateLimit {
+    pub limit: u32,
+    /// Whether each client address has its own limit, rather than all sharing one.
+    pub per_ip: bool,
+    /// Start of the current window of each client address, or of all, and the requests served in
+    /// it.
+    windows: Mutex<BTreeMap<Option<IpAddr>, (Instant, u32)>>,
+}
+
+impl RateLimit {
+    pub fn new(limit: u32, per_ip: bool) -> Self {
+        Self {
+            limit,
+            per_ip,
+            windows: <_>::default(),
+        }
+    }
+
+    /// Returns whether a request from `peer` is served, i.e. the limit of its current window isn't
+    /// reached yet.
+    pub fn admit(&self, peer: Option<IpAddr>) -> bool {
+        let key = if self.per_ip { peer } else { None };
+        let mut windows = self.windows.lock().expect("rate limit lock poisoned");
+        let now = Instant::now();
+        // forget clients whose window is over, so that the map doesn't grow with every address seen
+        windows.retain(|_, (start, _)| now.duration_since(*start) < RATE_LIMIT_WINDOW);
+        let (_, served) = windows.entry(key).or_insert((now, 0));
+        if *served >= self.limit {
+            return false;
+        }
+        *served += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_percent_accepts_0_to_100() {
+        assert_eq!(parse_percent("0"), Ok(0.0));
+        assert_eq!(parse_percent("12.5%"), Ok(0.125));
+        assert_eq!(parse_percent("100"), Ok(1.0));
+        for s in ["101%", "-1", "NaN", "inf", "", "%"] {
+            assert!(parse_percent(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! The ckBTC stack (`--ckbtc`): minter, ledger, index and Bitcoin checker, wired to the Bitcoin
+//! canister that PocketIC runs against the local bitcoind.
+//!
+//! The Wasm modules are read from a directory of IC release artifacts. The checker accepts every
+//! address and transaction, standing in for mainnet's KYT checks, and the minter signs with
+//! PocketIC's `dfx_test_key`.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Context;
+use candid::{CandidType, Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+
+use crate::{
+    canister,
+    icrc::{self, IcrcToken},
+    index,
+    ledger_archive::ArchiveConfig,
+};
+
+/// Cycles given to each ckBTC canister.
+const CKBTC_CYCLES: u128 = 100_000_000_000_000;
+/// Transfer fee of the ckBTC ledger, in satoshis, as on mainnet.
+const LEDGER_FEE: u128 = 10;
+
+/// Wasm modules of the ckBTC canisters.
+pub struct CkbtcWasms {
+    minter: Vec<u8>,
+    ledger: Vec<u8>,
+    index: Vec<u8>,
+    checker: Vec<u8>,
+}
+
+/// IDs of the deployed ckBTC canisters.
+pub struct CkbtcCanisters {
+    pub minter: Principal,
+    pub ledger: Principal,
+    pub index: Principal,
+    pub checker: Principal,
+}
+
+impl CkbtcCanisters {
+    /// Returns the canisters with the names they are registered under.
+    pub fn named(&self) -> BTreeMap<String, Principal> {
+        BTreeMap::from([
+            ("ckbtc-minter".to_string(), self.minter),
+            ("ckbtc-ledger".to_string(), self.ledger),
+            ("ckbtc-index".to_string(), self.index),
+            ("ckbtc-checker".to_string(), self.checker),
+        ])
+    }
+}
+
+/// Reads the ckBTC Wasm modules from `dir`, named as in IC releases.
+pub fn load(dir: &Path) -> anyhow::Result<CkbtcWasms> {
+    let read = |name: &str| {
+        let path = dir.join(name);
+        fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    };
+    Ok(CkbtcWasms {
+        minter: read("ic-ckbtc-minter.wasm.gz")?,
+        ledger: read("ic-icrc1-ledger.wasm.gz")?,
+        index: read("ic-icrc1-index-ng.wasm.gz")?,
+        checker: read("ic-btc-checker.wasm.gz")?,
+    })
+}
+
+#[derive(CandidType)]
+enum MinterArg {
+    Init(MinterInitArgs),
+}
+
+#[derive(CandidType)]
+struct MinterInitArgs {
+    btc_network: BtcNetwork,
+    ledger_id: Principal,
+    ecdsa_key_name: String,
+    retrieve_btc_min_amount: u64,
+    max_time_in_queue_nanos: u64,
+    min_confirmations: Option<u32>,
+    mode: Mode,
+    check_fee: Option<u64>,
+    btc_checker_principal: Option<Principal>,
+}
+
+#[derive(CandidType)]
+enum BtcNetwork {
+    Regtest,
+}
+
+#[derive(CandidType)]
+enum Mode {
+    GeneralAvailability,
+}
+
+#[derive(CandidType)]
+enum CheckArg {
+    InitArg(CheckInitArg),
+}
+
+#[derive(CandidType)]
+struct CheckInitArg {
+    btc_network: CheckerBtcNetwork,
+    check_mode: CheckMode,
+    num_subnet_nodes: u16,
+}
+
+#[derive(CandidType, Deserialize)]
+enum CheckerBtcNetwork {
+    #[serde(rename = "regtest")]
+    Regtest { json_rpc_url: String },
+}
+
+#[derive(CandidType)]
+enum CheckMode {
+    AcceptAll,
+}
+
+/// Deploys the ckBTC canisters from `wasms`, returning their IDs. The ledger archives its blocks
+/// as `archive` says.
+pub async fn deploy(
+    pic: &PocketIc,
+    wasms: CkbtcWasms,
+    archive: ArchiveConfig,
+) -> anyhow::Result<CkbtcCanisters> {
+    let create = async || {
+        let canister_id = pic.create_canister().await;
+        pic.add_cycles(canister_id, CKBTC_CYCLES).await;
+        canister_id
+    };
+    // the minter and ledger refer to each other, so all canisters are created first
+    let canisters = CkbtcCanisters {
+        minter: create().await,
+        ledger: create().await,
+        index: create().await,
+        checker: create().await,
+    };
+    let install = async |canister_id, wasm, arg, name: &str| {
+        canister::install_code(pic, canister_id, Principal::anonymous(), wasm, arg)
+            .await
+            .with_context(|| format!("failed to install the ckBTC {name}"))
+    };
+    install(
+        canisters.checker,
+        wasms.checker,
+        Encode!(&CheckArg::InitArg(CheckInitArg {
+            // only used to look up transactions, which AcceptAll never does
+            btc_network: CheckerBtcNetwork::Regtest {
+                json_rpc_url: String::new(),
+            },
+            check_mode: CheckMode::AcceptAll,
+            num_subnet_nodes: 1,
+        }))?,
+        "checker",
+    )
+    .await?;
+    let token = IcrcToken {
+        name: "ckBTC".to_string(),
+        symbol: "ckBTC".to_string(),
+        decimals: 8,
+        premint: BTreeMap::new(),
+    };
+    install(
+        canisters.ledger,
+        wasms.ledger,
+        icrc::init_arg(&token, canisters.minter, LEDGER_FEE, archive)?,
+        "ledger",
+    )
+    .await?;
+    install(
+        canisters.index,
+        wasms.index,
+        index::icrc_index_arg(canisters.ledger)?,
+        "index",
+    )
+    .await?;
+    install(
+        canisters.minter,
+        wasms.minter,
+        Encode!(&MinterArg::Init(MinterInitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ledger_id: canisters.ledger,
+            ecdsa_key_name: "dfx_test_key".to_string(),
+            retrieve_btc_min_amount: 10_000,
+            max_time_in_queue_nanos: 10_000_000_000,
+            // blocks are mined on demand locally, so one confirmation is enough
+            min_confirmations: Some(1),
+            mode: Mode::GeneralAvailability,
+            check_fee: Some(100),
+            btc_checker_principal: Some(canisters.checker),
+        }))?,
+        "minter",
+    )
+    .await?;
+    Ok(canisters)
+}
@@ -0,0 +1,186 @@
+//! The local ckETH stack (`--cketh-mock`): the EVM RPC canister and the ckETH ledger, installed
+//! at their mainnet IDs on the fiduciary subnet.
+//!
+//! The EVM RPC canister's providers are all overridden with one JSON-RPC endpoint: a local
+//! anvil/hardhat node given by `--eth-rpc-url`, or otherwise the control API's built-in mock. The
+//! mock answers the read-only calls made while a dapp starts up, against a chain with one empty
+//! block, and rejects everything else.
+
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+use serde_json::{Value, json};
+
+use crate::{
+    canister,
+    icrc::{self, IcrcToken},
+    ledger_archive::ArchiveConfig,
+};
+
+/// Mainnet ID of the EVM RPC canister.
+pub const EVM_RPC_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 48, 0, 204, 1, 1]);
+/// Mainnet ID of the ckETH ledger.
+pub const CKETH_LEDGER_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 48, 0, 157, 1, 1]);
+/// Cycles given to each canister.
+const CKETH_CYCLES: u128 = 100_000_000_000_000;
+/// Transfer fee of the ckETH ledger, in wei, as on mainnet.
+const LEDGER_FEE: u128 = 2_000_000_000_000;
+/// Chain ID reported by the mock, the same as anvil's and hardhat's.
+const MOCK_CHAIN_ID: &str = "0x7a69";
+
+/// Wasm modules of the ckETH canisters.
+pub struct CkethWasms {
+    evm_rpc: Vec<u8>,
+    ledger: Vec<u8>,
+}
+
+/// Reads the ckETH Wasm modules from `dir`, named as in their releases.
+pub fn load(dir: &Path) -> anyhow::Result<CkethWasms> {
+    let read = |name: &str| {
+        let path = dir.join(name);
+        fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    };
+    Ok(CkethWasms {
+        evm_rpc: read("evm_rpc.wasm.gz")?,
+        ledger: read("ic-icrc1-ledger.wasm.gz")?,
+    })
+}
+
+#[derive(CandidType)]
+#[allow(non_snake_case)]
+struct EvmRpcInstallArgs {
+    demo: Option<bool>,
+    overrideProvider: Option<OverrideProvider>,
+}
+
+#[derive(CandidType)]
+#[allow(non_snake_case)]
+struct OverrideProvider {
+    overrideUrl: Option<RegexSubstitution>,
+}
+
+#[derive(CandidType)]
+struct RegexSubstitution {
+    pattern: String,
+    replacement: String,
+}
+
+/// Installs the EVM RPC canister, sending all JSON-RPC requests to `rpc_url`, and the ckETH
+/// ledger, unless they are already installed. The ledger's minting account is the anonymous
+/// principal, as for `--icrc-token`, and it archives its blocks as `archive` says.
+pub async fn deploy(
+    pic: &PocketIc,
+    wasms: CkethWasms,
+    rpc_url: &str,
+    archive: ArchiveConfig,
+) -> anyhow::Result<()> {
+    let evm_rpc_arg = Encode!(&EvmRpcInstallArgs {
+        // demo mode makes calls free, as cycles are meaningless locally
+        demo: Some(true),
+        overrideProvider: Some(OverrideProvider {
+            overrideUrl: Some(RegexSubstitution {
+                pattern: ".*".to_string(),
+                replacement: rpc_url.to_string(),
+            }),
+        }),
+    })?;
+    install(pic, EVM_RPC_CANISTER_ID, wasms.evm_rpc, evm_rpc_arg)
+        .await
+        .context("failed to install the EVM RPC canister")?;
+    let token = IcrcToken {
+        name: "ckETH".to_string(),
+        symbol: "ckETH".to_string(),
+        decimals: 18,
+        premint: <_>::default(),
+    };
+    let ledger_arg = icrc::init_arg(&token, Principal::anonymous(), LEDGER_FEE, archive)?;
+    install(pic, CKETH_LEDGER_CANISTER_ID, wasms.ledger, ledger_arg)
+        .await
+        .context("failed to install the ckETH ledger")
+}
+
+async fn install(
+    pic: &PocketIc,
+    canister_id: Principal,
+    wasm: Vec<u8>,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    if pic.canister_exists(canister_id).await {
+        return Ok(());
+    }
+    pic.create_canister_with_id(None, None, canister_id)
+        .await
+        .map_err(|e| anyhow!("failed to create canister {canister_id}: {e}"))?;
+    pic.add_cycles(canister_id, CKETH_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, arg).await
+}
+
+/// Answers an Ethereum JSON-RPC request on behalf of the mock node.
+pub fn mock_rpc(request: &Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let result = match method {
+        "eth_chainId" | "net_version" => json!(MOCK_CHAIN_ID),
+        "eth_blockNumber" => json!("0x0"),
+        "eth_getBlockByNumber" => mock_block(),
+        "eth_getLogs" => json!([]),
+        "eth_getTransactionCount" | "eth_gasPrice" | "eth_maxPriorityFeePerGas" => json!("0x0"),
+        "eth_feeHistory" => json!({
+            "oldestBlock": "0x0",
+            "baseFeePerGas": ["0x1", "0x1"],
+            "gasUsedRatio": [0.0],
+            "reward": [["0x0"]],
+        }),
+        _ => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32601,
+                    "message": format!("the launcher's mock Ethereum node does not support {method}; pass --eth-rpc-url"),
+                },
+            });
+        }
+    };
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// The mock chain's only block.
+fn mock_block() -> Value {
+    let zero_hash = format!("0x{}", "0".repeat(64));
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    json!({
+        "number": "0x0",
+        "hash": format!("0x{}", "1".repeat(64)),
+        "parentHash": zero_hash,
+        "nonce": "0x0000000000000000",
+        "sha3Uncles": zero_hash,
+        "logsBloom": format!("0x{}", "0".repeat(512)),
+        "transactionsRoot": zero_hash,
+        "stateRoot": zero_hash,
+        "receiptsRoot": zero_hash,
+        "miner": format!("0x{}", "0".repeat(40)),
+        "difficulty": "0x0",
+        "totalDifficulty": "0x0",
+        "extraData": "0x",
+        "size": "0x0",
+        "gasLimit": "0x1c9c380",
+        "gasUsed": "0x0",
+        "timestamp": format!("{timestamp:#x}"),
+        "baseFeePerGas": "0x1",
+        "mixHash": zero_hash,
+        "transactions": [],
+        "uncles": [],
+    })
+}
@@ -0,0 +1,155 @@
+//! Response compression on the gateway (`--compress`), so that frontends are served with the
+//! transfer sizes they have behind boundary nodes, and bugs such as assets encoded twice surface
+//! locally.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! encodes responses with Brotli or gzip, whichever the client accepts, preferring Brotli.
+//! Responses that are already encoded, e.g. assets the asset canister stores gzipped, are left
+//! alone, as are those smaller than the minimum size and those of types that don't compress.
+
+use std::io::Write;
+
+use flate2::{Compression as GzipLevel, write::GzEncoder};
+use hyper::{
+    HeaderMap, StatusCode,
+    body::Bytes,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, HeaderValue, VARY},
+};
+
+/// The default of `--compress-min-size`, below which compression doesn't pay off.
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// Brotli quality, as boundary nodes compress on the fly rather than ahead of time.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+/// The compression of the gateway's responses.
+pub struct Compression {
+    min_size: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Compression {
+    pub fn new(min_size: u64) -> Self {
+        Self { min_size }
+    }
+
+    /// Whether a response with `status` and `headers` to a request with `request_headers` may be
+    /// compressed, so that the gateway only reads the bodies it may compress.
+    pub fn applies(
+        &self,
+        request_headers: &HeaderMap,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> bool {
+        status != StatusCode::PARTIAL_CONTENT
+            && !headers.contains_key(CONTENT_ENCODING)
+            && headers
+                .get(CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_some_and(is_compressible)
+            && negotiate(request_headers).is_some()
+    }
+
+    /// Compresses the response `body` with `status` and `headers` if the request's `request_headers`
+    /// accept an encoding it qualifies for, updating `headers` to match.
+    pub fn apply(
+        &self,
+        request_headers: &HeaderMap,
+        status: StatusCode,
+        headers: &mut HeaderMap,
+        body: Bytes,
+    ) -> Bytes {
+        if (body.len() as u64) < self.min_size || !self.applies(request_headers, status, headers) {
+            return body;
+        }
+        let Some(encoding) = negotiate(request_headers) else {
+            return body;
+        };
+        let (name, encoded) = match encoding {
+            Encoding::Brotli => {
+                let mut encoder =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                encoder
+                    .write_all(&body)
+                    .expect("writing to a Vec is infallible");
+                ("br", encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+                encoder
+                    .write_all(&body)
+                    .expect("writing to a Vec is infallible");
+                (
+                    "gzip",
+                    encoder.finish().expect("writing to a Vec is infallible"),
+                )
+            }
+        };
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(name));
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        Bytes::from(encoded)
+    }
+}
+
+/// The encoding to respond with, from the request's `Accept-Encoding`.
+fn negotiate(request_headers: &HeaderMap) -> Option<Encoding> {
+    let mut brotli = false;
+    let mut gzip = false;
+    for value in request_headers.get_all(ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for coding in value.split(',') {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            // `q=0` means the coding is not acceptable
+            if params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            }) {
+                continue;
+            }
+            match name.to_ascii_lowercase().as_str() {
+                "br" => brotli = true,
+                "gzip" | "*" => gzip = true,
+                _ => {}
+            }
+        }
+    }
+    if brotli {
+        Some(Encoding::Brotli)
+    } else if gzip {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Whether responses of `content_type` are worth compressing.
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/javascript"
+                | "application/json"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
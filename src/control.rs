@@ -0,0 +1,1073 @@
+//! HTTP control API for operating on a running network. The launcher serves it on loopback,
+//! records the port in status.json, and the launcher's own subcommands are its clients.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::{CONTENT_TYPE, HOST, ORIGIN},
+    http::{request::Parts, uri::Authority},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use ic_principal::Principal;
+use pocket_ic::{Time, nonblocking::PocketIc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, Notify},
+};
+
+use crate::{
+    SubnetKind,
+    access_log::AccessLog,
+    access_token::AccessToken,
+    bench::{self, BenchCall, BenchResult},
+    cache::GatewayCache,
+    canister,
+    chaos::{self, CreationThrottle, Faults, RateLimit, Stalls},
+    cketh,
+    compression::Compression,
+    dfx, enable_auto_progress, ephemeral,
+    errors::{ErrorReport, ErrorTracker},
+    faucet::Faucet,
+    governance::{self, ProposalAction},
+    har::HttpRecorder,
+    headers::HeaderRules,
+    ingress::{self, IngressMessage},
+    ledger, ledger_archive,
+    logs::{self, LogRecord},
+    passthrough::Passthrough,
+    query_stats::{self, CanisterQueryStats},
+    read_status,
+    registry::{self, RegistryNeuron},
+    report::{self, CanisterReport},
+    sns::{self, SnsCanisters},
+    xrc,
+};
+
+/// How long the launcher's servers wait after failing to accept a connection, e.g. when out of file
+/// descriptors, before accepting again, rather than spinning on the error.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Shared state of the control server.
+pub struct Control {
+    /// Non-owning handle to the instance; dropping it does not delete the instance.
+    pub pic: PocketIc,
+    pub state_dir: Option<PathBuf>,
+    /// Where the canister registry is kept, if the network has a status directory.
+    pub status_dir: Option<PathBuf>,
+    /// Delay before each round while rounds execute continuously, adjustable at runtime.
+    pub artificial_delay_ms: Mutex<Option<u64>>,
+    /// Whether rounds execute continuously, rather than only on `tick` (`--no-auto-progress`).
+    pub auto_progress: bool,
+    /// Held while an operation has paused rounds, so that operations don't resume each other's pauses.
+    pub progress: Mutex<()>,
+    /// Notified to make the launcher shut the network down.
+    pub shutdown: Arc<Notify>,
+    /// Last measured size of the state directory, in bytes.
+    pub state_size: AtomicU64,
+    pub state_quota: Option<u64>,
+    /// Set while the network is paused for exceeding `state_quota`.
+    pub quota_paused: AtomicBool,
+    pub faucet: Option<Faucet>,
+    /// Rates of the exchange rate mock, if `--xrc-mock` is enabled.
+    pub xrc_rates: Option<Mutex<xrc::Rates>>,
+    /// Usage of the registry's canisters when the network became ready, for `report`.
+    pub usage_baseline: Mutex<BTreeMap<Principal, report::Usage>>,
+    /// Counts of the calls the gateway rejected, if `--error-report` is enabled.
+    pub errors: Option<ErrorTracker>,
+    /// Where the gateway forwards WebSocket upgrades, if `--websocket-upstream` is set.
+    pub websocket_upstream: Option<SocketAddr>,
+    /// Token the gateway requires from its clients, if `--access-token` is set.
+    pub access_token: Option<AccessToken>,
+    /// Canisters the gateway sends to mainnet, if `--mainnet-passthrough` is set.
+    pub passthrough: Option<Passthrough>,
+    /// The gateway's cache, if `--gateway-cache` is set.
+    pub cache: Option<GatewayCache>,
+    /// Header rewriting on the gateway, if `--gateway-headers` is set.
+    pub header_rules: Option<HeaderRules>,
+    /// Whether the gateway explains its failures to serve canisters (`--gateway-error-pages`).
+    pub error_pages: bool,
+    /// Whether the gateway answers `/_launcher/health` (`--gateway-health`).
+    pub health_endpoint: bool,
+    /// Compression of the gateway's responses, if `--compress` is enabled.
+    pub compression: Option<Compression>,
+    /// Log of the gateway's requests, if `--access-log` is enabled.
+    pub access_log: Option<AccessLog>,
+    /// The gateway's HTTP traffic, if `--record-http` is enabled.
+    pub http_recording: Option<HttpRecorder>,
+    /// Subnets stalled by `stall-subnet`, if `--chaos` is enabled.
+    pub stalls: Option<Stalls>,
+    /// Faults injected into ingress messages at the gateway, if any are configured.
+    pub ingress_faults: Option<Faults>,
+    /// Faults injected into all gateway responses, if any are configured.
+    pub response_faults: Option<Faults>,
+    /// Limit on the requests the gateway serves, if `--rate-limit` is set.
+    pub rate_limit: Option<RateLimit>,
+    /// Limit on canister creations through the gateway, if `--max-canister-creations` is set.
+    pub creation_throttle: Option<CreationThrottle>,
+    /// Whether the gateway enforces `--strict-limits`.
+    pub strict_limits: bool,
+    /// Whether the gateway enforces `--mainnet-costs`.
+    pub mainnet_costs: bool,
+    /// Canisters the gateway serves at `<alias>.localhost` (`--alias`), by alias. Filled in once
+    /// the canisters are set up.
+    pub aliases: Mutex<BTreeMap<String, Principal>>,
+    /// Directories the gateway serves canisters' frontends from (`--serve-local`), by canister.
+    /// Filled in once the canisters are set up.
+    pub local_assets: Mutex<BTreeMap<Principal, PathBuf>>,
+    /// Instances created by `create-ephemeral`, by instance ID.
+    pub ephemeral: Mutex<BTreeMap<usize, PocketIc>>,
+    /// Base URL of PocketIC's gateway, which is on loopback behind the launcher's gateway whenever
+    /// the launcher serves the gateway port. Requests sent to it directly skip the launcher's
+    /// stages.
+    pub gateway_url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CanisterExportRequest {
+    pub canister_id: Principal,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CanisterExportResponse {
+    pub metadata: canister::Metadata,
+    /// The Wasm module, in base64.
+    pub wasm_module: String,
+    /// The stable memory, in base64.
+    pub stable_memory: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportDfxRequest {
+    pub canisters: Vec<dfx::ProjectCanister>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportDfxResponse {
+    pub canisters: Vec<dfx::ImportedCanister>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FaucetRequest {
+    pub principal: Principal,
+    /// ICP to give, in e8s.
+    #[serde(default)]
+    pub icp_e8s: u64,
+    /// Cycles to deposit into the principal's cycles ledger account.
+    #[serde(default)]
+    pub cycles: u128,
+    /// Amount of `token` to give on top of the above, as a decimal string (e.g. `"1.5"` ICP), for
+    /// clients whose numbers can't hold e8s or cycles exactly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    /// What `amount` is in, ICP by default.
+    #[serde(default)]
+    pub token: FaucetToken,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FaucetToken {
+    #[default]
+    Icp,
+    Cycles,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FaucetResponse {
+    pub icp_e8s: u64,
+    pub cycles: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProposalSubmitRequest {
+    pub proposer: Principal,
+    pub neuron_id: u64,
+    pub title: String,
+    pub summary: String,
+    pub url: String,
+    pub action: ProposalAction,
+    /// Neurons that vote to adopt the proposal once it is submitted.
+    pub adopt_with: Vec<RegistryNeuron>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProposalSubmitResponse {
+    pub proposal_id: u64,
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnsLaunchRequest {
+    pub config: sns::LaunchConfig,
+    /// Neuron that submits the proposal to create the SNS.
+    pub proposer: RegistryNeuron,
+    /// Neurons that vote to adopt the proposal.
+    pub voters: Vec<RegistryNeuron>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnsLaunchResponse {
+    pub canisters: SnsCanisters,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct XrcRatesRequest {
+    /// Rates to set, as in `--xrc-rates` files. Other pairs keep their rates.
+    pub rates: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct XrcRatesResponse {
+    /// All rates of the mock.
+    pub rates: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LedgerArchivesRequest {
+    /// Canisters to look for ledgers among, by registry name.
+    pub canisters: BTreeMap<String, Principal>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LedgerArchivesResponse {
+    /// Archives of the ledgers, named `<ledger>-archive-<n>`.
+    pub archives: BTreeMap<String, Principal>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BenchRequest {
+    pub calls: Vec<BenchCall>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BenchResponse {
+    pub results: Vec<BenchResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArtificialDelayRequest {
+    /// The new delay, in milliseconds, or none to execute rounds as fast as possible.
+    pub artificial_delay_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArtificialDelayResponse {}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdvanceTimeRequest {
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdvanceTimeResponse {
+    /// The network's time after advancing, in nanoseconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayRequest {
+    pub messages: Vec<IngressMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayResponse {
+    /// Descriptions of the messages the gateway rejected.
+    pub rejections: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TickRequest {
+    pub rounds: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TickResponse {
+    /// The network's time after the rounds, in nanoseconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusRequest {}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusResponse {
+    /// Size of the state directory in bytes, if the network has one.
+    pub state_dir_size: Option<u64>,
+    pub state_quota: Option<u64>,
+    /// Whether the network is paused for exceeding its state quota.
+    pub quota_paused: bool,
+    /// Query statistics of the registry's canisters, by name.
+    pub query_stats: BTreeMap<String, CanisterQueryStats>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogsRequest {
+    pub canister_id: Principal,
+    /// Only returns records with a higher index, to fetch just the new ones.
+    pub after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogsResponse {
+    pub records: Vec<LogRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StallSubnetRequest {
+    /// Subnet ID, or a kind of subnet such as `application` for the first subnet of that kind.
+    pub subnet: String,
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StallSubnetResponse {
+    pub subnet_id: Principal,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpgradeSubnetRequest {
+    /// Subnet ID, or a kind of subnet such as `application` for the first subnet of that kind.
+    pub subnet: String,
+    /// How long the subnet is down for.
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpgradeSubnetResponse {
+    pub subnet_id: Principal,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateEphemeralRequest {
+    /// Subnets of the instance besides the NNS subnet; an application subnet if empty.
+    #[serde(default)]
+    pub subnets: Vec<SubnetKind>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateEphemeralResponse {
+    pub instance_id: usize,
+    pub gateway_url: String,
+    pub root_key: String,
+    pub default_effective_canister_id: Principal,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteEphemeralRequest {
+    pub instance_id: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteEphemeralResponse {}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorsRequest {}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorsResponse {
+    pub report: ErrorReport,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PurgeGatewayCacheRequest {}
+
+#[derive(Serialize, Deserialize)]
+pub struct PurgeGatewayCacheResponse {
+    /// How many responses the cache held.
+    pub purged: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportRequest {}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportResponse {
+    /// Usage of the registry's canisters over the session, by name.
+    pub canisters: BTreeMap<String, CanisterReport>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShutdownRequest {}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShutdownResponse {}
+
+#[derive(Serialize, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+struct ControlError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ControlError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    fn forbidden(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ControlError {
+    fn from(e: anyhow::Error) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("{e:#}"),
+        }
+    }
+}
+
+/// Serves the control API on `listener` until the task is aborted.
+pub async fn serve(listener: TcpListener, control: Arc<Control>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            tokio::time::sleep(ACCEPT_BACKOFF).await;
+            continue;
+        };
+        let control = Arc::clone(&control);
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(Arc::clone(&control), req));
+            _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn handle(
+    control: Arc<Control>,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let result = match (admit(&parts), body.collect().await) {
+        (Err(e), _) => Err(e),
+        (Ok(()), Ok(body)) => {
+            route(&control, &parts.method, parts.uri.path(), &body.to_bytes()).await
+        }
+        (Ok(()), Err(e)) => Err(ControlError::bad_request(format!(
+            "failed to read request body: {e}"
+        ))),
+    };
+    Ok(result.unwrap_or_else(|e| json_response(e.status, &ErrorResponse { error: e.message })))
+}
+
+/// Rejects requests that a web page could have made. The control API has no authentication, so a
+/// page must not reach it by rebinding its own domain to loopback (a foreign `Host`) or by
+/// submitting a form to it (an `Origin`, or a body that isn't JSON).
+fn admit(parts: &Parts) -> Result<(), ControlError> {
+    let host = parts
+        .headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok());
+    let loopback = host.is_some_and(|host| {
+        let host = host.host();
+        host.eq_ignore_ascii_case("localhost")
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    });
+    if !loopback {
+        return Err(ControlError::forbidden(
+            "the Host header must name a loopback address",
+        ));
+    }
+    if parts.headers.contains_key(ORIGIN) {
+        return Err(ControlError::forbidden(
+            "cross-origin requests are not allowed",
+        ));
+    }
+    let json = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if parts.method == Method::POST && !json {
+        return Err(ControlError {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            message: "requests must have content type application/json".to_string(),
+        });
+    }
+    Ok(())
+}
+
+async fn route(
+    control: &Control,
+    method: &Method,
+    path: &str,
+    body: &[u8],
+) -> Result<Response<Full<Bytes>>, ControlError> {
+    match (method, path) {
+        (&Method::POST, "/canister/export") => {
+            respond(control.canister_export(parse(body)?).await?)
+        }
+        (&Method::POST, "/import-dfx") => respond(control.import_dfx(parse(body)?).await?),
+        (&Method::POST, "/eth") => respond(cketh::mock_rpc(&parse(body)?)),
+        (&Method::POST, "/faucet") => respond(control.faucet(parse(body)?).await?),
+        (&Method::POST, "/proposal/submit") => {
+            respond(control.proposal_submit(parse(body)?).await?)
+        }
+        (&Method::POST, "/sns/launch") => respond(control.sns_launch(parse(body)?).await?),
+        (&Method::POST, "/xrc/rates") => respond(control.xrc_rates(parse(body)?).await?),
+        (&Method::POST, "/ledger/archives") => {
+            respond(control.ledger_archives(parse(body)?).await?)
+        }
+        (&Method::POST, "/bench") => respond(control.bench(parse(body)?).await?),
+        (&Method::POST, "/artificial-delay") => {
+            respond(control.artificial_delay(parse(body)?).await?)
+        }
+        (&Method::POST, "/advance-time") => respond(control.advance_time(parse(body)?).await?),
+        (&Method::POST, "/replay") => respond(control.replay(parse(body)?).await?),
+        (&Method::POST, "/tick") => respond(control.tick(parse(body)?).await?),
+        (&Method::POST, "/status") => respond(control.status(parse(body)?).await?),
+        (&Method::POST, "/stall-subnet") => respond(control.stall_subnet(parse(body)?).await?),
+        (&Method::POST, "/upgrade-subnet") => respond(control.upgrade_subnet(parse(body)?).await?),
+        (&Method::POST, "/create-ephemeral") => {
+            respond(control.create_ephemeral(parse(body)?).await?)
+        }
+        (&Method::POST, "/delete-ephemeral") => {
+            respond(control.delete_ephemeral(parse(body)?).await?)
+        }
+        (&Method::POST, "/errors") => respond(control.errors(parse(body)?)?),
+        (&Method::POST, "/gateway-cache/purge") => {
+            respond(control.purge_gateway_cache(parse(body)?)?)
+        }
+        (&Method::POST, "/logs") => respond(control.logs(parse(body)?).await?),
+        (&Method::POST, "/report") => respond(control.report(parse(body)?).await?),
+        (&Method::POST, "/shutdown") => respond(control.shutdown(parse(body)?)),
+        _ => Err(ControlError {
+            status: StatusCode::NOT_FOUND,
+            message: format!("unknown control operation {method} {path}"),
+        }),
+    }
+}
+
+impl Control {
+    /// Resumes rounds after an operation paused them, unless they only run on `tick` or the
+    /// network is paused for exceeding its quota. Callers must hold `progress`.
+    pub async fn resume_progress(&self) -> anyhow::Result<()> {
+        if self.auto_progress && !self.quota_paused.load(Ordering::Relaxed) {
+            let artificial_delay_ms = *self.artificial_delay_ms.lock().await;
+            enable_auto_progress(&self.pic, artificial_delay_ms).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the network's time forward to `time`, if it is later than the current time, and
+    /// executes a round so that timers due by then run.
+    pub async fn advance_time_to(&self, time: Time) -> anyhow::Result<()> {
+        let _paused = self.progress.lock().await;
+        // pause rounds so that the time jumps between two of them
+        self.pic.stop_progress().await;
+        if time.as_nanos_since_unix_epoch() > self.pic.get_time().await.as_nanos_since_unix_epoch()
+        {
+            self.pic.set_time(time).await;
+        }
+        self.pic.tick().await;
+        self.resume_progress().await
+    }
+
+    async fn canister_export(
+        &self,
+        CanisterExportRequest { canister_id }: CanisterExportRequest,
+    ) -> Result<CanisterExportResponse, ControlError> {
+        let (metadata, contents) = canister::export(&self.pic, canister_id).await?;
+        Ok(CanisterExportResponse {
+            metadata,
+            wasm_module: STANDARD.encode(contents.wasm_module),
+            stable_memory: STANDARD.encode(contents.stable_memory),
+        })
+    }
+
+    async fn import_dfx(
+        &self,
+        ImportDfxRequest { canisters }: ImportDfxRequest,
+    ) -> Result<ImportDfxResponse, ControlError> {
+        let canisters = dfx::import(&self.pic, canisters).await?;
+        Ok(ImportDfxResponse { canisters })
+    }
+
+    async fn faucet(
+        &self,
+        FaucetRequest {
+            principal,
+            mut icp_e8s,
+            mut cycles,
+            amount,
+            token,
+        }: FaucetRequest,
+    ) -> Result<FaucetResponse, ControlError> {
+        let Some(faucet) = &self.faucet else {
+            return Err(ControlError::bad_request(
+                "the faucet requires the network to be started with --faucet",
+            ));
+        };
+        if let Some(amount) = amount {
+            match token {
+                FaucetToken::Icp => {
+                    let e8s = ledger::parse_icp(&amount).map_err(ControlError::bad_request)?;
+                    icp_e8s = icp_e8s.saturating_add(e8s);
+                }
+                FaucetToken::Cycles => {
+                    let amount: u128 = amount.parse().map_err(|_| {
+                        ControlError::bad_request(format!("invalid cycle amount `{amount}`"))
+                    })?;
+                    cycles = cycles.saturating_add(amount);
+                }
+            }
+        }
+        let cycles = faucet
+            .dispense(&self.pic, principal, icp_e8s, cycles)
+            .await?;
+        Ok(FaucetResponse { icp_e8s, cycles })
+    }
+
+    async fn proposal_submit(
+        &self,
+        ProposalSubmitRequest {
+            proposer,
+            neuron_id,
+            title,
+            summary,
+            url,
+            action,
+            adopt_with,
+        }: ProposalSubmitRequest,
+    ) -> Result<ProposalSubmitResponse, ControlError> {
+        let proposal_id = governance::submit_proposal(
+            &self.pic, proposer, neuron_id, title, summary, url, action,
+        )
+        .await?;
+        for neuron in &adopt_with {
+            // with --nns-instant-execution, following neurons may already have decided it
+            if governance::proposal_status(&self.pic, proposal_id).await? != "open" {
+                break;
+            }
+            // the proposer's neuron votes for its own proposal
+            if neuron.id != neuron_id {
+                governance::vote_yes(&self.pic, neuron.controller, neuron.id, proposal_id).await?;
+            }
+        }
+        // adopted proposals are executed asynchronously, so give execution a moment to finish
+        let mut status = governance::proposal_status(&self.pic, proposal_id).await?;
+        for _ in 0..20 {
+            if status != "adopted" && (status != "open" || adopt_with.is_empty()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            status = governance::proposal_status(&self.pic, proposal_id).await?;
+        }
+        Ok(ProposalSubmitResponse {
+            proposal_id,
+            status: status.to_string(),
+        })
+    }
+
+    async fn sns_launch(
+        &self,
+        SnsLaunchRequest {
+            config,
+            proposer,
+            voters,
+        }: SnsLaunchRequest,
+    ) -> Result<SnsLaunchResponse, ControlError> {
+        let canisters = sns::launch(self, config, &proposer, &voters).await?;
+        Ok(SnsLaunchResponse { canisters })
+    }
+
+    async fn xrc_rates(
+        &self,
+        XrcRatesRequest { rates }: XrcRatesRequest,
+    ) -> Result<XrcRatesResponse, ControlError> {
+        let Some(current) = &self.xrc_rates else {
+            return Err(ControlError::bad_request(
+                "exchange rates require the network to be started with --xrc-mock",
+            ));
+        };
+        let rates = xrc::parse_rates(&rates).map_err(ControlError::bad_request)?;
+        let mut current = current.lock().await;
+        let mut updated = current.clone();
+        updated.extend(rates);
+        xrc::install(&self.pic, &updated).await?;
+        *current = updated;
+        Ok(XrcRatesResponse {
+            rates: xrc::format_rates(&current),
+        })
+    }
+
+    async fn ledger_archives(
+        &self,
+        LedgerArchivesRequest { canisters }: LedgerArchivesRequest,
+    ) -> Result<LedgerArchivesResponse, ControlError> {
+        let archives = ledger_archive::find(&self.pic, &canisters).await?;
+        Ok(LedgerArchivesResponse { archives })
+    }
+
+    async fn bench(
+        &self,
+        BenchRequest { calls }: BenchRequest,
+    ) -> Result<BenchResponse, ControlError> {
+        if self.quota_paused.load(Ordering::Relaxed) {
+            return Err(ControlError::bad_request(
+                "the network is paused for exceeding its state quota",
+            ));
+        }
+        let results = bench::run(self, calls).await?;
+        Ok(BenchResponse { results })
+    }
+
+    async fn artificial_delay(
+        &self,
+        ArtificialDelayRequest {
+            artificial_delay_ms,
+        }: ArtificialDelayRequest,
+    ) -> Result<ArtificialDelayResponse, ControlError> {
+        if !self.auto_progress {
+            return Err(ControlError::bad_request(
+                "an artificial delay requires rounds to execute continuously, not with --no-auto-progress",
+            ));
+        }
+        let _paused = self.progress.lock().await;
+        *self.artificial_delay_ms.lock().await = artificial_delay_ms;
+        // auto-progress takes its delay when it is enabled, so it restarts with the new one
+        self.pic.stop_progress().await;
+        self.resume_progress().await?;
+        Ok(ArtificialDelayResponse {})
+    }
+
+    async fn advance_time(
+        &self,
+        AdvanceTimeRequest { seconds }: AdvanceTimeRequest,
+    ) -> Result<AdvanceTimeResponse, ControlError> {
+        if self.quota_paused.load(Ordering::Relaxed) {
+            return Err(ControlError::bad_request(
+                "the network is paused for exceeding its state quota",
+            ));
+        }
+        let time = self
+            .pic
+            .get_time()
+            .await
+            .as_nanos_since_unix_epoch()
+            .saturating_add(seconds.saturating_mul(1_000_000_000));
+        self.advance_time_to(Time::from_nanos_since_unix_epoch(time))
+            .await?;
+        Ok(AdvanceTimeResponse {
+            time: self.pic.get_time().await.as_nanos_since_unix_epoch(),
+        })
+    }
+
+    async fn replay(
+        &self,
+        ReplayRequest { messages }: ReplayRequest,
+    ) -> Result<ReplayResponse, ControlError> {
+        if self.quota_paused.load(Ordering::Relaxed) {
+            return Err(ControlError::bad_request(
+                "the network is paused for exceeding its state quota",
+            ));
+        }
+        let rejections = ingress::replay(self, messages).await?;
+        Ok(ReplayResponse { rejections })
+    }
+
+    async fn tick(
+        &self,
+        TickRequest { rounds }: TickRequest,
+    ) -> Result<TickResponse, ControlError> {
+        if self.auto_progress {
+            return Err(ControlError::bad_request(
+                "ticking requires the network to be started with --no-auto-progress",
+            ));
+        }
+        if self.quota_paused.load(Ordering::Relaxed) {
+            return Err(ControlError::bad_request(
+                "the network is paused for exceeding its state quota",
+            ));
+        }
+        let _paused = self.progress.lock().await;
+        for _ in 0..rounds {
+            self.pic.tick().await;
+        }
+        Ok(TickResponse {
+            time: self.pic.get_time().await.as_nanos_since_unix_epoch(),
+        })
+    }
+
+    async fn status(
+        &self,
+        StatusRequest {}: StatusRequest,
+    ) -> Result<StatusResponse, ControlError> {
+        let canisters = self.registry_canisters()?;
+        Ok(StatusResponse {
+            state_dir_size: self
+                .state_dir
+                .as_ref()
+                .map(|_| self.state_size.load(Ordering::Relaxed)),
+            state_quota: self.state_quota,
+            quota_paused: self.quota_paused.load(Ordering::Relaxed),
+            query_stats: query_stats::collect(&self.pic, &canisters).await,
+        })
+    }
+
+    async fn stall_subnet(
+        &self,
+        StallSubnetRequest { subnet, seconds }: StallSubnetRequest,
+    ) -> Result<StallSubnetResponse, ControlError> {
+        let Some(stalls) = &self.stalls else {
+            return Err(ControlError::bad_request(
+                "stalling subnets requires the network to be started with --chaos",
+            ));
+        };
+        let subnet_id = chaos::resolve_subnet(&self.pic, &subnet)
+            .await
+            .map_err(ControlError::bad_request)?;
+        stalls.stall(subnet_id, Duration::from_secs(seconds));
+        Ok(StallSubnetResponse { subnet_id })
+    }
+
+    /// Simulates an upgrade of a subnet, returning once it is back up.
+    async fn upgrade_subnet(
+        &self,
+        UpgradeSubnetRequest { subnet, seconds }: UpgradeSubnetRequest,
+    ) -> Result<UpgradeSubnetResponse, ControlError> {
+        let Some(stalls) = &self.stalls else {
+            return Err(ControlError::bad_request(
+                "simulating subnet upgrades requires the network to be started with --chaos",
+            ));
+        };
+        let subnet_id = chaos::resolve_subnet(&self.pic, &subnet)
+            .await
+            .map_err(ControlError::bad_request)?;
+        let downtime = Duration::from_secs(seconds);
+        // rounds keep executing, so that only this subnet is down and other operations go on
+        stalls.stall(subnet_id, downtime);
+        tokio::time::sleep(downtime).await;
+        Ok(UpgradeSubnetResponse { subnet_id })
+    }
+
+    async fn create_ephemeral(
+        &self,
+        CreateEphemeralRequest { subnets }: CreateEphemeralRequest,
+    ) -> Result<CreateEphemeralResponse, ControlError> {
+        let instance = ephemeral::create(self.pic.get_server_url(), &subnets).await?;
+        let topology = instance.topology().await;
+        let response = CreateEphemeralResponse {
+            instance_id: instance.instance_id,
+            gateway_url: instance
+                .url()
+                .expect("gateway url set in builder")
+                .as_str()
+                .trim_end_matches('/')
+                .to_string(),
+            root_key: hex::encode(
+                instance
+                    .root_key()
+                    .await
+                    .expect("root key should be available if there is a root subnet"),
+            ),
+            default_effective_canister_id: Principal::from_slice(
+                &topology.default_effective_canister_id.canister_id,
+            ),
+        };
+        self.ephemeral
+            .lock()
+            .await
+            .insert(instance.instance_id, instance);
+        Ok(response)
+    }
+
+    async fn delete_ephemeral(
+        &self,
+        DeleteEphemeralRequest { instance_id }: DeleteEphemeralRequest,
+    ) -> Result<DeleteEphemeralResponse, ControlError> {
+        let Some(instance) = self.ephemeral.lock().await.remove(&instance_id) else {
+            return Err(ControlError::bad_request(format!(
+                "no ephemeral instance {instance_id}"
+            )));
+        };
+        instance.drop().await;
+        Ok(DeleteEphemeralResponse {})
+    }
+
+    fn errors(&self, ErrorsRequest {}: ErrorsRequest) -> Result<ErrorsResponse, ControlError> {
+        let Some(errors) = &self.errors else {
+            return Err(ControlError::bad_request(
+                "error reports require the network to be started with --error-report",
+            ));
+        };
+        Ok(ErrorsResponse {
+            report: errors.report(),
+        })
+    }
+
+    fn purge_gateway_cache(
+        &self,
+        PurgeGatewayCacheRequest {}: PurgeGatewayCacheRequest,
+    ) -> Result<PurgeGatewayCacheResponse, ControlError> {
+        let Some(cache) = &self.cache else {
+            return Err(ControlError::bad_request(
+                "the gateway cache requires the network to be started with --gateway-cache",
+            ));
+        };
+        Ok(PurgeGatewayCacheResponse {
+            purged: cache.purge(),
+        })
+    }
+
+    async fn logs(
+        &self,
+        LogsRequest { canister_id, after }: LogsRequest,
+    ) -> Result<LogsResponse, ControlError> {
+        let records = logs::fetch(&self.pic, canister_id, after).await?;
+        Ok(LogsResponse { records })
+    }
+
+    async fn report(
+        &self,
+        ReportRequest {}: ReportRequest,
+    ) -> Result<ReportResponse, ControlError> {
+        let canisters = self.registry_canisters()?;
+        let baseline = self.usage_baseline.lock().await;
+        Ok(ReportResponse {
+            canisters: report::compare(&self.pic, &canisters, &baseline).await,
+        })
+    }
+
+    /// Returns the canisters in the registry, by name, or none if the network has no status
+    /// directory.
+    pub fn registry_canisters(&self) -> anyhow::Result<BTreeMap<String, Principal>> {
+        Ok(match &self.status_dir {
+            Some(status_dir) => registry::read(status_dir)?
+                .canisters
+                .into_iter()
+                .map(|(name, entry)| (name, entry.canister_id))
+                .collect(),
+            None => BTreeMap::new(),
+        })
+    }
+
+    fn shutdown(&self, ShutdownRequest {}: ShutdownRequest) -> ShutdownResponse {
+        self.shutdown.notify_one();
+        ShutdownResponse {}
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, ControlError> {
+    serde_json::from_slice(body)
+        .map_err(|e| ControlError::bad_request(format!("invalid request body: {e}")))
+}
+
+fn respond<T: Serialize>(value: T) -> Result<Response<Full<Bytes>>, ControlError> {
+    Ok(json_response(StatusCode::OK, &value))
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Full<Bytes>> {
+    let body = serde_json::to_vec(value).expect("infallible serialization");
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .expect("valid response")
+}
+
+/// Sends a control request to the network whose status file is in `status_dir`.
+pub async fn request<Req: Serialize, Resp: DeserializeOwned>(
+    status_dir: &Path,
+    path: &str,
+    req: &Req,
+) -> anyhow::Result<Resp> {
+    let status = read_status(status_dir)?;
+    let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{}{path}", status.control_port))
+        .json(req)
+        .send()
+        .await
+        .context("failed to contact the launcher; is the network running?")?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error = match response.json::<ErrorResponse>().await {
+            Ok(ErrorResponse { error }) => error,
+            Err(_) => status.to_string(),
+        };
+        anyhow::bail!("{error}");
+    }
+    response
+        .json()
+        .await
+        .context("failed to parse control response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().method(Method::POST).uri("/shutdown");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn admit_accepts_local_json() {
+        for host in ["127.0.0.1:4943", "localhost:4943", "[::1]:4943"] {
+            let parts = parts(&[("host", host), ("content-type", "application/json")]);
+            assert!(admit(&parts).is_ok(), "{host}");
+        }
+    }
+
+    #[test]
+    fn admit_rejects_browser_requests() {
+        let rebound = parts(&[
+            ("host", "evil.test:4943"),
+            ("content-type", "application/json"),
+        ]);
+        assert_eq!(admit(&rebound).unwrap_err().status, StatusCode::FORBIDDEN);
+        let cross_origin = parts(&[
+            ("host", "127.0.0.1:4943"),
+            ("origin", "http://evil.test"),
+            ("content-type", "application/json"),
+        ]);
+        assert_eq!(
+            admit(&cross_origin).unwrap_err().status,
+            StatusCode::FORBIDDEN
+        );
+        let form = parts(&[("host", "127.0.0.1:4943"), ("content-type", "text/plain")]);
+        assert_eq!(
+            admit(&form).unwrap_err().status,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+}
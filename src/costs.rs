@@ -0,0 +1,36 @@
+//! Mainnet costs (`--mainnet-costs`), so that cycle budgets can be validated locally.
+//!
+//! PocketIC's subnets have as many nodes as their mainnet counterparts, so execution, storage and
+//! messages are already charged at mainnet rates, scaled up on larger subnets such as fiduciary
+//! ones. What a local network adds is free cycles: canisters created with the provisional API get
+//! cycles out of thin air, and can be topped up the same way. With mainnet costs, the launcher's
+//! gateway (see [`crate::gateway`]) rejects those provisional calls, so that canisters are created
+//! and topped up through the cycles ledger or the cycles minting canister and paid for, as on
+//! mainnet. The launcher's own operations, such as the faucet, are unaffected.
+
+use candid::Principal;
+
+use crate::ingress;
+
+/// Methods of the management canister that mint cycles, which mainnet doesn't offer.
+const PROVISIONAL_METHODS: &[&str] = &[
+    "provisional_create_canister_with_cycles",
+    "provisional_top_up_canister",
+];
+
+/// Checks that the call in `envelope` would be possible on mainnet, returning why it is rejected
+/// if not.
+pub fn check(envelope: &[u8]) -> Result<(), String> {
+    match ingress::call_content(envelope) {
+        Some(content)
+            if content.canister_id == Principal::management_canister()
+                && PROVISIONAL_METHODS.contains(&content.method_name.as_str()) =>
+        {
+            Err(format!(
+                "{} is not available with --mainnet-costs; create canisters and top them up through the cycles ledger or the cycles minting canister",
+                content.method_name
+            ))
+        }
+        _ => Ok(()),
+    }
+}
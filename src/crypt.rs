@@ -0,0 +1,238 @@
+//! Encryption of archives for `--encrypt-state`.
+//!
+//! Encrypted archives start with a magic header and a random salt, from which the file's key is
+//! derived from the passphrase with PBKDF2. The data follows as AES-256-GCM chunks, each prefixed
+//! with whether it is the final chunk and its length. The nonce is made of the chunk's index and
+//! final-chunk flag, so that reordered or truncated files fail to decrypt.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    num::NonZeroU32,
+    process::Command,
+};
+
+use anyhow::{Context, bail};
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// Environment variable the passphrase is read from, before trying the system keychain.
+pub const KEY_ENV: &str = "ICP_CLI_NETWORK_LAUNCHER_STATE_KEY";
+/// Service and account name of the passphrase in the system keychain.
+const KEYCHAIN_SERVICE: &str = "icp-cli-network-launcher";
+const KEYCHAIN_ACCOUNT: &str = "state-key";
+
+const MAGIC: &[u8; 8] = b"ICPLENC1";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const CHUNK_SIZE: usize = 1 << 20;
+const TAG_LEN: usize = 16;
+
+/// The passphrase that archive keys are derived from.
+pub struct Passphrase(Vec<u8>);
+
+/// Reads the passphrase from the environment, or else from the system keychain.
+pub fn load_passphrase() -> anyhow::Result<Passphrase> {
+    if let Some(passphrase) = std::env::var_os(KEY_ENV) {
+        return Ok(Passphrase(passphrase.into_encoded_bytes()));
+    }
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("security");
+        cmd.args(["find-generic-password", "-s", KEYCHAIN_SERVICE])
+            .args(["-a", KEYCHAIN_ACCOUNT, "-w"]);
+        cmd
+    } else if cfg!(target_os = "linux") {
+        let mut cmd = Command::new("secret-tool");
+        cmd.args(["lookup", "service", KEYCHAIN_SERVICE])
+            .args(["account", KEYCHAIN_ACCOUNT]);
+        cmd
+    } else {
+        bail!("--encrypt-state requires {KEY_ENV} to be set on this platform");
+    };
+    let output = cmd.output().ok().filter(|output| output.status.success());
+    let Some(output) = output else {
+        bail!(
+            "--encrypt-state requires {KEY_ENV} to be set, or a passphrase stored in the system keychain under service `{KEYCHAIN_SERVICE}`, account `{KEYCHAIN_ACCOUNT}`"
+        );
+    };
+    let mut passphrase = output.stdout;
+    while passphrase
+        .last()
+        .is_some_and(|b| *b == b'\n' || *b == b'\r')
+    {
+        passphrase.pop();
+    }
+    if passphrase.is_empty() {
+        bail!("the passphrase in the system keychain is empty");
+    }
+    Ok(Passphrase(passphrase))
+}
+
+/// Returns whether `header`, the start of a file, marks it as encrypted.
+pub fn is_encrypted(header: &[u8]) -> bool {
+    header.starts_with(MAGIC)
+}
+
+fn derive_key(passphrase: &Passphrase, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).expect("nonzero"),
+        salt,
+        &passphrase.0,
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("valid key length"))
+}
+
+fn nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypts everything written to it. [`Encryptor::finish`] must be called to write the final chunk.
+pub struct Encryptor<W: Write> {
+    inner: W,
+    key: LessSafeKey,
+    buf: Vec<u8>,
+    index: u64,
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Writes the header to `inner` and returns a writer encrypting into it.
+    pub fn new(mut inner: W, passphrase: &Passphrase) -> anyhow::Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow::anyhow!("failed to generate salt"))?;
+        inner.write_all(MAGIC)?;
+        inner.write_all(&salt)?;
+        Ok(Self {
+            inner,
+            key: derive_key(passphrase, &salt),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            index: 0,
+        })
+    }
+
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        let mut chunk = std::mem::take(&mut self.buf);
+        self.key
+            .seal_in_place_append_tag(nonce(self.index, last), Aad::empty(), &mut chunk)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.inner.write_all(&[last as u8])?;
+        self.inner.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        self.inner.write_all(&chunk)?;
+        self.index += 1;
+        chunk.clear();
+        self.buf = chunk;
+        Ok(())
+    }
+
+    /// Writes the final chunk and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+        let n = data.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a file written by [`Encryptor`].
+pub struct Decryptor<R: Read> {
+    inner: R,
+    key: LessSafeKey,
+    plaintext: Vec<u8>,
+    pos: usize,
+    index: u64,
+    done: bool,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Reads the header from `inner` and returns a reader decrypting from it.
+    pub fn new(mut inner: R, passphrase: &Passphrase) -> anyhow::Result<Self> {
+        let mut header = [0; MAGIC.len() + SALT_LEN];
+        inner
+            .read_exact(&mut header)
+            .context("failed to read encryption header")?;
+        if !is_encrypted(&header) {
+            bail!("file is not encrypted");
+        }
+        Ok(Self {
+            inner,
+            key: derive_key(passphrase, &header[MAGIC.len()..]),
+            plaintext: vec![],
+            pos: 0,
+            index: 0,
+            done: false,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut header = [0; 5];
+        self.inner
+            .read_exact(&mut header)
+            .map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => {
+                    io::Error::new(ErrorKind::InvalidData, "file is truncated")
+                }
+                _ => e,
+            })?;
+        let last = header[0] == 1;
+        let len = u32::from_be_bytes(header[1..].try_into().expect("4 bytes")) as usize;
+        if header[0] > 1 || len > CHUNK_SIZE + TAG_LEN {
+            return Err(io::Error::new(ErrorKind::InvalidData, "file is corrupt"));
+        }
+        let mut chunk = vec![0; len];
+        self.inner.read_exact(&mut chunk)?;
+        let plaintext_len = self
+            .key
+            .open_in_place(nonce(self.index, last), Aad::empty(), &mut chunk)
+            .map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    "decryption failed; wrong passphrase or corrupt file",
+                )
+            })?
+            .len();
+        chunk.truncate(plaintext_len);
+        self.plaintext = chunk;
+        self.pos = 0;
+        self.index += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+        let n = out.len().min(self.plaintext.len() - self.pos);
+        out[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,140 @@
+//! Funding principals with cycles, at startup (`--cycles-for`) and through the faucet.
+//!
+//! Canisters are topped up directly by fabricating cycles. Other principals receive cycles in
+//! their cycles ledger account. Ingress messages can't carry cycles, so these deposits go through
+//! the cycles minting canister: ICP is minted to the recipient, who sends it to the CMC and has
+//! it notified to mint cycles into their cycles ledger account. The amount of ICP is derived from
+//! the CMC's conversion rate.
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Decode, Encode, Nat, Principal, types::value::IDLValue};
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+
+use crate::ledger::{self, AccountId};
+
+/// ID of the cycles minting canister.
+pub const CMC_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 4, 1, 1]);
+/// Memo of transfers to the CMC that are converted into cycles ledger deposits.
+const MEMO_MINT_CYCLES: u64 = 0x544e494d;
+
+#[derive(CandidType, Deserialize)]
+struct IcpXdrConversionRateResponse {
+    data: IcpXdrConversionRate,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpXdrConversionRate {
+    xdr_permyriad_per_icp: u64,
+}
+
+#[derive(CandidType)]
+struct NotifyMintCyclesArg {
+    block_index: u64,
+    to_subaccount: Option<Vec<u8>>,
+    deposit_memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct NotifyMintCyclesSuccess {
+    minted: Nat,
+}
+
+/// Parses a `PRINCIPAL=CYCLES` pair.
+pub fn parse_principal_cycles(s: &str) -> Result<(Principal, u128), String> {
+    let (principal, cycles) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `PRINCIPAL=CYCLES`, got `{s}`"))?;
+    let principal =
+        Principal::from_text(principal).map_err(|e| format!("invalid principal: {e}"))?;
+    let cycles = cycles
+        .parse()
+        .map_err(|e| format!("invalid cycle amount: {e}"))?;
+    Ok((principal, cycles))
+}
+
+/// Gives `cycles` to each principal: existing canisters have the cycles added to their balance,
+/// other principals have them deposited into their cycles ledger account.
+pub async fn fund(pic: &PocketIc, balances: &[(Principal, u128)]) -> anyhow::Result<()> {
+    for &(principal, cycles) in balances {
+        if pic.canister_exists(principal).await {
+            pic.add_cycles(principal, cycles).await;
+        } else {
+            deposit(pic, principal, cycles)
+                .await
+                .with_context(|| format!("failed to deposit cycles for {principal}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Deposits at least `cycles` into the cycles ledger account of `principal`, returning the
+/// amount actually deposited.
+pub async fn deposit(pic: &PocketIc, principal: Principal, cycles: u128) -> anyhow::Result<u128> {
+    let response = pic
+        .query_call(
+            CMC_CANISTER_ID,
+            Principal::anonymous(),
+            "get_icp_xdr_conversion_rate",
+            Encode!()?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to get ICP conversion rate: {e}"))?;
+    let rate = Decode!(&response, IcpXdrConversionRateResponse)?
+        .data
+        .xdr_permyriad_per_icp;
+    // one XDR is worth 10^12 cycles, so one e8 is worth `xdr_permyriad_per_icp` cycles
+    let e8s = u64::try_from(cycles.div_ceil(u128::from(rate.max(1))))
+        .context("cycle amount is too large")?;
+    ledger::mint(
+        pic,
+        &[(AccountId::from_principal(principal), e8s + ledger::FEE_E8S)],
+    )
+    .await?;
+    let block_index = ledger::transfer(
+        pic,
+        principal,
+        AccountId::new(CMC_CANISTER_ID, ledger::principal_subaccount(principal)),
+        e8s,
+        ledger::FEE_E8S,
+        MEMO_MINT_CYCLES,
+    )
+    .await
+    .context("failed to send ICP to the cycles minting canister")?;
+    let response = pic
+        .update_call(
+            CMC_CANISTER_ID,
+            principal,
+            "notify_mint_cycles",
+            Encode!(&NotifyMintCyclesArg {
+                block_index,
+                to_subaccount: None,
+                deposit_memo: None,
+            })?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to mint cycles: {e}"))?;
+    let minted = Decode!(&response, Result<NotifyMintCyclesSuccess, IDLValue>)?
+        .map_err(|e| anyhow!("failed to mint cycles: {e}"))?
+        .minted;
+    u128::try_from(minted.0).context("minted cycle amount is too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_principal_cycles_splits_the_pair() {
+        assert_eq!(
+            parse_principal_cycles("2vxsx-fae=1000"),
+            Ok((Principal::anonymous(), 1000))
+        );
+        for s in ["2vxsx-fae", "nope=1000", "2vxsx-fae=-1", "2vxsx-fae=1T"] {
+            assert!(
+                parse_principal_cycles(s).is_err(),
+                "`{s}` should be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Migration of canisters from a dfx project's local replica.
+//!
+//! The dfx replica's state directory is not readable by PocketIC, so canisters are recreated from
+//! the project's build output: each canister listed in `.dfx/local/canister_ids.json` is created
+//! at the same ID where the local subnets allow it, and its last built Wasm module is installed
+//! with an empty init argument. Canister data (and uploaded assets) are not migrated.
+//!
+//! The subcommand reads the project and sends the canisters to the control API, so that the
+//! launcher never reads files on a client's behalf.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+
+use crate::canister;
+
+/// Cycles given to each imported canister.
+const IMPORTED_CANISTER_CYCLES: u128 = 100_000_000_000_000;
+
+/// A canister of a dfx project, as deployed to dfx's local replica.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectCanister {
+    pub name: String,
+    /// The canister's ID in the dfx project.
+    pub dfx_canister_id: Principal,
+    /// The last built Wasm module, in base64.
+    pub wasm_module: Option<String>,
+    /// Why the Wasm module could not be read.
+    pub error: Option<String>,
+}
+
+/// The outcome of importing one canister.
+#[derive(Serialize, Deserialize)]
+pub struct ImportedCanister {
+    pub name: String,
+    /// The canister's ID in the dfx project.
+    pub dfx_canister_id: Principal,
+    /// The canister's ID on this network, which differs if the original ID was unavailable.
+    pub canister_id: Option<Principal>,
+    /// Why the canister could not be (fully) imported.
+    pub error: Option<String>,
+}
+
+/// Reads the canisters of the dfx project at `project`.
+pub fn read(project: &Path) -> anyhow::Result<Vec<ProjectCanister>> {
+    let local = project.join(".dfx").join("local");
+    let ids_file = local.join("canister_ids.json");
+    if !ids_file.exists() {
+        bail!(
+            "{} does not exist; has the project been deployed to dfx's local replica?",
+            ids_file.display()
+        );
+    }
+    let ids = fs::read_to_string(&ids_file)
+        .with_context(|| format!("failed to read {}", ids_file.display()))?;
+    let ids: BTreeMap<String, BTreeMap<String, Principal>> =
+        serde_json::from_str(&ids).context("failed to parse canister_ids.json")?;
+    let mut canisters = vec![];
+    for (name, networks) in ids {
+        let Some(&dfx_canister_id) = networks.get("local") else {
+            continue;
+        };
+        let wasm_module = match find_wasm(&local.join("canisters").join(&name)) {
+            Some(wasm) => fs::read(&wasm)
+                .with_context(|| format!("failed to read {}", wasm.display()))
+                .map(|wasm_module| STANDARD.encode(wasm_module)),
+            None => Err(anyhow!("no built Wasm module found")),
+        };
+        let (wasm_module, error) = match wasm_module {
+            Ok(wasm_module) => (Some(wasm_module), None),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+        canisters.push(ProjectCanister {
+            name,
+            dfx_canister_id,
+            wasm_module,
+            error,
+        });
+    }
+    Ok(canisters)
+}
+
+/// Recreates the canisters of a dfx project, read by [`read`], on the network.
+pub async fn import(
+    pic: &PocketIc,
+    canisters: Vec<ProjectCanister>,
+) -> anyhow::Result<Vec<ImportedCanister>> {
+    let mut imported = vec![];
+    for ProjectCanister {
+        name,
+        dfx_canister_id,
+        wasm_module,
+        error,
+    } in canisters
+    {
+        let mut result = ImportedCanister {
+            name,
+            dfx_canister_id,
+            canister_id: None,
+            error: None,
+        };
+        if pic.canister_exists(dfx_canister_id).await {
+            result.error =
+                Some("a canister with this ID already exists on the network".to_string());
+            imported.push(result);
+            continue;
+        }
+        let canister_id = match pic
+            .create_canister_with_id(None, None, dfx_canister_id)
+            .await
+        {
+            Ok(canister_id) => canister_id,
+            // the dfx replica allocates IDs from a range the local subnets may not cover
+            Err(_) => pic.create_canister().await,
+        };
+        result.canister_id = Some(canister_id);
+        pic.add_cycles(canister_id, IMPORTED_CANISTER_CYCLES).await;
+        let installed = match (wasm_module, error) {
+            (_, Some(error)) => Err(anyhow!(error)),
+            (Some(wasm_module), None) => match STANDARD.decode(wasm_module) {
+                Ok(wasm_module) => {
+                    canister::install_code(
+                        pic,
+                        canister_id,
+                        Principal::anonymous(),
+                        wasm_module,
+                        Encode!()?,
+                    )
+                    .await
+                }
+                Err(e) => Err(anyhow!("invalid Wasm module: {e}")),
+            },
+            (None, None) => Err(anyhow!("no built Wasm module found")),
+        };
+        if let Err(e) = installed {
+            result.error = Some(format!("{e:#}"));
+        }
+        imported.push(result);
+    }
+    Ok(imported)
+}
+
+/// Returns the Wasm module dfx built for a canister, given its `.dfx/local/canisters/<name>` dir.
+fn find_wasm(canister_dir: &Path) -> Option<PathBuf> {
+    let name = canister_dir.file_name()?.to_string_lossy().into_owned();
+    [
+        format!("{name}.wasm"),
+        format!("{name}.wasm.gz"),
+        // asset canisters
+        "assetstorage.wasm.gz".to_string(),
+    ]
+    .into_iter()
+    .map(|file| canister_dir.join(file))
+    .find(|path| path.is_file())
+}
@@ -0,0 +1,116 @@
+//! A custom root domain (`--domain`), so that browser tests can use production-like URLs such as
+//! `http://<canister id>.ic.local` rather than `localhost` ones.
+//!
+//! The gateway serves canisters under the domain as well as `localhost`, but browsers only resolve
+//! `*.localhost` to the loopback interface by themselves. With `--dns-port`, the launcher answers
+//! DNS queries for the domain and all its subdomains with the gateway's address, so that it can be
+//! configured as the resolver of that domain, e.g. with `/etc/resolver/<domain>` on macOS or a
+//! per-domain server in systemd-resolved or dnsmasq. Queries for other names are refused.
+
+use std::net::Ipv4Addr;
+
+use tokio::net::UdpSocket;
+
+/// How long resolvers may cache answers, in seconds.
+const TTL: u32 = 60;
+/// Largest DNS message over UDP without extensions.
+const MAX_MESSAGE_LEN: usize = 512;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_ANY: u16 = 255;
+pub const CLASS_IN: u16 = 1;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+/// Parses a root domain such as `ic.local`.
+pub fn parse_domain(s: &str) -> Result<String, String> {
+    let domain = s.trim_end_matches('.').to_ascii_lowercase();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if domain.split('.').all(valid_label) {
+        Ok(domain)
+    } else {
+        Err(format!("invalid domain `{s}`"))
+    }
+}
+
+/// Answers DNS queries on `socket` for `domain` and its subdomains with `address`, until the task
+/// is aborted.
+pub async fn serve(socket: UdpSocket, domain: String, address: Ipv4Addr) {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        if let Some(response) = respond(&buf[..len], &domain, address) {
+            _ = socket.send_to(&response, peer).await;
+        }
+    }
+}
+
+/// Returns the response to `query`, or `None` if it isn't a query.
+fn respond(query: &[u8], domain: &str, address: Ipv4Addr) -> Option<Vec<u8>> {
+    let header = query.get(..12)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    // responses are ignored
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let opcode = (flags >> 11) & 0xf;
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    // authoritative answer, with recursion desired copied from the query
+    let response_flags = 0x8000 | (opcode << 11) | 0x0400 | (flags & 0x0100);
+    let mut response = header[..2].to_vec();
+    if opcode != 0 || question_count != 1 {
+        response.extend((response_flags | RCODE_NOT_IMPLEMENTED).to_be_bytes());
+        response.extend([0; 8]);
+        return Some(response);
+    }
+    let (name, end) = parse_name(query, 12)?;
+    let question = query.get(12..end + 4)?;
+    let query_type = u16::from_be_bytes([query[end], query[end + 1]]);
+    let query_class = u16::from_be_bytes([query[end + 2], query[end + 3]]);
+    let in_domain = name == domain || name.ends_with(&format!(".{domain}"));
+    let answer = in_domain && matches!(query_type, TYPE_A | TYPE_ANY) && query_class == CLASS_IN;
+    let rcode = if in_domain { 0 } else { RCODE_REFUSED };
+    response.extend((response_flags | rcode).to_be_bytes());
+    response.extend(1u16.to_be_bytes());
+    response.extend(u16::from(answer).to_be_bytes());
+    response.extend([0; 4]);
+    response.extend(question);
+    if answer {
+        // the name is a pointer to the question's
+        response.extend(0xc00cu16.to_be_bytes());
+        response.extend(TYPE_A.to_be_bytes());
+        response.extend(CLASS_IN.to_be_bytes());
+        response.extend(TTL.to_be_bytes());
+        response.extend(4u16.to_be_bytes());
+        response.extend(address.octets());
+    }
+    Some(response)
+}
+
+/// Parses the uncompressed name at `start` of `message`, returning it in lowercase and the offset
+/// after it.
+pub fn parse_name(message: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut offset = start;
+    loop {
+        let len = usize::from(*message.get(offset)?);
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // names in queries are rarely compressed, so compressed ones are not supported
+        if len > 63 {
+            return None;
+        }
+        let label = message.get(offset..offset + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += len;
+    }
+    Some((labels.join("."), offset))
+}
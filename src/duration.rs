@@ -0,0 +1,66 @@
+//! Durations taken by options and configuration files, such as the window of Internet Identity's
+//! registration rate limit and neuron dissolve delays.
+
+use std::time::Duration;
+
+/// Parses a positive duration such as `90s`, `30m`, `6h` or `1d`, or a sum of them such as `3d4h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut total: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|e| format!("invalid duration `{s}`: {e}"))?;
+        let secs = match tail.chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(format!("expected a duration like `30m`, got `{s}`")),
+        };
+        total = number
+            .checked_mul(secs)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| format!("duration `{s}` is too long"))?;
+        rest = &tail[1..];
+    }
+    if total == 0 {
+        return Err(format!("duration `{s}` must be positive"));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units_and_sums() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("6h"), Ok(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(
+            parse_duration("3d4h"),
+            Ok(Duration::from_secs((3 * 24 + 4) * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in ["", "0s", "0d0h", "30", "s", "5w", "1.5h", "-1s", "10 s"] {
+            assert!(parse_duration(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        assert!(parse_duration("18446744073709551615d").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Throwaway instances on the network's PocketIC server (`ephemeral create`), for a fresh network
+//! per test without the cost of starting a launcher per test.
+//!
+//! An ephemeral instance has the requested subnets, an NNS subnet, and the ICP features every
+//! network has (the ICP and cycles ledgers, cycles minting and the registry), but none of the
+//! launcher's own setup. It lives until it is deleted or the network stops.
+
+use std::{net::Ipv4Addr, path::Path};
+
+use clap::{ArgAction, Subcommand};
+use pocket_ic::{
+    PocketIcBuilder,
+    common::rest::{IcpFeatures, IcpFeaturesConfig, InstanceHttpGatewayConfig},
+    nonblocking::PocketIc,
+};
+use reqwest::Url;
+
+use crate::{SubnetKind, control, enable_auto_progress};
+
+/// Creates an instance with `subnets`, or an application subnet if there are none, on the server
+/// at `server_url`, with its own gateway on loopback.
+pub async fn create(server_url: Url, subnets: &[SubnetKind]) -> anyhow::Result<PocketIc> {
+    let mut pic = PocketIcBuilder::new()
+        .with_server_url(server_url)
+        .with_http_gateway(InstanceHttpGatewayConfig {
+            ip_addr: Some(Ipv4Addr::LOCALHOST.to_string()),
+            port: None,
+            domains: Some(vec!["localhost".to_string()]),
+            https_config: None,
+        });
+    if subnets.is_empty() {
+        pic = pic.with_application_subnet();
+    }
+    for subnet in subnets {
+        pic = subnet.add_to(pic);
+    }
+    let pic = pic
+        .with_nns_subnet()
+        .with_icp_features(IcpFeatures {
+            cycles_minting: Some(IcpFeaturesConfig::DefaultConfig),
+            icp_token: Some(IcpFeaturesConfig::DefaultConfig),
+            cycles_token: Some(IcpFeaturesConfig::DefaultConfig),
+            registry: Some(IcpFeaturesConfig::DefaultConfig),
+            ..<_>::default()
+        })
+        .build_async()
+        .await;
+    if let Err(e) = enable_auto_progress(&pic, None).await {
+        pic.drop().await;
+        return Err(e);
+    }
+    Ok(pic)
+}
+
+#[derive(Subcommand)]
+pub enum EphemeralCommand {
+    /// Creates an instance with its own gateway, printing its instance ID, gateway URL and root
+    /// key as JSON. It has the ICP features every network has, but none of the launcher's setup.
+    Create {
+        /// Subnet to create besides the NNS subnet. Can be given multiple times; defaults to an
+        /// application subnet.
+        #[arg(long, value_enum, action = ArgAction::Append)]
+        subnet: Vec<SubnetKind>,
+    },
+    /// Deletes an instance created by `ephemeral create`.
+    Delete {
+        /// ID of the instance.
+        instance_id: usize,
+    },
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: EphemeralCommand) -> anyhow::Result<()> {
+    match command {
+        EphemeralCommand::Create { subnet } => {
+            let response: control::CreateEphemeralResponse = control::request(
+                status_dir,
+                "/create-ephemeral",
+                &control::CreateEphemeralRequest { subnets: subnet },
+            )
+            .await?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&response).expect("infallible serialization")
+            );
+        }
+        EphemeralCommand::Delete { instance_id } => {
+            let _: control::DeleteEphemeralResponse = control::request(
+                status_dir,
+                "/delete-ephemeral",
+                &control::DeleteEphemeralRequest { instance_id },
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
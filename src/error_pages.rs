@@ -0,0 +1,164 @@
+//! Error pages for browsers (`--gateway-error-pages`): when the gateway fails to serve a canister,
+//! e.g. because it doesn't exist, its `http_request` handler trapped, or its response failed
+//! certification, the bare status code and one-line message are replaced by a page explaining the
+//! failure and its likely cause.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]).
+//! The gateway describes failures in prose rather than structured errors, so the cause is guessed
+//! from the error codes and phrases in its message, which is shown as is. Only HTTP requests to
+//! canisters get error pages; the responses of the IC's API under `/api/` are left alone. Pages
+//! are HTML for browsers and JSON otherwise, depending on the `Accept` header.
+
+use candid::Principal;
+use hyper::{
+    HeaderMap, StatusCode,
+    body::Bytes,
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue},
+    http::request,
+};
+use serde::Serialize;
+
+/// Messages longer than this many bytes are cut, so that a trap with a large payload stays legible.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// A failure to serve a request to a canister.
+#[derive(Serialize)]
+struct ErrorPage {
+    status: u16,
+    canister_id: Option<Principal>,
+    /// The gateway's description of the failure.
+    message: String,
+    /// What likely caused the failure, and what to do about it.
+    cause: &'static str,
+}
+
+/// Whether the gateway's response with `status` to the request described by `parts` is replaced
+/// by an error page.
+pub fn applies(parts: &request::Parts, status: StatusCode) -> bool {
+    (status.is_client_error() || status.is_server_error()) && !parts.uri.path().starts_with("/api/")
+}
+
+/// Replaces the gateway's error response to the request described by `parts`, with `status`,
+/// `headers` and `body`, by an error page, if the request is for a canister. `canister_id` is
+/// the canister the request is for, and `exists` whether it exists.
+pub fn render(
+    parts: &request::Parts,
+    canister_id: Option<Principal>,
+    exists: bool,
+    status: StatusCode,
+    headers: &mut HeaderMap,
+    body: Bytes,
+) -> Bytes {
+    if !applies(parts, status) {
+        return body;
+    }
+    let mut message = String::from_utf8_lossy(&body).trim().to_string();
+    if message.len() > MAX_MESSAGE_LEN {
+        let mut end = MAX_MESSAGE_LEN;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+        message.push('…');
+    }
+    let page = ErrorPage {
+        status: status.as_u16(),
+        canister_id,
+        cause: cause(canister_id, exists, &message),
+        message,
+    };
+    let wants_html = parts
+        .headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let (content_type, body) = if wants_html {
+        ("text/html; charset=utf-8", html(&page))
+    } else {
+        (
+            "application/json",
+            serde_json::to_string_pretty(&page).expect("infallible serialization"),
+        )
+    };
+    // the page replaces the gateway's body, so its encoding no longer applies
+    headers.clear();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    Bytes::from(body)
+}
+
+/// Guesses what caused the gateway to fail with `message`.
+fn cause(canister_id: Option<Principal>, exists: bool, message: &str) -> &'static str {
+    let message = message.to_ascii_lowercase();
+    let mentions = |phrases: &[&str]| phrases.iter().any(|phrase| message.contains(phrase));
+    if canister_id.is_none() {
+        "The request doesn't name a canister. Canisters are served at \
+         http://<canister id>.localhost:<port>, or with a ?canisterId=<canister id> query parameter."
+    } else if !exists || mentions(&["ic0301", "canister not found", "not found: canister"]) {
+        "No canister with this ID exists on this network. It may not be deployed yet, have been \
+         deleted, or have been deployed to another network or state directory."
+    } else if mentions(&["ic0537", "no wasm module", "is empty"]) {
+        "The canister exists but has no code installed. Install its Wasm module, e.g. by \
+         deploying it again."
+    } else if mentions(&[
+        "ic0536",
+        "ic0302",
+        "has no query method",
+        "method not found",
+    ]) {
+        "The canister doesn't export an http_request query method, so it can't be browsed. It may \
+         be a backend canister; call its methods with an agent or the Candid UI instead."
+    } else if mentions(&["ic0503", "trapped", "ic0.trap", "panicked"]) {
+        "The canister's http_request handler trapped. Its logs, printed by the `logs` \
+         subcommand, may show why."
+    } else if mentions(&["ic0207", "out of cycles"]) {
+        "The canister is out of cycles. Top it up to serve requests again."
+    } else if mentions(&["ic0508", "is stopped", "is stopping"]) {
+        "The canister is stopped. Start it again to serve requests."
+    } else if mentions(&["certif", "verification"]) {
+        "The canister's response failed certification, so the gateway refused to serve it. The \
+         canister may not certify this path, e.g. an asset canister whose upload batch wasn't \
+         committed. Requests to http://<canister id>.raw.localhost:<port> skip verification."
+    } else {
+        "The gateway couldn't serve the canister's response; its message above may say why."
+    }
+}
+
+/// Renders `page` as an HTML document.
+fn html(page: &ErrorPage) -> String {
+    let canister = match page.canister_id {
+        Some(canister_id) => format!("<p>Canister: <code>{canister_id}</code></p>\n"),
+        None => String::new(),
+    };
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><meta charset=\"utf-8\"><title>{status} – gateway error</title></head>\n\
+         <body style=\"font-family: sans-serif; max-width: 50em; margin: 2em auto\">\n\
+         <h1>{status} – the gateway couldn't serve this request</h1>\n\
+         {canister}\
+         <h2>Likely cause</h2>\n\
+         <p>{cause}</p>\n\
+         <h2>Gateway message</h2>\n\
+         <pre style=\"white-space: pre-wrap\">{message}</pre>\n\
+         </body>\n\
+         </html>\n",
+        status = page.status,
+        cause = escape(page.cause),
+        message = escape(&page.message),
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,264 @@
+//! Aggregation of the calls the gateway rejects (`--error-report`), to make flaky test failures
+//! easier to triage: which canisters trap most, and which reject messages come up most.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`])
+//! and inspects the responses to queries and synchronous calls. Asynchronous (v2) calls are
+//! answered before they execute, so their outcomes go unseen.
+
+use std::{cmp::Reverse, collections::BTreeMap, sync::Mutex};
+
+use ciborium::Value;
+use ic_principal::Principal;
+use serde::{Deserialize, Serialize};
+
+/// Reject code of calls that failed in the canister, e.g. by trapping, as opposed to calls it
+/// rejected explicitly (4) or that the system rejected.
+const CANISTER_ERROR: u64 = 5;
+/// Reject messages are cut to this many characters, so that long payloads don't split them.
+const MAX_MESSAGE_LEN: usize = 200;
+/// Number of most common reject messages in a report.
+const TOP_MESSAGES: usize = 10;
+
+/// Counts of the rejected calls seen so far.
+#[derive(Default)]
+pub struct ErrorTracker {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    canisters: BTreeMap<Principal, (u64, u64)>,
+    messages: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// Canisters with rejected calls, most traps first.
+    pub canisters: Vec<CanisterErrors>,
+    /// The most common reject messages, most common first.
+    pub messages: Vec<MessageCount>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CanisterErrors {
+    pub canister_id: Principal,
+    /// Calls that failed in the canister, e.g. by trapping.
+    pub traps: u64,
+    /// Calls rejected otherwise.
+    pub rejects: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MessageCount {
+    pub message: String,
+    pub count: u64,
+}
+
+impl ErrorTracker {
+    /// Counts the rejection in the gateway's `response` to a request to `path`, if there is one.
+    pub fn observe(&self, path: &str, response: &[u8]) {
+        let ["", "api", _, "canister", canister_id, kind] = path.split('/').collect::<Vec<_>>()[..]
+        else {
+            return;
+        };
+        let Ok(canister_id) = Principal::from_text(canister_id) else {
+            return;
+        };
+        let Ok(response) = ciborium::from_reader::<Value, _>(response) else {
+            return;
+        };
+        let rejection = match (kind, text(field(&response, "status"))) {
+            ("query", Some("rejected")) | ("call", Some("non_replicated_rejection")) => {
+                match (
+                    field(&response, "reject_code"),
+                    text(field(&response, "reject_message")),
+                ) {
+                    (Some(Value::Integer(code)), Some(message)) => u64::try_from(*code)
+                        .ok()
+                        .map(|code| (code, message.to_string())),
+                    _ => None,
+                }
+            }
+            ("call", Some("replied")) => certified_rejection(&response),
+            _ => None,
+        };
+        let Some((code, message)) = rejection else {
+            return;
+        };
+        let mut counts = self.counts.lock().expect("error counts lock poisoned");
+        let (traps, rejects) = counts.canisters.entry(canister_id).or_default();
+        if code == CANISTER_ERROR {
+            *traps += 1;
+        } else {
+            *rejects += 1;
+        }
+        let message = message.chars().take(MAX_MESSAGE_LEN).collect();
+        *counts.messages.entry(message).or_default() += 1;
+    }
+
+    /// Returns the rejected calls seen so far.
+    pub fn report(&self) -> ErrorReport {
+        let counts = self.counts.lock().expect("error counts lock poisoned");
+        let mut canisters = counts
+            .canisters
+            .iter()
+            .map(|(&canister_id, &(traps, rejects))| CanisterErrors {
+                canister_id,
+                traps,
+                rejects,
+            })
+            .collect::<Vec<_>>();
+        canisters.sort_by_key(|canister| Reverse((canister.traps, canister.rejects)));
+        let mut messages = counts
+            .messages
+            .iter()
+            .map(|(message, &count)| MessageCount {
+                message: message.clone(),
+                count,
+            })
+            .collect::<Vec<_>>();
+        messages.sort_by_key(|message| Reverse(message.count));
+        messages.truncate(TOP_MESSAGES);
+        ErrorReport {
+            canisters,
+            messages,
+        }
+    }
+}
+
+/// Returns the rejection recorded in the certificate of a synchronous call's response, if the
+/// call was rejected.
+fn certified_rejection(response: &Value) -> Option<(u64, String)> {
+    let Some(Value::Bytes(certificate)) = field(response, "certificate") else {
+        return None;
+    };
+    let certificate = ciborium::from_reader::<Value, _>(&certificate[..]).ok()?;
+    let tree = field(&certificate, "tree")?;
+    // the certificate only holds the status of this call
+    if lookup(tree, b"status")? != b"rejected" {
+        return None;
+    }
+    let code = leb128(lookup(tree, b"reject_code")?)?;
+    let message = String::from_utf8_lossy(lookup(tree, b"reject_message")?).into_owned();
+    Some((code, message))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Map(map) => map
+            .iter()
+            .find_map(|(key, value)| (key.as_text() == Some(name)).then_some(value)),
+        Value::Tag(_, value) => field(value, name),
+        _ => None,
+    }
+}
+
+fn text(value: Option<&Value>) -> Option<&str> {
+    match value {
+        Some(Value::Text(text)) => Some(text),
+        _ => None,
+    }
+}
+
+/// Returns the leaf under the first node of the hash tree `tree` labeled `label`.
+fn lookup<'a>(tree: &'a Value, label: &[u8]) -> Option<&'a [u8]> {
+    let Value::Array(node) = tree else {
+        return None;
+    };
+    let (tag, node) = node.split_first()?;
+    match (i128::from(tag.as_integer()?), node) {
+        (1, [left, right]) => lookup(left, label).or_else(|| lookup(right, label)),
+        (2, [Value::Bytes(node_label), subtree]) => {
+            if node_label == label {
+                let Value::Array(leaf) = subtree else {
+                    return None;
+                };
+                match &leaf[..] {
+                    [tag, Value::Bytes(data)] if tag.as_integer() == Some(3.into()) => Some(data),
+                    _ => None,
+                }
+            } else {
+                lookup(subtree, label)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn leb128(bytes: &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Prints `report` as two tables, naming canisters with `names` where possible.
+pub fn print(report: &ErrorReport, names: &BTreeMap<Principal, String>) {
+    if report.canisters.is_empty() {
+        println!("no rejected calls");
+        return;
+    }
+    println!("{:<30}  {:>7}  {:>7}", "CANISTER", "TRAPS", "REJECTS");
+    for canister in &report.canisters {
+        let name = names
+            .get(&canister.canister_id)
+            .cloned()
+            .unwrap_or_else(|| canister.canister_id.to_text());
+        println!(
+            "{:<30}  {:>7}  {:>7}",
+            name, canister.traps, canister.rejects
+        );
+    }
+    println!();
+    println!("{:>7}  MESSAGE", "COUNT");
+    for message in &report.messages {
+        println!("{:>7}  {}", message.count, message.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(data: &[u8]) -> Value {
+        Value::Array(vec![3.into(), Value::Bytes(data.to_vec())])
+    }
+
+    fn labeled(label: &str, subtree: Value) -> Value {
+        Value::Array(vec![2.into(), Value::Bytes(label.into()), subtree])
+    }
+
+    #[test]
+    fn certified_rejection_reads_the_tree() {
+        let tree = Value::Array(vec![
+            1.into(),
+            labeled("status", leaf(b"rejected")),
+            Value::Array(vec![
+                1.into(),
+                labeled("reject_code", leaf(&[5])),
+                labeled("reject_message", leaf(b"canister trapped")),
+            ]),
+        ]);
+        let mut certificate = vec![];
+        ciborium::into_writer(
+            &Value::Map(vec![(Value::Text("tree".into()), tree)]),
+            &mut certificate,
+        )
+        .unwrap();
+        let response = Value::Tag(
+            55799,
+            Box::new(Value::Map(vec![(
+                Value::Text("certificate".into()),
+                Value::Bytes(certificate),
+            )])),
+        );
+        assert_eq!(
+            certified_rejection(&response),
+            Some((CANISTER_ERROR, "canister trapped".to_string()))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! A faucet that gives ICP and cycles to any principal of the local network (`--faucet`).
+//!
+//! The faucet is served by the control API rather than by a canister, so that it needs no Wasm
+//! module and can mint directly. Each principal can receive up to the configured limits over the
+//! lifetime of the launcher process.
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use candid::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use tokio::sync::Mutex;
+
+use crate::{
+    cycles,
+    ledger::{self, AccountId},
+};
+
+pub struct Faucet {
+    /// Maximum ICP per principal, in e8s.
+    max_icp_e8s: u64,
+    /// Maximum cycles per principal.
+    max_cycles: u128,
+    /// ICP (in e8s) and cycles given to each principal so far. Held for the duration of a request.
+    dispensed: Mutex<BTreeMap<Principal, (u64, u128)>>,
+}
+
+impl Faucet {
+    pub fn new(max_icp_e8s: u64, max_cycles: u128) -> Self {
+        Self {
+            max_icp_e8s,
+            max_cycles,
+            dispensed: <_>::default(),
+        }
+    }
+
+    /// Gives `icp_e8s` ICP to the default account of `principal`, and `cycles` cycles to its
+    /// cycles ledger account. Returns the amount of cycles deposited, which may be slightly more.
+    pub async fn dispense(
+        &self,
+        pic: &PocketIc,
+        principal: Principal,
+        icp_e8s: u64,
+        cycles: u128,
+    ) -> anyhow::Result<u128> {
+        let mut dispensed = self.dispensed.lock().await;
+        let (icp_so_far, cycles_so_far) = dispensed.entry(principal).or_default();
+        if icp_so_far.saturating_add(icp_e8s) > self.max_icp_e8s {
+            bail!(
+                "{principal} would exceed the faucet's limit of {} e8s ({} e8s given so far)",
+                self.max_icp_e8s,
+                icp_so_far
+            );
+        }
+        if cycles_so_far.saturating_add(cycles) > self.max_cycles {
+            bail!(
+                "{principal} would exceed the faucet's limit of {} cycles ({} given so far)",
+                self.max_cycles,
+                cycles_so_far
+            );
+        }
+        if icp_e8s > 0 {
+            ledger::mint(pic, &[(AccountId::from_principal(principal), icp_e8s)]).await?;
+            *icp_so_far += icp_e8s;
+        }
+        let mut deposited = 0;
+        if cycles > 0 {
+            deposited = cycles::deposit(pic, principal, cycles).await?;
+            *cycles_so_far += deposited;
+        }
+        Ok(deposited)
+    }
+}
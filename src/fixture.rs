@@ -0,0 +1,200 @@
+//! Fixture packs: reusable network setups for test suites.
+//!
+//! A fixture pack is a directory, meant to be checked into version control, containing:
+//! - `fixture.json`, describing the pack, its canister registry and its identities
+//! - `state.tar.zst`, the network's state in the `export` format
+//! - `identities/<name>.pem`, the keys of identities the state was seeded for (e.g. funded users)
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use candid::Principal;
+use clap::{ArgAction, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{self, Manifest},
+    crypt::Passphrase,
+    identity::{self, IDENTITIES_DIR},
+    registry::{self, RegistryEntry},
+};
+
+const FIXTURE_FILE: &str = "fixture.json";
+const STATE_ARCHIVE: &str = "state.tar.zst";
+
+#[derive(Serialize, Deserialize)]
+pub struct Fixture {
+    pub v: String,
+    /// Version of the launcher that created the pack.
+    pub launcher_version: String,
+    /// Unix timestamp of the pack's creation, in seconds.
+    pub created_at: u64,
+    /// Canister IDs by name.
+    pub canisters: BTreeMap<String, Principal>,
+    /// Principals of the pack's identities by name.
+    pub identities: BTreeMap<String, Principal>,
+}
+
+/// Parses a `NAME=VALUE` pair.
+pub fn parse_named<T: std::str::FromStr>(s: &str) -> Result<(String, T), String>
+where
+    T::Err: std::fmt::Display,
+{
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=VALUE`, got `{s}`"))?;
+    let value = value
+        .parse()
+        .map_err(|e| format!("invalid value for `{name}`: {e}"))?;
+    Ok((name.to_string(), value))
+}
+
+/// Creates a fixture pack at `output` from the state directory of a stopped network.
+/// The registry in `status_dir`, if any, is included along with `canisters`; `identities` are
+/// paths of PEM files to include. The state archive is encrypted with `passphrase` if given.
+pub fn create(
+    state_dir: &Path,
+    status_dir: Option<&Path>,
+    output: &Path,
+    canisters: Vec<(String, Principal)>,
+    identities: Vec<(String, PathBuf)>,
+    passphrase: Option<&Passphrase>,
+) -> anyhow::Result<()> {
+    if output.exists() {
+        bail!("{} already exists", output.display());
+    }
+    let mut fixture = Fixture {
+        v: "1".to_string(),
+        launcher_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock should be after the unix epoch")
+            .as_secs(),
+        canisters: BTreeMap::new(),
+        identities: BTreeMap::new(),
+    };
+    if let Some(status_dir) = status_dir {
+        for (name, entry) in registry::read(status_dir)?.canisters {
+            fixture.canisters.insert(name, entry.canister_id);
+        }
+    }
+    fixture.canisters.extend(canisters);
+    fs::create_dir_all(output.join(IDENTITIES_DIR))
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    for (name, pem) in identities {
+        let principal = identity::load(&pem)?
+            .sender()
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("failed to get principal of identity {name}"))?;
+        fs::copy(
+            &pem,
+            output.join(IDENTITIES_DIR).join(format!("{name}.pem")),
+        )
+        .with_context(|| format!("failed to copy {}", pem.display()))?;
+        fixture.identities.insert(name, principal);
+    }
+    archive::export(
+        state_dir,
+        status_dir,
+        &output.join(STATE_ARCHIVE),
+        passphrase,
+    )?;
+    let mut contents = serde_json::to_string_pretty(&fixture).expect("infallible serialization");
+    contents.push('\n');
+    fs::write(output.join(FIXTURE_FILE), contents).context("failed to write fixture manifest")?;
+    Ok(())
+}
+
+/// Unpacks the fixture pack at `fixture` into `state_dir`. If `status_dir` is given, the pack's
+/// canisters are added to its registry and its identities are copied to `identities/` within it.
+/// Returns the manifest of the pack's state archive.
+pub fn apply(
+    fixture: &Path,
+    state_dir: &Path,
+    status_dir: Option<&Path>,
+    force: bool,
+) -> anyhow::Result<Manifest> {
+    let contents = fs::read_to_string(fixture.join(FIXTURE_FILE))
+        .with_context(|| format!("{} is not a fixture pack", fixture.display()))?;
+    let pack: Fixture = serde_json::from_str(&contents).context("failed to parse fixture")?;
+    if pack.v != "1" {
+        bail!("unsupported fixture version {}", pack.v);
+    }
+    let manifest = archive::import(&fixture.join(STATE_ARCHIVE), state_dir, force)?;
+    if let Some(status_dir) = status_dir {
+        let mut registry = registry::read(status_dir)?;
+        for (name, canister_id) in pack.canisters {
+            registry
+                .canisters
+                .insert(name, RegistryEntry::new(canister_id));
+        }
+        registry::write(status_dir, &registry)?;
+        let identities = status_dir.join(IDENTITIES_DIR);
+        fs::create_dir_all(&identities).context("failed to create identities directory")?;
+        for name in pack.identities.keys() {
+            let file = format!("{name}.pem");
+            fs::copy(
+                fixture.join(IDENTITIES_DIR).join(&file),
+                identities.join(&file),
+            )
+            .with_context(|| format!("failed to copy identity {name}"))?;
+        }
+    }
+    Ok(manifest)
+}
+
+#[derive(Subcommand)]
+pub enum FixtureCommand {
+    /// Creates a fixture pack from the `--state-dir` of a stopped network. Pass `--status-dir` to
+    /// include the network's canister registry.
+    Create {
+        /// Directory to create the fixture pack in.
+        output: PathBuf,
+        /// Canister to record in the pack's registry, as `NAME=ID`.
+        #[arg(long, value_name = "NAME=ID", value_parser = parse_named::<Principal>, action = ArgAction::Append)]
+        canister: Vec<(String, Principal)>,
+        /// Identity to include in the pack, as `NAME=PEM_FILE`.
+        #[arg(long, value_name = "NAME=PEM_FILE", value_parser = parse_named::<PathBuf>, action = ArgAction::Append)]
+        identity: Vec<(String, PathBuf)>,
+    },
+    /// Unpacks a fixture pack into `--state-dir`, and its registry and identities into
+    /// `--status-dir` if given.
+    Apply {
+        /// Directory of the fixture pack.
+        fixture: PathBuf,
+        /// Replaces the contents of `--state-dir` if it is not empty.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Runs `command` on `state_dir`, and on the registry in `status_dir` if given.
+pub fn run_command(
+    state_dir: &Path,
+    status_dir: Option<&Path>,
+    passphrase: Option<&Passphrase>,
+    command: FixtureCommand,
+) -> anyhow::Result<()> {
+    match command {
+        FixtureCommand::Create {
+            output,
+            canister,
+            identity,
+        } => create(
+            state_dir, status_dir, &output, canister, identity, passphrase,
+        ),
+        FixtureCommand::Apply {
+            fixture: fixture_path,
+            force,
+        } => {
+            let manifest = apply(&fixture_path, state_dir, status_dir, force)?;
+            archive::warn_on_version_mismatch(&manifest);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Mainnet fork mode: copies canisters from mainnet into the local network at the same IDs.
+//!
+//! Canister state is only readable by the canister's controllers, and only through canister
+//! snapshots, so the launcher reads the most recent existing snapshot of each canister using a
+//! controller's identity. It never modifies anything on mainnet.
+
+use std::path::Path;
+
+use anyhow::{Context, anyhow, bail};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_agent::Agent;
+use pocket_ic::{common::rest::BlobCompression, nonblocking::PocketIc};
+use serde::Deserialize;
+
+use crate::{
+    canister::{self, SnapshotContents},
+    identity,
+};
+
+/// Cycles given to each imported canister.
+const FORKED_CANISTER_CYCLES: u128 = 100_000_000_000_000;
+
+/// A canister's code and data, as read from mainnet.
+pub struct ForkedCanister {
+    pub canister_id: Principal,
+    pub wasm_module: Vec<u8>,
+    pub stable_memory: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct ListCanisterSnapshotsArgs {
+    canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Snapshot {
+    id: Vec<u8>,
+    taken_at_timestamp: u64,
+}
+
+/// Creates an agent for `url` that signs requests with the identity in the PEM file `identity`.
+pub fn agent(url: &str, identity: &Path) -> anyhow::Result<Agent> {
+    Agent::builder()
+        .with_url(url)
+        .with_boxed_identity(identity::load(identity)?)
+        .build()
+        .context("failed to create agent")
+}
+
+/// Reads the Wasm module and stable memory of `canister_id` from its most recent snapshot.
+pub async fn fetch(agent: &Agent, canister_id: Principal) -> anyhow::Result<ForkedCanister> {
+    let snapshots = management_call(
+        agent,
+        canister_id,
+        "list_canister_snapshots",
+        Encode!(&ListCanisterSnapshotsArgs { canister_id })?,
+    )
+    .await?;
+    let snapshots = Decode!(&snapshots, Vec<Snapshot>)?;
+    let Some(snapshot) = snapshots.into_iter().max_by_key(|s| s.taken_at_timestamp) else {
+        bail!(
+            "canister {canister_id} has no snapshots on mainnet; take one first (e.g. `icp canister snapshot create`)"
+        );
+    };
+    let SnapshotContents {
+        wasm_module,
+        stable_memory,
+    } = canister::read_snapshot(
+        |method, arg| management_call(agent, canister_id, method, arg),
+        canister_id,
+        &snapshot.id,
+    )
+    .await?;
+    Ok(ForkedCanister {
+        canister_id,
+        wasm_module,
+        stable_memory,
+    })
+}
+
+/// Installs a forked canister on the local network at its mainnet ID.
+///
+/// The canister is installed with an empty init argument, its stable memory is replaced with the
+/// mainnet copy, and it is then upgraded so that `post_upgrade` restores state from stable memory.
+/// Heap state that the canister does not persist across upgrades cannot be imported.
+pub async fn install(pic: &PocketIc, canister: ForkedCanister) -> anyhow::Result<()> {
+    let ForkedCanister {
+        canister_id,
+        wasm_module,
+        stable_memory,
+    } = canister;
+    pic.create_canister_with_id(None, None, canister_id)
+        .await
+        .map_err(|e| anyhow!("failed to create canister {canister_id}: {e}"))?;
+    pic.add_cycles(canister_id, FORKED_CANISTER_CYCLES).await;
+    pic.install_canister(canister_id, wasm_module.clone(), Encode!()?, None)
+        .await;
+    pic.set_stable_memory(canister_id, stable_memory, BlobCompression::NoCompression)
+        .await;
+    pic.upgrade_canister(canister_id, wasm_module, Encode!()?, None)
+        .await
+        .map_err(|e| anyhow!("failed to upgrade canister {canister_id}: {e}"))?;
+    Ok(())
+}
+
+async fn management_call(
+    agent: &Agent,
+    canister_id: Principal,
+    method: &str,
+    arg: Vec<u8>,
+) -> anyhow::Result<Vec<u8>> {
+    agent
+        .update(&Principal::management_canister(), method)
+        .with_effective_canister_id(canister_id)
+        .with_arg(arg)
+        .call_and_wait()
+        .await
+        .with_context(|| format!("{method} failed for canister {canister_id}"))
+}
@@ -0,0 +1,856 @@
+//! The launcher's gateway, served on the gateway port in front of PocketIC's whenever a feature
+//! needs to see or change the gateway's traffic.
+//!
+//! PocketIC's gateway then listens on an internal loopback port, and the launcher serves the
+//! gateway port in its place, over HTTP/1.1 or HTTP/2, in the clear or over TLS (see
+//! [`crate::tls`]), and on a Unix socket with `--gateway-socket`. Each request passes through the
+//! stages in [`STAGES`] in order: a stage either answers the request itself or hands it on with
+//! [`Next::run`], and may change the response on its way back, and [`forward`] sends what passes
+//! all of them to PocketIC's gateway.
+//!
+//! Bodies are streamed both ways, so that large assets and slow responses pass through as they
+//! come. A stage only reads a body whole when it inspects it, and only for the requests it applies
+//! to: calls for the limits and recording, error responses for error pages, compressible responses
+//! for compression, and so on.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    error::Error,
+    fs::{self, File},
+    future::Future,
+    net::IpAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::Context as _;
+use candid::Principal;
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Body, Bytes, Frame, Incoming, SizeHint},
+    header::{CONTENT_TYPE, HOST, HeaderName, HeaderValue, RETRY_AFTER, SET_COOKIE},
+    http::request,
+    service::service_fn,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener},
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    access_token::{self, Access},
+    cache::{CACHE_STATUS, Lookup},
+    control::{ACCEPT_BACKOFF, Control},
+    costs, error_pages, health, ingress, limits, local_assets, passthrough, tls, websocket,
+};
+
+/// Body of the requests and responses passing through the gateway.
+pub type GatewayBody = BoxBody<Bytes, Box<dyn Error + Send + Sync>>;
+
+/// A step of the gateway, which answers `req` or hands it on to `next`.
+type Stage = for<'a> fn(Request<GatewayBody>, Next<'a>) -> Handled<'a>;
+
+/// The response a stage answers with.
+type Handled<'a> = Pin<Box<dyn Future<Output = Response<GatewayBody>> + Send + 'a>>;
+
+/// The gateway's stages, in the order requests pass through them.
+const STAGES: &[Stage] = &[
+    check_health,
+    log,
+    authorize,
+    rewrite_headers,
+    limit_rate,
+    upgrade_websocket,
+    serve_local,
+    pass_through,
+    cache,
+    stall,
+    reject_ingress,
+    check_calls,
+    record_http,
+    compress,
+    render_error_pages,
+    track_errors,
+    record_ingress,
+    inject_errors,
+];
+
+struct Gateway {
+    /// Base URL of PocketIC's gateway.
+    upstream: String,
+    client: reqwest::Client,
+    control: Arc<Control>,
+    /// The recording, if ingress is recorded.
+    recording: Option<Mutex<File>>,
+}
+
+/// The stages after the current one, for a request from `peer`, which is `None` for Unix sockets.
+#[derive(Clone, Copy)]
+struct Next<'a> {
+    gateway: &'a Gateway,
+    peer: Option<IpAddr>,
+    stages: &'a [Stage],
+}
+
+impl<'a> Next<'a> {
+    /// Hands `req` to the next stage, or forwards it to PocketIC's gateway after the last.
+    fn run(self, req: Request<GatewayBody>) -> Handled<'a> {
+        match self.stages.split_first() {
+            Some((stage, stages)) => stage(req, Next { stages, ..self }),
+            None => Box::pin(forward(self.gateway, req)),
+        }
+    }
+}
+
+/// Serves the gateway on `listener`, also over TLS if there is an `acceptor`, and on `socket`,
+/// forwarding requests to PocketIC's gateway at `upstream`, appending the calls it accepts to
+/// `recording` and counting rejections in `control`'s error tracker, until the task is aborted.
+pub async fn serve(
+    listener: Option<TcpListener>,
+    acceptor: Option<TlsAcceptor>,
+    socket: Option<UnixListener>,
+    upstream: String,
+    control: Arc<Control>,
+    recording: Option<File>,
+) {
+    let gateway = Arc::new(Gateway {
+        upstream,
+        client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("valid client configuration"),
+        control,
+        recording: recording.map(Mutex::new),
+    });
+    let tcp = async {
+        let Some(listener) = listener else {
+            return;
+        };
+        loop {
+            let Ok((stream, peer)) = listener.accept().await else {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            };
+            match &acceptor {
+                Some(acceptor) => {
+                    serve_tcp(Arc::clone(&gateway), acceptor.clone(), stream, peer.ip())
+                }
+                None => serve_connection(Arc::clone(&gateway), stream, Some(peer.ip())),
+            }
+        }
+    };
+    let unix = async {
+        let Some(socket) = socket else {
+            return;
+        };
+        loop {
+            let Ok((stream, _)) = socket.accept().await else {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            };
+            serve_connection(Arc::clone(&gateway), stream, None);
+        }
+    };
+    tokio::join!(tcp, unix);
+}
+
+/// Serves the connection `stream` from `peer` over TLS if it starts with a TLS handshake, and in
+/// the clear otherwise.
+fn serve_tcp(gateway: Arc<Gateway>, acceptor: TlsAcceptor, stream: TcpStream, peer: IpAddr) {
+    tokio::spawn(async move {
+        if let Some(stream) = tls::accept(Some(&acceptor), stream).await {
+            serve_connection(gateway, stream, Some(peer));
+        }
+    });
+}
+
+/// Serves the connection `stream` from `peer`, which is `None` for Unix sockets, over HTTP/1.1 or
+/// HTTP/2, whichever the client speaks.
+fn serve_connection<S>(gateway: Arc<Gateway>, stream: S, peer: Option<IpAddr>)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    tokio::spawn(async move {
+        let service = service_fn(move |req| handle(Arc::clone(&gateway), req, peer));
+        _ = auto::Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .await;
+    });
+}
+
+/// Binds a Unix socket at `path`, replacing the socket a previous run left behind.
+pub fn bind_socket(path: &Path) -> anyhow::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))
+}
+
+/// Passes `req` from `peer` through the stages.
+async fn handle(
+    gateway: Arc<Gateway>,
+    req: Request<Incoming>,
+    peer: Option<IpAddr>,
+) -> Result<Response<GatewayBody>, Infallible> {
+    let next = Next {
+        gateway: &gateway,
+        peer,
+        stages: STAGES,
+    };
+    Ok(next
+        .run(req.map(|body| body.map_err(Into::into).boxed()))
+        .await)
+}
+
+/// Answers `--gateway-health` probes, before the access log and token, so that probes neither
+/// flood the log nor need the token.
+fn check_health<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Gateway {
+            upstream,
+            client,
+            control,
+            ..
+        } = next.gateway;
+        if control.health_endpoint
+            && req.uri().path() == health::HEALTH_PATH
+            && matches!(*req.method(), Method::GET | Method::HEAD)
+        {
+            return boxed(health::check(client, upstream, control).await);
+        }
+        next.run(req).await
+    })
+}
+
+/// Writes the request to the `--access-log` once its response has been sent.
+fn log<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        if next.gateway.control.access_log.is_none() {
+            return next.run(req).await;
+        }
+        let received = SystemTime::now();
+        let start = Instant::now();
+        let (parts, body) = req.into_parts();
+        let logged = parts.clone();
+        let response = next.run(Request::from_parts(parts, body)).await;
+        let control = Arc::clone(&next.gateway.control);
+        let peer = next.peer;
+        let status = response.status().as_u16();
+        response.map(|body| {
+            Logged {
+                body,
+                sent: 0,
+                on_end: Some(Box::new(move |size| {
+                    if let Some(access_log) = &control.access_log {
+                        access_log.log(&logged, peer, received, status, size, start.elapsed());
+                    }
+                })),
+            }
+            .boxed()
+        })
+    })
+}
+
+/// Requires the `--access-token`, turning a token given as a query parameter into a cookie, and
+/// removes it from the request.
+fn authorize<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(access_token) = &next.gateway.control.access_token else {
+            return next.run(req).await;
+        };
+        let access = access_token.check(req.headers(), req.uri(), next.peer);
+        if access == Access::Denied {
+            return boxed(access_token::denied());
+        }
+        let (mut parts, body) = req.into_parts();
+        access_token.strip(&mut parts);
+        let mut response = next.run(Request::from_parts(parts, body)).await;
+        if access == Access::GrantedByQuery
+            && let Some(cookie) = access_token.cookie()
+        {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+        response
+    })
+}
+
+/// Applies the `--gateway-headers` rules to the request and its response.
+fn rewrite_headers<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(rules) = &next.gateway.control.header_rules else {
+            return next.run(req).await;
+        };
+        let (mut parts, body) = req.into_parts();
+        let original = parts.clone();
+        rules.rewrite_request(&original, next.peer, &mut parts.headers);
+        let mut response = next.run(Request::from_parts(parts, body)).await;
+        rules.rewrite_response(&original, response.headers_mut());
+        response
+    })
+}
+
+/// Turns away requests over the `--rate-limit`.
+fn limit_rate<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        if let Some(rate_limit) = &next.gateway.control.rate_limit
+            && !rate_limit.admit(next.peer)
+        {
+            let mut response = reject(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate limit exceeded (--rate-limit); retry later".to_string(),
+            );
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+            return response;
+        }
+        next.run(req).await
+    })
+}
+
+/// Hands WebSocket upgrades to the `--websocket-upstream`, once the access log, token, header
+/// rules and rate limit have seen them.
+fn upgrade_websocket<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        if let Some(upstream) = next.gateway.control.websocket_upstream
+            && websocket::is_upgrade(&req)
+        {
+            return match websocket::proxy(upstream, req).await {
+                Ok(response) => boxed(response),
+                Err(e) => reject(StatusCode::BAD_GATEWAY, format!("{e:#}")),
+            };
+        }
+        next.run(req).await
+    })
+}
+
+/// Serves the assets of canisters with a `--serve-local` directory from it.
+fn serve_local<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let control = &next.gateway.control;
+        let (parts, body) = req.into_parts();
+        if matches!(parts.method, Method::GET | Method::HEAD)
+            && !parts.uri.path().starts_with("/api/")
+            && let Some(dir) = local_dir(control, &parts).await
+        {
+            return boxed(local_assets::serve(&dir, &parts, control.compression.as_ref()).await);
+        }
+        next.run(Request::from_parts(parts, body)).await
+    })
+}
+
+/// Sends the requests for `--mainnet-passthrough` canisters to mainnet.
+fn pass_through<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(passthrough) = &next.gateway.control.passthrough else {
+            return next.run(req).await;
+        };
+        let (parts, body) = req.into_parts();
+        let url = passthrough.target(&parts, &*next.gateway.control.aliases.lock().await);
+        match url {
+            Some(url) => passthrough::forward(&next.gateway.client, &parts, body, url).await,
+            None => next.run(Request::from_parts(parts, body)).await,
+        }
+    })
+}
+
+/// Answers from the `--gateway-cache`, and stores the certified responses it may keep.
+fn cache<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(cache) = &next.gateway.control.cache else {
+            return next.run(req).await;
+        };
+        let (parts, body) = req.into_parts();
+        let (cache_status, storable) = match cache.lookup(&parts) {
+            Lookup::Hit(response) => return boxed(response),
+            Lookup::Miss => ("MISS", true),
+            Lookup::Bypass => ("BYPASS", true),
+            Lookup::Uncacheable => ("BYPASS", false),
+        };
+        let stored = parts.clone();
+        let response = next.run(Request::from_parts(parts, body)).await;
+        let (mut response_parts, body) = response.into_parts();
+        let body = if storable && cache.admits(&response_parts, body.size_hint().lower()) {
+            match read_response(body).await {
+                Ok(body) => {
+                    cache.store(&stored, &response_parts, &body);
+                    full(body)
+                }
+                Err(response) => return response,
+            }
+        } else {
+            body
+        };
+        response_parts
+            .headers
+            .insert(CACHE_STATUS, HeaderValue::from_static(cache_status));
+        Response::from_parts(response_parts, body)
+    })
+}
+
+/// Holds requests to subnets stalled by `stall-subnet` until the stall ends.
+fn stall<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let control = &next.gateway.control;
+        if let Some(stalls) = &control.stalls {
+            stalls.wait(&control.pic, req.uri().path()).await;
+        }
+        next.run(req).await
+    })
+}
+
+/// Delays or rejects a share of calls, for `--ingress-delay-rate` and `--ingress-reject-rate`.
+fn reject_ingress<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        if let Some(faults) = &next.gateway.control.ingress_faults
+            && is_call(&req)
+            && faults.inject().await
+        {
+            return reject(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "ingress message rejected by the launcher's fault injection (--ingress-reject-rate)"
+                    .to_string(),
+            );
+        }
+        next.run(req).await
+    })
+}
+
+/// Rejects the calls `--strict-limits`, `--mainnet-costs` or `--max-canister-creations` forbid.
+fn check_calls<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let control = &next.gateway.control;
+        if !is_call(&req)
+            || !(control.strict_limits
+                || control.mainnet_costs
+                || control.creation_throttle.is_some())
+        {
+            return next.run(req).await;
+        }
+        let (parts, body) = req.into_parts();
+        let envelope = match read_request(body).await {
+            Ok(envelope) => envelope,
+            Err(response) => return response,
+        };
+        if control.strict_limits
+            && let Err(message) = limits::check(&control.pic, &envelope).await
+        {
+            return reject(StatusCode::PAYLOAD_TOO_LARGE, message);
+        }
+        if control.mainnet_costs
+            && let Err(message) = costs::check(&envelope)
+        {
+            return reject(StatusCode::BAD_REQUEST, message);
+        }
+        if let Some(throttle) = &control.creation_throttle
+            && !throttle.admit(&envelope)
+        {
+            return reject(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "canister creation throttled: {} canisters were already created in the last second (--max-canister-creations); retry later",
+                    throttle.limit
+                ),
+            );
+        }
+        next.run(Request::from_parts(parts, full(envelope))).await
+    })
+}
+
+/// Records the exchange for `--record-http`.
+fn record_http<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(http_recording) = &next.gateway.control.http_recording else {
+            return next.run(req).await;
+        };
+        let (parts, body) = req.into_parts();
+        let body = match read_request(body).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let recorded = parts.clone();
+        let started = SystemTime::now();
+        let sent = Instant::now();
+        let response = next
+            .run(Request::from_parts(parts, full(body.clone())))
+            .await;
+        let (response_parts, response_body) = response.into_parts();
+        let response_body = match read_response(response_body).await {
+            Ok(response_body) => response_body,
+            Err(response) => return response,
+        };
+        http_recording.record(
+            &recorded,
+            &body,
+            &response_parts,
+            &response_body,
+            started,
+            sent.elapsed(),
+        );
+        Response::from_parts(response_parts, full(response_body))
+    })
+}
+
+/// Compresses responses for `--compress`.
+fn compress<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(compression) = &next.gateway.control.compression else {
+            return next.run(req).await;
+        };
+        let request_headers = req.headers().clone();
+        let response = next.run(req).await;
+        if !compression.applies(&request_headers, response.status(), response.headers()) {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let body = match read_response(body).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let body = compression.apply(&request_headers, parts.status, &mut parts.headers, body);
+        Response::from_parts(parts, full(body))
+    })
+}
+
+/// Replaces the gateway's error responses for canisters by `--gateway-error-pages`.
+fn render_error_pages<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let control = &next.gateway.control;
+        if !control.error_pages {
+            return next.run(req).await;
+        }
+        let (parts, body) = req.into_parts();
+        let original = parts.clone();
+        let response = next.run(Request::from_parts(parts, body)).await;
+        if !error_pages::applies(&original, response.status()) {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let body = match read_response(body).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let canister_id = local_assets::canister_id(&original, &*control.aliases.lock().await);
+        let exists = match canister_id {
+            Some(canister_id) => control.pic.canister_exists(canister_id).await,
+            None => false,
+        };
+        let body = error_pages::render(
+            &original,
+            canister_id,
+            exists,
+            parts.status,
+            &mut parts.headers,
+            body,
+        );
+        Response::from_parts(parts, full(body))
+    })
+}
+
+/// Counts the rejections in the gateway's responses for `--error-report`.
+fn track_errors<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(errors) = &next.gateway.control.errors else {
+            return next.run(req).await;
+        };
+        if req.method() != Method::POST || !req.uri().path().starts_with("/api/") {
+            return next.run(req).await;
+        }
+        let path = req.uri().path().to_string();
+        let response = next.run(req).await;
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let body = match read_response(body).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        errors.observe(&path, &body);
+        Response::from_parts(parts, full(body))
+    })
+}
+
+/// Records the calls the gateway accepts for `--record-ingress`.
+fn record_ingress<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let Some(recording) = &next.gateway.recording else {
+            return next.run(req).await;
+        };
+        if !is_call(&req) {
+            return next.run(req).await;
+        }
+        let (parts, body) = req.into_parts();
+        let envelope = match read_request(body).await {
+            Ok(envelope) => envelope,
+            Err(response) => return response,
+        };
+        let path = parts.uri.path().to_string();
+        let response = next
+            .run(Request::from_parts(parts, full(envelope.clone())))
+            .await;
+        // v2 calls are accepted with 202, v3 and v4 calls with 202 or, once executed, 200
+        if matches!(response.status(), StatusCode::OK | StatusCode::ACCEPTED) {
+            let time = next.gateway.control.pic.get_time().await;
+            ingress::record(recording, time, &path, &envelope);
+        }
+        response
+    })
+}
+
+/// Fails a share of the gateway's responses, for `--inject-error-rate`.
+fn inject_errors<'a>(req: Request<GatewayBody>, next: Next<'a>) -> Handled<'a> {
+    Box::pin(async move {
+        let response = next.run(req).await;
+        if let Some(faults) = &next.gateway.control.response_faults
+            && faults.inject().await
+        {
+            return reject(
+                StatusCode::BAD_GATEWAY,
+                "response failed by the launcher's fault injection (--inject-error-rate)"
+                    .to_string(),
+            );
+        }
+        response
+    })
+}
+
+/// Sends `req` to PocketIC's gateway, with the canister's host for an aliased one.
+async fn forward(gateway: &Gateway, req: Request<GatewayBody>) -> Response<GatewayBody> {
+    let (parts, body) = req.into_parts();
+    let path_and_query = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let mut request = gateway
+        .client
+        .request(
+            parts.method.clone(),
+            format!("{}{path_and_query}", gateway.upstream),
+        )
+        .body(reqwest::Body::wrap(body));
+    // HTTP/2 requests carry the host in the URI's authority rather than a Host header, and
+    // PocketIC's gateway is reached over HTTP/1.1
+    let authority = parts.uri.authority().map(|authority| authority.as_str());
+    let host = match parts.headers.get(HOST) {
+        Some(host) => host.to_str().ok(),
+        None => authority,
+    };
+    let aliased_host = match host {
+        Some(host) => alias_host(host, &*gateway.control.aliases.lock().await),
+        None => None,
+    };
+    for (name, value) in &parts.headers {
+        // an aliased host is replaced by the canister's
+        if is_hop_by_hop(name) || (name == HOST && aliased_host.is_some()) {
+            continue;
+        }
+        request = request.header(name, value);
+    }
+    if let Some(host) = aliased_host {
+        request = request.header(HOST, host);
+    } else if !parts.headers.contains_key(HOST)
+        && let Some(host) = authority.and_then(|host| HeaderValue::from_str(host).ok())
+    {
+        request = request.header(HOST, host);
+    }
+    match request.send().await {
+        Ok(response) => stream(response),
+        Err(e) => reject(
+            StatusCode::BAD_GATEWAY,
+            format!("failed to reach the gateway: {e}"),
+        ),
+    }
+}
+
+/// A response body that reports how many bytes of it were sent once it ends, fails or is
+/// dropped, for the access log.
+struct Logged {
+    body: GatewayBody,
+    sent: usize,
+    on_end: Option<Box<dyn FnOnce(usize) + Send + Sync>>,
+}
+
+impl Body for Logged {
+    type Data = Bytes;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.body).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => self.sent += frame.data_ref().map_or(0, Bytes::len),
+            _ => self.end(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl Logged {
+    fn end(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(self.sent);
+        }
+    }
+}
+
+impl Drop for Logged {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+/// Reads the whole request `body`, for a stage that inspects it.
+async fn read_request(body: GatewayBody) -> Result<Bytes, Response<GatewayBody>> {
+    match body.collect().await {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) => Err(reject(
+            StatusCode::BAD_REQUEST,
+            format!("failed to read request body: {e}"),
+        )),
+    }
+}
+
+/// Reads the whole response `body`, for a stage that inspects it.
+async fn read_response(body: GatewayBody) -> Result<Bytes, Response<GatewayBody>> {
+    match body.collect().await {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) => Err(reject(
+            StatusCode::BAD_GATEWAY,
+            format!("failed to read the gateway's response: {e}"),
+        )),
+    }
+}
+
+/// A body that was read whole.
+fn full(body: Bytes) -> GatewayBody {
+    Full::new(body).map_err(|never| match never {}).boxed()
+}
+
+/// Turns a response built whole by a feature into one the gateway serves.
+pub fn boxed(response: Response<Full<Bytes>>) -> Response<GatewayBody> {
+    response.map(|body| body.map_err(|never| match never {}).boxed())
+}
+
+/// Turns `response` from an upstream into one the gateway serves, streaming its body.
+pub fn stream(response: reqwest::Response) -> Response<GatewayBody> {
+    let mut response = Response::<reqwest::Body>::from(response);
+    let hop_by_hop = response
+        .headers()
+        .keys()
+        .filter(|name| is_hop_by_hop(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in hop_by_hop {
+        response.headers_mut().remove(name);
+    }
+    response.map(|body| body.map_err(Into::into).boxed())
+}
+
+fn reject(status: StatusCode, message: String) -> Response<GatewayBody> {
+    boxed(plain_response(status, message))
+}
+
+/// Whether `req` is an update call.
+fn is_call<B>(req: &Request<B>) -> bool {
+    req.method() == Method::POST
+        && matches!(
+            req.uri().path().split('/').collect::<Vec<_>>()[..],
+            ["", "api", "v2" | "v3" | "v4", "canister", _, "call"]
+        )
+}
+
+/// Whether `name` describes a single connection rather than the message, so it isn't forwarded.
+/// Lengths are recomputed for the forwarded bodies.
+pub fn is_hop_by_hop(name: &HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "connection" | "keep-alive" | "transfer-encoding" | "upgrade" | "content-length"
+    )
+}
+
+pub fn plain_response(status: StatusCode, message: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Full::new(Bytes::from(message)))
+        .expect("valid response")
+}
+
+/// Returns the directory `--serve-local` serves the request described by `parts` from, if any.
+async fn local_dir(control: &Control, parts: &request::Parts) -> Option<PathBuf> {
+    let local_assets = control.local_assets.lock().await;
+    if local_assets.is_empty() {
+        return None;
+    }
+    let canister_id = local_assets::canister_id(parts, &*control.aliases.lock().await)?;
+    local_assets.get(&canister_id).cloned()
+}
+
+/// Parses an alias of the form `ALIAS=CANISTER`, where `CANISTER` is a canister ID or name, e.g.
+/// `myapp=frontend`.
+pub fn parse_alias(s: &str) -> Result<(String, String), String> {
+    let (alias, canister) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `ALIAS=CANISTER`, got `{s}`"))?;
+    if alias.is_empty()
+        || !alias
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "invalid alias `{alias}`, expected lowercase letters, digits and dashes"
+        ));
+    }
+    Ok((alias.to_string(), canister.to_string()))
+}
+
+/// Returns the host the gateway serves the canister aliased in `host` at, if `host` is
+/// `<alias>.localhost`, with or without a port.
+fn alias_host(host: &str, aliases: &BTreeMap<String, Principal>) -> Option<HeaderValue> {
+    let (name, port) = match host.split_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    let canister_id = aliases.get(name.strip_suffix(".localhost")?)?;
+    let host = match port {
+        Some(port) => format!("{canister_id}.localhost:{port}"),
+        None => format!("{canister_id}.localhost"),
+    };
+    HeaderValue::from_str(&host).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_alias_checks_the_alias() {
+        assert_eq!(
+            parse_alias("my-app2=frontend"),
+            Ok(("my-app2".to_string(), "frontend".to_string()))
+        );
+        for s in ["myapp", "=frontend", "MyApp=frontend", "my.app=frontend"] {
+            assert!(parse_alias(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
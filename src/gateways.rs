@@ -0,0 +1,190 @@
+//! Additional HTTP gateways (`--gateway`), so that setups with several boundary domains, such as
+//! an app and a raw domain or a domain per brand, can be tested locally.
+//!
+//! Each is a gateway of the PocketIC server forwarding to the network's instance, with its own port,
+//! domains and, optionally, TLS certificate. While the launcher serves the main gateway port (see
+//! [`crate::gateway`]), it serves theirs the same way, in front of a PocketIC gateway for their
+//! domains on loopback, so that the access token, rate limits and faults apply to them too; only
+//! `--record-ingress` is limited to the main gateway. They stop with the server.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use pocket_ic::{
+    common::rest::{CreateHttpGatewayResponse, HttpGatewayBackend, HttpGatewayConfig, HttpsConfig},
+    nonblocking::PocketIc,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{control::Control, gateway, tls};
+
+/// A gateway to start besides the main one.
+#[derive(Clone)]
+pub struct GatewaySpec {
+    /// Port to listen on, or a random one if `None`.
+    pub port: Option<u16>,
+    pub domains: Vec<String>,
+    /// Certificate and key to serve HTTPS with, in PEM files.
+    pub https: Option<(PathBuf, PathBuf)>,
+}
+
+/// A gateway started besides the main one, as recorded in the status file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GatewayInfo {
+    pub port: u16,
+    pub domains: Vec<String>,
+    pub https: bool,
+}
+
+/// Parses a gateway of the form `[port=PORT][,domain=DOMAIN]...[,https,cert=PEM,key=PEM]`, e.g.
+/// `port=8443,domain=myapp.test,https,cert=cert.pem,key=key.pem`. Domains default to
+/// `localhost`.
+pub fn parse_gateway(s: &str) -> Result<GatewaySpec, String> {
+    let mut port = None;
+    let mut domains = vec![];
+    let mut https = false;
+    let mut cert = None;
+    let mut key = None;
+    for option in s.split(',') {
+        match option.split_once('=') {
+            Some(("port", value)) => {
+                port = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid port `{value}`: {e}"))?,
+                );
+            }
+            Some(("domain", value)) => domains.push(value.to_string()),
+            Some(("cert", value)) => cert = Some(PathBuf::from(value)),
+            Some(("key", value)) => key = Some(PathBuf::from(value)),
+            None if option == "https" => https = true,
+            _ => {
+                return Err(format!(
+                    "unknown gateway option `{option}`, expected `port=PORT`, `domain=DOMAIN`, `https`, `cert=PEM` or `key=PEM`"
+                ));
+            }
+        }
+    }
+    if domains.is_empty() {
+        domains.push("localhost".to_string());
+    }
+    let https = match (https, cert, key) {
+        (false, None, None) => None,
+        (true, Some(cert), Some(key)) => Some((cert, key)),
+        (true, _, _) => return Err("`https` requires `cert=PEM` and `key=PEM`".to_string()),
+        (false, _, _) => return Err("`cert` and `key` require `https`".to_string()),
+    };
+    Ok(GatewaySpec {
+        port,
+        domains,
+        https,
+    })
+}
+
+/// Starts the gateway `spec` on the PocketIC server of `pic`, forwarding to its instance and
+/// listening on `ip_addr`.
+pub async fn start(
+    pic: &PocketIc,
+    spec: &GatewaySpec,
+    ip_addr: Option<IpAddr>,
+) -> anyhow::Result<GatewayInfo> {
+    let config = HttpGatewayConfig {
+        ip_addr: ip_addr.map(|ip| ip.to_string()),
+        port: spec.port,
+        forward_to: HttpGatewayBackend::PocketIcInstance(pic.instance_id),
+        domains: Some(spec.domains.clone()),
+        https_config: spec.https.as_ref().map(|(cert, key)| HttpsConfig {
+            cert_path: cert.display().to_string(),
+            key_path: key.display().to_string(),
+        }),
+    };
+    let url = pic
+        .get_server_url()
+        .join("/http_gateway")
+        .expect("valid url");
+    let response = Client::new()
+        .post(url)
+        .json(&config)
+        .send()
+        .await
+        .context("failed to send gateway config to pocket-ic")?
+        .error_for_status()
+        .context("failed to start gateway")?
+        .json::<CreateHttpGatewayResponse>()
+        .await
+        .context("failed to parse pocket-ic's gateway response")?;
+    match response {
+        CreateHttpGatewayResponse::Created(info) => Ok(GatewayInfo {
+            port: info.port,
+            domains: spec.domains.clone(),
+            https: spec.https.is_some(),
+        }),
+        CreateHttpGatewayResponse::Error { message } => {
+            bail!("failed to start gateway: {message}")
+        }
+    }
+}
+
+/// Binds the port of the gateway `spec` on `ip_addr`, for the launcher to serve it with
+/// [`serve`], along with the TLS acceptor it serves HTTPS with.
+pub async fn bind(
+    spec: &GatewaySpec,
+    ip_addr: IpAddr,
+) -> anyhow::Result<(TcpListener, Option<TlsAcceptor>)> {
+    let listener = TcpListener::bind((ip_addr, spec.port.unwrap_or(0)))
+        .await
+        .with_context(|| format!("failed to bind the port of gateway {}", spec.domains[0]))?;
+    let acceptor = spec
+        .https
+        .as_ref()
+        .map(|(cert, key)| tls::acceptor(cert, key))
+        .transpose()?;
+    Ok((listener, acceptor))
+}
+
+/// Serves the gateway `spec` on `listener` through the launcher's gateway, in front of a PocketIC
+/// gateway for its domains on loopback, until the returned task is aborted.
+pub async fn serve(
+    pic: &PocketIc,
+    spec: &GatewaySpec,
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    control: Arc<Control>,
+) -> anyhow::Result<(GatewayInfo, JoinHandle<()>)> {
+    let internal = GatewaySpec {
+        port: None,
+        domains: spec.domains.clone(),
+        https: None,
+    };
+    let upstream = start(pic, &internal, Some(Ipv4Addr::LOCALHOST.into())).await?;
+    let info = GatewayInfo {
+        port: listener
+            .local_addr()
+            .context("failed to get gateway address")?
+            .port(),
+        domains: spec.domains.clone(),
+        https: spec.https.is_some(),
+    };
+    let task = tokio::spawn(gateway::serve(
+        Some(listener),
+        acceptor,
+        None,
+        format!("http://127.0.0.1:{}", upstream.port),
+        control,
+        None,
+    ));
+    Ok((info, task))
+}
+
+/// Returns the URL of `gateway` at its first domain.
+pub fn url(gateway: &GatewayInfo) -> String {
+    let scheme = if gateway.https { "https" } else { "http" };
+    format!("{scheme}://{}:{}", gateway.domains[0], gateway.port)
+}
@@ -0,0 +1,549 @@
+//! Interaction with NNS governance: seeding test neurons (`--test-neuron`), adjusting governance
+//! for local use (`--nns-instant-execution`, `--nns-neuron-minimum-stake`), and submitting and
+//! voting on proposals (`proposal submit`).
+//!
+//! Neurons are created the way wallets create them: ICP is minted to the neuron's staking
+//! subaccount of the governance canister, the neuron is claimed for its controller, and its
+//! dissolve delay is increased on the controller's behalf. Neurons start with no age bonus, since
+//! their age can only grow as the network's time passes.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, anyhow, bail};
+use candid::{CandidType, Decode, Encode, Principal, types::value::IDLValue};
+use clap::{ArgAction, Subcommand};
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    control, duration,
+    ledger::{self, AccountId},
+    registry,
+    sns::CreateServiceNervousSystem,
+};
+
+/// ID of the NNS governance canister.
+pub const GOVERNANCE_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+
+/// A neuron to create at startup.
+#[derive(Clone)]
+pub struct TestNeuron {
+    pub controller: Principal,
+    pub stake_e8s: u64,
+    pub dissolve_delay: Duration,
+}
+
+/// Parses a test neuron of the form `PRINCIPAL:STAKE:DISSOLVE_DELAY`, e.g. `2vxsx-fae:100:365d`.
+pub fn parse_test_neuron(s: &str) -> Result<TestNeuron, String> {
+    let [controller, stake, dissolve_delay] = s.split(':').collect::<Vec<_>>()[..] else {
+        return Err(format!(
+            "expected `PRINCIPAL:STAKE:DISSOLVE_DELAY`, got `{s}`"
+        ));
+    };
+    Ok(TestNeuron {
+        controller: Principal::from_text(controller)
+            .map_err(|e| format!("invalid principal: {e}"))?,
+        stake_e8s: ledger::parse_icp(stake)?,
+        dissolve_delay: duration::parse_duration(dissolve_delay)?,
+    })
+}
+
+#[derive(CandidType)]
+struct ClaimOrRefreshNeuronFromAccount {
+    controller: Option<Principal>,
+    memo: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ClaimOrRefreshNeuronFromAccountResponse {
+    result: Option<ClaimOrRefreshResult>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum ClaimOrRefreshResult {
+    Error(IDLValue),
+    NeuronId(NeuronId),
+}
+
+#[derive(CandidType, Deserialize)]
+struct NeuronId {
+    id: u64,
+}
+
+/// The action of a proposal submitted by `proposal submit`.
+#[derive(Serialize, Deserialize)]
+pub enum ProposalAction {
+    Motion {
+        text: String,
+    },
+    /// Elects a replica (GuestOS) version, allowing subnets to be upgraded to it.
+    BlessReplicaVersion {
+        version: String,
+        sha256: String,
+        urls: Vec<String>,
+    },
+    /// Calls an NNS canister method by its `NnsFunction` number, with a Candid-encoded payload.
+    NnsFunction {
+        function: i32,
+        payload: Vec<u8>,
+    },
+    /// Creates an SNS; only submitted by `sns launch`.
+    #[serde(skip)]
+    CreateServiceNervousSystem(Box<CreateServiceNervousSystem>),
+    /// Changes network economics; zero amounts keep their current values.
+    ManageNetworkEconomics {
+        neuron_minimum_stake_e8s: u64,
+        reject_cost_e8s: u64,
+    },
+}
+
+/// `NnsFunction::ReviseElectedGuestosVersions`.
+const NNS_FUNCTION_REVISE_ELECTED_GUESTOS_VERSIONS: i32 = 38;
+
+#[derive(CandidType)]
+struct ReviseElectedGuestosVersionsPayload {
+    replica_version_to_elect: Option<String>,
+    release_package_sha256_hex: Option<String>,
+    release_package_urls: Vec<String>,
+    replica_versions_to_unelect: Vec<String>,
+}
+
+#[derive(CandidType)]
+struct Proposal {
+    title: Option<String>,
+    summary: String,
+    url: String,
+    action: Option<Action>,
+}
+
+#[derive(CandidType)]
+enum Action {
+    Motion(Motion),
+    ExecuteNnsFunction(ExecuteNnsFunction),
+    CreateServiceNervousSystem(Box<CreateServiceNervousSystem>),
+    ManageNetworkEconomics(NetworkEconomics),
+}
+
+/// Governance treats zero fields as unchanged, so only the fields set here are changed.
+#[derive(CandidType, Default)]
+struct NetworkEconomics {
+    neuron_minimum_stake_e8s: u64,
+    max_proposals_to_keep_per_topic: u32,
+    neuron_management_fee_per_proposal_e8s: u64,
+    reject_cost_e8s: u64,
+    transaction_fee_e8s: u64,
+    neuron_spawn_dissolve_delay_seconds: u64,
+    minimum_icp_xdr_rate: u64,
+    maximum_node_provider_rewards_e8s: u64,
+}
+
+#[derive(CandidType)]
+struct Motion {
+    motion_text: String,
+}
+
+#[derive(CandidType)]
+struct ExecuteNnsFunction {
+    nns_function: i32,
+    payload: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ProposalId {
+    id: u64,
+}
+
+#[derive(CandidType)]
+struct RegisterVote {
+    proposal: Option<ProposalId>,
+    vote: i32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct MakeProposalResponse {
+    proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ProposalInfo {
+    status: i32,
+}
+
+#[derive(CandidType)]
+struct ManageNeuronRequest {
+    id: Option<NeuronId>,
+    command: Option<ManageNeuronCommand>,
+}
+
+#[derive(CandidType)]
+enum ManageNeuronCommand {
+    Configure(Configure),
+    Follow(Follow),
+    MakeProposal(Box<Proposal>),
+    RegisterVote(RegisterVote),
+}
+
+#[derive(CandidType)]
+struct Follow {
+    topic: i32,
+    followees: Vec<NeuronId>,
+}
+
+/// Topics to follow a neuron on for it to decide every proposal: the catch-all topic, and the
+/// governance and SNS & Neurons' Fund topics, which the catch-all doesn't cover.
+const FOLLOW_TOPICS: [i32; 3] = [0, 4, 14];
+
+#[derive(CandidType)]
+struct Configure {
+    operation: Option<Operation>,
+}
+
+#[derive(CandidType)]
+enum Operation {
+    IncreaseDissolveDelay(IncreaseDissolveDelay),
+}
+
+#[derive(CandidType)]
+struct IncreaseDissolveDelay {
+    additional_dissolve_delay_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ManageNeuronResponse {
+    command: Option<ManageNeuronResponseCommand>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum ManageNeuronResponseCommand {
+    Error(IDLValue),
+    Configure {},
+    Follow {},
+    MakeProposal(MakeProposalResponse),
+    RegisterVote {},
+}
+
+/// Creates `neuron`, returning its ID. `memo` distinguishes neurons of the same controller.
+pub async fn create_neuron(pic: &PocketIc, neuron: &TestNeuron, memo: u64) -> anyhow::Result<u64> {
+    let subaccount: [u8; 32] = Sha256::new()
+        .chain_update([0x0c])
+        .chain_update(b"neuron-stake")
+        .chain_update(neuron.controller.as_slice())
+        .chain_update(memo.to_be_bytes())
+        .finalize()
+        .into();
+    ledger::mint(
+        pic,
+        &[(
+            AccountId::new(GOVERNANCE_CANISTER_ID, subaccount),
+            neuron.stake_e8s,
+        )],
+    )
+    .await?;
+    let response = pic
+        .update_call(
+            GOVERNANCE_CANISTER_ID,
+            neuron.controller,
+            "claim_or_refresh_neuron_from_account",
+            Encode!(&ClaimOrRefreshNeuronFromAccount {
+                controller: Some(neuron.controller),
+                memo,
+            })?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to claim neuron: {e}"))?;
+    let id = match Decode!(&response, ClaimOrRefreshNeuronFromAccountResponse)?.result {
+        Some(ClaimOrRefreshResult::NeuronId(NeuronId { id })) => id,
+        Some(ClaimOrRefreshResult::Error(e)) => bail!("failed to claim neuron: {e}"),
+        None => bail!("failed to claim neuron: governance returned no result"),
+    };
+    let additional_dissolve_delay_seconds =
+        u32::try_from(neuron.dissolve_delay.as_secs()).context("dissolve delay is too long")?;
+    manage_neuron(
+        pic,
+        neuron.controller,
+        id,
+        ManageNeuronCommand::Configure(Configure {
+            operation: Some(Operation::IncreaseDissolveDelay(IncreaseDissolveDelay {
+                additional_dissolve_delay_seconds,
+            })),
+        }),
+    )
+    .await
+    .with_context(|| format!("failed to set dissolve delay of neuron {id}"))?;
+    Ok(id)
+}
+
+/// Sends `command` to the neuron `neuron_id` on behalf of its controller.
+async fn manage_neuron(
+    pic: &PocketIc,
+    controller: Principal,
+    neuron_id: u64,
+    command: ManageNeuronCommand,
+) -> anyhow::Result<ManageNeuronResponseCommand> {
+    let response = pic
+        .update_call(
+            GOVERNANCE_CANISTER_ID,
+            controller,
+            "manage_neuron",
+            Encode!(&ManageNeuronRequest {
+                id: Some(NeuronId { id: neuron_id }),
+                command: Some(command),
+            })?,
+        )
+        .await
+        .map_err(|e| anyhow!("{e}"))?;
+    match Decode!(&response, ManageNeuronResponse)?.command {
+        Some(ManageNeuronResponseCommand::Error(e)) => bail!("{e}"),
+        Some(command) => Ok(command),
+        None => bail!("governance returned no result"),
+    }
+}
+
+/// Makes the neuron `neuron_id`, controlled by `controller`, vote as `followee` does on every
+/// proposal.
+pub async fn follow_on_all_topics(
+    pic: &PocketIc,
+    controller: Principal,
+    neuron_id: u64,
+    followee: u64,
+) -> anyhow::Result<()> {
+    for topic in FOLLOW_TOPICS {
+        manage_neuron(
+            pic,
+            controller,
+            neuron_id,
+            ManageNeuronCommand::Follow(Follow {
+                topic,
+                followees: vec![NeuronId { id: followee }],
+            }),
+        )
+        .await
+        .with_context(|| format!("failed to make neuron {neuron_id} follow neuron {followee}"))?;
+    }
+    Ok(())
+}
+
+/// Submits a proposal on behalf of the neuron `neuron_id`, controlled by `proposer`, returning the
+/// proposal's ID. The neuron must be eligible to submit proposals.
+pub async fn submit_proposal(
+    pic: &PocketIc,
+    proposer: Principal,
+    neuron_id: u64,
+    title: String,
+    summary: String,
+    url: String,
+    action: ProposalAction,
+) -> anyhow::Result<u64> {
+    let action = match action {
+        ProposalAction::Motion { text } => Action::Motion(Motion { motion_text: text }),
+        ProposalAction::BlessReplicaVersion {
+            version,
+            sha256,
+            urls,
+        } => Action::ExecuteNnsFunction(ExecuteNnsFunction {
+            nns_function: NNS_FUNCTION_REVISE_ELECTED_GUESTOS_VERSIONS,
+            payload: Encode!(&ReviseElectedGuestosVersionsPayload {
+                replica_version_to_elect: Some(version),
+                release_package_sha256_hex: Some(sha256),
+                release_package_urls: urls,
+                replica_versions_to_unelect: vec![],
+            })?,
+        }),
+        ProposalAction::NnsFunction { function, payload } => {
+            Action::ExecuteNnsFunction(ExecuteNnsFunction {
+                nns_function: function,
+                payload,
+            })
+        }
+        ProposalAction::CreateServiceNervousSystem(create) => {
+            Action::CreateServiceNervousSystem(create)
+        }
+        ProposalAction::ManageNetworkEconomics {
+            neuron_minimum_stake_e8s,
+            reject_cost_e8s,
+        } => Action::ManageNetworkEconomics(NetworkEconomics {
+            neuron_minimum_stake_e8s,
+            reject_cost_e8s,
+            ..<_>::default()
+        }),
+    };
+    let command = ManageNeuronCommand::MakeProposal(Box::new(Proposal {
+        title: Some(title),
+        summary,
+        url,
+        action: Some(action),
+    }));
+    match manage_neuron(pic, proposer, neuron_id, command)
+        .await
+        .context("failed to submit proposal")?
+    {
+        ManageNeuronResponseCommand::MakeProposal(MakeProposalResponse {
+            proposal_id: Some(ProposalId { id }),
+        }) => Ok(id),
+        _ => bail!("failed to submit proposal: governance returned no proposal ID"),
+    }
+}
+
+/// Votes to adopt the proposal `proposal_id` with the neuron `neuron_id`, controlled by `controller`.
+pub async fn vote_yes(
+    pic: &PocketIc,
+    controller: Principal,
+    neuron_id: u64,
+    proposal_id: u64,
+) -> anyhow::Result<()> {
+    manage_neuron(
+        pic,
+        controller,
+        neuron_id,
+        ManageNeuronCommand::RegisterVote(RegisterVote {
+            proposal: Some(ProposalId { id: proposal_id }),
+            vote: 1,
+        }),
+    )
+    .await
+    .with_context(|| format!("failed to vote with neuron {neuron_id}"))?;
+    Ok(())
+}
+
+/// Returns the status of the proposal `proposal_id`, e.g. `open` or `executed`.
+pub async fn proposal_status(pic: &PocketIc, proposal_id: u64) -> anyhow::Result<&'static str> {
+    let response = pic
+        .query_call(
+            GOVERNANCE_CANISTER_ID,
+            Principal::anonymous(),
+            "get_proposal_info",
+            Encode!(&proposal_id)?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to get proposal {proposal_id}: {e}"))?;
+    let info = Decode!(&response, Option<ProposalInfo>)?
+        .with_context(|| format!("proposal {proposal_id} does not exist"))?;
+    Ok(match info.status {
+        1 => "open",
+        2 => "rejected",
+        3 => "adopted",
+        4 => "executed",
+        5 => "failed",
+        _ => "unknown",
+    })
+}
+
+#[derive(Subcommand)]
+pub enum ProposalCommand {
+    /// Submits a proposal on behalf of a neuron seeded by `--test-neuron`, printing its ID and
+    /// status.
+    Submit {
+        /// ID of the proposing neuron. Defaults to the first seeded neuron.
+        #[arg(long)]
+        neuron: Option<u64>,
+        /// Title of the proposal. Defaults to a description of the action.
+        #[arg(long)]
+        title: Option<String>,
+        /// Summary of the proposal.
+        #[arg(long, default_value = "")]
+        summary: String,
+        /// URL of the proposal's discussion.
+        #[arg(long, default_value = "")]
+        proposal_url: String,
+        /// Votes to adopt the proposal with all seeded neurons. It is adopted if they hold a
+        /// majority of the voting power.
+        #[arg(long)]
+        adopt: bool,
+        #[command(subcommand)]
+        action: ProposalActionCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProposalActionCommand {
+    /// A motion proposal.
+    Motion {
+        /// Text of the motion.
+        text: String,
+    },
+    /// Elects a replica (GuestOS) version, allowing subnets to be upgraded to it.
+    BlessReplicaVersion {
+        /// The version to elect, i.e. its git commit.
+        version: String,
+        /// Hex-encoded SHA-256 hash of the release package.
+        #[arg(long)]
+        sha256: String,
+        /// URL of the release package.
+        #[arg(long = "release-package-url", action = ArgAction::Append, required = true)]
+        urls: Vec<String>,
+    },
+    /// Executes an NNS function.
+    NnsFunction {
+        /// Number of the function, as in governance's `NnsFunction` enum.
+        function: i32,
+        /// Payload of the function call as Candid text, e.g. `(record { ... })`.
+        #[arg(long, default_value = "()")]
+        payload: String,
+    },
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: ProposalCommand) -> anyhow::Result<()> {
+    match command {
+        ProposalCommand::Submit {
+            neuron,
+            title,
+            summary,
+            proposal_url,
+            adopt,
+            action,
+        } => {
+            let registry = registry::read(status_dir)?;
+            let proposer = match neuron {
+                Some(id) => registry.test_neurons.iter().find(|n| n.id == id),
+                None => registry.test_neurons.first(),
+            }
+            .context("proposals must be submitted by a neuron seeded by --test-neuron")?;
+            let (default_title, action) = match action {
+                ProposalActionCommand::Motion { text } => {
+                    ("Motion".to_string(), ProposalAction::Motion { text })
+                }
+                ProposalActionCommand::BlessReplicaVersion {
+                    version,
+                    sha256,
+                    urls,
+                } => (
+                    format!("Elect replica version {version}"),
+                    ProposalAction::BlessReplicaVersion {
+                        version,
+                        sha256,
+                        urls,
+                    },
+                ),
+                ProposalActionCommand::NnsFunction { function, payload } => (
+                    format!("Execute NNS function {function}"),
+                    ProposalAction::NnsFunction {
+                        function,
+                        payload: candid_parser::parse_idl_args(&payload)
+                            .context("invalid payload")?
+                            .to_bytes()
+                            .context("invalid payload")?,
+                    },
+                ),
+            };
+            let response: control::ProposalSubmitResponse = control::request(
+                status_dir,
+                "/proposal/submit",
+                &control::ProposalSubmitRequest {
+                    proposer: proposer.controller,
+                    neuron_id: proposer.id,
+                    title: title.unwrap_or(default_title),
+                    summary,
+                    url: proposal_url,
+                    action,
+                    adopt_with: if adopt { registry.test_neurons } else { vec![] },
+                },
+            )
+            .await?;
+            println!("proposal {}: {}", response.proposal_id, response.status);
+        }
+    }
+    Ok(())
+}
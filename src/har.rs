@@ -0,0 +1,269 @@
+//! Recording of the gateway's HTTP traffic (`--record-http`) as an HTTP Archive (HAR), for
+//! inspection in browser devtools when debugging certification and caching issues.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! records each request it forwards with the response, bodies included. Responses the launcher
+//! makes up itself, such as throttling or injected faults, are not recorded. An archive is a single
+//! JSON document, so the exchanges are kept in memory and written when the network stops.
+
+use std::{
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hyper::{
+    HeaderMap,
+    header::CONTENT_TYPE,
+    http::{request, response},
+};
+use serde::Serialize;
+
+use crate::timestamp;
+
+/// The exchanges recorded so far.
+#[derive(Default)]
+pub struct HttpRecorder {
+    entries: Mutex<Vec<Entry>>,
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: Log<'a>,
+}
+
+#[derive(Serialize)]
+struct Log<'a> {
+    version: &'static str,
+    creator: Creator,
+    entries: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    /// Milliseconds from sending the request until the response was read.
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: Cache,
+    timings: Timings,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    http_version: &'static str,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    cookies: Vec<NameValue>,
+    headers_size: i64,
+    body_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+    /// `base64` if the text is the base64 encoding of a binary body.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: &'static str,
+    headers: Vec<NameValue>,
+    cookies: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: usize,
+    mime_type: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+impl HttpRecorder {
+    /// Records the request described by `parts` with `body`, sent at `started`, and the response
+    /// with the head `response` and `response_body`, read `elapsed` later.
+    pub fn record(
+        &self,
+        parts: &request::Parts,
+        body: &[u8],
+        response: &response::Parts,
+        response_body: &[u8],
+        started: SystemTime,
+        elapsed: Duration,
+    ) {
+        let host = parts
+            .headers
+            .get(hyper::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        let path_and_query = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+        let query_string = parts
+            .uri
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                NameValue {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }
+            })
+            .collect();
+        let post_data = (!body.is_empty()).then(|| {
+            let (text, encoding) = body_text(body);
+            PostData {
+                mime_type: mime_type(&parts.headers),
+                text,
+                encoding,
+            }
+        });
+        let (text, encoding) = body_text(response_body);
+        let time = elapsed.as_secs_f64() * 1000.0;
+        let entry = Entry {
+            started_date_time: timestamp::format_timestamp(
+                started
+                    .duration_since(UNIX_EPOCH)
+                    .expect("system clock should be after the unix epoch")
+                    .as_nanos() as u64,
+            ),
+            time,
+            request: HarRequest {
+                method: parts.method.to_string(),
+                url: format!("http://{host}{path_and_query}"),
+                http_version: "HTTP/1.1",
+                headers: headers(&parts.headers),
+                query_string,
+                cookies: vec![],
+                headers_size: -1,
+                body_size: body.len(),
+                post_data,
+            },
+            response: HarResponse {
+                status: response.status.as_u16(),
+                status_text: response
+                    .status
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string(),
+                http_version: "HTTP/1.1",
+                headers: headers(&response.headers),
+                cookies: vec![],
+                content: Content {
+                    size: response_body.len(),
+                    mime_type: mime_type(&response.headers),
+                    text,
+                    encoding,
+                },
+                redirect_url: response
+                    .headers
+                    .get(hyper::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string(),
+                headers_size: -1,
+                body_size: response_body.len(),
+            },
+            cache: Cache {},
+            timings: Timings {
+                send: 0.0,
+                wait: time,
+                receive: 0.0,
+            },
+        };
+        self.entries
+            .lock()
+            .expect("http recording lock poisoned")
+            .push(entry);
+    }
+
+    /// Writes the exchanges recorded so far to `path` as an archive.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let entries = self.entries.lock().expect("http recording lock poisoned");
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries: &entries,
+            },
+        };
+        let contents = serde_json::to_string(&har).expect("infallible serialization");
+        fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Vec<NameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| NameValue {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+fn mime_type(headers: &HeaderMap) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Returns `body` as text if it is UTF-8, or else base64-encoded.
+fn body_text(body: &[u8]) -> (String, Option<&'static str>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (STANDARD.encode(body), Some("base64")),
+    }
+}
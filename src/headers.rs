@@ -0,0 +1,175 @@
+//! Header rewriting on the gateway (`--gateway-headers`), so that teams can emulate their
+//! production edge locally, e.g. the `X-Forwarded-*` headers their proxy adds or the
+//! Content-Security-Policy their CDN sets.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! applies the rules of a TOML file to requests before they are forwarded and to responses before
+//! they are returned, in order, e.g.
+//!
+//! ```toml
+//! [[request]]
+//! forwarded = true                  # adds X-Forwarded-For and X-Forwarded-Host
+//! set = { "X-Forwarded-Proto" = "https" }
+//!
+//! [[response]]
+//! host = "*.localhost"              # optional, `*` matches any sequence of characters
+//! path = "/"                        # optional prefix of the path
+//! set = { "Content-Security-Policy" = "default-src 'self'" }
+//! remove = ["Server"]
+//! ```
+
+use std::{collections::BTreeMap, fs, net::IpAddr, path::Path};
+
+use anyhow::{Context, bail};
+use hyper::{
+    HeaderMap,
+    header::{HOST, HeaderName, HeaderValue},
+    http::request,
+};
+use serde::Deserialize;
+
+use crate::outcalls;
+
+/// The rules of a `--gateway-headers` file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRules {
+    #[serde(default)]
+    request: Vec<Rule>,
+    #[serde(default)]
+    response: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// Pattern of the host names the rule applies to.
+    #[serde(default)]
+    host: Option<String>,
+    /// Prefix of the paths the rule applies to.
+    #[serde(default)]
+    path: Option<String>,
+    /// Headers to remove.
+    #[serde(default)]
+    remove: Vec<String>,
+    /// Headers to set, replacing existing values.
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+    /// Headers to add, keeping existing values.
+    #[serde(default)]
+    append: BTreeMap<String, String>,
+    /// Adds `X-Forwarded-For` and `X-Forwarded-Host`, for request rules.
+    #[serde(default)]
+    forwarded: bool,
+}
+
+/// Reads the rules at `path`, checking that their headers are valid.
+pub fn load(path: &Path) -> anyhow::Result<HeaderRules> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let rules: HeaderRules =
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    for rule in rules.request.iter().chain(&rules.response) {
+        for name in rule
+            .remove
+            .iter()
+            .chain(rule.set.keys())
+            .chain(rule.append.keys())
+        {
+            HeaderName::try_from(name.as_str())
+                .with_context(|| format!("invalid header name `{name}` in {}", path.display()))?;
+        }
+        for value in rule.set.values().chain(rule.append.values()) {
+            HeaderValue::try_from(value.as_str())
+                .with_context(|| format!("invalid header value `{value}` in {}", path.display()))?;
+        }
+    }
+    if rules.response.iter().any(|rule| rule.forwarded) {
+        bail!(
+            "`forwarded` only applies to request rules, in {}",
+            path.display()
+        );
+    }
+    Ok(rules)
+}
+
+impl HeaderRules {
+    /// Applies the request rules to `headers` of the request described by `parts` from `peer`.
+    pub fn rewrite_request(
+        &self,
+        parts: &request::Parts,
+        peer: Option<IpAddr>,
+        headers: &mut HeaderMap,
+    ) {
+        for rule in self.request.iter().filter(|rule| rule.applies_to(parts)) {
+            if rule.forwarded {
+                if let Some(peer) = peer {
+                    append(headers, "x-forwarded-for", &peer.to_string());
+                }
+                if let Some(host) = host(parts) {
+                    set(headers, "x-forwarded-host", host);
+                }
+            }
+            rule.apply(headers);
+        }
+    }
+
+    /// Applies the response rules to `headers` of the response to the request described by
+    /// `parts`.
+    pub fn rewrite_response(&self, parts: &request::Parts, headers: &mut HeaderMap) {
+        for rule in self.response.iter().filter(|rule| rule.applies_to(parts)) {
+            rule.apply(headers);
+        }
+    }
+}
+
+impl Rule {
+    fn applies_to(&self, parts: &request::Parts) -> bool {
+        let host = host(parts)
+            .map(|host| host.split_once(':').map_or(host, |(host, _)| host))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.host
+            .as_ref()
+            .is_none_or(|pattern| outcalls::glob_match(&pattern.to_ascii_lowercase(), &host))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| parts.uri.path().starts_with(path.as_str()))
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            if let Ok(name) = HeaderName::try_from(name.as_str()) {
+                headers.remove(name);
+            }
+        }
+        for (name, value) in &self.set {
+            set(headers, name, value);
+        }
+        for (name, value) in &self.append {
+            append(headers, name, value);
+        }
+    }
+}
+
+/// The host the request described by `parts` is sent to.
+fn host(parts: &request::Parts) -> Option<&str> {
+    // HTTP/2 requests carry the host in the URI's authority rather than a Host header
+    match parts.headers.get(HOST) {
+        Some(host) => host.to_str().ok(),
+        None => parts.uri.authority().map(|authority| authority.as_str()),
+    }
+}
+
+fn set(headers: &mut HeaderMap, name: &str, value: &str) {
+    if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+        headers.insert(name, value);
+    }
+}
+
+fn append(headers: &mut HeaderMap, name: &str, value: &str) {
+    if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+        headers.append(name, value);
+    }
+}
@@ -0,0 +1,115 @@
+//! The gateway's health endpoint (`--gateway-health`), `/_launcher/health` on the gateway port, a
+//! stable target for load balancers and test harnesses that front the gateway and need to know
+//! whether it can serve requests.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`])
+//! and answers the endpoint itself, whatever the host, rather than passing it on to a canister.
+//! Readiness is checked on each request: PocketIC's gateway must answer the IC's status endpoint,
+//! which it forwards to the instance, and the instance must report itself healthy and not be
+//! paused for exceeding its `--state-quota`.
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use ciborium::Value;
+use http_body_util::Full;
+use hyper::{
+    Response, StatusCode,
+    body::Bytes,
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+};
+use serde::Serialize;
+
+use crate::control::Control;
+
+/// Path of the health endpoint on the gateway port.
+pub const HEALTH_PATH: &str = "/_launcher/health";
+/// How long to wait for PocketIC's gateway before reporting it unavailable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Health {
+    /// Whether the network can serve requests.
+    ready: bool,
+    /// Whether PocketIC's gateway answered.
+    gateway: bool,
+    /// Whether the instance reported itself healthy.
+    instance: bool,
+    /// Whether the network is paused for exceeding its state quota.
+    paused: bool,
+    /// Why the network isn't ready, if it isn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Checks the readiness of PocketIC's gateway at `upstream` and the instance of `control`,
+/// answering 200 if both are ready and 503 otherwise, with the details as JSON.
+pub async fn check(
+    client: &reqwest::Client,
+    upstream: &str,
+    control: &Control,
+) -> Response<Full<Bytes>> {
+    let paused = control.quota_paused.load(Ordering::Relaxed);
+    let (gateway, instance, reason) = match probe(client, upstream).await {
+        Ok(status) => {
+            let healthy = status == "healthy";
+            let reason = (!healthy).then(|| format!("instance reported itself {status}"));
+            (true, healthy, reason)
+        }
+        Err(reason) => (false, false, Some(reason)),
+    };
+    let reason = reason
+        .or_else(|| paused.then(|| "network is paused for exceeding its state quota".to_string()));
+    let health = Health {
+        ready: gateway && instance && !paused,
+        gateway,
+        instance,
+        paused,
+        reason,
+    };
+    let status = if health.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::to_vec(&health).expect("infallible serialization");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(body)))
+        .expect("valid response")
+}
+
+/// Returns the health the instance behind PocketIC's gateway at `upstream` reports, e.g.
+/// `healthy`, or why it couldn't be found out.
+async fn probe(client: &reqwest::Client, upstream: &str) -> Result<String, String> {
+    let response = client
+        .get(format!("{upstream}/api/v2/status"))
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("failed to reach the gateway: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!("gateway answered with {}", response.status()));
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| format!("failed to read the gateway's response: {e}"))?;
+    let status: Value = ciborium::from_reader(&body[..])
+        .map_err(|e| format!("failed to parse the gateway's status: {e}"))?;
+    match field(&status, "replica_health_status") {
+        Some(Value::Text(status)) => Ok(status.clone()),
+        _ => Err("gateway's status has no replica health".to_string()),
+    }
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Map(map) => map
+            .iter()
+            .find_map(|(key, value)| (key.as_text() == Some(name)).then_some(value)),
+        Value::Tag(_, value) => field(value, name),
+        _ => None,
+    }
+}
@@ -0,0 +1,89 @@
+//! Commands run once the network is ready (`--on-ready`), such as a project's own seeding or
+//! deployment scripts.
+//!
+//! Each command runs through the system shell, one after the other, with the network's URLs and
+//! IDs in its environment. Canister IDs are named as dfx names them (`CANISTER_ID_<NAME>`), so
+//! frontend tooling that reads them works unmodified.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, bail};
+use candid::Principal;
+use tokio::process::Command;
+
+/// Prefix of the environment variables describing the network.
+const ENV_PREFIX: &str = "ICP_CLI_NETWORK_LAUNCHER_";
+
+/// What a hook learns about the network from its environment.
+pub struct NetworkInfo<'a> {
+    pub gateway_port: u16,
+    pub control_port: u16,
+    pub config_port: u16,
+    pub instance_id: usize,
+    pub root_key: &'a str,
+    pub default_effective_canister_id: Principal,
+    pub status_dir: Option<&'a Path>,
+    /// Canisters by name, as in the canister registry.
+    pub canisters: &'a BTreeMap<String, Principal>,
+}
+
+impl NetworkInfo<'_> {
+    /// Returns the environment variables passed to hooks.
+    fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            (
+                "GATEWAY_URL",
+                format!("http://localhost:{}", self.gateway_port),
+            ),
+            ("GATEWAY_PORT", self.gateway_port.to_string()),
+            ("CONTROL_PORT", self.control_port.to_string()),
+            ("CONFIG_PORT", self.config_port.to_string()),
+            ("INSTANCE_ID", self.instance_id.to_string()),
+            ("ROOT_KEY", self.root_key.to_string()),
+            (
+                "DEFAULT_EFFECTIVE_CANISTER_ID",
+                self.default_effective_canister_id.to_string(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (format!("{ENV_PREFIX}{name}"), value))
+        .collect::<Vec<_>>();
+        if let Some(status_dir) = self.status_dir {
+            env.push((
+                format!("{ENV_PREFIX}STATUS_DIR"),
+                status_dir.display().to_string(),
+            ));
+        }
+        for (name, canister_id) in self.canisters {
+            let name = name.to_uppercase().replace(['-', '.', ' '], "_");
+            env.push((format!("CANISTER_ID_{name}"), canister_id.to_string()));
+        }
+        env
+    }
+}
+
+/// Runs `commands` in order, stopping at the first that fails.
+pub async fn run(commands: &[String], network: &NetworkInfo<'_>) -> anyhow::Result<()> {
+    let env = network.env();
+    for command in commands {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let status = shell
+            .arg(command)
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .status()
+            .await
+            .with_context(|| format!("failed to run `{command}`"))?;
+        if !status.success() {
+            bail!("`{command}` failed with {status}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! Test tokens: ICRC-1/ICRC-2 ledgers deployed at startup (`--icrc-token`).
+//!
+//! The launcher ships no ledger Wasm, so the module is taken from `--icrc-ledger-wasm`. Each
+//! token's minting account is the anonymous principal, so tests can mint more of it with a plain
+//! anonymous ICRC-1 transfer.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use candid::{CandidType, Empty, Encode, Nat, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::{canister, ledger_archive::ArchiveConfig};
+
+/// Cycles given to each token ledger.
+const LEDGER_CYCLES: u128 = 100_000_000_000_000;
+/// Cycles a ledger gives to each archive it spawns.
+const ARCHIVE_CREATION_CYCLES: u64 = 10_000_000_000_000;
+
+/// A token to deploy at startup.
+#[derive(Clone)]
+pub struct IcrcToken {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// Initial balances in the token's smallest unit.
+    pub premint: BTreeMap<Principal, u128>,
+}
+
+/// Parses a token of the form `NAME:SYMBOL:DECIMALS[:PREMINT]`, where `PREMINT` is a
+/// comma-separated list of `PRINCIPAL=AMOUNT`, e.g. `Test Token:TST:8:2vxsx-fae=1000`.
+pub fn parse_token(s: &str) -> Result<IcrcToken, String> {
+    let (name, symbol, decimals, premint) = match s.split(':').collect::<Vec<_>>()[..] {
+        [name, symbol, decimals] => (name, symbol, decimals, None),
+        [name, symbol, decimals, premint] => (name, symbol, decimals, Some(premint)),
+        _ => {
+            return Err(format!(
+                "expected `NAME:SYMBOL:DECIMALS[:PREMINT]`, got `{s}`"
+            ));
+        }
+    };
+    let decimals: u8 = decimals
+        .parse()
+        .ok()
+        .filter(|&decimals| decimals <= 18)
+        .ok_or_else(|| format!("invalid number of decimals `{decimals}`"))?;
+    let mut balances = BTreeMap::new();
+    for balance in premint.into_iter().flat_map(|premint| premint.split(',')) {
+        let (principal, amount) = balance
+            .split_once('=')
+            .ok_or_else(|| format!("expected `PRINCIPAL=AMOUNT`, got `{balance}`"))?;
+        let principal =
+            Principal::from_text(principal).map_err(|e| format!("invalid principal: {e}"))?;
+        balances.insert(principal, parse_amount(amount, decimals)?);
+    }
+    Ok(IcrcToken {
+        name: name.to_string(),
+        symbol: symbol.to_string(),
+        decimals,
+        premint: balances,
+    })
+}
+
+/// Parses an amount of a token with `decimals` decimal places, e.g. `100` or `0.5`, into its
+/// smallest unit.
+fn parse_amount(s: &str, decimals: u8) -> Result<u128, String> {
+    let invalid = || format!("invalid token amount `{s}`");
+    let decimals = usize::from(decimals);
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() || fraction.len() > decimals {
+        return Err(invalid());
+    }
+    let whole: u128 = whole.parse().map_err(|_| invalid())?;
+    let fraction: u128 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{fraction:0<decimals$}")
+            .parse()
+            .map_err(|_| invalid())?
+    };
+    whole
+        .checked_mul(10u128.pow(decimals as u32))
+        .and_then(|units| units.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+#[derive(CandidType)]
+enum LedgerArg {
+    Init(InitArgs),
+}
+
+#[derive(CandidType)]
+struct InitArgs {
+    minting_account: Account,
+    transfer_fee: Nat,
+    decimals: Option<u8>,
+    token_symbol: String,
+    token_name: String,
+    /// Always empty, so its values' type is left out.
+    metadata: Vec<(String, Empty)>,
+    initial_balances: Vec<(Account, Nat)>,
+    feature_flags: Option<FeatureFlags>,
+    archive_options: ArchiveOptions,
+}
+
+#[derive(CandidType)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType)]
+struct FeatureFlags {
+    icrc2: bool,
+}
+
+#[derive(CandidType)]
+struct ArchiveOptions {
+    num_blocks_to_archive: u64,
+    trigger_threshold: u64,
+    controller_id: Principal,
+    cycles_for_archive_creation: Option<u64>,
+}
+
+/// Returns the init argument of a ledger for `token`, minted by `minting_account`, charging `fee`
+/// units per transfer and archiving its blocks as `archive` says.
+pub fn init_arg(
+    token: &IcrcToken,
+    minting_account: Principal,
+    fee: u128,
+    archive: ArchiveConfig,
+) -> anyhow::Result<Vec<u8>> {
+    let account = |owner| Account {
+        owner,
+        subaccount: None,
+    };
+    let init = LedgerArg::Init(InitArgs {
+        minting_account: account(minting_account),
+        transfer_fee: Nat::from(fee),
+        decimals: Some(token.decimals),
+        token_symbol: token.symbol.clone(),
+        token_name: token.name.clone(),
+        metadata: vec![],
+        initial_balances: token
+            .premint
+            .iter()
+            .map(|(&owner, &amount)| (account(owner), Nat::from(amount)))
+            .collect(),
+        feature_flags: Some(FeatureFlags { icrc2: true }),
+        archive_options: ArchiveOptions {
+            num_blocks_to_archive: archive.num_blocks_to_archive,
+            trigger_threshold: archive.trigger_threshold,
+            controller_id: Principal::anonymous(),
+            // taken from the ledger's own cycles, since ledgers outside system subnets pay for
+            // the archives they spawn
+            cycles_for_archive_creation: Some(ARCHIVE_CREATION_CYCLES),
+        },
+    });
+    Ok(Encode!(&init)?)
+}
+
+/// Deploys a ledger for `token` from the Wasm module `wasm`, returning its canister ID. The
+/// transfer fee is 0.0001 tokens, or one unit for tokens with fewer than 4 decimals.
+pub async fn deploy(
+    pic: &PocketIc,
+    token: &IcrcToken,
+    wasm: Vec<u8>,
+    archive: ArchiveConfig,
+) -> anyhow::Result<Principal> {
+    let fee = 10u128.pow(u32::from(token.decimals.saturating_sub(4)));
+    let arg = init_arg(token, Principal::anonymous(), fee, archive)?;
+    let canister_id = pic.create_canister().await;
+    pic.add_cycles(canister_id, LEDGER_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, arg)
+        .await
+        .with_context(|| format!("failed to install the {} ledger", token.symbol))?;
+    Ok(canister_id)
+}
@@ -0,0 +1,216 @@
+//! Configuration and seeding of the Internet Identity canister for local development.
+//!
+//! PocketIC installs II with its default configuration. Overrides are applied by upgrading II to
+//! its own Wasm module with an init argument containing only the overridden fields, which II
+//! merges into its existing configuration.
+//!
+//! Test users' anchors are registered through II's registration flow, on behalf of the
+//! self-authenticating principal of each user's key, with the key as the anchor's only device.
+
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use candid::{CandidType, Decode, Encode, Principal, types::value::IDLValue};
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+
+use crate::{canister, duration};
+
+/// ID of the Internet Identity canister.
+pub const II_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 7, 1, 1]);
+
+/// Overrides of II's configuration.
+#[derive(Default)]
+pub struct IiConfig {
+    pub disable_captcha: bool,
+    pub related_origins: Vec<String>,
+    /// Maximum burst of registrations, and the time after which one more is allowed.
+    pub registration_rate_limit: Option<(u64, Duration)>,
+}
+
+impl IiConfig {
+    pub fn is_empty(&self) -> bool {
+        !self.disable_captcha
+            && self.related_origins.is_empty()
+            && self.registration_rate_limit.is_none()
+    }
+}
+
+/// Parses a registration rate limit of the form `N/INTERVAL`, e.g. `100/1s`.
+pub fn parse_rate_limit(s: &str) -> Result<(u64, Duration), String> {
+    let (max, interval) = s
+        .split_once('/')
+        .ok_or_else(|| format!("expected `N/INTERVAL`, got `{s}`"))?;
+    let max = max
+        .parse()
+        .map_err(|e| format!("invalid number of registrations: {e}"))?;
+    Ok((max, duration::parse_duration(interval)?))
+}
+
+#[derive(CandidType)]
+struct InternetIdentityInit {
+    register_rate_limit: Option<RateLimitConfig>,
+    captcha_config: Option<CaptchaConfig>,
+    related_origins: Option<Vec<String>>,
+}
+
+#[derive(CandidType)]
+struct RateLimitConfig {
+    time_per_token_ns: u64,
+    max_tokens: u64,
+}
+
+#[derive(CandidType)]
+struct CaptchaConfig {
+    max_unsolved_captchas: u64,
+    captcha_trigger: CaptchaTrigger,
+}
+
+#[derive(CandidType)]
+enum CaptchaTrigger {
+    Static(StaticCaptchaTrigger),
+}
+
+#[derive(CandidType)]
+enum StaticCaptchaTrigger {
+    CaptchaDisabled,
+}
+
+/// Applies `config` to the II canister.
+pub async fn configure(pic: &PocketIc, config: &IiConfig) -> anyhow::Result<()> {
+    let init = InternetIdentityInit {
+        register_rate_limit: config
+            .registration_rate_limit
+            .map(|(max_tokens, interval)| RateLimitConfig {
+                time_per_token_ns: interval.as_nanos() as u64,
+                max_tokens,
+            }),
+        captcha_config: config.disable_captcha.then_some(CaptchaConfig {
+            // II's default; irrelevant while captchas are disabled
+            max_unsolved_captchas: 500,
+            captcha_trigger: CaptchaTrigger::Static(StaticCaptchaTrigger::CaptchaDisabled),
+        }),
+        related_origins: (!config.related_origins.is_empty())
+            .then(|| config.related_origins.clone()),
+    };
+    canister::reconfigure(pic, II_CANISTER_ID, Encode!(&Some(init))?)
+        .await
+        .context("failed to reconfigure Internet Identity")
+}
+
+#[derive(CandidType, Deserialize)]
+struct IdRegNextStepResult {
+    next_step: RegistrationFlowNextStep,
+}
+
+#[derive(CandidType, Deserialize)]
+enum RegistrationFlowNextStep {
+    CheckCaptcha { captcha_png_base64: String },
+    Finish,
+}
+
+#[derive(CandidType)]
+struct IdRegFinishArg {
+    authn_method: AuthnMethodData,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IdRegFinishResult {
+    identity_number: u64,
+}
+
+#[derive(CandidType)]
+struct AuthnMethodData {
+    authn_method: AuthnMethod,
+    metadata: Vec<(String, MetadataEntry)>,
+    security_settings: AuthnMethodSecuritySettings,
+    last_authentication: Option<u64>,
+}
+
+#[derive(CandidType)]
+enum AuthnMethod {
+    PubKey { pubkey: Vec<u8> },
+}
+
+#[derive(CandidType, Deserialize)]
+enum MetadataEntry {
+    #[serde(rename = "string")]
+    String(String),
+}
+
+#[derive(CandidType)]
+struct AuthnMethodSecuritySettings {
+    protection: AuthnMethodProtection,
+    purpose: AuthnMethodPurpose,
+}
+
+#[derive(CandidType)]
+enum AuthnMethodProtection {
+    Unprotected,
+}
+
+#[derive(CandidType)]
+enum AuthnMethodPurpose {
+    Authentication,
+}
+
+/// Registers a new II anchor whose only device is the DER-encoded public key `pubkey`, named
+/// `alias`, returning the anchor number.
+pub async fn register_anchor(pic: &PocketIc, alias: &str, pubkey: Vec<u8>) -> anyhow::Result<u64> {
+    let caller = Principal::self_authenticating(&pubkey);
+    let response = pic
+        .update_call(
+            II_CANISTER_ID,
+            caller,
+            "identity_registration_start",
+            Encode!()?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to start registration: {e}"))?;
+    let started = Decode!(&response, Result<IdRegNextStepResult, IDLValue>)?
+        .map_err(|e| anyhow!("failed to start registration: {e}"))?;
+    if let RegistrationFlowNextStep::CheckCaptcha { .. } = started.next_step {
+        bail!("Internet Identity requires a captcha for registration; pass --ii-disable-captcha");
+    }
+    let response = pic
+        .update_call(
+            II_CANISTER_ID,
+            caller,
+            "identity_registration_finish",
+            Encode!(&IdRegFinishArg {
+                authn_method: AuthnMethodData {
+                    authn_method: AuthnMethod::PubKey { pubkey },
+                    metadata: vec![(
+                        "alias".to_string(),
+                        MetadataEntry::String(alias.to_string())
+                    )],
+                    security_settings: AuthnMethodSecuritySettings {
+                        protection: AuthnMethodProtection::Unprotected,
+                        purpose: AuthnMethodPurpose::Authentication,
+                    },
+                    last_authentication: None,
+                },
+            })?,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to finish registration: {e}"))?;
+    let finished = Decode!(&response, Result<IdRegFinishResult, IDLValue>)?
+        .map_err(|e| anyhow!("failed to finish registration: {e}"))?;
+    Ok(finished.identity_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate_limit_splits_count_and_duration() {
+        assert_eq!(
+            parse_rate_limit("100/1s"),
+            Ok((100, Duration::from_secs(1)))
+        );
+        for s in ["100", "x/1s", "100/0s", "100/1"] {
+            assert!(parse_rate_limit(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Index canisters, which serve the transaction histories of the network's ledgers to wallet
+//! frontends (`--icp-index`, `--cycles-ledger-index`).
+//!
+//! PocketIC installs the ledgers but the launcher ships no index Wasm, so modules are taken from
+//! the command line. Each index is installed at its mainnet canister ID, next to its ledger.
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::{canister, ledger::LEDGER_CANISTER_ID};
+
+/// Mainnet ID of the ICP index canister.
+pub const ICP_INDEX_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 11, 1, 1]);
+/// Mainnet ID of the cycles ledger.
+pub const CYCLES_LEDGER_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 16, 0, 2, 1, 1]);
+/// Mainnet ID of the cycles ledger's index canister.
+pub const CYCLES_LEDGER_INDEX_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 16, 0, 3, 1, 1]);
+/// Cycles given to each index canister.
+const INDEX_CYCLES: u128 = 100_000_000_000_000;
+
+#[derive(CandidType)]
+struct IcpIndexInitArg {
+    ledger_id: Principal,
+}
+
+/// Installs the ICP index Wasm module `wasm`, indexing the ICP ledger, unless it is already
+/// installed.
+pub async fn install_icp_index(pic: &PocketIc, wasm: Vec<u8>) -> anyhow::Result<()> {
+    install(
+        pic,
+        ICP_INDEX_CANISTER_ID,
+        wasm,
+        Encode!(&IcpIndexInitArg {
+            ledger_id: LEDGER_CANISTER_ID,
+        })?,
+    )
+    .await
+    .context("failed to install ICP index")
+}
+
+#[derive(CandidType)]
+enum IndexArg {
+    Init(IndexInitArg),
+}
+
+#[derive(CandidType)]
+struct IndexInitArg {
+    ledger_id: Principal,
+}
+
+/// Returns the init argument of an ICRC index canister indexing `ledger_id`.
+pub fn icrc_index_arg(ledger_id: Principal) -> anyhow::Result<Vec<u8>> {
+    Ok(Encode!(&Some(IndexArg::Init(IndexInitArg { ledger_id })))?)
+}
+
+/// Installs the ICRC index Wasm module `wasm`, indexing the cycles ledger, unless it is already
+/// installed.
+pub async fn install_cycles_ledger_index(pic: &PocketIc, wasm: Vec<u8>) -> anyhow::Result<()> {
+    install(
+        pic,
+        CYCLES_LEDGER_INDEX_CANISTER_ID,
+        wasm,
+        icrc_index_arg(CYCLES_LEDGER_CANISTER_ID)?,
+    )
+    .await
+    .context("failed to install cycles ledger index")
+}
+
+async fn install(
+    pic: &PocketIc,
+    canister_id: Principal,
+    wasm: Vec<u8>,
+    arg: Vec<u8>,
+) -> anyhow::Result<()> {
+    if pic.canister_exists(canister_id).await {
+        return Ok(());
+    }
+    pic.create_canister_with_id(None, None, canister_id)
+        .await
+        .map_err(|e| anyhow!("failed to create canister {canister_id}: {e}"))?;
+    pic.add_cycles(canister_id, INDEX_CYCLES).await;
+    canister::install_code(pic, canister_id, Principal::anonymous(), wasm, arg).await
+}
@@ -0,0 +1,166 @@
+//! Recording of the ingress messages the gateway accepts (`--record-ingress`) and their replay
+//! onto another network (`replay`), to reproduce a regression from captured traffic.
+//!
+//! PocketIC's gateway doesn't expose the messages it receives, so while recording, the launcher
+//! serves the gateway port in its place (see [`crate::gateway`]) and stores the signed envelopes
+//! of the calls it accepts as they were sent, along with the network's time, one JSON object per
+//! line.
+//!
+//! Envelopes expire minutes after they are signed, so replay moves the network's time forward to
+//! when each message was recorded. A network whose time is already past that, such as one started
+//! at the host's time after the recording, rejects the messages as expired; replay onto a network
+//! started with `--deterministic` or an early enough `--initial-time` instead.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use candid::Principal;
+use ciborium::Value;
+use hyper::{StatusCode, header::CONTENT_TYPE};
+use pocket_ic::Time;
+use serde::{Deserialize, Serialize};
+
+use crate::control::Control;
+
+/// An accepted ingress message.
+#[derive(Serialize, Deserialize)]
+pub struct IngressMessage {
+    /// The network's time when the message was accepted, in nanoseconds since the Unix epoch.
+    pub time: u64,
+    /// Gateway endpoint the message was sent to, e.g. `/api/v3/canister/<id>/call`.
+    pub path: String,
+    /// The CBOR-encoded signed envelope, in hex.
+    pub envelope: String,
+}
+
+/// The content of a call's signed envelope.
+pub struct CallContent {
+    pub sender: Principal,
+    pub canister_id: Principal,
+    pub method_name: String,
+    pub arg: Vec<u8>,
+}
+
+/// Opens the recording or log at `path`, appending to it if it exists.
+pub fn open(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))
+}
+
+/// Reads the messages recorded at `path`.
+pub fn load(path: &Path) -> anyhow::Result<Vec<IngressMessage>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("failed to parse message {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// Appends the call to `path` with `envelope`, accepted at the network's `time`, to `recording`.
+pub fn record(recording: &Mutex<File>, time: Time, path: &str, envelope: &[u8]) {
+    let message = IngressMessage {
+        time: time.as_nanos_since_unix_epoch(),
+        path: path.to_string(),
+        envelope: hex::encode(envelope),
+    };
+    let line = serde_json::to_string(&message).expect("infallible serialization");
+    let mut file = recording.lock().expect("recording lock poisoned");
+    if let Err(e) = writeln!(file, "{line}") {
+        eprintln!("Warning: failed to record an ingress message: {e:#}");
+    }
+}
+
+/// Returns the content of `envelope`, if it is the envelope of a call.
+pub fn call_content(envelope: &[u8]) -> Option<CallContent> {
+    let envelope = ciborium::from_reader::<Value, _>(envelope).ok()?;
+    let content = field(&envelope, "content")?;
+    match (
+        field(content, "sender"),
+        field(content, "canister_id"),
+        field(content, "method_name"),
+        field(content, "arg"),
+    ) {
+        (
+            Some(Value::Bytes(sender)),
+            Some(Value::Bytes(canister_id)),
+            Some(Value::Text(method_name)),
+            Some(Value::Bytes(arg)),
+        ) => Some(CallContent {
+            sender: Principal::try_from_slice(sender).ok()?,
+            canister_id: Principal::try_from_slice(canister_id).ok()?,
+            method_name: method_name.clone(),
+            arg: arg.clone(),
+        }),
+        _ => None,
+    }
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Map(map) => map
+            .iter()
+            .find_map(|(key, value)| (key.as_text() == Some(name)).then_some(value)),
+        Value::Tag(_, value) => field(value, name),
+        _ => None,
+    }
+}
+
+/// Sends `messages` in order to the gateway of `control`'s network, moving its time forward to
+/// when each was recorded. Returns a description of each message the gateway rejected.
+pub async fn replay(
+    control: &Control,
+    messages: Vec<IngressMessage>,
+) -> anyhow::Result<Vec<String>> {
+    let client = reqwest::Client::new();
+    let mut rejections = vec![];
+    for (i, message) in messages.into_iter().enumerate() {
+        let envelope = hex::decode(&message.envelope)
+            .with_context(|| format!("message {} has an invalid envelope", i + 1))?;
+        if message.time > control.pic.get_time().await.as_nanos_since_unix_epoch() {
+            control
+                .advance_time_to(Time::from_nanos_since_unix_epoch(message.time))
+                .await?;
+        }
+        // without auto-progress, a synchronous call would wait for rounds that never come, so the
+        // message is sent asynchronously and a round executes it
+        let path = match message.path.split_once("/canister/") {
+            Some((_, rest)) if !control.auto_progress => format!("/api/v2/canister/{rest}"),
+            _ => message.path,
+        };
+        let response = client
+            .post(format!("{}{path}", control.gateway_url))
+            .header(CONTENT_TYPE, "application/cbor")
+            .body(envelope)
+            .send()
+            .await
+            .context("failed to send a message to the gateway")?;
+        let status = response.status();
+        if status != StatusCode::OK && status != StatusCode::ACCEPTED {
+            let body = response.text().await.unwrap_or_default();
+            rejections.push(format!(
+                "message {} ({path}): {status}: {}",
+                i + 1,
+                body.trim()
+            ));
+        }
+        if !control.auto_progress {
+            let _paused = control.progress.lock().await;
+            control.pic.tick().await;
+        }
+    }
+    Ok(rejections)
+}
@@ -0,0 +1,188 @@
+//! Offline inspection of state directories and their snapshots.
+//!
+//! Each subnet's state lives in its own subdirectory, in the replica's checkpoint layout
+//! (`checkpoints/<height>/canister_states/<canister id>/`). Only the latest checkpoint of each
+//! subnet is considered. Cycle balances are stored in the replica's protobuf encoding and are not
+//! read.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+use crate::quota::{dir_size, format_size};
+
+/// The contents of a state directory.
+#[derive(Default)]
+pub struct Inspection {
+    /// Subnets by the name of their state subdirectory.
+    pub subnets: BTreeMap<String, SubnetInfo>,
+}
+
+#[derive(Default)]
+pub struct SubnetInfo {
+    /// Size of the subnet's latest checkpoint, in bytes.
+    pub size: u64,
+    pub canisters: BTreeMap<Principal, CanisterInfo>,
+}
+
+#[derive(PartialEq, Eq)]
+pub struct CanisterInfo {
+    /// Size of the canister's state, in bytes.
+    pub size: u64,
+    /// Hex-encoded SHA-256 hash of the Wasm module, if one is installed.
+    pub module_hash: Option<String>,
+}
+
+/// Reads the subnets and canisters stored in `state_dir`.
+pub fn inspect(state_dir: &Path) -> anyhow::Result<Inspection> {
+    let mut inspection = Inspection::default();
+    for entry in fs::read_dir(state_dir)
+        .with_context(|| format!("failed to read {}", state_dir.display()))?
+    {
+        let entry = entry.with_context(|| format!("failed to read {}", state_dir.display()))?;
+        let Some(checkpoint) = latest_checkpoint(&entry.path())? else {
+            continue;
+        };
+        let mut subnet = SubnetInfo {
+            size: dir_size(&checkpoint)?,
+            canisters: BTreeMap::new(),
+        };
+        let canister_states = checkpoint.join("canister_states");
+        if canister_states.is_dir() {
+            for canister in fs::read_dir(&canister_states)
+                .with_context(|| format!("failed to read {}", canister_states.display()))?
+            {
+                let canister = canister
+                    .with_context(|| format!("failed to read {}", canister_states.display()))?;
+                let Some(canister_id) = canister
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| hex::decode(name).ok())
+                    .map(|bytes| Principal::from_slice(&bytes))
+                else {
+                    continue;
+                };
+                subnet
+                    .canisters
+                    .insert(canister_id, inspect_canister(&canister.path())?);
+            }
+        }
+        inspection
+            .subnets
+            .insert(entry.file_name().to_string_lossy().into_owned(), subnet);
+    }
+    Ok(inspection)
+}
+
+/// Returns the most recent checkpoint directory of a subnet's state directory, if it is one.
+fn latest_checkpoint(subnet_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let checkpoints = subnet_dir.join("checkpoints");
+    if !checkpoints.is_dir() {
+        return Ok(None);
+    }
+    let mut heights = vec![];
+    for entry in fs::read_dir(&checkpoints)
+        .with_context(|| format!("failed to read {}", checkpoints.display()))?
+    {
+        let entry = entry.with_context(|| format!("failed to read {}", checkpoints.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // heights are fixed-width hex; anything else is a checkpoint being written
+        if let Ok(height) = u64::from_str_radix(&name, 16) {
+            heights.push((height, entry.path()));
+        }
+    }
+    Ok(heights.into_iter().max().map(|(_, path)| path))
+}
+
+fn inspect_canister(dir: &Path) -> anyhow::Result<CanisterInfo> {
+    let wasm = dir.join("software.wasm");
+    let module_hash = if wasm.exists() {
+        let module =
+            fs::read(&wasm).with_context(|| format!("failed to read {}", wasm.display()))?;
+        Some(hex::encode(Sha256::digest(&module)))
+    } else {
+        None
+    };
+    Ok(CanisterInfo {
+        size: dir_size(dir)?,
+        module_hash,
+    })
+}
+
+/// Prints the subnets and canisters of an inspection.
+pub fn print(inspection: &Inspection) {
+    for (name, subnet) in &inspection.subnets {
+        println!("subnet {name} ({})", format_size(subnet.size));
+        for (canister_id, canister) in &subnet.canisters {
+            println!(
+                "  {canister_id}  {}  module {}",
+                format_size(canister.size),
+                canister.module_hash.as_deref().unwrap_or("none")
+            );
+        }
+    }
+}
+
+/// Prints the subnets and canisters that differ between two inspections.
+pub fn print_diff(old: &Inspection, new: &Inspection) {
+    let empty = SubnetInfo::default();
+    let names: BTreeSet<_> = old.subnets.keys().chain(new.subnets.keys()).collect();
+    let mut any_changes = false;
+    for name in names {
+        let old_subnet = old.subnets.get(name).unwrap_or(&empty);
+        let new_subnet = new.subnets.get(name).unwrap_or(&empty);
+        let mut lines = vec![];
+        for (canister_id, old_canister) in &old_subnet.canisters {
+            let new_canister = match new_subnet.canisters.get(canister_id) {
+                Some(new_canister) if new_canister != old_canister => new_canister,
+                Some(_) => continue,
+                None => {
+                    lines.push(format!("  - {canister_id}"));
+                    continue;
+                }
+            };
+            let mut changes = vec![];
+            if new_canister.size != old_canister.size {
+                changes.push(format!(
+                    "size {} -> {}",
+                    format_size(old_canister.size),
+                    format_size(new_canister.size)
+                ));
+            }
+            if new_canister.module_hash != old_canister.module_hash {
+                changes.push(format!(
+                    "module {} -> {}",
+                    old_canister.module_hash.as_deref().unwrap_or("none"),
+                    new_canister.module_hash.as_deref().unwrap_or("none")
+                ));
+            }
+            lines.push(format!("  ~ {canister_id}  {}", changes.join(", ")));
+        }
+        for canister_id in new_subnet.canisters.keys() {
+            if !old_subnet.canisters.contains_key(canister_id) {
+                lines.push(format!("  + {canister_id}"));
+            }
+        }
+        if lines.is_empty() && old_subnet.size == new_subnet.size {
+            continue;
+        }
+        any_changes = true;
+        println!(
+            "subnet {name} ({} -> {})",
+            format_size(old_subnet.size),
+            format_size(new_subnet.size)
+        );
+        for line in lines {
+            println!("{line}");
+        }
+    }
+    if !any_changes {
+        println!("no changes");
+    }
+}
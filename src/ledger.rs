@@ -0,0 +1,187 @@
+//! Transfers on the ICP ledger, including funding accounts at startup (`--ledger-account`).
+//!
+//! The ICP ledger installed by PocketIC uses the NNS governance canister as its minting account,
+//! so balances are minted by transferring from governance to each account with a zero fee.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Decode, Encode, Principal, types::value::IDLValue};
+use pocket_ic::nonblocking::PocketIc;
+use sha2::{Digest, Sha224};
+
+use crate::governance::GOVERNANCE_CANISTER_ID;
+
+/// ID of the ICP ledger canister.
+pub const LEDGER_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1]);
+/// Number of e8s in one ICP.
+const E8S_PER_ICP: u64 = 100_000_000;
+/// Fee of a transfer, in e8s.
+pub const FEE_E8S: u64 = 10_000;
+
+/// An ICP ledger account identifier.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AccountId([u8; 32]);
+
+impl AccountId {
+    /// Returns the identifier of the default subaccount of `principal`.
+    pub fn from_principal(principal: Principal) -> Self {
+        Self::new(principal, [0; 32])
+    }
+
+    /// Returns the identifier of a subaccount of `owner`.
+    pub fn new(owner: Principal, subaccount: [u8; 32]) -> Self {
+        let hash = Sha224::new()
+            .chain_update(b"\x0aaccount-id")
+            .chain_update(owner.as_slice())
+            .chain_update(subaccount)
+            .finalize();
+        let mut id = [0; 32];
+        id[..4].copy_from_slice(&crc32fast::hash(&hash).to_be_bytes());
+        id[4..].copy_from_slice(&hash);
+        Self(id)
+    }
+}
+
+/// Returns the subaccount that canisters such as the CMC and SNS swaps associate with `principal`.
+pub fn principal_subaccount(principal: Principal) -> [u8; 32] {
+    let mut subaccount = [0; 32];
+    let bytes = principal.as_slice();
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..=bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+impl FromStr for AccountId {
+    type Err = String;
+
+    /// Parses a principal, or a hex account identifier.
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.len() == 64
+            && let Ok(bytes) = hex::decode(s)
+        {
+            let id: [u8; 32] = bytes.try_into().expect("64 hex digits are 32 bytes");
+            if crc32fast::hash(&id[4..]).to_be_bytes() != id[..4] {
+                return Err(format!("invalid checksum in account identifier `{s}`"));
+            }
+            return Ok(Self(id));
+        }
+        Principal::from_text(s)
+            .map(Self::from_principal)
+            .map_err(|_| format!("`{s}` is neither a principal nor an account identifier"))
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+/// Parses an amount of ICP with up to 8 decimal places, e.g. `100` or `0.5`, into e8s.
+pub fn parse_icp(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid ICP amount `{s}`");
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() || fraction.len() > 8 {
+        return Err(invalid());
+    }
+    let whole: u64 = whole.parse().map_err(|_| invalid())?;
+    let fraction: u64 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{fraction:0<8}").parse().map_err(|_| invalid())?
+    };
+    whole
+        .checked_mul(E8S_PER_ICP)
+        .and_then(|e8s| e8s.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+/// Parses an `ACCOUNT=ICP` pair.
+pub fn parse_account_balance(s: &str) -> Result<(AccountId, u64), String> {
+    let (account, amount) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `ACCOUNT=ICP`, got `{s}`"))?;
+    Ok((account.parse()?, parse_icp(amount)?))
+}
+
+#[derive(CandidType)]
+struct Tokens {
+    e8s: u64,
+}
+
+#[derive(CandidType)]
+struct TransferArgs {
+    memo: u64,
+    amount: Tokens,
+    fee: Tokens,
+    from_subaccount: Option<Vec<u8>>,
+    to: Vec<u8>,
+    created_at_time: Option<TimeStamp>,
+}
+
+#[derive(CandidType)]
+struct TimeStamp {
+    timestamp_nanos: u64,
+}
+
+/// Mints `balances` (in e8s) to their accounts on the ICP ledger.
+pub async fn mint(pic: &PocketIc, balances: &[(AccountId, u64)]) -> anyhow::Result<()> {
+    for &(account, e8s) in balances {
+        transfer(pic, GOVERNANCE_CANISTER_ID, account, e8s, 0, 0)
+            .await
+            .with_context(|| format!("failed to mint ICP to {account}"))?;
+    }
+    Ok(())
+}
+
+/// Transfers `e8s` from the default account of `from` to `to`, returning the block index.
+pub async fn transfer(
+    pic: &PocketIc,
+    from: Principal,
+    to: AccountId,
+    e8s: u64,
+    fee: u64,
+    memo: u64,
+) -> anyhow::Result<u64> {
+    let response = pic
+        .update_call(
+            LEDGER_CANISTER_ID,
+            from,
+            "transfer",
+            Encode!(&TransferArgs {
+                memo,
+                amount: Tokens { e8s },
+                fee: Tokens { e8s: fee },
+                from_subaccount: None,
+                to: to.0.to_vec(),
+                created_at_time: None,
+            })?,
+        )
+        .await
+        .map_err(|e| anyhow!("transfer failed: {e}"))?;
+    Decode!(&response, Result<u64, IDLValue>)?.map_err(|e| anyhow!("transfer failed: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_icp_converts_to_e8s() {
+        assert_eq!(parse_icp("100"), Ok(100 * E8S_PER_ICP));
+        assert_eq!(parse_icp("0.5"), Ok(E8S_PER_ICP / 2));
+        assert_eq!(parse_icp("0.00000001"), Ok(1));
+        for s in [
+            "",
+            ".5",
+            "0.000000001",
+            "1.2.3",
+            "-1",
+            "1e3",
+            "184467440738",
+        ] {
+            assert!(parse_icp(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Archiving of the ledgers' blocks (`--ledger-archive-threshold`, `--ledger-archive-blocks`).
+//!
+//! Ledgers move their oldest blocks to archive canisters they spawn once they hold more than a
+//! threshold of blocks. Low thresholds let tests exercise history spread across archives without
+//! millions of transactions. The ICRC ledgers the launcher deploys take the thresholds at init, and
+//! the ICP ledger is reconfigured with them, since PocketIC installs it.
+//!
+//! Archives are spawned as blocks accumulate, so they are looked up on every start and by
+//! `ledger archives`, and recorded in the canister registry as `<ledger>-archive-<n>`.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, bail};
+use candid::{CandidType, Decode, Encode, Principal};
+use clap::Subcommand;
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+
+use crate::{canister, control, ledger::LEDGER_CANISTER_ID, read_status, registry};
+
+/// How ledgers archive their blocks.
+#[derive(Clone, Copy)]
+pub struct ArchiveConfig {
+    /// Number of blocks a ledger holds before it archives some.
+    pub trigger_threshold: u64,
+    /// Number of blocks archived at once.
+    pub num_blocks_to_archive: u64,
+}
+
+impl Default for ArchiveConfig {
+    /// The ledgers' recommended defaults.
+    fn default() -> Self {
+        Self {
+            trigger_threshold: 2000,
+            num_blocks_to_archive: 1000,
+        }
+    }
+}
+
+impl ArchiveConfig {
+    /// Returns the configuration set by the command line, if any. A missing value is derived
+    /// from the other one in the ratio of the defaults.
+    pub fn from_args(
+        trigger_threshold: Option<u64>,
+        num_blocks_to_archive: Option<u64>,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match (trigger_threshold, num_blocks_to_archive) {
+            (None, None) => return Ok(None),
+            (Some(trigger_threshold), None) => Self {
+                trigger_threshold,
+                num_blocks_to_archive: (trigger_threshold / 2).max(1),
+            },
+            (None, Some(num_blocks_to_archive)) => Self {
+                trigger_threshold: num_blocks_to_archive.saturating_mul(2),
+                num_blocks_to_archive,
+            },
+            (Some(trigger_threshold), Some(num_blocks_to_archive)) => Self {
+                trigger_threshold,
+                num_blocks_to_archive,
+            },
+        };
+        if config.num_blocks_to_archive == 0 {
+            bail!("--ledger-archive-blocks must be at least 1");
+        }
+        if config.num_blocks_to_archive > config.trigger_threshold {
+            bail!("--ledger-archive-blocks cannot exceed --ledger-archive-threshold");
+        }
+        Ok(Some(config))
+    }
+}
+
+#[derive(CandidType)]
+enum LedgerCanisterPayload {
+    Upgrade(Option<UpgradeArgs>),
+}
+
+#[derive(CandidType)]
+struct UpgradeArgs {
+    change_archive_options: Option<ChangeArchiveOptions>,
+}
+
+#[derive(CandidType)]
+struct ChangeArchiveOptions {
+    trigger_threshold: Option<u64>,
+    num_blocks_to_archive: Option<u64>,
+}
+
+/// Applies `config` to the ICP ledger.
+pub async fn configure_icp_ledger(pic: &PocketIc, config: ArchiveConfig) -> anyhow::Result<()> {
+    let arg = Encode!(&Some(LedgerCanisterPayload::Upgrade(Some(UpgradeArgs {
+        change_archive_options: Some(ChangeArchiveOptions {
+            trigger_threshold: Some(config.trigger_threshold),
+            num_blocks_to_archive: Some(config.num_blocks_to_archive),
+        }),
+    }))))?;
+    canister::reconfigure(pic, LEDGER_CANISTER_ID, arg)
+        .await
+        .context("failed to configure archiving of the ICP ledger")
+}
+
+#[derive(CandidType, Deserialize)]
+struct ArchiveInfo {
+    canister_id: Principal,
+}
+
+/// The ICP ledger's response to `archives`; ICRC ledgers return the list itself.
+#[derive(CandidType, Deserialize)]
+struct IcpArchives {
+    archives: Vec<ArchiveInfo>,
+}
+
+/// Returns the archives spawned by the ledgers among `canisters`, named `<ledger>-archive-<n>`
+/// in the order they were spawned. Ledgers are told apart from other canisters by answering
+/// `archives`.
+pub async fn find(
+    pic: &PocketIc,
+    canisters: &BTreeMap<String, Principal>,
+) -> anyhow::Result<BTreeMap<String, Principal>> {
+    let mut found = BTreeMap::new();
+    for (name, &canister_id) in canisters {
+        // archives don't answer `archives`, but skipping them saves the calls
+        if name.contains("-archive-") {
+            continue;
+        }
+        let Ok(response) = pic
+            .query_call(canister_id, Principal::anonymous(), "archives", Encode!()?)
+            .await
+        else {
+            continue;
+        };
+        let archives = match Decode!(&response, Vec<ArchiveInfo>) {
+            Ok(archives) => archives,
+            Err(_) => match Decode!(&response, IcpArchives) {
+                Ok(IcpArchives { archives }) => archives,
+                Err(_) => continue,
+            },
+        };
+        for (n, archive) in archives.into_iter().enumerate() {
+            found.insert(format!("{name}-archive-{}", n + 1), archive.canister_id);
+        }
+    }
+    Ok(found)
+}
+
+#[derive(Subcommand)]
+pub enum LedgerCommand {
+    /// Records the archives spawned by the network's ledgers in the canister registry, printing
+    /// them.
+    Archives,
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: LedgerCommand) -> anyhow::Result<()> {
+    match command {
+        LedgerCommand::Archives => {
+            let mut registry = registry::read(status_dir)?;
+            let response: control::LedgerArchivesResponse = control::request(
+                status_dir,
+                "/ledger/archives",
+                &control::LedgerArchivesRequest {
+                    canisters: registry
+                        .canisters
+                        .iter()
+                        .map(|(name, entry)| (name.clone(), entry.canister_id))
+                        .collect(),
+                },
+            )
+            .await?;
+            for (name, canister_id) in response.archives {
+                println!("{name}: {canister_id}");
+                registry
+                    .canisters
+                    .insert(name, registry::RegistryEntry::new(canister_id));
+            }
+            registry::set_urls(&mut registry, read_status(status_dir)?.gateway_port);
+            registry::write(status_dir, &registry)?;
+        }
+    }
+    Ok(())
+}
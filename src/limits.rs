@@ -0,0 +1,71 @@
+//! Strict resource limits (`--strict-limits`), so that teams run into resource limit failures
+//! locally rather than on mainnet: canisters get less Wasm memory than mainnet allows, and smaller
+//! Wasm modules than mainnet accepts.
+//!
+//! PocketIC has no settings for these limits, so they are enforced by the launcher's gateway (see
+//! [`crate::gateway`]): `install_code` messages to the management canister are rejected if their
+//! Wasm module is too large. Otherwise, if they are sent by one of the canister's controllers, the
+//! canister's `wasm_memory_limit` is lowered on the sender's behalf before they are forwarded, so
+//! that nobody else can change a canister's settings through the gateway. Modules installed in
+//! chunks, or by other canisters, are not checked. PocketIC has no setting to lower instruction
+//! limits, so they stay at mainnet's.
+
+use candid::{CandidType, Decode, Nat, Principal};
+use pocket_ic::{management_canister::CanisterSettings, nonblocking::PocketIc};
+use serde::Deserialize;
+
+use crate::ingress;
+
+/// Largest Wasm module, as sent (possibly gzipped), that can be installed, against mainnet's
+/// 2 MiB ingress messages.
+pub const WASM_MODULE_LIMIT: usize = 1024 * 1024;
+/// Wasm memory of canisters, against mainnet's default of 3 GiB.
+pub const WASM_MEMORY_LIMIT: u64 = 1024 * 1024 * 1024;
+
+#[derive(CandidType, Deserialize)]
+struct InstallCodeArgs {
+    canister_id: Principal,
+    wasm_module: Vec<u8>,
+}
+
+/// Checks the ingress message `envelope` against the strict limits, returning why it is rejected
+/// if it exceeds them. Lowers the Wasm memory limit of canisters a controller installs code in.
+pub async fn check(pic: &PocketIc, envelope: &[u8]) -> Result<(), String> {
+    let Some((sender, args)) = install_code_args(envelope) else {
+        return Ok(());
+    };
+    if args.wasm_module.len() > WASM_MODULE_LIMIT {
+        return Err(format!(
+            "Wasm module of {} bytes exceeds the limit of {WASM_MODULE_LIMIT} bytes (--strict-limits)",
+            args.wasm_module.len()
+        ));
+    }
+    let canister_id = args.canister_id;
+    // an install by someone who isn't a controller fails anyway, and mustn't change the settings
+    if !pic.get_controllers(canister_id).await.contains(&sender) {
+        return Ok(());
+    }
+    let settings = CanisterSettings {
+        wasm_memory_limit: Some(Nat::from(WASM_MEMORY_LIMIT)),
+        ..<_>::default()
+    };
+    if let Err(e) = pic
+        .update_canister_settings(canister_id, Some(sender), settings)
+        .await
+    {
+        eprintln!("Warning: failed to limit the Wasm memory of canister {canister_id}: {e}");
+    }
+    Ok(())
+}
+
+/// Returns the sender and arguments of `envelope` if it is an `install_code` call to the
+/// management canister.
+fn install_code_args(envelope: &[u8]) -> Option<(Principal, InstallCodeArgs)> {
+    let content = ingress::call_content(envelope)?;
+    if content.canister_id != Principal::management_canister()
+        || content.method_name != "install_code"
+    {
+        return None;
+    }
+    Some((content.sender, Decode!(&content.arg, InstallCodeArgs).ok()?))
+}
@@ -0,0 +1,149 @@
+//! Load testing of a canister through the gateway (`loadtest`).
+//!
+//! Workers send calls back to back for a fixed time, each waiting for its previous call, so the
+//! number of workers is the number of calls in flight. Calls go through the HTTP gateway, like a
+//! frontend's or agent's would, so the measured latencies include ingress handling and
+//! certification. Queries and updates are interleaved evenly in the configured ratio.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use candid::{Encode, Principal};
+use ic_agent::Agent;
+
+/// The traffic to generate.
+pub struct LoadTest {
+    pub canister_id: Principal,
+    pub update_method: Option<String>,
+    pub query_method: Option<String>,
+    /// Share of calls that are queries, if both methods are given.
+    pub query_ratio: f64,
+    pub arg: Vec<u8>,
+    pub concurrency: usize,
+    pub duration: Duration,
+}
+
+/// Returns the argument of the calls: `text` in Candid, or else a blob of `payload_size` bytes, or
+/// else no arguments.
+pub fn arg(text: Option<&str>, payload_size: Option<usize>) -> anyhow::Result<Vec<u8>> {
+    match (text, payload_size) {
+        (Some(text), _) => candid_parser::parse_idl_args(text)
+            .context("invalid arg")?
+            .to_bytes()
+            .context("invalid arg"),
+        // `vec nat8` is encoded like `blob`
+        (None, Some(size)) => Ok(Encode!(&vec![0u8; size])?),
+        (None, None) => Ok(Encode!()?),
+    }
+}
+
+#[derive(Default)]
+struct Outcomes {
+    /// Latencies of the calls that succeeded.
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+impl Outcomes {
+    fn merge(&mut self, other: Outcomes) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+}
+
+/// Runs `test` against the gateway at `gateway_url` and prints throughput and latency
+/// percentiles of each kind of call.
+pub async fn run(gateway_url: &str, test: LoadTest) -> anyhow::Result<()> {
+    let agent = Agent::builder()
+        .with_url(gateway_url)
+        .build()
+        .context("failed to create agent")?;
+    agent
+        .fetch_root_key()
+        .await
+        .context("failed to fetch the network's root key")?;
+    let query_ratio = match (&test.update_method, &test.query_method) {
+        (Some(_), Some(_)) => test.query_ratio,
+        (None, _) => 1.0,
+        (_, None) => 0.0,
+    };
+    let deadline = Instant::now() + test.duration;
+    let start = Instant::now();
+    let mut workers = vec![];
+    for _ in 0..test.concurrency {
+        let agent = agent.clone();
+        let canister_id = test.canister_id;
+        let update_method = test.update_method.clone().unwrap_or_default();
+        let query_method = test.query_method.clone().unwrap_or_default();
+        let arg = test.arg.clone();
+        workers.push(tokio::spawn(async move {
+            let mut updates = Outcomes::default();
+            let mut queries = Outcomes::default();
+            let mut n = 0u64;
+            while Instant::now() < deadline {
+                // the call is a query whenever the running count of queries reaches a new integer
+                let is_query =
+                    ((n + 1) as f64 * query_ratio).floor() > (n as f64 * query_ratio).floor();
+                n += 1;
+                let call_start = Instant::now();
+                let (outcomes, result) = if is_query {
+                    let result = agent
+                        .query(&canister_id, &query_method)
+                        .with_arg(arg.clone())
+                        .call()
+                        .await;
+                    (&mut queries, result)
+                } else {
+                    let result = agent
+                        .update(&canister_id, &update_method)
+                        .with_arg(arg.clone())
+                        .call_and_wait()
+                        .await;
+                    (&mut updates, result)
+                };
+                match result {
+                    Ok(_) => outcomes.latencies.push(call_start.elapsed()),
+                    Err(_) => outcomes.errors += 1,
+                }
+            }
+            (updates, queries)
+        }));
+    }
+    let mut updates = Outcomes::default();
+    let mut queries = Outcomes::default();
+    for worker in workers {
+        let (worker_updates, worker_queries) = worker.await.expect("load test worker panicked");
+        updates.merge(worker_updates);
+        queries.merge(worker_queries);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<7}  {:>7}  {:>6}  {:>9}  {:>9}  {:>9}  {:>9}",
+        "KIND", "CALLS", "ERRORS", "CALLS/S", "P50", "P90", "P99"
+    );
+    for (kind, mut outcomes) in [("update", updates), ("query", queries)] {
+        let calls = outcomes.latencies.len() as u64 + outcomes.errors;
+        if calls == 0 {
+            continue;
+        }
+        outcomes.latencies.sort();
+        let percentile = |p: usize| match outcomes.latencies.len() {
+            0 => "-".to_string(),
+            len => format!(
+                "{:.1}ms",
+                outcomes.latencies[(len - 1) * p / 100].as_secs_f64() * 1000.0
+            ),
+        };
+        println!(
+            "{:<7}  {:>7}  {:>6}  {:>9.1}  {:>9}  {:>9}  {:>9}",
+            kind,
+            calls,
+            outcomes.errors,
+            outcomes.latencies.len() as f64 / elapsed.as_secs_f64(),
+            percentile(50),
+            percentile(90),
+            percentile(99)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Canister frontends served from local directories (`--serve-local`), so that a frontend can be
+//! developed with hot reload against the local backends, without uploading its assets after every
+//! change.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! answers `GET` and `HEAD` requests for the canister from the directory instead, whether the
+//! canister is addressed by subdomain, `--alias` or `?canisterId=`. Calls to the canister's API go
+//! to the network as usual. Like the asset canister, paths without a file fall back to
+//! `index.html`, so client-side routing works.
+
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+
+use candid::Principal;
+use http_body_util::Full;
+use hyper::{
+    HeaderMap, Response, StatusCode,
+    body::Bytes,
+    header::{CACHE_CONTROL, CONTENT_TYPE, HOST, HeaderValue},
+    http::request,
+};
+
+use crate::{assets, compression::Compression};
+
+/// Parses a local directory of the form `CANISTER=DIR`, where `CANISTER` is a canister ID or name,
+/// e.g. `frontend=./dist`.
+pub fn parse_serve_local(s: &str) -> Result<(String, PathBuf), String> {
+    let (canister, dir) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `CANISTER=DIR`, got `{s}`"))?;
+    if canister.is_empty() || dir.is_empty() {
+        return Err(format!("expected `CANISTER=DIR`, got `{s}`"));
+    }
+    Ok((canister.to_string(), PathBuf::from(dir)))
+}
+
+/// Returns the canister the request described by `parts` is for: the one whose subdomain or alias
+/// in `aliases` it is sent to, or the one in its `canisterId` query parameter.
+pub fn canister_id(
+    parts: &request::Parts,
+    aliases: &BTreeMap<String, Principal>,
+) -> Option<Principal> {
+    // HTTP/2 requests carry the host in the URI's authority rather than a Host header
+    let host = match parts.headers.get(HOST) {
+        Some(host) => host.to_str().ok(),
+        None => parts.uri.authority().map(|authority| authority.as_str()),
+    };
+    if let Some((subdomain, domain)) = host.and_then(|host| host.split_once('.')) {
+        if let Ok(canister_id) = Principal::from_text(subdomain) {
+            return Some(canister_id);
+        }
+        let domain = domain.split_once(':').map_or(domain, |(domain, _)| domain);
+        if domain == "localhost"
+            && let Some(&canister_id) = aliases.get(subdomain)
+        {
+            return Some(canister_id);
+        }
+    }
+    parts.uri.query()?.split('&').find_map(|param| {
+        let canister_id = param.strip_prefix("canisterId=")?;
+        Principal::from_text(canister_id).ok()
+    })
+}
+
+/// Serves the file at `path` in `dir` to the request described by `parts`, compressing it with
+/// `compression` if there is one.
+pub async fn serve(
+    dir: &Path,
+    parts: &request::Parts,
+    compression: Option<&Compression>,
+) -> Response<Full<Bytes>> {
+    let Some(file) = resolve(dir, parts.uri.path()).await else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Full::new(Bytes::from(format!(
+                "{} not found in {} (--serve-local)",
+                parts.uri.path(),
+                dir.display()
+            ))))
+            .expect("valid response");
+    };
+    let body = match tokio::fs::read(&file).await {
+        Ok(body) => Bytes::from(body),
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(CONTENT_TYPE, "text/plain")
+                .body(Full::new(Bytes::from(format!(
+                    "failed to read {}: {e}",
+                    file.display()
+                ))))
+                .expect("valid response");
+        }
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(assets::content_type(&file.to_string_lossy())),
+    );
+    // the files change under the developer's hands
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    let body = match compression {
+        Some(compression) => compression.apply(&parts.headers, StatusCode::OK, &mut headers, body),
+        None => body,
+    };
+    let mut response = Response::new(Full::new(body));
+    *response.headers_mut() = headers;
+    response
+}
+
+/// Returns the file in `dir` that serves `path`: the file itself, the `index.html` of the
+/// directory, or the `index.html` at the root.
+async fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_decode(path)?;
+    let relative = Path::new(path.trim_start_matches('/'));
+    // requests can't reach outside the directory
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let file = dir.join(relative);
+    for candidate in [
+        file.clone(),
+        file.join("index.html"),
+        dir.join("index.html"),
+    ] {
+        if tokio::fs::metadata(&candidate)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// Decodes the `%XX` escapes in `path`, if it is valid UTF-8 once decoded.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,92 @@
+//! Canister logs (`logs`), fetched from whichever subnet hosts each canister and merged into one
+//! stream in order of time, so that following several canisters needs no polling scripts.
+//!
+//! Logs are only visible to controllers by default, so they are fetched on behalf of one. When
+//! following, the launcher asks every [`POLL_INTERVAL`] for the records after the last ones it
+//! printed.
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::{anyhow, bail};
+use ic_principal::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+
+use crate::{control, timestamp::format_timestamp};
+
+/// How often new records are fetched when following.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+pub struct LogRecord {
+    pub idx: u64,
+    pub timestamp_nanos: u64,
+    pub content: String,
+}
+
+/// Returns the log records of `canister_id` with an index above `after`, if given.
+pub async fn fetch(
+    pic: &PocketIc,
+    canister_id: Principal,
+    after: Option<u64>,
+) -> anyhow::Result<Vec<LogRecord>> {
+    if !pic.canister_exists(canister_id).await {
+        bail!("canister {canister_id} does not exist");
+    }
+    let Some(&controller) = pic.get_controllers(canister_id).await.first() else {
+        bail!("canister {canister_id} has no controllers, so its logs cannot be read");
+    };
+    let records = pic
+        .fetch_canister_logs(canister_id, controller)
+        .await
+        .map_err(|e| anyhow!("failed to fetch logs of canister {canister_id}: {e}"))?;
+    Ok(records
+        .into_iter()
+        .filter(|record| after.is_none_or(|after| record.idx > after))
+        .map(|record| LogRecord {
+            idx: record.idx,
+            timestamp_nanos: record.timestamp_nanos,
+            content: String::from_utf8_lossy(&record.content).into_owned(),
+        })
+        .collect())
+}
+
+/// Prints the logs of `canisters` on the network whose status file is in `status_dir`, and with
+/// `follow`, keeps printing new records until interrupted.
+pub async fn tail(
+    status_dir: &Path,
+    canisters: &BTreeMap<String, Principal>,
+    follow: bool,
+) -> anyhow::Result<()> {
+    let mut last_idx = BTreeMap::<Principal, u64>::new();
+    loop {
+        let mut records = vec![];
+        for (name, &canister_id) in canisters {
+            let response: control::LogsResponse = control::request(
+                status_dir,
+                "/logs",
+                &control::LogsRequest {
+                    canister_id,
+                    after: last_idx.get(&canister_id).copied(),
+                },
+            )
+            .await?;
+            if let Some(last) = response.records.last() {
+                last_idx.insert(canister_id, last.idx);
+            }
+            records.extend(response.records.into_iter().map(|record| (name, record)));
+        }
+        records.sort_by_key(|(_, record)| record.timestamp_nanos);
+        for (name, record) in records {
+            println!(
+                "{} [{name}] {}",
+                format_timestamp(record.timestamp_nanos),
+                record.content
+            );
+        }
+        if !follow {
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
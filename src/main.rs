@@ -1,27 +1,110 @@
 use std::{
+    collections::BTreeMap,
     fs,
     io::{ErrorKind, Read, stderr},
     mem,
-    net::{IpAddr, SocketAddr},
-    path::PathBuf,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
 use anyhow::Context;
-use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
+use candid::Nat;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
 use ic_principal::Principal;
 use notify::{Event, RecursiveMode, Watcher, recommended_watcher};
 use pocket_ic::{
-    PocketIcBuilder,
+    PocketIcBuilder, Time,
     common::rest::{AutoProgressConfig, IcpFeatures, IcpFeaturesConfig, InstanceHttpGatewayConfig},
+    management_canister::CanisterSettings,
+    nonblocking::PocketIc,
 };
-use reqwest::Client;
+use reqwest::{Client, Url};
 use semver::{Version, VersionReq};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sysinfo::{ProcessesToUpdate, Signal, System};
 use tempfile::{NamedTempFile, TempDir};
-use tokio::select;
-use tokio::{process::Command, signal::unix::SignalKind};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    process::Command,
+    signal::unix::SignalKind,
+};
+use tokio::{
+    select,
+    sync::{Mutex, Notify},
+};
+
+mod access_log;
+mod access_token;
+mod announce;
+mod archive;
+mod assets;
+mod bench;
+mod blackhole;
+mod bounded;
+mod branch;
+mod cache;
+mod candid_ui;
+mod canister;
+mod chaos;
+mod ckbtc;
+mod cketh;
+mod compression;
+mod control;
+mod costs;
+mod crypt;
+mod cycles;
+mod dfx;
+mod dns;
+mod duration;
+mod ephemeral;
+mod error_pages;
+mod errors;
+mod faucet;
+mod fixture;
+mod fork;
+mod gateway;
+mod gateways;
+mod governance;
+mod har;
+mod headers;
+mod health;
+mod hook;
+mod icrc;
+mod identity;
+mod ii;
+mod index;
+mod ingress;
+mod inspect;
+mod ledger;
+mod ledger_archive;
+mod limits;
+mod loadtest;
+mod local_assets;
+mod logs;
+mod mdns;
+mod outcalls;
+mod passthrough;
+mod pool;
+mod preload;
+mod query_stats;
+mod quota;
+mod registry;
+mod remote;
+mod report;
+mod snapshot;
+mod sns;
+mod sns_wasm;
+mod system;
+mod threshold_key;
+mod time_dilation;
+mod timestamp;
+mod tls;
+mod tunnel;
+mod wallet;
+mod websocket;
+mod xrc;
 
 /// CLI launcher for the pocket-ic server, primarily for use with icp-cli.
 #[derive(Parser)]
@@ -33,18 +116,327 @@ struct Cli {
     /// Port for the HTTP gateway for the ICP API to listen on.
     #[arg(long)]
     gateway_port: Option<u16>,
+    /// Additional HTTP gateway to start, as `[port=PORT][,domain=DOMAIN]...[,https,cert=PEM,key=PEM]`,
+    /// e.g. `--gateway port=8443,domain=myapp.test,https,cert=cert.pem,key=key.pem`, to mimic
+    /// several boundary domains. Can be given multiple times. Domains default to `localhost`.
+    #[arg(long, value_name = "OPTIONS", value_parser = gateways::parse_gateway, action = ArgAction::Append)]
+    gateway: Vec<gateways::GatewaySpec>,
     /// Port for the PocketIC admin interface to listen on.
     #[arg(long)]
     config_port: Option<u16>,
+    /// Port for the launcher's control API to listen on. It is always bound to loopback.
+    #[arg(long)]
+    control_port: Option<u16>,
     /// Network interface to bind the PocketIC server on.
     #[arg(long)]
     bind: Option<IpAddr>,
-    /// Directory to store the PocketIC state.
+    /// Token that clients must present to the gateway, and to PocketIC's admin API when `--bind`
+    /// exposes it, as a bearer token or an `access_token` query parameter. Clients on this machine
+    /// need it too, as tunnels and reverse proxies connect from it; see `--trust-gateway-socket`.
+    /// Required by `--tunnel`.
+    #[arg(
+        long,
+        env = "ICP_CLI_NETWORK_LAUNCHER_ACCESS_TOKEN",
+        hide_env_values = true
+    )]
+    access_token: Option<String>,
+    /// Keeps PocketIC's admin API on loopback even when `--bind` exposes the gateway to other
+    /// machines.
     #[arg(long)]
+    config_loopback_only: bool,
+    /// Bearer token that clients of PocketIC's admin API on the config port must present, including
+    /// those on this machine. The launcher serves the config port in front of PocketIC, which still
+    /// listens without a token on a random loopback port, so this doesn't keep out other processes
+    /// on this machine that look for it.
+    #[arg(
+        long,
+        env = "ICP_CLI_NETWORK_LAUNCHER_CONFIG_TOKEN",
+        hide_env_values = true
+    )]
+    config_token: Option<String>,
+    /// Directory to store the PocketIC state.
+    #[arg(long, global = true)]
     state_dir: Option<PathBuf>,
-    /// Artificial delay for execution, in milliseconds.
+    /// Snapshots the state directory into `<state-dir>.snapshots` every time the network stops,
+    /// keeping only the most recent N snapshots (5 if unspecified). Requires `--state-dir`.
+    #[arg(
+        long,
+        value_name = "keep=N",
+        num_args = 0..=1,
+        default_missing_value = "keep=5",
+        value_parser = snapshot::parse_retention,
+        requires = "state_dir"
+    )]
+    snapshot_on_exit: Option<usize>,
+    /// Maximum size of the state directory, e.g. `20GiB`. The launcher warns as the state directory
+    /// approaches the quota and pauses the network once it is reached. Requires `--state-dir`.
+    #[arg(long, value_parser = quota::parse_size, requires = "state_dir")]
+    state_quota: Option<u64>,
+    /// Existing state to start from: a state directory, or an archive created by `export`.
+    /// The base is never modified. With `--state-dir`, an empty state directory is seeded with a
+    /// copy of the base (copy-on-write where the filesystem supports it); otherwise the state
+    /// directory is used as-is. Requires `--state-dir` or `--ephemeral`.
     #[arg(long)]
+    base_state: Option<PathBuf>,
+    /// Runs the network directly on top of `--base-state` without persisting any changes, so that
+    /// many networks can share one base.
+    #[arg(long, requires = "base_state", conflicts_with = "state_dir")]
+    ephemeral: bool,
+    /// Artificial delay for execution, in milliseconds. The `artificial-delay` subcommand changes
+    /// it while the network runs.
+    #[arg(long, conflicts_with_all = ["no_auto_progress", "deterministic"])]
     artificial_delay_ms: Option<u64>,
+    /// Executes rounds only when asked to by the `tick` subcommand, instead of continuously, for
+    /// deterministic control over round execution.
+    #[arg(long)]
+    no_auto_progress: bool,
+    /// Time the network starts at when it starts from fresh state, as an RFC 3339 timestamp
+    /// (e.g. `2030-01-01T00:00:00Z`) or Unix seconds, instead of the host's time. It moves on
+    /// from there as rounds execute.
+    #[arg(long, value_name = "RFC3339|UNIX", value_parser = timestamp::parse_timestamp)]
+    initial_time: Option<u64>,
+    /// Makes the network's time advance this many times as fast as the host's clock, e.g. `60`
+    /// for an hour per minute, so that long timelines such as neuron aging elapse quickly.
+    #[arg(long, value_name = "FACTOR", value_parser = time_dilation::parse_factor, conflicts_with_all = ["no_auto_progress", "deterministic"])]
+    time_dilation: Option<f64>,
+    /// Executes this many rounds once the network is ready, then shuts it down, for benchmark
+    /// scripts and CI runs that must never run unbounded. The launcher executes the rounds itself
+    /// instead of auto-progress, still following the host's clock and `--artificial-delay-ms`.
+    #[arg(long, value_name = "N", conflicts_with_all = ["no_auto_progress", "deterministic"])]
+    max_rounds: Option<u64>,
+    /// TOML, YAML or JSON file of canned responses to canister HTTPS outcalls, by host and optionally
+    /// path and method, so that canisters making outcalls can be tested offline. Other outcalls are
+    /// made for real, as `--outcall-allow` and `--outcall-deny` permit. The launcher executes the
+    /// rounds itself instead of auto-progress to answer them, still following the host's clock and
+    /// `--artificial-delay-ms`.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    mock_outcalls: Option<PathBuf>,
+    /// Host pattern, e.g. `*.sandbox.example.com`, that canister HTTPS outcalls are restricted
+    /// to; others are rejected. `*` matches any sequence of characters. Mocked outcalls are always
+    /// answered. Can be given multiple times. Like `--mock-outcalls`, the launcher executes the
+    /// rounds itself.
+    #[arg(long, value_name = "PATTERN", action = ArgAction::Append, conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    outcall_allow: Vec<String>,
+    /// Host pattern that canister HTTPS outcalls are rejected for, e.g. `*` to keep tests from
+    /// reaching real APIs. Takes precedence over `--outcall-allow`. Can be given multiple times.
+    #[arg(long, value_name = "PATTERN", action = ArgAction::Append, conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    outcall_deny: Vec<String>,
+    /// Records canister HTTPS outcalls, without their headers, and their responses to this file,
+    /// appending one JSON object per line, for `--replay-outcalls`. Like `--mock-outcalls`, the
+    /// launcher executes the rounds itself.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    record_outcalls: Option<PathBuf>,
+    /// Answers canister HTTPS outcalls with the responses recorded by `--record-outcalls`, in the
+    /// order they were recorded, and rejects the others, for hermetic CI runs.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    replay_outcalls: Option<PathBuf>,
+    /// HTTP or SOCKS proxy that canister HTTPS outcalls go through, e.g. `http://proxy.corp:3128`
+    /// or `socks5h://127.0.0.1:1080`. Like `--mock-outcalls`, the launcher executes the rounds
+    /// itself, to make the outcalls through the proxy.
+    #[arg(long, value_name = "URL", value_parser = outcalls::parse_proxy, conflicts_with_all = ["no_auto_progress", "deterministic", "max_rounds"])]
+    outcall_proxy: Option<reqwest::Proxy>,
+    /// Shuts the network down once it has been ready for this long, e.g. `10m`.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    max_duration: Option<Duration>,
+    /// Decouples the network from the host, so that the same sequence of calls and `tick`s
+    /// produces the same state on every run: implies `--no-auto-progress`, starts fresh state at
+    /// a fixed time unless `--initial-time` is given, and derives `--identity` keys from their
+    /// names instead of generating them randomly.
+    #[arg(long)]
+    deterministic: bool,
+    /// Records the ingress messages the gateway accepts to this file, appending one JSON object
+    /// per line, so that they can be re-applied to another network with `replay`.
+    #[arg(long, value_name = "FILE")]
+    record_ingress: Option<PathBuf>,
+    /// Records the gateway's requests and responses to this HTTP Archive (HAR) file when the
+    /// network stops, for inspection in browser devtools.
+    #[arg(long, value_name = "FILE")]
+    record_http: Option<PathBuf>,
+    /// Appends a line for each gateway request to this file, in the Combined Log Format followed
+    /// by the canister the request is for and how long it took.
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+    /// Forwards WebSocket upgrade requests on the gateway port to this address, e.g. an
+    /// ic-websocket gateway at `127.0.0.1:8081`, as PocketIC's gateway doesn't serve WebSockets.
+    #[arg(long, value_name = "HOST:PORT")]
+    websocket_upstream: Option<String>,
+    /// Compresses the gateway's responses with Brotli or gzip, as boundary nodes do, unless they
+    /// are already encoded.
+    #[arg(long)]
+    compress: bool,
+    /// Smallest response body that `--compress` compresses, e.g. `1KiB`.
+    #[arg(long, value_name = "SIZE", value_parser = quota::parse_size, default_value_t = compression::DEFAULT_MIN_SIZE, requires = "compress")]
+    compress_min_size: u64,
+    /// Caches the gateway's certified responses to `GET` requests for this long, e.g. `60s`, or
+    /// less if their `Cache-Control` says so, like boundary nodes. Requests with
+    /// `Cache-Control: no-cache` bypass the cache, and `purge-gateway-cache` empties it.
+    #[arg(long, value_name = "TTL", value_parser = duration::parse_duration)]
+    gateway_cache: Option<Duration>,
+    /// TOML file of rules that add, replace or remove headers of the gateway's requests and
+    /// responses, e.g. `X-Forwarded-*` or a Content-Security-Policy, to emulate a production edge.
+    #[arg(long, value_name = "FILE")]
+    gateway_headers: Option<PathBuf>,
+    /// Prints the gateway URL of each canister created while the network runs once it serves
+    /// HTTP, and records it in the canister registry if there is a status directory.
+    #[arg(long)]
+    announce_canisters: bool,
+    /// Answers the gateway's failures to serve a canister, e.g. because it doesn't exist or its
+    /// HTTP handler trapped, with an HTML or JSON page explaining the failure and its likely cause.
+    #[arg(long)]
+    gateway_error_pages: bool,
+    /// Answers `/_launcher/health` on the gateway port with the readiness of the gateway and the
+    /// instance, 200 if both are ready and 503 otherwise, for load balancers and test harnesses.
+    #[arg(long)]
+    gateway_health: bool,
+    /// Also serves the gateway on a Unix socket at this path, e.g. for a reverse proxy or a
+    /// devcontainer to front it without a TCP port of its own. The TCP gateway keeps running.
+    #[arg(long, value_name = "PATH")]
+    gateway_socket: Option<PathBuf>,
+    /// Lets clients of `--gateway-socket` reach the gateway without the `--access-token`, e.g. a
+    /// reverse proxy that checks clients itself. Anyone who can open the socket gets in.
+    #[arg(long, requires_all = ["gateway_socket", "access_token"])]
+    trust_gateway_socket: bool,
+    /// Serves HTTP/2 on the gateway port alongside HTTP/1.1, for clients that speak it without
+    /// TLS (h2c), e.g. gRPC-web tooling. Implied by `--gateway-tls-cert`.
+    #[arg(long)]
+    gateway_http2: bool,
+    /// Also accepts TLS on the gateway port with this PEM certificate chain, negotiating HTTP/2 or
+    /// HTTP/1.1 like a boundary node. Plain HTTP keeps working on the same port.
+    #[arg(long, value_name = "FILE", requires = "gateway_tls_key")]
+    gateway_tls_cert: Option<PathBuf>,
+    /// The PEM private key for `--gateway-tls-cert`.
+    #[arg(long, value_name = "FILE", requires = "gateway_tls_cert")]
+    gateway_tls_key: Option<PathBuf>,
+    /// Also accepts TLS with the `--gateway-tls-cert` certificate on the config port, where the
+    /// launcher serves PocketIC's admin API when it is exposed beyond loopback. Plain HTTP keeps
+    /// working on the same port. The control API stays plain HTTP on loopback.
+    #[arg(long, requires = "gateway_tls_cert")]
+    config_tls: bool,
+    /// Serves a canister at `ALIAS.localhost` on the gateway port, as `ALIAS=CANISTER` where the
+    /// canister is an ID or a name in the canister registry, e.g. `--alias myapp=frontend`, for
+    /// stable host names in frontend configuration. Can be given multiple times.
+    #[arg(long, value_name = "ALIAS=CANISTER", value_parser = gateway::parse_alias, action = ArgAction::Append)]
+    alias: Vec<(String, String)>,
+    /// Serves a canister's frontend from a local directory on the gateway port, as `CANISTER=DIR`
+    /// where the canister is an ID or a name in the canister registry, e.g.
+    /// `--serve-local frontend=./dist`. Calls to the canister still go to the network, so a
+    /// frontend dev server's output can be used without redeploying. Can be given multiple times.
+    #[arg(long, value_name = "CANISTER=DIR", value_parser = local_assets::parse_serve_local, action = ArgAction::Append)]
+    serve_local: Vec<(String, PathBuf)>,
+    /// Canister whose gateway requests go to mainnet rather than the local network, e.g. a live
+    /// ledger or Internet Identity for a frontend to integrate with. Agents talking to it must use
+    /// mainnet's root key. Can be given multiple times.
+    #[arg(long, value_name = "CANISTER_ID", action = ArgAction::Append)]
+    mainnet_passthrough: Vec<Principal>,
+    /// Opens a public HTTPS tunnel to the gateway with this provider and prints its URL, to share
+    /// the network or test WebAuthn and OAuth callbacks that need a public origin. The provider's
+    /// CLI must be installed, and logged in for ngrok. The tunnel publishes the whole gateway,
+    /// including the API that deploys and calls canisters, so it requires `--access-token`.
+    #[arg(
+        long,
+        value_name = "PROVIDER",
+        conflicts_with = "tunnel_command",
+        requires = "access_token"
+    )]
+    tunnel: Option<tunnel::Provider>,
+    /// Opens the tunnel with this shell command instead of a provider, with `{port}` and `{host}`
+    /// replaced by the gateway port and the Host header to send. The first `https://` URL it prints
+    /// is taken as the tunnel's. Requires `--access-token`, like `--tunnel`.
+    #[arg(long, value_name = "COMMAND", requires = "access_token")]
+    tunnel_command: Option<String>,
+    /// Canister the tunnel serves at its root, an ID or a name in the canister registry. Otherwise
+    /// canisters are selected with `?canisterId=`.
+    #[arg(long, value_name = "CANISTER")]
+    tunnel_canister: Option<String>,
+    /// Root domain the gateway serves canisters under besides `localhost`, e.g. `ic.local` for
+    /// `<canister id>.ic.local`. Browsers only resolve it with `--dns-port` or a hosts entry.
+    #[arg(long, value_parser = dns::parse_domain)]
+    domain: Option<String>,
+    /// Answers DNS queries for `--domain` and all its subdomains on this UDP port with the gateway's
+    /// address, to be configured as the resolver of that domain, e.g. in `/etc/resolver/<domain>`
+    /// on macOS.
+    #[arg(long, value_name = "PORT", requires = "domain")]
+    dns_port: Option<u16>,
+    /// Advertises the gateway as `NAME.local` over multicast DNS, so that phones and tablets on
+    /// the same network can open dapps at `http://<canister id>.NAME.local:<gateway port>`. The
+    /// gateway must listen on the LAN, e.g. with `--bind 0.0.0.0`.
+    #[arg(long, value_name = "NAME", value_parser = dns::parse_domain, requires = "bind")]
+    announce_mdns: Option<String>,
+    /// Prints each canister's change in cycles and memory, and the instructions its queries
+    /// executed, when the network stops, to catch cycle leaks early. The `report` subcommand
+    /// prints the same while it runs.
+    #[arg(long)]
+    usage_report: bool,
+    /// Counts the queries and calls the gateway rejects, by canister and by reject message, and
+    /// prints the top trapping canisters and most common messages when the network stops. The
+    /// `errors` subcommand prints the same while it runs.
+    #[arg(long)]
+    error_report: bool,
+    /// Serves the gateway through the launcher, so that the `stall-subnet` subcommand can hold
+    /// back requests for a subnet's canisters to simulate it lagging, and `upgrade-subnet` can
+    /// simulate a subnet upgrade.
+    #[arg(long)]
+    chaos: bool,
+    /// Delays this share of ingress messages at the gateway by `--ingress-delay`, e.g. `20`
+    /// percent, to test how a frontend copes with slow calls.
+    #[arg(long, value_name = "PERCENT", value_parser = chaos::parse_percent, default_value = "100", requires = "ingress_delay")]
+    ingress_delay_rate: f64,
+    /// How long to delay ingress messages at the gateway, e.g. `5s`. See `--ingress-delay-rate`.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    ingress_delay: Option<Duration>,
+    /// Rejects this share of ingress messages at the gateway with 503 Service Unavailable, e.g.
+    /// `5` percent, to test how a frontend retries failed calls.
+    #[arg(long, value_name = "PERCENT", value_parser = chaos::parse_percent)]
+    ingress_reject_rate: Option<f64>,
+    /// Delays this share of all gateway responses by `--inject-latency`, e.g. `50` percent, to test
+    /// a frontend's loading states.
+    #[arg(long, value_name = "PERCENT", value_parser = chaos::parse_percent, default_value = "100", requires = "inject_latency")]
+    inject_latency_rate: f64,
+    /// How long to delay gateway responses, e.g. `2s`. See `--inject-latency-rate`.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    inject_latency: Option<Duration>,
+    /// Replaces this share of all gateway responses with 502 Bad Gateway, e.g. `10` percent, to
+    /// test a frontend's error handling. Calls may still have executed.
+    #[arg(long, value_name = "PERCENT", value_parser = chaos::parse_percent)]
+    inject_error_rate: Option<f64>,
+    /// Rejects canister creations through the gateway with 429 Too Many Requests once this many
+    /// were created within a second, as a busy subnet does, to test how a dapp backs off. Only
+    /// canisters created by ingress messages count, not those created by other canisters.
+    #[arg(long, value_name = "N")]
+    max_canister_creations: Option<u32>,
+    /// Answers gateway requests with 429 Too Many Requests once this many were served within a
+    /// second, as boundary nodes throttle clients, to test how clients retry and back off.
+    #[arg(long, value_name = "N")]
+    rate_limit: Option<u32>,
+    /// Applies `--rate-limit` to each client IP address separately, rather than to all clients
+    /// together. Requests over `--gateway-socket` share one limit.
+    #[arg(long, requires = "rate_limit")]
+    rate_limit_per_ip: bool,
+    /// Lowers resource limits below mainnet's, to surface limit failures locally: Wasm modules
+    /// installed through the gateway are limited to 1 MiB, and their canisters get 1 GiB of Wasm
+    /// memory. Instruction limits stay at mainnet's, as PocketIC can't lower them.
+    #[arg(long)]
+    strict_limits: bool,
+    /// Makes canisters pay for their cycles as on mainnet: the gateway rejects the provisional
+    /// calls that create and top up canisters with free cycles, so they must go through the cycles
+    /// ledger or the cycles minting canister. Execution and storage are always charged at mainnet
+    /// rates for the subnet's size.
+    #[arg(long)]
+    mainnet_costs: bool,
+    /// Starts this many isolated networks on the same PocketIC server, each with its own gateway,
+    /// and lists them in `pool.json` in the status directory, for sharding test suites. The first
+    /// is set up by all the other flags; the rest only get the same subnets and ICP features, on
+    /// fresh state or on `--base-state`. Their gateways are PocketIC's own, without the launcher's
+    /// in front, so they are limited to loopback and can't be combined with `--access-token`.
+    /// Requires `--status-dir`.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "status_dir",
+        conflicts_with = "access_token",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pool: Option<u16>,
     /// List of subnets to create. `--subnet=nns` is always implied. Defaults to `--subnet=application`.
     #[arg(long, value_enum, action = ArgAction::Append)]
     subnet: Vec<SubnetKind>,
@@ -56,12 +448,268 @@ struct Cli {
     /// Implies `--subnet=bitcoin`.
     #[arg(long, action = ArgAction::Append)]
     dogecoind_addr: Vec<String>,
+    /// Deploys the ckBTC minter, ledger, index and Bitcoin checker against the local bitcoind when
+    /// the network starts from fresh state. Takes a directory containing their Wasm modules as
+    /// named in IC releases (`ic-ckbtc-minter.wasm.gz`, `ic-icrc1-ledger.wasm.gz`,
+    /// `ic-icrc1-index-ng.wasm.gz` and `ic-btc-checker.wasm.gz`). Their IDs are recorded in the
+    /// canister registry in `--status-dir` as `ckbtc-minter`, `ckbtc-ledger` and so on. Requires
+    /// `--bitcoind-addr`.
+    #[arg(long, value_name = "WASM_DIR", requires = "bitcoind_addr")]
+    ckbtc: Option<PathBuf>,
+    /// Installs the EVM RPC canister and the ckETH ledger at their mainnet canister IDs. Takes a
+    /// directory containing their Wasm modules (`evm_rpc.wasm.gz` and `ic-icrc1-ledger.wasm.gz`).
+    /// All Ethereum JSON-RPC requests go to `--eth-rpc-url`, or to a built-in mock node that
+    /// serves a chain with a single empty block. Their IDs are recorded in the canister registry
+    /// in `--status-dir` as `evm-rpc` and `cketh-ledger`. Implies `--subnet=fiduciary`.
+    #[arg(long, value_name = "WASM_DIR")]
+    cketh_mock: Option<PathBuf>,
+    /// JSON-RPC endpoint of a local Ethereum node, such as anvil or hardhat, for `--cketh-mock`.
+    #[arg(long, value_name = "URL", requires = "cketh_mock")]
+    eth_rpc_url: Option<String>,
+    /// Name of a secp256k1 ECDSA key for canisters to sign with, e.g. `dfx_test_key`. The local
+    /// network holds `dfx_test_key`, `test_key_1` and `key_1`, on the II subnet, which this adds.
+    /// Keys are recorded in the status file.
+    #[arg(long, value_name = "NAME", value_parser = threshold_key::parse_ecdsa_key, action = ArgAction::Append)]
+    ecdsa_key: Vec<String>,
+    /// Schnorr key for canisters to sign with, as `[ALGORITHM:]NAME` where `ALGORITHM` is
+    /// `bip340secp256k1` (the default) or `ed25519`, e.g. `ed25519:dfx_test_key`. Key names are
+    /// as for `--ecdsa-key`.
+    #[arg(long, value_name = "[ALGORITHM:]NAME", value_parser = threshold_key::parse_schnorr_key, action = ArgAction::Append)]
+    schnorr_key: Vec<(threshold_key::SchnorrAlgorithm, String)>,
+    /// Enables the vetKD system API, for canisters that derive encryption keys with
+    /// `vetkd_derive_key`. The local network's BLS12-381 G2 test master keys are named as for
+    /// `--ecdsa-key`, and recorded in the status file.
+    #[arg(long)]
+    vetkd: bool,
+    /// Installs a mock exchange rate canister at the XRC's mainnet canister ID, which answers
+    /// `get_exchange_rate` with the rates from `--xrc-rate` and `--xrc-rates`, and in reverse.
+    /// Rates can be changed while the network runs with the `xrc set-rate` subcommand.
+    #[arg(long)]
+    xrc_mock: bool,
+    /// Rate of the exchange rate mock, as `BASE/QUOTE=RATE`, e.g. `ICP/USD=12.5`.
+    #[arg(long, value_name = "BASE/QUOTE=RATE", value_parser = xrc::parse_rate, action = ArgAction::Append, requires = "xrc_mock")]
+    xrc_rate: Vec<((String, String), u64)>,
+    /// JSON file of rates for the exchange rate mock, mapping `BASE/QUOTE` to a rate, e.g.
+    /// `{"ICP/USD": 12.5}`. `--xrc-rate` takes precedence.
+    #[arg(long, value_name = "FILE", requires = "xrc_mock")]
+    xrc_rates: Option<PathBuf>,
     /// Installs the Internet Identity canister.
     #[arg(long)]
     ii: bool,
+    /// Disables captchas during Internet Identity registration. Requires `--ii` or `--nns`.
+    #[arg(long)]
+    ii_disable_captcha: bool,
+    /// Origin that Internet Identity treats as related, so that it derives the same principals
+    /// for it as for the other related origins. Requires `--ii` or `--nns`.
+    #[arg(long, value_name = "ORIGIN", action = ArgAction::Append)]
+    ii_related_origin: Vec<String>,
+    /// Rate limit of Internet Identity registrations, as `N/INTERVAL`: bursts of up to N
+    /// registrations, with one more allowed every INTERVAL (e.g. `1000/1s`). Requires `--ii` or
+    /// `--nns`.
+    #[arg(long, value_name = "N/INTERVAL", value_parser = ii::parse_rate_limit)]
+    ii_registration_rate_limit: Option<(u64, Duration)>,
+    /// Internet Identity anchor to register for a test user when the network starts from fresh
+    /// state, as `NAME=PEM_FILE`. The key in the PEM file is the anchor's only device, so tests
+    /// can log in by signing with it. Anchor numbers are recorded in the canister registry in
+    /// `--status-dir`. Requires `--ii` or `--nns`.
+    #[arg(long, value_name = "NAME=PEM_FILE", value_parser = fixture::parse_named::<PathBuf>, action = ArgAction::Append)]
+    ii_anchor: Vec<(String, PathBuf)>,
     /// Installs the NNS and SNS. Implies `--ii` and `--subnet=sns`.
     #[arg(long)]
     nns: bool,
+    /// Neuron to create when the network starts from fresh state, as
+    /// `PRINCIPAL:STAKE:DISSOLVE_DELAY` with the stake in ICP, e.g. `2vxsx-fae:100:365d`. Neuron
+    /// IDs are recorded in the canister registry in `--status-dir`. Can also be set in the
+    /// `--preload` manifest. Requires `--nns`.
+    #[arg(long, value_name = "PRINCIPAL:STAKE:DISSOLVE_DELAY", value_parser = governance::parse_test_neuron, action = ArgAction::Append, requires = "nns")]
+    test_neuron: Vec<governance::TestNeuron>,
+    /// Makes the other test neurons follow the first one on all topics when the network starts
+    /// from fresh state. Its proposals are then decided as soon as they are submitted, instead of
+    /// at the end of the voting period, as long as the test neurons hold a majority of the voting
+    /// power.
+    #[arg(long, requires = "nns")]
+    nns_instant_execution: bool,
+    /// Minimum stake of a neuron, in ICP, set by a proposal of the test neurons when the network
+    /// starts from fresh state.
+    #[arg(long, value_name = "ICP", value_parser = ledger::parse_icp, requires = "nns")]
+    nns_neuron_minimum_stake: Option<u64>,
+    /// Cost of a rejected proposal to its proposer, in ICP, set by a proposal of the test neurons
+    /// when the network starts from fresh state.
+    #[arg(long, value_name = "ICP", value_parser = ledger::parse_icp, requires = "nns")]
+    nns_reject_cost: Option<u64>,
+    /// Skips installing the NNS dapp frontend with `--nns`. Otherwise its URL is printed and
+    /// recorded in the status file.
+    #[arg(long, requires = "nns")]
+    no_nns_dapp: bool,
+    /// Installs the SNS aggregator from this Wasm module (e.g. `sns_aggregator.wasm.gz` from an
+    /// NNS dapp release) at its mainnet canister ID, so that frontends reading aggregated SNS data
+    /// work unmodified. Its URL is printed and recorded in the status file. Requires `--nns`.
+    #[arg(long, value_name = "WASM", requires = "nns")]
+    sns_aggregator: Option<PathBuf>,
+    /// SNS Wasm modules that SNS-W deploys SNSes from: `pinned` for the ones bundled with
+    /// PocketIC, `latest` for the ones currently on mainnet (fetched from `--fork-url`), or a
+    /// directory of modules named as in IC releases (e.g. `sns-governance-canister.wasm.gz`),
+    /// where missing ones stay pinned. Added when the network starts from fresh state, so the
+    /// network works offline afterwards. Requires `--nns`.
+    #[arg(long, value_name = "pinned|latest|DIR", value_parser = sns_wasm::parse_source, default_value = "pinned", requires = "nns")]
+    sns_wasms: sns_wasm::SnsWasmSource,
+    /// Canisters to copy from mainnet into the local network at the same canister IDs. Their Wasm
+    /// and stable memory are read from each canister's most recent mainnet snapshot, so
+    /// `--fork-identity` must be a controller. Only works for canister IDs that fall within the
+    /// ranges of the local subnets.
+    #[arg(long, action = ArgAction::Append, requires = "fork_identity")]
+    fork_canister: Vec<Principal>,
+    /// PEM file of an identity that controls the `--fork-canister` canisters on mainnet.
+    #[arg(long)]
+    fork_identity: Option<PathBuf>,
+    /// URL of the mainnet API used by `--fork-canister`, `--sns-wasms latest` and
+    /// `--mainnet-passthrough`.
+    #[arg(long, default_value = "https://icp-api.io")]
+    fork_url: String,
+    /// Manifest of canisters (`canisters.toml`) to create and install once the network is ready.
+    /// Their IDs are recorded in the status file. When the network resumes from existing state,
+    /// the canisters are assumed to be installed already.
+    #[arg(long)]
+    preload: Option<PathBuf>,
+    /// Freezing threshold, in seconds, of the canisters the launcher sets up (system canisters,
+    /// `--preload` ones and the others in the canister registry), applied on every start.
+    #[arg(long, value_name = "SECONDS")]
+    freezing_threshold: Option<u64>,
+    /// Reserved cycles limit of the canisters the launcher sets up, applied on every start.
+    #[arg(long, value_name = "CYCLES")]
+    reserved_cycles_limit: Option<u128>,
+    /// Compute allocation, in percent, of the canisters the launcher sets up, applied on every
+    /// start.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u64).range(0..=100))]
+    compute_allocation: Option<u64>,
+    /// ICP to mint to a ledger account when the network starts from fresh state, as
+    /// `ACCOUNT=ICP` where the account is a principal or an account identifier, e.g.
+    /// `--ledger-account 2vxsx-fae=100`. Can also be set in the `--preload` manifest.
+    #[arg(long, value_name = "ACCOUNT=ICP", value_parser = ledger::parse_account_balance, action = ArgAction::Append)]
+    ledger_account: Vec<(ledger::AccountId, u64)>,
+    /// Cycles to give to a principal when the network starts from fresh state, as
+    /// `PRINCIPAL=CYCLES`. Canisters (including `--preload` ones) are topped up directly; other
+    /// principals receive the cycles in their cycles ledger account.
+    #[arg(long, value_name = "PRINCIPAL=CYCLES", value_parser = cycles::parse_principal_cycles, action = ArgAction::Append)]
+    cycles_for: Vec<(Principal, u128)>,
+    /// Test identity to provision when the network starts from fresh state, as
+    /// `NAME[:PEM_FILE]`. The identity is imported from `PEM_FILE`, or else generated (reusing
+    /// the one generated by a previous start), saved as `identities/NAME.pem` in `--status-dir`,
+    /// funded with `--identity-icp` and `--identity-cycles`, and its principal recorded in the
+    /// canister registry.
+    #[arg(long = "identity", value_name = "NAME[:PEM_FILE]", value_parser = identity::parse_identity, action = ArgAction::Append, requires = "status_dir")]
+    test_identity: Vec<(String, Option<PathBuf>)>,
+    /// ICP to mint to the default account of each `--identity`.
+    #[arg(long, value_name = "ICP", default_value = "100", value_parser = ledger::parse_icp)]
+    identity_icp: u64,
+    /// Cycles to deposit into the cycles ledger account of each `--identity`.
+    #[arg(long, value_name = "CYCLES", default_value = "100000000000000")]
+    identity_cycles: u128,
+    /// Lets any principal request ICP and cycles through the control API, with the `faucet`
+    /// subcommand or a plain `POST` of `{"principal": "...", "amount": "10"}` with content type
+    /// `application/json` to the faucet's URL (add `"token": "cycles"` for cycles). The faucet's
+    /// URL is recorded in the status file. Like the rest of the control API, it refuses requests
+    /// from browsers.
+    #[arg(long)]
+    faucet: bool,
+    /// Maximum ICP the faucet gives to each principal.
+    #[arg(long, value_name = "ICP", default_value = "1000", value_parser = ledger::parse_icp)]
+    faucet_max_icp: u64,
+    /// Maximum cycles the faucet gives to each principal.
+    #[arg(long, value_name = "CYCLES", default_value = "1000000000000000")]
+    faucet_max_cycles: u128,
+    /// Installs the ICP index canister from this Wasm module (e.g. `ic-icp-index-canister.wasm.gz`
+    /// from an IC release) at its mainnet canister ID, indexing the ICP ledger, so that wallets can
+    /// query transaction histories. Its ID is recorded in the canister registry in `--status-dir`
+    /// as `icp-index`.
+    #[arg(long, value_name = "WASM")]
+    icp_index: Option<PathBuf>,
+    /// Installs the cycles ledger's index canister from this ICRC index Wasm module (e.g.
+    /// `ic-icrc1-index-ng.wasm.gz` from an IC release) at its mainnet canister ID. Its ID is
+    /// recorded in the canister registry in `--status-dir` as `cycles-ledger-index`.
+    #[arg(long, value_name = "WASM")]
+    cycles_ledger_index: Option<PathBuf>,
+    /// ICRC-1/ICRC-2 ledger to deploy when the network starts from fresh state, as
+    /// `NAME:SYMBOL:DECIMALS[:PREMINT]` where `PREMINT` lists initial balances as
+    /// `PRINCIPAL=AMOUNT[,...]`, e.g. `Test Token:TST:8:2vxsx-fae=1000`. Ledger IDs are recorded in
+    /// the canister registry in `--status-dir` under the lowercased symbol. The anonymous principal
+    /// is the minting account. Requires `--icrc-ledger-wasm`.
+    #[arg(long, value_name = "NAME:SYMBOL:DECIMALS[:PREMINT]", value_parser = icrc::parse_token, action = ArgAction::Append, requires = "icrc_ledger_wasm")]
+    icrc_token: Vec<icrc::IcrcToken>,
+    /// Wasm module of the ICRC-1 ledger deployed by `--icrc-token` (e.g.
+    /// `ic-icrc1-ledger.wasm.gz` from an IC release).
+    #[arg(long, value_name = "WASM")]
+    icrc_ledger_wasm: Option<PathBuf>,
+    /// Number of blocks a ledger holds before it moves some to an archive canister, for the ICP
+    /// ledger and the ledgers deployed by the launcher. Defaults to twice `--ledger-archive-blocks`.
+    /// Archives are recorded in the canister registry in `--status-dir` as `<ledger>-archive-<n>`.
+    #[arg(long, value_name = "N")]
+    ledger_archive_threshold: Option<u64>,
+    /// Number of blocks a ledger moves to an archive canister at once. Defaults to half of
+    /// `--ledger-archive-threshold`.
+    #[arg(long, value_name = "N")]
+    ledger_archive_blocks: Option<u64>,
+    /// Principal to create a cycles wallet for when the network starts from fresh state, as
+    /// `PRINCIPAL[=CYCLES]` (100T cycles by default). The principal controls the wallet and is its
+    /// custodian. Wallet IDs are recorded in the canister registry in `--status-dir`. Requires
+    /// `--cycles-wallet-wasm`.
+    #[arg(long, value_name = "PRINCIPAL[=CYCLES]", value_parser = wallet::parse_wallet, action = ArgAction::Append, requires = "cycles_wallet_wasm")]
+    cycles_wallet: Vec<(Principal, u128)>,
+    /// Wasm module of the wallets created by `--cycles-wallet` (e.g. `wallet.wasm` from a
+    /// cycles-wallet release).
+    #[arg(long, value_name = "WASM")]
+    cycles_wallet_wasm: Option<PathBuf>,
+    /// Installs the Candid UI from this Wasm module (e.g. `candid_ui.wasm` from a Candid release)
+    /// and prints a link to it for each canister in the canister registry and each `--preload`
+    /// canister. Its ID is recorded in the registry as `candid-ui`, and its URL in the status file.
+    #[arg(long, value_name = "WASM")]
+    candid_ui: Option<PathBuf>,
+    /// Installs the blackhole canister from this Wasm module (e.g. `blackhole-opt.wasm` from an
+    /// ic-blackhole release) at a fixed canister ID, controlled only by itself, for canister
+    /// monitoring code that expects one. Its ID is recorded in the canister registry in
+    /// `--status-dir` as `blackhole`.
+    #[arg(long, value_name = "WASM")]
+    blackhole: Option<PathBuf>,
+    /// Asset canister to serve a local directory from, as `NAME=DIR`, e.g.
+    /// `--asset-canister frontend=./dist`. The directory is uploaded on every start, and the
+    /// canister's ID is recorded in the canister registry in `--status-dir` under `NAME`.
+    /// Requires `--asset-canister-wasm`.
+    #[arg(long, value_name = "NAME=DIR", value_parser = assets::parse_asset_canister, action = ArgAction::Append, requires = "asset_canister_wasm")]
+    asset_canister: Vec<(String, PathBuf)>,
+    /// Wasm module of the asset canisters created by `--asset-canister` (e.g.
+    /// `assetstorage.wasm.gz` from an SDK release).
+    #[arg(long, value_name = "WASM")]
+    asset_canister_wasm: Option<PathBuf>,
+    /// Uploads the `--asset-canister` directories again whenever their files change.
+    #[arg(long, requires = "asset_canister")]
+    watch_assets: bool,
+    /// Custom Wasm module of a system canister, as `NAME=WASM`, e.g.
+    /// `--system-canister-wasm ledger=ledger.wasm.gz`. The canister is upgraded to it after PocketIC
+    /// installs its bundled build, keeping its initial state, and again on every start. `NAME` is
+    /// one of `registry`, `governance`, `ledger`, `root`, `cmc`, `lifeline`, `ii`, `nns-dapp`,
+    /// `sns-wasm` or `cycles-ledger`.
+    #[arg(long, value_name = "NAME=WASM", value_parser = system::parse_wasm_override, action = ArgAction::Append)]
+    system_canister_wasm: Vec<(String, PathBuf)>,
+    /// Principal to add as a controller of every canister the launcher creates (`--icrc-token`
+    /// ledgers, `--preload` canisters without `controllers`, `--ckbtc`, `--xrc-mock` and so on),
+    /// so that a developer identity can upgrade and manage them. The launcher keeps control
+    /// through the anonymous principal. The blackhole stays controlled only by itself.
+    #[arg(long, action = ArgAction::Append)]
+    controller: Vec<Principal>,
+    /// Shell command to run once the network is ready and the status file is written, e.g. a
+    /// seeding or deployment script. Commands run in order, and the launcher stops if one fails.
+    /// The network is described by `ICP_CLI_NETWORK_LAUNCHER_GATEWAY_URL`, `_GATEWAY_PORT`,
+    /// `_CONTROL_PORT`, `_CONFIG_PORT`, `_INSTANCE_ID`, `_ROOT_KEY`,
+    /// `_DEFAULT_EFFECTIVE_CANISTER_ID` and `_STATUS_DIR`, and each canister in the canister
+    /// registry by `CANISTER_ID_<NAME>`.
+    #[arg(long, value_name = "COMMAND", action = ArgAction::Append)]
+    on_ready: Vec<String>,
+    /// Shell command to run when the launcher is asked to stop, before the network is torn down,
+    /// e.g. to export logs or take a final snapshot. Commands run in order with the same
+    /// environment as `--on-ready`, and the control API is still available. Failures are reported
+    /// but don't stop the shutdown.
+    #[arg(long, value_name = "COMMAND", action = ArgAction::Append)]
+    on_shutdown: Vec<String>,
     /// Path to the pocket-ic server binary. By default, looks for `pocket-ic` next to the launcher.
     /// The launcher is unlikely to be usable with a different version than it shipped with.
     #[arg(long)]
@@ -73,16 +721,259 @@ struct Cli {
     #[arg(long)]
     stderr_file: Option<PathBuf>,
     /// Directory to write status signal files to. Used by automated setups.
-    #[arg(long)]
+    /// Subcommands use it to find the running network.
+    #[arg(long, global = true)]
     status_dir: Option<PathBuf>,
+    /// Encrypts the archives created by `export`, `snapshot push` and `fixture create`. The
+    /// passphrase is read from `ICP_CLI_NETWORK_LAUNCHER_STATE_KEY`, or else from the system
+    /// keychain (service `icp-cli-network-launcher`, account `state-key`). Encrypted archives are
+    /// always decrypted the same way when imported.
+    #[arg(long, global = true)]
+    encrypt_state: bool,
     /// Enables verbose logging from pocket-ic. By default only errors are printed.
     #[arg(long)]
     verbose: bool,
     #[arg(trailing_var_arg = true, hide = true, allow_hyphen_values = true)]
     unknown_args: Vec<String>,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+/// Operations on an existing network. Without a subcommand, the launcher starts a network.
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Packages the state directory of a stopped network into a `.tar.zst` archive, along with
+    /// a manifest describing the network. Pass `--status-dir` to include the network's topology.
+    Export {
+        /// Path of the archive to create.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Unpacks an archive created by `export` into `--state-dir`.
+    Import {
+        /// Path of the archive to unpack.
+        archive: PathBuf,
+        /// Replaces the contents of `--state-dir` if it is not empty.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Recreates the canisters of a dfx project's local replica on the running network, at the
+    /// same IDs where possible, installing their last built Wasm modules. Canister data is not
+    /// migrated. Requires `--status-dir`.
+    ImportDfx {
+        /// Path of the dfx project.
+        #[arg(default_value = ".")]
+        project: PathBuf,
+    },
+    /// Stops the network if it is running, then deletes its state directory and status files.
+    Clean {
+        /// Also deletes the snapshots of `--state-dir`.
+        #[arg(long)]
+        include_snapshots: bool,
+        /// Starts a fresh network with the same arguments afterwards. Requires the network to
+        /// have been running with `--status-dir`. `--access-token` and `--config-token` are not
+        /// recorded, so they must be set in their environment variables to relaunch.
+        #[arg(long)]
+        relaunch: bool,
+    },
+    /// Same as `clean --relaunch`.
+    Reset {
+        /// Also deletes the snapshots of `--state-dir`.
+        #[arg(long)]
+        include_snapshots: bool,
+    },
+    /// Gives ICP and cycles to a principal from the running network's faucet. The network must
+    /// have been started with `--faucet` and `--status-dir`.
+    Faucet {
+        /// Principal to fund.
+        principal: Principal,
+        /// ICP to give to the principal's default account.
+        #[arg(long, value_name = "ICP", value_parser = ledger::parse_icp)]
+        icp: Option<u64>,
+        /// Cycles to deposit into the principal's cycles ledger account.
+        #[arg(long)]
+        cycles: Option<u128>,
+    },
+    /// Prints the running network's live status as JSON, including the size of its state
+    /// directory. Requires `--status-dir`.
+    Status,
+    /// Prints the number of queries each canister in the registry has answered, with their
+    /// instructions and payload sizes, busiest first. Requires `--status-dir`.
+    Stats {
+        /// Prints the statistics as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Empties the gateway's cache on a network started with `--gateway-cache`, e.g. after
+    /// deploying a frontend. Requires `--status-dir`.
+    PurgeGatewayCache,
+    /// Prints the canisters whose queries and calls the gateway rejected most, and the most common
+    /// reject messages, on a network started with `--error-report`. Requires `--status-dir`.
+    Errors {
+        /// Prints the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Prints the logs of canisters of the running network, merged in order of time, from
+    /// whichever subnets host them. Requires `--status-dir`.
+    Logs {
+        /// Canister whose logs to print, by registry name or canister ID. Can be given multiple
+        /// times; defaults to all canisters in the registry.
+        #[arg(long = "canister", action = ArgAction::Append)]
+        canisters: Vec<String>,
+        /// Keeps printing new log records as they are written, until interrupted.
+        #[arg(long)]
+        follow: bool,
+    },
+    /// Prints each canister's change in cycles and memory since the network became ready, and the
+    /// instructions its queries executed, biggest cycle consumers first. Requires `--status-dir`.
+    Report {
+        /// Prints the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Moves the running network's time forward, e.g. by `3d4h`, and executes a round so that
+    /// timers and heartbeats due by then run, printing the network's time afterwards. Requires
+    /// `--status-dir`.
+    AdvanceTime {
+        /// How far to move time forward, as a sum of days, hours, minutes and seconds, e.g.
+        /// `1d12h` or `90s`.
+        #[arg(value_parser = duration::parse_duration)]
+        duration: Duration,
+    },
+    /// Changes the delay before each round of the running network, e.g. to simulate slower
+    /// finality partway through a test. Requires `--status-dir`.
+    ArtificialDelay {
+        /// The new delay, in milliseconds. Omit it to execute rounds as fast as possible.
+        ms: Option<u64>,
+    },
+    /// Runs the calls listed in a TOML manifest against the running network and reports their
+    /// cycles, estimated instructions and wall time per call. Requires `--status-dir`.
+    Bench {
+        /// Path of the manifest listing the calls.
+        manifest: PathBuf,
+        /// Prints the results as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Sends concurrent calls to a canister through the running network's gateway for a while,
+    /// then reports throughput and latency percentiles. Requires `--status-dir`.
+    Loadtest {
+        /// Canister to call, by registry name or canister ID.
+        #[arg(long)]
+        canister: String,
+        /// Update method to call.
+        #[arg(long, required_unless_present = "query_method")]
+        update_method: Option<String>,
+        /// Query method to call.
+        #[arg(long)]
+        query_method: Option<String>,
+        /// Share of calls that are queries, between 0 and 1, when both methods are given.
+        #[arg(long, default_value = "0.5", value_parser = parse_ratio)]
+        query_ratio: f64,
+        /// Argument of the calls, in Candid text. Defaults to no arguments.
+        #[arg(long, conflicts_with = "payload_size")]
+        arg: Option<String>,
+        /// Sends a blob of this many bytes as the calls' argument instead.
+        #[arg(long, value_name = "BYTES")]
+        payload_size: Option<usize>,
+        /// Number of calls in flight at once.
+        #[arg(long, default_value = "10")]
+        concurrency: usize,
+        /// How long to send calls for, e.g. `30s` or `5m`.
+        #[arg(long, default_value = "30s", value_parser = duration::parse_duration)]
+        duration: Duration,
+    },
+    /// Re-sends the ingress messages recorded with `--record-ingress` to the running network in
+    /// order, moving its time forward to when each was recorded. For a faithful reproduction,
+    /// start the network from the same fresh or restored state the recording started from.
+    /// Requires `--status-dir`.
+    Replay {
+        /// Path of the recording.
+        recording: PathBuf,
+    },
+    /// Executes rounds on a network started with `--no-auto-progress`, printing the network's
+    /// time afterwards. Requires `--status-dir`.
+    Tick {
+        /// Number of rounds to execute.
+        #[arg(default_value = "1")]
+        rounds: u64,
+    },
+    /// Manages named branches of `--state-dir`, so that separate network states can be kept
+    /// side by side and switched between.
+    State {
+        #[command(subcommand)]
+        command: branch::StateCommand,
+    },
+    /// Manages fixture packs: network states bundled with their canister registry and identities.
+    Fixture {
+        #[command(subcommand)]
+        command: fixture::FixtureCommand,
+    },
+    /// Operates on individual canisters of the running network.
+    Canister {
+        #[command(subcommand)]
+        command: canister::CanisterCommand,
+    },
+    /// Works with NNS proposals on the running network. Requires `--status-dir`.
+    Proposal {
+        #[command(subcommand)]
+        command: governance::ProposalCommand,
+    },
+    /// Manages snapshots of a state directory.
+    Snapshot {
+        #[command(subcommand)]
+        command: snapshot::SnapshotCommand,
+    },
+    /// Works with the exchange rate mock of the running network. Requires `--status-dir` and
+    /// `--xrc-mock`.
+    Xrc {
+        #[command(subcommand)]
+        command: xrc::XrcCommand,
+    },
+    /// Works with SNSes on the running network. Requires `--status-dir` and an NNS.
+    Sns {
+        #[command(subcommand)]
+        command: sns::SnsCommand,
+    },
+    /// Works with the ledgers of the running network. Requires `--status-dir`.
+    Ledger {
+        #[command(subcommand)]
+        command: ledger_archive::LedgerCommand,
+    },
+    /// Holds back the gateway's requests for a subnet's canisters for a while, on a network started
+    /// with `--chaos`, to check how a dapp copes when one subnet lags. Requires `--status-dir`.
+    StallSubnet {
+        /// Subnet ID, or a kind of subnet such as `application` or `fiduciary` for the first
+        /// subnet of that kind.
+        subnet: String,
+        /// How long to stall the subnet for, e.g. `30s`.
+        #[arg(value_parser = duration::parse_duration)]
+        duration: Duration,
+    },
+    /// Simulates an upgrade of a subnet on a network started with `--chaos`: the gateway holds back
+    /// requests for the subnet's canisters for the downtime, to check that clients and in-flight
+    /// calls survive mainnet maintenance, while other subnets keep serving requests. The subnet's
+    /// canisters keep executing meanwhile, as PocketIC can't stop or restart a single subnet.
+    /// Returns once the subnet is back up. Requires `--status-dir`.
+    UpgradeSubnet {
+        /// Subnet ID, or a kind of subnet such as `application` or `fiduciary` for the first
+        /// subnet of that kind.
+        subnet: String,
+        /// How long the subnet is down for.
+        #[arg(long, value_parser = duration::parse_duration, default_value = "30s")]
+        downtime: Duration,
+    },
+    /// Creates and deletes throwaway instances on the running network's PocketIC server, for a
+    /// fresh network per test. Requires `--status-dir`.
+    Ephemeral {
+        #[command(subcommand)]
+        command: ephemeral::EphemeralCommand,
+    },
 }
 
-#[derive(ValueEnum, Clone)]
+#[derive(ValueEnum, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum SubnetKind {
     Application,
     System,
@@ -93,27 +984,311 @@ enum SubnetKind {
     Sns,
 }
 
+impl SubnetKind {
+    /// Adds a subnet of this kind to the instance `pic` configures.
+    fn add_to(&self, pic: PocketIcBuilder) -> PocketIcBuilder {
+        match self {
+            SubnetKind::Application => pic.with_application_subnet(),
+            SubnetKind::System => pic.with_system_subnet(),
+            SubnetKind::VerifiedApplication => pic.with_verified_application_subnet(),
+            SubnetKind::Bitcoin => pic.with_bitcoin_subnet(),
+            SubnetKind::Fiduciary => pic.with_fiduciary_subnet(),
+            SubnetKind::Nns => pic.with_nns_subnet(),
+            SubnetKind::Sns => pic.with_sns_subnet(),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let Cli {
         gateway_port,
+        gateway,
         config_port,
+        control_port,
         bind,
+        access_token,
+        config_loopback_only,
+        config_token,
         state_dir,
+        snapshot_on_exit,
+        state_quota,
+        base_state,
+        ephemeral,
         artificial_delay_ms,
+        no_auto_progress,
+        initial_time,
+        time_dilation,
+        max_rounds,
+        mock_outcalls,
+        outcall_allow,
+        outcall_deny,
+        record_outcalls,
+        replay_outcalls,
+        outcall_proxy,
+        max_duration,
+        deterministic,
+        record_ingress,
+        record_http,
+        access_log,
+        websocket_upstream,
+        compress,
+        compress_min_size,
+        gateway_cache,
+        gateway_headers,
+        announce_canisters,
+        gateway_error_pages,
+        gateway_health,
+        gateway_socket,
+        trust_gateway_socket,
+        gateway_http2,
+        gateway_tls_cert,
+        gateway_tls_key,
+        config_tls,
+        alias,
+        serve_local,
+        mainnet_passthrough,
+        tunnel,
+        tunnel_command,
+        tunnel_canister,
+        domain,
+        dns_port,
+        announce_mdns,
+        usage_report,
+        error_report,
+        chaos,
+        ingress_delay_rate,
+        ingress_delay,
+        ingress_reject_rate,
+        inject_latency_rate,
+        inject_latency,
+        inject_error_rate,
+        max_canister_creations,
+        rate_limit,
+        rate_limit_per_ip,
+        strict_limits,
+        mainnet_costs,
+        pool,
         subnet,
         bitcoind_addr,
+        ckbtc,
+        cketh_mock,
+        eth_rpc_url,
+        ecdsa_key,
+        schnorr_key,
+        vetkd,
+        xrc_mock,
+        xrc_rate,
+        xrc_rates,
         dogecoind_addr,
         ii,
+        ii_disable_captcha,
+        ii_related_origin,
+        ii_registration_rate_limit,
+        ii_anchor,
         nns,
+        no_nns_dapp,
+        sns_aggregator,
+        sns_wasms,
+        test_neuron,
+        nns_instant_execution,
+        nns_neuron_minimum_stake,
+        nns_reject_cost,
+        fork_canister,
+        fork_identity,
+        fork_url,
+        preload,
+        freezing_threshold,
+        reserved_cycles_limit,
+        compute_allocation,
+        ledger_account,
+        cycles_for,
+        test_identity,
+        identity_icp,
+        identity_cycles,
+        faucet,
+        faucet_max_icp,
+        faucet_max_cycles,
+        icp_index,
+        candid_ui,
+        blackhole,
+        asset_canister,
+        asset_canister_wasm,
+        watch_assets,
+        system_canister_wasm,
+        controller,
+        on_ready,
+        on_shutdown,
+        cycles_ledger_index,
+        icrc_token,
+        icrc_ledger_wasm,
+        ledger_archive_threshold,
+        ledger_archive_blocks,
+        cycles_wallet,
+        cycles_wallet_wasm,
         pocketic_server_path,
         stdout_file,
         stderr_file,
         status_dir,
+        encrypt_state,
         verbose,
         interface_version: _,
         unknown_args: _,
+        command,
     } = get_errorchecked_args();
+    if let Some(command) = command {
+        return run_command(command, status_dir, state_dir, encrypt_state).await;
+    }
+    let fresh_state = base_state.is_none()
+        && match &state_dir {
+            Some(state_dir) => is_empty_dir(state_dir)?,
+            None => true,
+        };
+    // an archived base state is unpacked for the lifetime of the network
+    let mut _unpacked_base_state = None;
+    let base_state = match base_state {
+        Some(path) if path.is_file() => {
+            let tmpdir = TempDir::new().context("failed to create temporary directory")?;
+            let unpacked = tmpdir.path().join("state");
+            archive::import(&path, &unpacked, false).context("failed to unpack --base-state")?;
+            _unpacked_base_state = Some(tmpdir);
+            Some(unpacked)
+        }
+        base_state => base_state,
+    };
+    if let Some(base_state) = &base_state {
+        if !base_state.is_dir() {
+            anyhow::bail!("--base-state {} does not exist", base_state.display());
+        }
+        match &state_dir {
+            Some(state_dir) => {
+                if is_empty_dir(state_dir)? {
+                    snapshot::copy_dir(base_state, state_dir)
+                        .context("failed to seed state directory from --base-state")?;
+                }
+            }
+            None if !ephemeral => {
+                anyhow::bail!("--base-state requires --state-dir or --ephemeral")
+            }
+            None => {}
+        }
+    }
+    // SNS-W keeps its modules in the state, so they are only added once
+    let sns_wasms = if fresh_state {
+        sns_wasm::load(&sns_wasms, &fork_url).await?
+    } else {
+        vec![]
+    };
+    // read forked canisters before starting anything, since mainnet is the most likely thing to fail
+    let mut forked_canisters = vec![];
+    if !fork_canister.is_empty() {
+        let identity = fork_identity.expect("--fork-canister requires --fork-identity");
+        let agent = fork::agent(&fork_url, &identity)?;
+        for canister_id in fork_canister {
+            let canister = fork::fetch(&agent, canister_id)
+                .await
+                .with_context(|| format!("failed to fetch canister {canister_id} from mainnet"))?;
+            forked_canisters.push(canister);
+        }
+    }
+    let sns_aggregator_wasm = read_optional_wasm(sns_aggregator)?;
+    let ckbtc_wasms = ckbtc.as_deref().map(ckbtc::load).transpose()?;
+    let cketh_wasms = cketh_mock.as_deref().map(cketh::load).transpose()?;
+    let mut xrc_initial_rates = match &xrc_rates {
+        Some(path) => xrc::load_rates(path)?,
+        None => xrc::Rates::new(),
+    };
+    xrc_initial_rates.extend(xrc_rate);
+    let icp_index_wasm = read_optional_wasm(icp_index)?;
+    let cycles_ledger_index_wasm = read_optional_wasm(cycles_ledger_index)?;
+    let icrc_ledger_wasm = read_optional_wasm(icrc_ledger_wasm)?;
+    let cycles_wallet_wasm = read_optional_wasm(cycles_wallet_wasm)?;
+    let candid_ui_wasm = read_optional_wasm(candid_ui)?;
+    let blackhole_wasm = read_optional_wasm(blackhole)?;
+    let system_wasm_overrides = system::load(system_canister_wasm)?;
+    let asset_canister_wasm = read_optional_wasm(asset_canister_wasm)?;
+    let ii_config = ii::IiConfig {
+        disable_captcha: ii_disable_captcha,
+        related_origins: ii_related_origin,
+        registration_rate_limit: ii_registration_rate_limit,
+    };
+    if (!ii_config.is_empty() || !ii_anchor.is_empty()) && !ii && !nns {
+        anyhow::bail!("Internet Identity options require --ii or --nns");
+    }
+    let outcall_rules = if mock_outcalls.is_some()
+        || !outcall_allow.is_empty()
+        || !outcall_deny.is_empty()
+        || record_outcalls.is_some()
+        || replay_outcalls.is_some()
+        || outcall_proxy.is_some()
+    {
+        Some(outcalls::Rules {
+            mocks: match &mock_outcalls {
+                Some(path) => outcalls::load(path)?,
+                None => vec![],
+            },
+            allow: outcall_allow,
+            deny: outcall_deny,
+            recording: record_outcalls.as_deref().map(ingress::open).transpose()?,
+            replay: replay_outcalls
+                .as_deref()
+                .map(outcalls::Replay::load)
+                .transpose()?,
+            proxy: outcall_proxy,
+        })
+    } else {
+        None
+    };
+    let no_auto_progress =
+        no_auto_progress || deterministic || max_rounds.is_some() || outcall_rules.is_some();
+    let initial_time = initial_time.or(deterministic.then_some(DETERMINISTIC_INITIAL_TIME));
+    let mut ii_anchor_keys = vec![];
+    for (name, pem) in ii_anchor {
+        let public_key = identity::load(&pem)?
+            .public_key()
+            .with_context(|| format!("{} has no public key", pem.display()))?;
+        ii_anchor_keys.push((name, public_key));
+    }
+    let mut preload = preload.as_deref().map(preload::load).transpose()?;
+    let mut ledger_accounts = ledger_account;
+    let mut test_neurons = test_neuron;
+    if let Some(preload) = &mut preload {
+        ledger_accounts.append(&mut preload.ledger_accounts);
+        test_neurons.append(&mut preload.test_neurons);
+    }
+    // keys go to the status directory and balances into the state, so identities are only
+    // provisioned once
+    let mut cycles_for = cycles_for;
+    let mut test_identities = BTreeMap::new();
+    if fresh_state && let Some(status_dir) = &status_dir {
+        for (name, pem) in test_identity {
+            let principal = identity::provision(status_dir, &name, pem.as_deref(), deterministic)
+                .with_context(|| format!("failed to provision identity {name}"))?;
+            eprintln!("provisioned identity {name} as {principal}");
+            if identity_icp > 0 {
+                ledger_accounts.push((ledger::AccountId::from_principal(principal), identity_icp));
+            }
+            if identity_cycles > 0 {
+                cycles_for.push((principal, identity_cycles));
+            }
+            test_identities.insert(name, principal);
+        }
+    }
+    if pool.is_some() && bind.is_some_and(|bind| !bind.is_loopback()) {
+        anyhow::bail!(
+            "--pool can't expose its shards' gateways with --bind, as the launcher doesn't serve them"
+        );
+    }
+    if !test_neurons.is_empty() && !nns {
+        anyhow::bail!("test neurons require --nns");
+    }
+    if (nns_instant_execution || nns_neuron_minimum_stake.is_some() || nns_reject_cost.is_some())
+        && test_neurons.is_empty()
+    {
+        anyhow::bail!("NNS governance options require test neurons to vote with");
+    }
+    let ledger_archive_config =
+        ledger_archive::ArchiveConfig::from_args(ledger_archive_threshold, ledger_archive_blocks)?;
     // pocket-ic is expected to be installed next to the launcher (see package.sh)
     let pocketic_server_path = if let Some(path) = pocketic_server_path {
         path
@@ -131,9 +1306,116 @@ async fn main() -> anyhow::Result<()> {
         }
         assumed
     };
+    // while ingress is recorded, rejections are counted, faults are injected or limits or costs
+    // are enforced, the launcher serves the gateway port in front of PocketIC's gateway
+    let ingress_faults = chaos::Faults::new(ingress_delay, ingress_delay_rate, ingress_reject_rate);
+    let response_faults =
+        chaos::Faults::new(inject_latency, inject_latency_rate, inject_error_rate);
+    let gateway_proxy = if record_ingress.is_some()
+        || record_http.is_some()
+        || access_log.is_some()
+        || websocket_upstream.is_some()
+        || compress
+        || gateway_cache.is_some()
+        || gateway_headers.is_some()
+        || gateway_error_pages
+        || gateway_health
+        || gateway_http2
+        || gateway_tls_cert.is_some()
+        || error_report
+        || chaos
+        || ingress_faults.is_some()
+        || response_faults.is_some()
+        || max_canister_creations.is_some()
+        || rate_limit.is_some()
+        || strict_limits
+        || mainnet_costs
+        || !alias.is_empty()
+        || !serve_local.is_empty()
+        || !mainnet_passthrough.is_empty()
+        || access_token.is_some()
+    {
+        let listener = TcpListener::bind((
+            bind.unwrap_or(Ipv4Addr::LOCALHOST.into()),
+            gateway_port.unwrap_or(0),
+        ))
+        .await
+        .context("failed to bind the gateway port")?;
+        Some(listener)
+    } else {
+        None
+    };
+    // the additional gateways are served the same way as the main one
+    let extra_gateway_listeners = if gateway_proxy.is_some() {
+        let mut listeners = vec![];
+        for spec in &gateway {
+            listeners.push(gateways::bind(spec, bind.unwrap_or(Ipv4Addr::LOCALHOST.into())).await?);
+        }
+        Some(listeners)
+    } else {
+        None
+    };
+    let gateway_tls = match (&gateway_tls_cert, &gateway_tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+        _ => None,
+    };
+    let access_token =
+        access_token.map(|token| access_token::AccessToken::new(token, trust_gateway_socket));
+    let config_bind = bind.filter(|_| !config_loopback_only);
+    let exposed = config_bind.filter(|bind| !bind.is_loopback());
+    let config_tls = gateway_tls.clone().filter(|_| config_tls);
+    // with a token or TLS, PocketIC's admin API is served by the launcher in front of PocketIC
+    let admin_token = match (config_token, &access_token) {
+        (Some(config_token), _) => Some(access_token::AccessToken::new(config_token, false)),
+        (None, Some(access_token)) if exposed.is_some() => Some(access_token.clone()),
+        (None, _) => None,
+    };
+    let admin_listener = if admin_token.is_some() || (exposed.is_some() && config_tls.is_some()) {
+        Some((
+            TcpListener::bind((
+                config_bind.unwrap_or(Ipv4Addr::LOCALHOST.into()),
+                config_port.unwrap_or(0),
+            ))
+            .await
+            .context("failed to bind the config port")?,
+            admin_token,
+        ))
+    } else {
+        None
+    };
+    if let Some(exposed) = exposed
+        && admin_listener
+            .as_ref()
+            .is_none_or(|(_, token)| token.is_none())
+    {
+        eprintln!(
+            "Warning: PocketIC's admin API is reachable by anyone on {exposed}; use --access-token to require a token or --config-loopback-only to keep it local"
+        );
+    }
+    let gateway_socket_listener = gateway_socket
+        .as_deref()
+        .map(gateway::bind_socket)
+        .transpose()?;
+    let ingress_recording = record_ingress.as_deref().map(ingress::open).transpose()?;
+    let access_log = access_log.as_deref().map(ingress::open).transpose()?;
+    let header_rules = gateway_headers.as_deref().map(headers::load).transpose()?;
+    let websocket_upstream = match &websocket_upstream {
+        Some(addr) => Some(resolve_addrs(std::slice::from_ref(addr)).await?[0]),
+        None => None,
+    };
+    let dns_socket = match dns_port {
+        Some(dns_port) => Some(
+            UdpSocket::bind((bind.unwrap_or(Ipv4Addr::LOCALHOST.into()), dns_port))
+                .await
+                .context("failed to bind the DNS port")?,
+        ),
+        None => None,
+    };
+    let mdns_host = announce_mdns.map(|name| format!("{name}.local"));
+    let mdns_socket = mdns_host.as_ref().map(|_| mdns::bind()).transpose()?;
 
     // pocket-ic produces a lot of output so we're going to mute stderr for a moment
-    let (pic, mut child, topology, config_port) = try_with_maybe_muted_stderr(verbose, async {
+    let (pic, shards, mut child, config_port) = try_with_maybe_muted_stderr(verbose, async {
         // We learn the port by pocket-ic writing it to a file
         let tmpdir = TempDir::new().context("failed to create temporary directory")?;
         let port_file = tmpdir.path().join("pocketic.port");
@@ -172,11 +1454,15 @@ async fn main() -> anyhow::Result<()> {
         // the default TTL is 1m - increase to 30 days. We manually shut the network down instead of relying on idle timeout.
         cmd.args(["--ttl", "2592000"]);
         cmd.arg("--port-file").arg(&port_file);
-        if let Some(config_port) = config_port {
+        if let Some(config_port) = config_port
+            && admin_listener.is_none()
+        {
             cmd.args(["--port", &config_port.to_string()]);
         }
-        if let Some(bind) = bind {
-            cmd.arg("--ip-addr").arg(bind.to_string());
+        if let Some(config_bind) = config_bind
+            && admin_listener.is_none()
+        {
+            cmd.arg("--ip-addr").arg(config_bind.to_string());
         }
         if let Some(stdout_file) = stdout_file {
             let file =
@@ -205,127 +1491,815 @@ async fn main() -> anyhow::Result<()> {
         drop(watcher);
         // pocket-ic CLI setup ends here
         // initial HTTP setup
-        let mut pic = PocketIcBuilder::new()
-            .with_server_url(
-                format!("http://127.0.0.1:{config_port}/")
-                    .parse()
-                    .expect("valid url"),
-            )
-            .with_http_gateway(InstanceHttpGatewayConfig {
-                ip_addr: bind.map(|ip| ip.to_string()),
-                port: gateway_port,
-                domains: Some(vec!["localhost".to_string()]),
-                https_config: None,
-            });
-        if let Some(dir) = state_dir {
-            pic = pic.with_state_dir(dir);
-        }
-        if subnet.is_empty() {
-            pic = pic.with_application_subnet();
+        let (pocketic_gateway_bind, pocketic_gateway_port) = if gateway_proxy.is_some() {
+            (Some(IpAddr::from(Ipv4Addr::LOCALHOST)), None)
         } else {
-            for subnet in subnet {
-                match subnet {
-                    SubnetKind::Application => pic = pic.with_application_subnet(),
-                    SubnetKind::System => pic = pic.with_system_subnet(),
-                    SubnetKind::VerifiedApplication => pic = pic.with_verified_application_subnet(),
-                    SubnetKind::Bitcoin => pic = pic.with_bitcoin_subnet(),
-                    SubnetKind::Fiduciary => pic = pic.with_fiduciary_subnet(),
-                    SubnetKind::Nns => pic = pic.with_nns_subnet(),
-                    SubnetKind::Sns => pic = pic.with_sns_subnet(),
+            (bind, gateway_port)
+        };
+        let server_url: Url = format!("http://127.0.0.1:{config_port}/")
+            .parse()
+            .expect("valid url");
+        let bitcoind_addrs = if bitcoind_addr.is_empty() {
+            vec![]
+        } else {
+            resolve_addrs(&bitcoind_addr)
+                .await
+                .context("failed to resolve --bitcoind-addr")?
+        };
+        let dogecoind_addrs = if dogecoind_addr.is_empty() {
+            vec![]
+        } else {
+            resolve_addrs(&dogecoind_addr)
+                .await
+                .context("failed to resolve --dogecoind-addr")?
+        };
+        // the subnets and ICP features, which the shards of a --pool share
+        let configure = |mut pic: PocketIcBuilder| {
+            if subnet.is_empty() {
+                pic = pic.with_application_subnet();
+            } else {
+                for subnet in &subnet {
+                    pic = subnet.add_to(pic);
                 }
             }
-        }
-        pic = pic.with_nns_subnet();
-        // --bitcoind-addr and --dogecoind-addr imply --subnet=bitcoin
-        if !bitcoind_addr.is_empty() || !dogecoind_addr.is_empty() {
-            pic = pic.with_bitcoin_subnet();
-        }
-        let mut features = IcpFeatures {
-            cycles_minting: Some(IcpFeaturesConfig::DefaultConfig),
-            icp_token: Some(IcpFeaturesConfig::DefaultConfig),
-            cycles_token: Some(IcpFeaturesConfig::DefaultConfig),
-            registry: Some(IcpFeaturesConfig::DefaultConfig),
-            ..<_>::default()
+            pic = pic.with_nns_subnet();
+            // --bitcoind-addr and --dogecoind-addr imply --subnet=bitcoin
+            if !bitcoind_addr.is_empty() || !dogecoind_addr.is_empty() {
+                pic = pic.with_bitcoin_subnet();
+            }
+            // --cketh-mock implies --subnet=fiduciary, which hosts the ckETH canisters on mainnet
+            if cketh_wasms.is_some() {
+                pic = pic.with_fiduciary_subnet();
+            }
+            // the XRC's mainnet ID falls within the II subnet's range
+            if xrc_mock {
+                pic = pic.with_ii_subnet();
+            }
+            // the II subnet holds the threshold signing keys
+            if !ecdsa_key.is_empty() || !schnorr_key.is_empty() || vetkd {
+                pic = pic.with_ii_subnet();
+            }
+            let mut features = IcpFeatures {
+                cycles_minting: Some(IcpFeaturesConfig::DefaultConfig),
+                icp_token: Some(IcpFeaturesConfig::DefaultConfig),
+                cycles_token: Some(IcpFeaturesConfig::DefaultConfig),
+                registry: Some(IcpFeaturesConfig::DefaultConfig),
+                ..<_>::default()
+            };
+            // II subnet provides threshold signature keys (tECDSA) needed for Bitcoin/Dogecoin signing
+            if nns || ii || !bitcoind_addr.is_empty() || !dogecoind_addr.is_empty() {
+                pic = pic.with_ii_subnet();
+                features.ii = Some(IcpFeaturesConfig::DefaultConfig);
+            }
+            if nns {
+                pic = pic.with_sns_subnet();
+                features.nns_governance = Some(IcpFeaturesConfig::DefaultConfig);
+                if !no_nns_dapp {
+                    features.nns_ui = Some(IcpFeaturesConfig::DefaultConfig);
+                }
+                features.sns = Some(IcpFeaturesConfig::DefaultConfig);
+                features.canister_migration = Some(IcpFeaturesConfig::DefaultConfig);
+            }
+            if !bitcoind_addr.is_empty() {
+                features.bitcoin = Some(IcpFeaturesConfig::DefaultConfig);
+            }
+            if !dogecoind_addr.is_empty() {
+                features.dogecoin = Some(IcpFeaturesConfig::DefaultConfig);
+            }
+            pic = pic.with_icp_features(features);
+            if !bitcoind_addrs.is_empty() {
+                pic = pic.with_bitcoind_addrs(bitcoind_addrs.clone());
+            }
+            if !dogecoind_addrs.is_empty() {
+                pic = pic.with_dogecoind_addrs(dogecoind_addrs.clone());
+            }
+            pic
         };
-        // II subnet provides threshold signature keys (tECDSA) needed for Bitcoin/Dogecoin signing
-        if nns || ii || !bitcoind_addr.is_empty() || !dogecoind_addr.is_empty() {
-            pic = pic.with_ii_subnet();
-            features.ii = Some(IcpFeaturesConfig::DefaultConfig);
-        }
-        if nns {
-            pic = pic.with_sns_subnet();
-            features.nns_governance = Some(IcpFeaturesConfig::DefaultConfig);
-            features.nns_ui = Some(IcpFeaturesConfig::DefaultConfig);
-            features.sns = Some(IcpFeaturesConfig::DefaultConfig);
-            features.canister_migration = Some(IcpFeaturesConfig::DefaultConfig);
-        }
-        if !bitcoind_addr.is_empty() {
-            features.bitcoin = Some(IcpFeaturesConfig::DefaultConfig);
-        }
-        if !dogecoind_addr.is_empty() {
-            features.dogecoin = Some(IcpFeaturesConfig::DefaultConfig);
-        }
-        pic = pic.with_icp_features(features);
-        if !bitcoind_addr.is_empty() {
-            let addrs = resolve_addrs(&bitcoind_addr)
-                .await
-                .context("failed to resolve --bitcoind-addr")?;
-            pic = pic.with_bitcoind_addrs(addrs);
+        let mut pic = configure(
+            PocketIcBuilder::new()
+                .with_server_url(server_url.clone())
+                .with_http_gateway(InstanceHttpGatewayConfig {
+                    ip_addr: pocketic_gateway_bind.map(|ip| ip.to_string()),
+                    port: pocketic_gateway_port,
+                    domains: Some(
+                        ["localhost".to_string()]
+                            .into_iter()
+                            .chain(domain.clone())
+                            .chain(mdns_host.clone())
+                            .collect(),
+                    ),
+                    https_config: None,
+                }),
+        );
+        if let Some(dir) = &state_dir {
+            pic = pic.with_state_dir(dir.clone());
+        } else if ephemeral {
+            let base = base_state
+                .as_ref()
+                .expect("--ephemeral requires --base-state");
+            pic = pic.with_read_only_state(base);
         }
-        if !dogecoind_addr.is_empty() {
-            let addrs = resolve_addrs(&dogecoind_addr)
-                .await
-                .context("failed to resolve --dogecoind-addr")?;
-            pic = pic.with_dogecoind_addrs(addrs);
+        // the time is part of the state, so a resumed network keeps its own
+        if let Some(initial_time) = initial_time {
+            if fresh_state {
+                pic = pic.with_initial_time(Time::from_nanos_since_unix_epoch(initial_time));
+            } else {
+                eprintln!("Warning: --initial-time only applies to fresh state; ignoring it");
+            }
         }
         let pic = pic.build_async().await;
-        // pocket-ic crate doesn't currently support setting artificial delay via builder
-        let client = Client::new();
-        let progress_url = pic
-            .get_server_url()
-            .join(&format!("/instances/{}/auto_progress", pic.instance_id))
-            .expect("valid url");
-        client
-            .post(progress_url)
-            .json(&AutoProgressConfig {
-                artificial_delay_ms,
-            })
-            .send()
-            .await
-            .context("failed to send auto progress config to pocket-ic")?
-            .error_for_status()
-            .context("failed to configure pocket-ic for auto-progress")?;
-        let topology = pic.topology().await;
-        Ok((pic, child, topology, config_port))
+        if !no_auto_progress {
+            enable_auto_progress(&pic, artificial_delay_ms).await?;
+        }
+        let mut shards = vec![];
+        for _ in 1..pool.unwrap_or(1) {
+            let mut shard = configure(
+                PocketIcBuilder::new()
+                    .with_server_url(server_url.clone())
+                    .with_http_gateway(InstanceHttpGatewayConfig {
+                        ip_addr: bind.map(|ip| ip.to_string()),
+                        port: None,
+                        domains: Some(vec!["localhost".to_string()]),
+                        https_config: None,
+                    }),
+            );
+            if let Some(base) = &base_state {
+                shard = shard.with_read_only_state(base);
+            } else if let Some(initial_time) = initial_time {
+                shard = shard.with_initial_time(Time::from_nanos_since_unix_epoch(initial_time));
+            }
+            let shard = shard.build_async().await;
+            if !no_auto_progress {
+                enable_auto_progress(&shard, artificial_delay_ms).await?;
+            }
+            shards.push(shard);
+        }
+        Ok((pic, shards, child, config_port))
     })
     .await?;
+    let config_port = match &admin_listener {
+        Some((listener, _)) => listener
+            .local_addr()
+            .context("failed to get config address")?
+            .port(),
+        None => config_port,
+    };
+    let admin_proxy = admin_listener.map(|(listener, token)| {
+        let upstream = pic
+            .get_server_url()
+            .as_str()
+            .trim_end_matches('/')
+            .to_string();
+        tokio::spawn(access_token::serve_admin(
+            listener,
+            config_tls.clone(),
+            upstream,
+            token,
+        ))
+    });
+    let topology = pic.topology().await;
     let default_ecid = Principal::from_slice(&topology.default_effective_canister_id.canister_id);
     let gateway_url = pic.url().expect("gateway url set in builder");
-    let gateway_port = gateway_url
-        .port_or_known_default()
-        .expect("gateway urls should have a known port");
+    let gateway_port = match &gateway_proxy {
+        Some(listener) => listener
+            .local_addr()
+            .context("failed to get gateway address")?
+            .port(),
+        None => gateway_url
+            .port_or_known_default()
+            .expect("gateway urls should have a known port"),
+    };
+    let nns_dapp_url = (nns && !no_nns_dapp)
+        .then(|| format!("http://{NNS_DAPP_CANISTER_ID}.localhost:{gateway_port}"));
+    let sns_aggregator_url = sns_aggregator_wasm.as_ref().map(|_| {
+        format!(
+            "http://{}.localhost:{gateway_port}",
+            sns::SNS_AGGREGATOR_CANISTER_ID
+        )
+    });
+    let control_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, control_port.unwrap_or(0)))
+        .await
+        .context("failed to bind control API")?;
+    let control_port = control_listener
+        .local_addr()
+        .context("failed to get control API address")?
+        .port();
+    let shutdown = Arc::new(Notify::new());
+    let control = Arc::new(control::Control {
+        pic: PocketIc::new_from_existing_instance(pic.get_server_url(), pic.instance_id, None),
+        state_dir: state_dir.clone(),
+        status_dir: status_dir.clone(),
+        artificial_delay_ms: Mutex::new(artificial_delay_ms),
+        auto_progress: !no_auto_progress,
+        progress: <_>::default(),
+        shutdown: Arc::clone(&shutdown),
+        state_size: <_>::default(),
+        state_quota,
+        quota_paused: <_>::default(),
+        faucet: faucet.then(|| faucet::Faucet::new(faucet_max_icp, faucet_max_cycles)),
+        xrc_rates: xrc_mock.then(|| Mutex::new(xrc_initial_rates.clone())),
+        usage_baseline: <_>::default(),
+        stalls: chaos.then(chaos::Stalls::default),
+        ingress_faults,
+        response_faults,
+        rate_limit: rate_limit.map(|limit| chaos::RateLimit::new(limit, rate_limit_per_ip)),
+        creation_throttle: max_canister_creations.map(chaos::CreationThrottle::new),
+        strict_limits,
+        mainnet_costs,
+        access_token,
+        passthrough: passthrough::Passthrough::new(mainnet_passthrough, fork_url.clone()),
+        aliases: <_>::default(),
+        local_assets: <_>::default(),
+        ephemeral: <_>::default(),
+        errors: error_report.then(errors::ErrorTracker::default),
+        websocket_upstream,
+        cache: gateway_cache.map(cache::GatewayCache::new),
+        header_rules,
+        error_pages: gateway_error_pages,
+        health_endpoint: gateway_health,
+        compression: compress.then(|| compression::Compression::new(compress_min_size)),
+        access_log: access_log.map(access_log::AccessLog::new),
+        http_recording: record_http.is_some().then(har::HttpRecorder::default),
+        gateway_url: gateway_url.as_str().trim_end_matches('/').to_string(),
+    });
+    let control_server = tokio::spawn(control::serve(control_listener, Arc::clone(&control)));
+    let quota_monitor = state_dir
+        .clone()
+        .map(|dir| tokio::spawn(quota::monitor(Arc::clone(&control), dir, state_quota)));
+    let dilated_time =
+        time_dilation.map(|factor| tokio::spawn(time_dilation::run(Arc::clone(&control), factor)));
+    // a gateway listening on all interfaces is reached on loopback
+    let dns_address = match bind {
+        Some(IpAddr::V4(ip)) if !ip.is_unspecified() => ip,
+        _ => Ipv4Addr::LOCALHOST,
+    };
+    let mdns = match mdns_socket {
+        Some(socket) => {
+            let address = match bind {
+                Some(IpAddr::V4(ip)) if !ip.is_unspecified() && !ip.is_loopback() => ip,
+                _ => mdns::lan_address()?,
+            };
+            let host = mdns_host.clone().expect("mDNS socket bound for a host");
+            Some(tokio::spawn(mdns::serve(socket, host, address)))
+        }
+        None => None,
+    };
+    let dns = dns_socket.map(|socket| {
+        let domain = domain.clone().expect("--dns-port requires --domain");
+        tokio::spawn(dns::serve(socket, domain, dns_address))
+    });
+    let gateway_proxy = (gateway_proxy.is_some() || gateway_socket_listener.is_some()).then(|| {
+        tokio::spawn(gateway::serve(
+            gateway_proxy,
+            gateway_tls,
+            gateway_socket_listener,
+            control.gateway_url.clone(),
+            Arc::clone(&control),
+            ingress_recording,
+        ))
+    });
+    // before II is reconfigured, so that its settings are passed to the custom build
+    system::apply(&pic, system_wasm_overrides).await?;
+    if !ii_config.is_empty() {
+        ii::configure(&pic, &ii_config).await?;
+    }
+    if let Some(config) = ledger_archive_config
+        && pic.canister_exists(ledger::LEDGER_CANISTER_ID).await
+    {
+        ledger_archive::configure_icp_ledger(&pic, config).await?;
+    }
+    if !sns_wasms.is_empty() {
+        let count = sns_wasms.len();
+        sns_wasm::add(&pic, sns_wasms).await?;
+        eprintln!("added {count} SNS Wasm modules to SNS-W");
+    }
+    // canisters created by the launcher, to hand over to --controller principals
+    let mut launcher_created = vec![];
+    // anchors, neurons and tokens are part of the state, so they are only created once
+    if fresh_state
+        && (!ii_anchor_keys.is_empty()
+            || !test_neurons.is_empty()
+            || !icrc_token.is_empty()
+            || ckbtc_wasms.is_some()
+            || !cycles_wallet.is_empty())
+    {
+        let mut registry = match &status_dir {
+            Some(status_dir) => Some(registry::read(status_dir)?),
+            None => None,
+        };
+        for (name, public_key) in ii_anchor_keys {
+            let anchor = ii::register_anchor(&pic, &name, public_key)
+                .await
+                .with_context(|| format!("failed to register II anchor {name}"))?;
+            eprintln!("registered II anchor {anchor} for {name}");
+            if let Some(registry) = &mut registry {
+                registry.ii_anchors.insert(name, anchor);
+            }
+        }
+        let mut seeded_neurons = vec![];
+        for (memo, neuron) in test_neurons.iter().enumerate() {
+            let id = governance::create_neuron(&pic, neuron, memo as u64)
+                .await
+                .with_context(|| format!("failed to create neuron for {}", neuron.controller))?;
+            eprintln!("created neuron {id} for {}", neuron.controller);
+            seeded_neurons.push((id, neuron.controller));
+            if let Some(registry) = &mut registry {
+                registry.test_neurons.push(registry::RegistryNeuron {
+                    id,
+                    controller: neuron.controller,
+                });
+            }
+        }
+        if let Some(&(first_id, first_controller)) = seeded_neurons.first() {
+            if nns_instant_execution {
+                for &(id, controller) in &seeded_neurons[1..] {
+                    governance::follow_on_all_topics(&pic, controller, id, first_id).await?;
+                }
+            }
+            if nns_neuron_minimum_stake.is_some() || nns_reject_cost.is_some() {
+                let proposal_id = governance::submit_proposal(
+                    &pic,
+                    first_controller,
+                    first_id,
+                    "Adjust network economics for a local network".to_string(),
+                    String::new(),
+                    String::new(),
+                    governance::ProposalAction::ManageNetworkEconomics {
+                        neuron_minimum_stake_e8s: nns_neuron_minimum_stake.unwrap_or(0),
+                        reject_cost_e8s: nns_reject_cost.unwrap_or(0),
+                    },
+                )
+                .await?;
+                // followers already voted along with the proposer
+                if !nns_instant_execution {
+                    for &(id, controller) in &seeded_neurons[1..] {
+                        governance::vote_yes(&pic, controller, id, proposal_id).await?;
+                    }
+                }
+                let status = governance::proposal_status(&pic, proposal_id).await?;
+                if status != "executed" {
+                    anyhow::bail!(
+                        "network economics proposal {proposal_id} is {status}; the test neurons need a majority of the voting power"
+                    );
+                }
+            }
+        }
+        for token in &icrc_token {
+            let wasm = icrc_ledger_wasm
+                .clone()
+                .expect("--icrc-token requires --icrc-ledger-wasm");
+            let canister_id =
+                icrc::deploy(&pic, token, wasm, ledger_archive_config.unwrap_or_default()).await?;
+            eprintln!("deployed {} ledger at {canister_id}", token.symbol);
+            launcher_created.push(canister_id);
+            if let Some(registry) = &mut registry {
+                registry.canisters.insert(
+                    token.symbol.to_lowercase(),
+                    registry::RegistryEntry::new(canister_id),
+                );
+            }
+        }
+        if let Some(wasms) = ckbtc_wasms {
+            let canisters =
+                ckbtc::deploy(&pic, wasms, ledger_archive_config.unwrap_or_default()).await?;
+            for (name, canister_id) in canisters.named() {
+                eprintln!("deployed {name} at {canister_id}");
+                launcher_created.push(canister_id);
+                if let Some(registry) = &mut registry {
+                    registry
+                        .canisters
+                        .insert(name, registry::RegistryEntry::new(canister_id));
+                }
+            }
+        }
+        for &(owner, cycles) in &cycles_wallet {
+            let wasm = cycles_wallet_wasm
+                .clone()
+                .expect("--cycles-wallet requires --cycles-wallet-wasm");
+            let canister_id = wallet::create(&pic, owner, cycles, wasm).await?;
+            eprintln!("created cycles wallet {canister_id} for {owner}");
+            if let Some(registry) = &mut registry {
+                registry.wallets.insert(owner, canister_id);
+            }
+        }
+        if let (Some(status_dir), Some(registry)) = (&status_dir, registry) {
+            registry::write(status_dir, &registry)?;
+        }
+    }
+    for canister in forked_canisters {
+        fork::install(&pic, canister)
+            .await
+            .context("failed to import forked canister")?;
+    }
+    if let Some(wasm) = sns_aggregator_wasm {
+        sns::install_aggregator(&pic, wasm)
+            .await
+            .context("failed to install SNS aggregator")?;
+        launcher_created.push(sns::SNS_AGGREGATOR_CANISTER_ID);
+    }
+    // canisters installed at their mainnet IDs, to record in the registry
+    let mut well_known = vec![];
+    if let Some(wasm) = icp_index_wasm {
+        index::install_icp_index(&pic, wasm).await?;
+        well_known.push(("icp-index", index::ICP_INDEX_CANISTER_ID));
+    }
+    if let Some(wasm) = cycles_ledger_index_wasm {
+        index::install_cycles_ledger_index(&pic, wasm).await?;
+        well_known.push((
+            "cycles-ledger-index",
+            index::CYCLES_LEDGER_INDEX_CANISTER_ID,
+        ));
+    }
+    if xrc_mock {
+        xrc::install(&pic, &xrc_initial_rates).await?;
+        well_known.push(("xrc", xrc::XRC_CANISTER_ID));
+    }
+    if let Some(wasms) = cketh_wasms {
+        let rpc_url = eth_rpc_url.unwrap_or_else(|| format!("http://127.0.0.1:{control_port}/eth"));
+        cketh::deploy(
+            &pic,
+            wasms,
+            &rpc_url,
+            ledger_archive_config.unwrap_or_default(),
+        )
+        .await?;
+        well_known.push(("evm-rpc", cketh::EVM_RPC_CANISTER_ID));
+        well_known.push(("cketh-ledger", cketh::CKETH_LEDGER_CANISTER_ID));
+    }
+    if let Some(wasm) = blackhole_wasm {
+        blackhole::install(&pic, wasm)
+            .await
+            .context("failed to install blackhole")?;
+        well_known.push(("blackhole", blackhole::BLACKHOLE_CANISTER_ID));
+    }
+    let mut asset_watchers = vec![];
+    for (name, dir) in &asset_canister {
+        let wasm = asset_canister_wasm
+            .clone()
+            .expect("--asset-canister requires --asset-canister-wasm");
+        let existing = match &status_dir {
+            Some(status_dir) => registry::read(status_dir)?
+                .canisters
+                .get(name)
+                .map(|entry| entry.canister_id),
+            None => None,
+        };
+        let canister_id = assets::install(&pic, existing, wasm)
+            .await
+            .with_context(|| format!("failed to install asset canister {name}"))?;
+        let count = assets::sync(&pic, canister_id, dir)
+            .await
+            .with_context(|| format!("failed to upload {} to {name}", dir.display()))?;
+        eprintln!(
+            "uploaded {count} files from {} to {name} at {canister_id}",
+            dir.display()
+        );
+        well_known.push((name.as_str(), canister_id));
+        if watch_assets {
+            asset_watchers.push(assets::watch(
+                PocketIc::new_from_existing_instance(pic.get_server_url(), pic.instance_id, None),
+                name.clone(),
+                canister_id,
+                dir.clone(),
+            )?);
+        }
+    }
+    let mut candid_ui_id = None;
+    if let Some(wasm) = candid_ui_wasm {
+        let existing = match &status_dir {
+            Some(status_dir) => registry::read(status_dir)?
+                .canisters
+                .get(candid_ui::REGISTRY_NAME)
+                .map(|entry| entry.canister_id),
+            None => None,
+        };
+        let canister_id = candid_ui::install(&pic, existing, wasm)
+            .await
+            .context("failed to install Candid UI")?;
+        well_known.push((candid_ui::REGISTRY_NAME, canister_id));
+        candid_ui_id = Some(canister_id);
+    }
+    if let Some(status_dir) = &status_dir
+        && !well_known.is_empty()
+    {
+        let mut registry = registry::read(status_dir)?;
+        for &(name, canister_id) in &well_known {
+            registry
+                .canisters
+                .insert(name.to_string(), registry::RegistryEntry::new(canister_id));
+        }
+        registry::write(status_dir, &registry)?;
+    }
+    // balances are part of the state, so they are only minted once
+    if fresh_state {
+        ledger::mint(&pic, &ledger_accounts)
+            .await
+            .context("failed to fund ledger accounts")?;
+    }
+    let preloaded_canisters = match preload {
+        Some(preload) => preload::install(&pic, preload, fresh_state, &controller)
+            .await
+            .context("failed to preload canisters")?,
+        None => BTreeMap::new(),
+    };
+    if fresh_state {
+        cycles::fund(&pic, &cycles_for)
+            .await
+            .context("failed to fund --cycles-for principals")?;
+    }
+    if !controller.is_empty() {
+        launcher_created.extend(
+            well_known
+                .iter()
+                .filter(|&&(name, _)| name != "blackhole")
+                .map(|&(_, canister_id)| canister_id),
+        );
+        for canister_id in launcher_created {
+            canister::add_controllers(&pic, canister_id, &controller)
+                .await
+                .context("failed to add --controller principals")?;
+        }
+    }
+    // record system and preloaded canisters by name, and every canister's URL on this gateway
+    if let Some(status_dir) = &status_dir {
+        let mut registry = registry::read(status_dir)?;
+        for &(name, canister_id) in system::SYSTEM_CANISTERS {
+            if pic.canister_exists(canister_id).await {
+                registry
+                    .canisters
+                    .insert(name.to_string(), registry::RegistryEntry::new(canister_id));
+            }
+        }
+        for (name, &canister_id) in &preloaded_canisters {
+            registry
+                .canisters
+                .insert(name.clone(), registry::RegistryEntry::new(canister_id));
+        }
+        registry.identities.extend(test_identities);
+        let canisters = registry
+            .canisters
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.canister_id))
+            .collect();
+        for (name, canister_id) in ledger_archive::find(&pic, &canisters).await? {
+            registry
+                .canisters
+                .insert(name, registry::RegistryEntry::new(canister_id));
+        }
+        registry::set_urls(&mut registry, gateway_port);
+        registry::write(status_dir, &registry)?;
+    }
+    // without a status directory, only the canisters installed this run are known
+    let mut named_canisters: BTreeMap<_, _> = match &status_dir {
+        Some(status_dir) => registry::read(status_dir)?
+            .canisters
+            .into_iter()
+            .map(|(name, entry)| (name, entry.canister_id))
+            .collect(),
+        None => well_known
+            .iter()
+            .map(|&(name, canister_id)| (name.to_string(), canister_id))
+            .collect(),
+    };
+    named_canisters.extend(preloaded_canisters.clone());
+    if freezing_threshold.is_some()
+        || reserved_cycles_limit.is_some()
+        || compute_allocation.is_some()
+    {
+        let settings = CanisterSettings {
+            freezing_threshold: freezing_threshold.map(Nat::from),
+            reserved_cycles_limit: reserved_cycles_limit.map(Nat::from),
+            compute_allocation: compute_allocation.map(Nat::from),
+            ..<_>::default()
+        };
+        for (name, &canister_id) in &named_canisters {
+            if let Err(e) = canister::update_settings(&pic, canister_id, settings.clone()).await {
+                eprintln!("Warning: failed to apply canister settings to {name}: {e:#}");
+            }
+        }
+    }
+    for (name, canister) in &alias {
+        let canister_id = match Principal::from_text(canister) {
+            Ok(canister_id) => canister_id,
+            Err(_) => *named_canisters
+                .get(canister)
+                .with_context(|| format!("unknown canister `{canister}` for alias {name}"))?,
+        };
+        control
+            .aliases
+            .lock()
+            .await
+            .insert(name.clone(), canister_id);
+        eprintln!("{canister} available at http://{name}.localhost:{gateway_port}");
+    }
+    for (canister, dir) in &serve_local {
+        let canister_id = match Principal::from_text(canister) {
+            Ok(canister_id) => canister_id,
+            Err(_) => *named_canisters
+                .get(canister)
+                .with_context(|| format!("unknown canister `{canister}` for --serve-local"))?,
+        };
+        control
+            .local_assets
+            .lock()
+            .await
+            .insert(canister_id, dir.clone());
+        eprintln!("{canister} served from {}", dir.display());
+    }
+    let candid_ui_urls = match candid_ui_id {
+        Some(candid_ui_id) => {
+            candid_ui::canister_urls(candid_ui_id, gateway_port, &named_canisters)
+        }
+        None => vec![],
+    };
+    let root_key = hex::encode(
+        pic.root_key()
+            .await
+            .expect("root key should be available if there is a root subnet"),
+    );
     // write everything to the status file
-    if let Some(status_dir) = status_dir {
-        fs::create_dir_all(&status_dir).context("failed to create status directory")?;
+    let mut extra_gateways = vec![];
+    let mut extra_gateway_proxies = vec![];
+    match extra_gateway_listeners {
+        Some(listeners) => {
+            for (spec, (listener, acceptor)) in gateway.iter().zip(listeners) {
+                let (info, proxy) =
+                    gateways::serve(&pic, spec, listener, acceptor, Arc::clone(&control)).await?;
+                extra_gateways.push(info);
+                extra_gateway_proxies.push(proxy);
+            }
+        }
+        None => {
+            for spec in &gateway {
+                extra_gateways.push(gateways::start(&pic, spec, bind).await?);
+            }
+        }
+    }
+    let tunnel_opener = match (tunnel, tunnel_command) {
+        (Some(provider), _) => Some(tunnel::Opener::Provider(provider)),
+        (None, Some(command)) => Some(tunnel::Opener::Command(command)),
+        (None, None) => None,
+    };
+    let tunnel = match &tunnel_opener {
+        Some(opener) => {
+            let host = match &tunnel_canister {
+                Some(canister) => {
+                    let canister_id = match Principal::from_text(canister) {
+                        Ok(canister_id) => canister_id,
+                        Err(_) => *named_canisters.get(canister).with_context(|| {
+                            format!("unknown canister `{canister}` for --tunnel-canister")
+                        })?,
+                    };
+                    format!("{canister_id}.localhost")
+                }
+                None => "localhost".to_string(),
+            };
+            Some(tunnel::open(opener, gateway_port, &host).await?)
+        }
+        None => None,
+    };
+    let tunnel_url = tunnel.as_ref().map(|(_, url)| url.clone());
+    if let Some(status_dir) = &status_dir {
+        fs::create_dir_all(status_dir).context("failed to create status directory")?;
+        let mut topology_json = serde_json::to_string(&topology).expect("infallible serialization");
+        topology_json.push('\n');
+        fs::write(status_dir.join("topology.json"), topology_json)
+            .context("failed to write topology file")?;
         let status_file = status_dir.join("status.json");
+        let (args, redacted_args) = redact_args(
+            std::env::args_os()
+                .skip(1)
+                .map(|arg| arg.to_string_lossy().into_owned()),
+        );
         let status = Status {
-            v: "1".to_string(),
+            v: INTERFACE_VERSION.to_string(),
             instance_id: pic.instance_id,
             config_port,
             gateway_port,
-            root_key: hex::encode(
-                pic.root_key()
-                    .await
-                    .expect("root key should be available if there is a root subnet"),
-            ),
+            gateways: extra_gateways.clone(),
+            control_port,
+            root_key: root_key.clone(),
             default_effective_canister_id: default_ecid,
+            canisters: preloaded_canisters,
+            nns_dapp_url: nns_dapp_url.clone(),
+            sns_aggregator_url: sns_aggregator_url.clone(),
+            candid_ui_url: candid_ui_id.map(|id| candid_ui::url(id, gateway_port)),
+            faucet_url: faucet.then(|| format!("http://127.0.0.1:{control_port}/faucet")),
+            tunnel_url: tunnel_url.clone(),
+            threshold_keys: ecdsa_key
+                .iter()
+                .map(|name| format!("ecdsa:secp256k1:{name}"))
+                .chain(
+                    schnorr_key
+                        .iter()
+                        .map(|(algorithm, name)| format!("schnorr:{algorithm}:{name}")),
+                )
+                .chain(
+                    threshold_key::KEY_NAMES
+                        .iter()
+                        .filter(|_| vetkd)
+                        .map(|name| format!("vetkd:bls12_381_g2:{name}")),
+                )
+                .collect(),
+            pid: std::process::id(),
+            args,
+            redacted_args,
         };
         let mut contents = serde_json::to_string(&status).expect("infallible serialization");
         contents.push('\n');
         fs::write(status_file, contents).context("failed to write status file")?;
+        if !shards.is_empty() {
+            let mut manifest =
+                vec![pool::describe(0, &pic, gateway_port, Some(control_port)).await];
+            for (i, shard) in shards.iter().enumerate() {
+                manifest.push(pool::describe(i + 1, shard, pool::gateway_port(shard), None).await);
+            }
+            pool::write(status_dir, manifest)?;
+        }
     }
     eprintln!("pocket-ic instance running with gateway port {gateway_port}");
+    for gateway in &extra_gateways {
+        eprintln!("additional gateway running at {}", gateways::url(gateway));
+    }
+    if let Some(domain) = &domain {
+        eprintln!("canisters available at http://<canister id>.{domain}:{gateway_port}");
+        if let Some(dns_port) = dns_port {
+            eprintln!("DNS for {domain} served on UDP port {dns_port}");
+        }
+    }
+    if let Some(mdns_host) = &mdns_host {
+        eprintln!("announced over mDNS at http://{mdns_host}:{gateway_port}");
+    }
+    if let Some(tunnel_url) = &tunnel_url {
+        eprintln!(
+            "tunnel open at {tunnel_url}; open {tunnel_url}/?access_token=<token> once in a browser to get past --access-token"
+        );
+    }
+    for (i, shard) in shards.iter().enumerate() {
+        eprintln!(
+            "pool shard {} running with gateway port {}",
+            i + 1,
+            pool::gateway_port(shard)
+        );
+    }
+    if let Some(nns_dapp_url) = nns_dapp_url {
+        eprintln!("NNS dapp available at {nns_dapp_url}");
+    }
+    if let Some(sns_aggregator_url) = sns_aggregator_url {
+        eprintln!("SNS aggregator available at {sns_aggregator_url}");
+    }
+    if let Some(candid_ui_id) = candid_ui_id {
+        eprintln!(
+            "Candid UI available at {}",
+            candid_ui::url(candid_ui_id, gateway_port)
+        );
+        for (name, url) in candid_ui_urls {
+            eprintln!("  {name}: {url}");
+        }
+    }
+    // without a status directory, the canisters are only known by the names given at startup
+    let report_canisters = if status_dir.is_some() {
+        control.registry_canisters()?
+    } else {
+        named_canisters.clone()
+    };
+    *control.usage_baseline.lock().await = report::measure(&pic, &report_canisters).await;
+    let bounded_rounds =
+        max_rounds.map(|rounds| tokio::spawn(bounded::run(Arc::clone(&control), rounds)));
+    let answered_outcalls =
+        outcall_rules.map(|rules| tokio::spawn(outcalls::run(Arc::clone(&control), rules)));
+    let announced_canisters = announce_canisters.then(|| {
+        tokio::spawn(announce::run(
+            Arc::clone(&control),
+            status_dir.clone(),
+            gateway_port,
+        ))
+    });
+    let network = hook::NetworkInfo {
+        gateway_port,
+        control_port,
+        config_port,
+        instance_id: pic.instance_id,
+        root_key: &root_key,
+        default_effective_canister_id: default_ecid,
+        status_dir: status_dir.as_deref(),
+        canisters: &named_canisters,
+    };
+    if !on_ready.is_empty() {
+        hook::run(&on_ready, &network)
+            .await
+            .context("--on-ready command failed")?;
+    }
     let ctrlc = tokio::signal::ctrl_c();
+    let deadline = async {
+        match max_duration {
+            Some(duration) => {
+                tokio::time::sleep(duration).await;
+                eprintln!("reached --max-duration; shutting down");
+            }
+            None => std::future::pending().await,
+        }
+    };
     #[cfg(unix)]
     {
         let mut sigterm = tokio::signal::unix::signal(SignalKind::terminate())
@@ -333,11 +2307,91 @@ async fn main() -> anyhow::Result<()> {
         select! {
             res = ctrlc => res.context("failed to listen for ctrl-c")?,
             _ = sigterm.recv() => {},
+            _ = shutdown.notified() => {},
+            _ = deadline => {},
         }
     }
     #[cfg(not(unix))]
     {
-        ctrlc.await.context("failed to listen for ctrl-c")?;
+        select! {
+            res = ctrlc => res.context("failed to listen for ctrl-c")?,
+            _ = shutdown.notified() => {},
+            _ = deadline => {},
+        }
+    }
+    // the network and the control API stay up until the hooks are done
+    if !on_shutdown.is_empty()
+        && let Err(e) = hook::run(&on_shutdown, &network).await
+    {
+        eprintln!("Warning: --on-shutdown command failed: {e:#}");
+    }
+    if usage_report {
+        let canisters = if status_dir.is_some() {
+            control.registry_canisters()?
+        } else {
+            report_canisters
+        };
+        let baseline = control.usage_baseline.lock().await;
+        report::print(&report::compare(&pic, &canisters, &baseline).await);
+    }
+    if let Some(errors) = &control.errors {
+        let names = named_canisters
+            .iter()
+            .map(|(name, &canister_id)| (canister_id, name.clone()))
+            .collect();
+        errors::print(&errors.report(), &names);
+    }
+    if let (Some(http_recording), Some(path)) = (&control.http_recording, &record_http) {
+        match http_recording.write(path) {
+            Ok(()) => eprintln!("recorded HTTP traffic to {}", path.display()),
+            Err(e) => eprintln!("Warning: failed to record HTTP traffic: {e:#}"),
+        }
+    }
+    control_server.abort();
+    for (_watcher, task) in asset_watchers {
+        task.abort();
+    }
+    if let Some(quota_monitor) = quota_monitor {
+        quota_monitor.abort();
+    }
+    if let Some(dilated_time) = dilated_time {
+        dilated_time.abort();
+    }
+    if let Some(dns) = dns {
+        dns.abort();
+    }
+    if let Some(mdns) = mdns {
+        mdns.abort();
+    }
+    if let Some(bounded_rounds) = bounded_rounds {
+        bounded_rounds.abort();
+    }
+    if let Some(answered_outcalls) = answered_outcalls {
+        answered_outcalls.abort();
+    }
+    if let Some(announced_canisters) = announced_canisters {
+        announced_canisters.abort();
+    }
+    if let Some(gateway_proxy) = gateway_proxy {
+        gateway_proxy.abort();
+    }
+    for proxy in extra_gateway_proxies {
+        proxy.abort();
+    }
+    if let Some(admin_proxy) = admin_proxy {
+        admin_proxy.abort();
+    }
+    if let Some(gateway_socket) = &gateway_socket {
+        _ = fs::remove_file(gateway_socket);
+    }
+    if let Some((mut tunnel, _)) = tunnel {
+        _ = tunnel.kill().await;
+    }
+    for shard in shards {
+        shard.drop().await;
+    }
+    for (_, instance) in std::mem::take(&mut *control.ephemeral.lock().await) {
+        instance.drop().await;
     }
     pic.drop().await;
     let pid = child.id().expect("child process should have an id") as usize;
@@ -346,15 +2400,486 @@ async fn main() -> anyhow::Result<()> {
     if let Some(process) = sys.process(pid.into()) {
         process.kill_with(Signal::Interrupt);
     }
-    select! {
-        _ = child.wait() => {},
+    let exited = select! {
+        exited = child.wait() => exited.is_ok(),
         _ = tokio::time::sleep(Duration::from_secs(5)) => {
             let _ = child.kill().await;
+            false
+        }
+    };
+    // pocket-ic only finishes writing the state directory once the instance is deleted, and may
+    // not have finished if it had to be killed
+    if snapshot_on_exit.is_some() && !exited {
+        eprintln!(
+            "Warning: pocket-ic did not shut down cleanly, so its state may be incomplete; not taking a snapshot (--snapshot-on-exit)"
+        );
+    } else if let Some(keep) = snapshot_on_exit {
+        let state_dir = state_dir.expect("--snapshot-on-exit requires --state-dir");
+        let path = snapshot::take(
+            &state_dir,
+            &snapshot::timestamped_name(snapshot::EXIT_PREFIX),
+        )
+        .context("failed to snapshot state directory")?;
+        snapshot::prune(&state_dir, snapshot::EXIT_PREFIX, keep)
+            .context("failed to prune old snapshots")?;
+        eprintln!("saved snapshot to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Runs a subcommand against an existing network.
+async fn run_command(
+    command: CliCommand,
+    status_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    encrypt_state: bool,
+) -> anyhow::Result<()> {
+    let passphrase = if encrypt_state {
+        Some(crypt::load_passphrase()?)
+    } else {
+        None
+    };
+    match command {
+        CliCommand::Export { output } => {
+            let state_dir = state_dir.context("--state-dir is required to export a network")?;
+            archive::export(
+                &state_dir,
+                status_dir.as_deref(),
+                &output,
+                passphrase.as_ref(),
+            )?;
+        }
+        CliCommand::Import {
+            archive: archive_path,
+            force,
+        } => {
+            let state_dir = state_dir.context("--state-dir is required to import a network")?;
+            let manifest = archive::import(&archive_path, &state_dir, force)?;
+            archive::warn_on_version_mismatch(&manifest);
+        }
+        CliCommand::ImportDfx { project } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let canisters = dfx::read(&project)?;
+            let response: control::ImportDfxResponse = control::request(
+                &status_dir,
+                "/import-dfx",
+                &control::ImportDfxRequest { canisters },
+            )
+            .await?;
+            for canister in response.canisters {
+                match (canister.canister_id, canister.error) {
+                    (_, Some(error)) => eprintln!("{}: {error}", canister.name),
+                    (Some(id), None) if id != canister.dfx_canister_id => println!(
+                        "{}: {id} (was {} under dfx)",
+                        canister.name, canister.dfx_canister_id
+                    ),
+                    (Some(id), None) => println!("{}: {id}", canister.name),
+                    (None, None) => {}
+                }
+            }
+        }
+        CliCommand::Clean {
+            include_snapshots,
+            relaunch,
+        } => clean(status_dir, state_dir, include_snapshots, relaunch).await?,
+        CliCommand::Reset { include_snapshots } => {
+            clean(status_dir, state_dir, include_snapshots, true).await?
+        }
+        CliCommand::Faucet {
+            principal,
+            icp,
+            cycles,
+        } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::FaucetResponse = control::request(
+                &status_dir,
+                "/faucet",
+                &control::FaucetRequest {
+                    principal,
+                    icp_e8s: icp.unwrap_or(0),
+                    cycles: cycles.unwrap_or(0),
+                    amount: None,
+                    token: <_>::default(),
+                },
+            )
+            .await?;
+            println!(
+                "gave {principal} {} e8s and {} cycles",
+                response.icp_e8s, response.cycles
+            );
+        }
+        CliCommand::Status => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::StatusResponse =
+                control::request(&status_dir, "/status", &control::StatusRequest {}).await?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&response).expect("infallible serialization")
+            );
+        }
+        CliCommand::Stats { json } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::StatusResponse =
+                control::request(&status_dir, "/status", &control::StatusRequest {}).await?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&response.query_stats)
+                        .expect("infallible serialization")
+                );
+            } else {
+                query_stats::print(&response.query_stats);
+            }
+        }
+        CliCommand::PurgeGatewayCache => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::PurgeGatewayCacheResponse = control::request(
+                &status_dir,
+                "/gateway-cache/purge",
+                &control::PurgeGatewayCacheRequest {},
+            )
+            .await?;
+            eprintln!("purged {} cached responses", response.purged);
+        }
+        CliCommand::Errors { json } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::ErrorsResponse =
+                control::request(&status_dir, "/errors", &control::ErrorsRequest {}).await?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&response.report)
+                        .expect("infallible serialization")
+                );
+            } else {
+                let names = registry::read(&status_dir)?
+                    .canisters
+                    .into_iter()
+                    .map(|(name, entry)| (entry.canister_id, name))
+                    .collect();
+                errors::print(&response.report, &names);
+            }
+        }
+        CliCommand::Logs { canisters, follow } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let registered = registry::read(&status_dir)?.canisters;
+            let canisters = if canisters.is_empty() {
+                registered
+                    .into_iter()
+                    .map(|(name, entry)| (name, entry.canister_id))
+                    .collect()
+            } else {
+                canisters
+                    .into_iter()
+                    .map(|canister| {
+                        let canister_id = match registered.get(&canister) {
+                            Some(entry) => entry.canister_id,
+                            None => Principal::from_text(&canister)
+                                .with_context(|| format!("unknown canister `{canister}`"))?,
+                        };
+                        Ok((canister, canister_id))
+                    })
+                    .collect::<anyhow::Result<BTreeMap<_, _>>>()?
+            };
+            logs::tail(&status_dir, &canisters, follow).await?;
+        }
+        CliCommand::Report { json } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::ReportResponse =
+                control::request(&status_dir, "/report", &control::ReportRequest {}).await?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&response.canisters)
+                        .expect("infallible serialization")
+                );
+            } else {
+                report::print(&response.canisters);
+            }
+        }
+        CliCommand::AdvanceTime { duration } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::AdvanceTimeResponse = control::request(
+                &status_dir,
+                "/advance-time",
+                &control::AdvanceTimeRequest {
+                    seconds: duration.as_secs(),
+                },
+            )
+            .await?;
+            println!("{}", response.time);
+        }
+        CliCommand::ArtificialDelay { ms } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let _: control::ArtificialDelayResponse = control::request(
+                &status_dir,
+                "/artificial-delay",
+                &control::ArtificialDelayRequest {
+                    artificial_delay_ms: ms,
+                },
+            )
+            .await?;
+        }
+        CliCommand::Bench { manifest, json } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let canisters = registry::read(&status_dir)?
+                .canisters
+                .into_iter()
+                .map(|(name, entry)| (name, entry.canister_id))
+                .collect();
+            let calls = bench::load(&manifest, &canisters)?;
+            let response: control::BenchResponse =
+                control::request(&status_dir, "/bench", &control::BenchRequest { calls }).await?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&response.results)
+                        .expect("infallible serialization")
+                );
+            } else {
+                bench::print(&response.results);
+            }
+        }
+        CliCommand::Loadtest {
+            canister,
+            update_method,
+            query_method,
+            query_ratio,
+            arg,
+            payload_size,
+            concurrency,
+            duration,
+        } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let canister_id = match registry::read(&status_dir)?.canisters.get(&canister) {
+                Some(entry) => entry.canister_id,
+                None => Principal::from_text(&canister)
+                    .with_context(|| format!("unknown canister `{canister}`"))?,
+            };
+            let arg = loadtest::arg(arg.as_deref(), payload_size)?;
+            let gateway_url = format!(
+                "http://localhost:{}",
+                read_status(&status_dir)?.gateway_port
+            );
+            loadtest::run(
+                &gateway_url,
+                loadtest::LoadTest {
+                    canister_id,
+                    update_method,
+                    query_method,
+                    query_ratio,
+                    arg,
+                    concurrency: concurrency.max(1),
+                    duration,
+                },
+            )
+            .await?;
+        }
+        CliCommand::Replay { recording } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let messages = ingress::load(&recording)?;
+            let count = messages.len();
+            let response: control::ReplayResponse =
+                control::request(&status_dir, "/replay", &control::ReplayRequest { messages })
+                    .await?;
+            for rejection in &response.rejections {
+                eprintln!("Warning: rejected {rejection}");
+            }
+            eprintln!(
+                "replayed {} of {count} messages",
+                count - response.rejections.len()
+            );
+        }
+        CliCommand::Tick { rounds } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::TickResponse =
+                control::request(&status_dir, "/tick", &control::TickRequest { rounds }).await?;
+            println!("{}", response.time);
+        }
+        CliCommand::State { command } => {
+            let state_dir = state_dir.context("--state-dir is required to manage branches")?;
+            branch::run_command(&state_dir, command)?;
+        }
+        CliCommand::Fixture { command } => {
+            let state_dir = state_dir.context("--state-dir is required for fixture packs")?;
+            fixture::run_command(
+                &state_dir,
+                status_dir.as_deref(),
+                passphrase.as_ref(),
+                command,
+            )?;
+        }
+        CliCommand::Canister { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            canister::run_command(&status_dir, command).await?;
+        }
+        CliCommand::Proposal { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            governance::run_command(&status_dir, command).await?;
+        }
+        CliCommand::Xrc { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            xrc::run_command(&status_dir, command).await?;
+        }
+        CliCommand::Sns { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            sns::run_command(&status_dir, command).await?;
+        }
+        CliCommand::StallSubnet { subnet, duration } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::StallSubnetResponse = control::request(
+                &status_dir,
+                "/stall-subnet",
+                &control::StallSubnetRequest {
+                    subnet,
+                    seconds: duration.as_secs(),
+                },
+            )
+            .await?;
+            println!("{}", response.subnet_id);
+        }
+        CliCommand::UpgradeSubnet { subnet, downtime } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            let response: control::UpgradeSubnetResponse = control::request(
+                &status_dir,
+                "/upgrade-subnet",
+                &control::UpgradeSubnetRequest {
+                    subnet,
+                    seconds: downtime.as_secs(),
+                },
+            )
+            .await?;
+            println!("{}", response.subnet_id);
+        }
+        CliCommand::Ephemeral { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            ephemeral::run_command(&status_dir, command).await?;
+        }
+        CliCommand::Ledger { command } => {
+            let status_dir = status_dir.context("--status-dir is required to find the network")?;
+            ledger_archive::run_command(&status_dir, command).await?;
+        }
+        CliCommand::Snapshot { command } => {
+            let state_dir = state_dir.context("--state-dir is required to find snapshots")?;
+            snapshot::run_command(&state_dir, passphrase, command).await?;
         }
     }
     Ok(())
 }
 
+/// Implements `clean` and `reset`.
+async fn clean(
+    status_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    include_snapshots: bool,
+    relaunch: bool,
+) -> anyhow::Result<()> {
+    if status_dir.is_none() && state_dir.is_none() {
+        anyhow::bail!("--status-dir or --state-dir is required to find the network");
+    }
+    let mut relaunch_args = None;
+    if let Some(status_dir) = &status_dir
+        && status_dir.join("status.json").exists()
+    {
+        let status = read_status(status_dir)?;
+        let pid = (status.pid as usize).into();
+        let mut sys = System::new();
+        sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        // if the control API doesn't answer, the status file is stale and the PID belongs to something else
+        let running = sys.process(pid).is_some()
+            && control::request::<_, control::ShutdownResponse>(
+                status_dir,
+                "/shutdown",
+                &control::ShutdownRequest {},
+            )
+            .await
+            .is_ok();
+        if running {
+            // the launcher keeps running until pocket-ic has written its state, so wait for it to exit
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+            loop {
+                sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+                if sys.process(pid).is_none() {
+                    break;
+                }
+                if tokio::time::Instant::now() > deadline {
+                    anyhow::bail!("timed out waiting for the network to stop");
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+        for file in STATUS_FILES {
+            match fs::remove_file(status_dir.join(file)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("failed to delete {file}"));
+                }
+                _ => {}
+            }
+        }
+        relaunch_args = Some((status.args, status.redacted_args));
+    }
+    if let Some(state_dir) = &state_dir {
+        if state_dir.exists() {
+            fs::remove_dir_all(state_dir).context("failed to delete state directory")?;
+        }
+        let snapshots = snapshot::snapshots_dir(state_dir)?;
+        if include_snapshots && snapshots.exists() {
+            fs::remove_dir_all(&snapshots).context("failed to delete snapshots")?;
+        }
+    }
+    if relaunch {
+        let (args, redacted_args) = relaunch_args
+            .context("cannot relaunch: no status file recording the network's arguments")?;
+        // the relaunched network reads the secrets from the environment, as they are not recorded
+        for (flag, env) in SECRET_ARGS {
+            if redacted_args.iter().any(|arg| arg == flag) && std::env::var_os(env).is_none() {
+                anyhow::bail!(
+                    "cannot relaunch: the network was started with {flag}, whose value is not \
+                     recorded; set {env} to relaunch it"
+                );
+            }
+        }
+        let exe = std::env::current_exe().context("Failed to get current exe path")?;
+        let mut cmd = std::process::Command::new(exe);
+        cmd.args(args);
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            return Err(cmd.exec()).context("failed to relaunch the network");
+        }
+        #[cfg(not(unix))]
+        {
+            let status = cmd.status().context("failed to relaunch the network")?;
+            std::process::exit(status.code().unwrap_or(1));
+        }
+    }
+    Ok(())
+}
+
+/// Starts auto-progress on the instance with the given artificial delay.
+async fn enable_auto_progress(
+    pic: &PocketIc,
+    artificial_delay_ms: Option<u64>,
+) -> anyhow::Result<()> {
+    // pocket-ic crate doesn't currently support setting artificial delay via builder
+    let client = Client::new();
+    let progress_url = pic
+        .get_server_url()
+        .join(&format!("/instances/{}/auto_progress", pic.instance_id))
+        .expect("valid url");
+    client
+        .post(progress_url)
+        .json(&AutoProgressConfig {
+            artificial_delay_ms,
+        })
+        .send()
+        .await
+        .context("failed to send auto progress config to pocket-ic")?
+        .error_for_status()
+        .context("failed to configure pocket-ic for auto-progress")?;
+    Ok(())
+}
+
 /// Resolves a list of address strings (hostname:port or ip:port) to socket addresses.
 async fn resolve_addrs(addrs: &[String]) -> anyhow::Result<Vec<SocketAddr>> {
     let mut resolved = Vec::with_capacity(addrs.len());
@@ -379,7 +2904,7 @@ fn get_errorchecked_args() -> Cli {
         }
         return cli;
     };
-    let our_version = Version::parse("1.0.0").expect("valid version");
+    let our_version = Version::parse(INTERFACE_VERSION).expect("valid version");
     // Backwards compatibility: if at all possible, the requirement should be kept at ^1.0.0 while retaining semver.
     let requirement = VersionReq::parse("^1.0.0").expect("valid version req");
     if !requirement.matches(interface_version) {
@@ -390,8 +2915,8 @@ fn get_errorchecked_args() -> Cli {
     }
     // Forwards compatibility: unknown arguments for a newer version should be ignored rather than erroring.
     if !cli.unknown_args.is_empty() {
-        if *interface_version == our_version {
-            // If this is the exact same version, unknown args are bad args.
+        if *interface_version <= our_version {
+            // If this is the same or an older version, unknown args are bad args.
             unknown_arg(&mut command, &cli.unknown_args[0]);
         } else {
             // If this is a future version, unknown args are possibly correct.
@@ -426,7 +2951,6 @@ async fn try_with_maybe_muted_stderr<R>(
     f: impl Future<Output = anyhow::Result<R>>,
 ) -> anyhow::Result<R> {
     use std::io::{Seek, SeekFrom};
-    use std::sync::Arc;
     if verbose {
         f.await
     } else {
@@ -482,12 +3006,163 @@ async fn try_with_maybe_muted_stderr<R>(
     f.await
 }
 
-#[derive(Serialize)]
+/// ID of the NNS dapp canister installed by `--nns`.
+const NNS_DAPP_CANISTER_ID: &str = "qoctq-giaaa-aaaaa-aaaea-cai";
+
+/// Time fresh state starts at with `--deterministic`: 2025-01-01T00:00:00Z, in nanoseconds since
+/// the Unix epoch.
+const DETERMINISTIC_INITIAL_TIME: u64 = 1_735_689_600_000_000_000;
+
+/// Files the launcher writes to `--status-dir`.
+const STATUS_FILES: &[&str] = &["status.json", "topology.json", registry::REGISTRY_FILE];
+
+/// Flags whose values are secrets, with the environment variables they can also be given in.
+const SECRET_ARGS: &[(&str, &str)] = &[
+    ("--access-token", "ICP_CLI_NETWORK_LAUNCHER_ACCESS_TOKEN"),
+    ("--config-token", "ICP_CLI_NETWORK_LAUNCHER_CONFIG_TOKEN"),
+];
+
+/// Removes the flags in `SECRET_ARGS` and their values from `args`, returning the remaining
+/// arguments and the removed flags.
+fn redact_args(args: impl IntoIterator<Item = String>) -> (Vec<String>, Vec<String>) {
+    let mut kept = vec![];
+    let mut redacted = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let secret = SECRET_ARGS.iter().map(|(flag, _)| *flag).find(|flag| {
+            arg == *flag
+                || arg
+                    .strip_prefix(flag)
+                    .is_some_and(|value| value.starts_with('='))
+        });
+        match secret {
+            Some(flag) => {
+                if arg == flag {
+                    args.next();
+                }
+                redacted.push(flag.to_string());
+            }
+            None => kept.push(arg),
+        }
+    }
+    (kept, redacted)
+}
+
+/// Parses a ratio between 0 and 1.
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("expected a number between 0 and 1, got `{s}`")),
+    }
+}
+
+/// Returns whether `dir` is empty or doesn't exist.
+fn is_empty_dir(dir: &Path) -> anyhow::Result<bool> {
+    Ok(!dir.exists()
+        || fs::read_dir(dir)
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .next()
+            .is_none())
+}
+
+/// Reads the Wasm module at `path`, if one was given to override a bundled canister.
+fn read_optional_wasm(path: Option<PathBuf>) -> anyhow::Result<Option<Vec<u8>>> {
+    path.map(|path| fs::read(&path).with_context(|| format!("failed to read {}", path.display())))
+        .transpose()
+}
+
+/// Reads the status file of a running network.
+fn read_status(status_dir: &Path) -> anyhow::Result<Status> {
+    let status_file = status_dir.join("status.json");
+    let status = fs::read_to_string(&status_file)
+        .with_context(|| format!("failed to read {}", status_file.display()))?;
+    serde_json::from_str(&status).context("failed to parse status file")
+}
+
+/// Version of the interface the launcher implements, which callers declare with
+/// `--interface-version` and which is reported in the status file.
+const INTERFACE_VERSION: &str = "1.1.0";
+
+#[derive(Serialize, Deserialize)]
 struct Status {
+    /// Interface version of the launcher that wrote the file.
     v: String,
     instance_id: usize,
     config_port: u16,
     gateway_port: u16,
+    /// Gateways started with `--gateway` besides the one on `gateway_port`.
+    #[serde(default)]
+    gateways: Vec<gateways::GatewayInfo>,
+    control_port: u16,
     root_key: String,
     default_effective_canister_id: Principal,
+    /// IDs of the canisters installed by `--preload`, by name.
+    #[serde(default)]
+    canisters: BTreeMap<String, Principal>,
+    /// Gateway URL of the NNS dapp, if it is installed.
+    #[serde(default)]
+    nns_dapp_url: Option<String>,
+    /// Gateway URL of the SNS aggregator, if it is installed.
+    #[serde(default)]
+    sns_aggregator_url: Option<String>,
+    /// Gateway URL of the Candid UI, if it is installed.
+    #[serde(default)]
+    candid_ui_url: Option<String>,
+    /// URL of the control API's faucet endpoint, if `--faucet` is enabled.
+    #[serde(default)]
+    faucet_url: Option<String>,
+    /// Public URL of the tunnel to the gateway, if `--tunnel` is enabled.
+    #[serde(default)]
+    tunnel_url: Option<String>,
+    /// Threshold keys requested with `--ecdsa-key`, `--schnorr-key` and `--vetkd`, as
+    /// `ecdsa:secp256k1:NAME`, `schnorr:ALGORITHM:NAME` or `vetkd:bls12_381_g2:NAME`.
+    #[serde(default)]
+    threshold_keys: Vec<String>,
+    /// Process ID of the launcher.
+    pid: u32,
+    /// Arguments the launcher was started with, without the flags in `SECRET_ARGS`.
+    args: Vec<String>,
+    /// Flags in `SECRET_ARGS` the launcher was started with, whose values are not recorded.
+    #[serde(default)]
+    redacted_args: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ratio_accepts_0_to_1() {
+        assert_eq!(parse_ratio("0"), Ok(0.0));
+        assert_eq!(parse_ratio("0.25"), Ok(0.25));
+        assert_eq!(parse_ratio("1"), Ok(1.0));
+        for s in ["1.01", "-0.1", "NaN", "25%"] {
+            assert!(parse_ratio(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+
+    #[test]
+    fn redact_args_removes_secrets() {
+        let args = [
+            "--bind",
+            "0.0.0.0",
+            "--access-token",
+            "hunter2",
+            "--config-token=swordfish",
+            "--state-dir",
+            "state",
+        ]
+        .map(String::from);
+        let (kept, redacted) = redact_args(args);
+        assert_eq!(kept, ["--bind", "0.0.0.0", "--state-dir", "state"]);
+        assert_eq!(redacted, ["--access-token", "--config-token"]);
+    }
+
+    #[test]
+    fn redact_args_keeps_similar_flags() {
+        let args = ["--access-tokens", "x", "--config-token-file", "y"].map(String::from);
+        let (kept, redacted) = redact_args(args.clone());
+        assert_eq!(kept, args);
+        assert!(redacted.is_empty());
+    }
 }
@@ -0,0 +1,143 @@
+//! LAN discovery (`--announce-mdns`), so that phones and tablets on the same network can open a
+//! local dapp at `http://<name>.local:<port>` without typing IP addresses.
+//!
+//! The launcher answers multicast DNS queries for `<name>.local` and its subdomains, such as
+//! `<canister id>.<name>.local`, with the host's LAN address, and announces the name when the
+//! network starts. Only IPv4 is supported. The gateway must listen on the LAN address, e.g. with
+//! `--bind 0.0.0.0`, for the name to be of use.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    time::Duration,
+};
+
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::dns;
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+/// How long the answers may be cached, in seconds, as recommended for host names.
+const TTL: u32 = 120;
+/// Class bit telling caches that an answer replaces the ones they hold for the name.
+const CACHE_FLUSH: u16 = 0x8000;
+/// Class bit of questions asking for a unicast response, which is ignored.
+const UNICAST_RESPONSE: u16 = 0x8000;
+const MAX_MESSAGE_LEN: usize = 9000;
+
+/// Joins the mDNS group on the mDNS port, which other responders on the host may share.
+pub fn bind() -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+        .context("failed to create mDNS socket")?;
+    socket
+        .set_reuse_address(true)
+        .and_then(|()| socket.set_reuse_port(true))
+        .context("failed to share the mDNS port")?;
+    socket
+        .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())
+        .context("failed to bind the mDNS port")?;
+    socket
+        .join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)
+        .context("failed to join the mDNS group")?;
+    socket
+        .set_nonblocking(true)
+        .context("failed to configure mDNS socket")?;
+    UdpSocket::from_std(socket.into()).context("failed to register mDNS socket")
+}
+
+/// Returns the address of the interface multicast traffic leaves the host on.
+pub fn lan_address() -> anyhow::Result<Ipv4Addr> {
+    // connecting a UDP socket sends nothing, but picks the interface
+    let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect((MDNS_GROUP, MDNS_PORT)).map(|()| socket))
+        .context("failed to find the LAN address")?;
+    match socket
+        .local_addr()
+        .context("failed to find the LAN address")?
+    {
+        SocketAddr::V4(addr) if !addr.ip().is_unspecified() => Ok(*addr.ip()),
+        _ => anyhow::bail!("the host has no IPv4 LAN address"),
+    }
+}
+
+/// Announces `host` (e.g. `myapp.local`) at `address` on `socket`, then answers queries for it
+/// and its subdomains until the task is aborted.
+pub async fn serve(socket: UdpSocket, host: String, address: Ipv4Addr) {
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let announcement = response(std::slice::from_ref(&host), address);
+    // announcements are sent twice, a second apart, in case the first is lost
+    for i in 0..2 {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        if let Err(e) = socket.send_to(&announcement, group).await {
+            eprintln!("Warning: failed to announce {host} over mDNS: {e:#}");
+        }
+    }
+    let mut buf = vec![0; MAX_MESSAGE_LEN];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let names = queried_names(&buf[..len], &host);
+        if !names.is_empty() {
+            _ = socket.send_to(&response(&names, address), group).await;
+        }
+    }
+}
+
+/// Returns the names of `host` and its subdomains that `message` queries the address of.
+fn queried_names(message: &[u8], host: &str) -> Vec<String> {
+    let Some(header) = message.get(..12) else {
+        return vec![];
+    };
+    // responses, including other responders' answers, are ignored
+    if header[2] & 0x80 != 0 {
+        return vec![];
+    }
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    let mut names = vec![];
+    let mut offset = 12;
+    for _ in 0..question_count {
+        let Some((name, end)) = dns::parse_name(message, offset) else {
+            break;
+        };
+        let Some(question) = message.get(end..end + 4) else {
+            break;
+        };
+        let query_type = u16::from_be_bytes([question[0], question[1]]);
+        let query_class = u16::from_be_bytes([question[2], question[3]]) & !UNICAST_RESPONSE;
+        if (name == host || name.ends_with(&format!(".{host}")))
+            && matches!(query_type, dns::TYPE_A | dns::TYPE_ANY)
+            && query_class == dns::CLASS_IN
+        {
+            names.push(name);
+        }
+        offset = end + 4;
+    }
+    names
+}
+
+/// Returns a response with the address records of `names`.
+fn response(names: &[String], address: Ipv4Addr) -> Vec<u8> {
+    // ID 0, an authoritative answer
+    let mut response = vec![0, 0, 0x84, 0];
+    response.extend(0u16.to_be_bytes());
+    response.extend((names.len() as u16).to_be_bytes());
+    response.extend([0; 4]);
+    for name in names {
+        for label in name.split('.') {
+            response.push(label.len() as u8);
+            response.extend(label.as_bytes());
+        }
+        response.push(0);
+        response.extend(dns::TYPE_A.to_be_bytes());
+        response.extend((dns::CLASS_IN | CACHE_FLUSH).to_be_bytes());
+        response.extend(TTL.to_be_bytes());
+        response.extend(4u16.to_be_bytes());
+        response.extend(address.octets());
+    }
+    response
+}
@@ -0,0 +1,598 @@
+//! Canned responses to canister HTTPS outcalls (`--mock-outcalls`), so that canisters depending on
+//! outcalls can be tested offline and deterministically.
+//!
+//! PocketIC can't resolve host names to a mock server of the launcher's, so the launcher answers
+//! the outcalls itself instead: it executes the rounds, as with `--max-rounds`, and after each one
+//! responds to the pending outcalls through PocketIC's outcall mocking API. Outcalls matching a
+//! mock get its response; others are made by the launcher for real, as PocketIC would have. Real
+//! outcalls are made concurrently while rounds keep executing, and answered in the round after their
+//! response arrives. Mocks are listed in a TOML file, e.g.
+//!
+//! ```toml
+//! [[mock]]
+//! host = "api.example.com"
+//! path = "/v1/price"        # prefix of the path, optional
+//! method = "GET"            # optional
+//! status = 200
+//! headers = { content-type = "application/json" }
+//! body = '{"price": 42}'    # or `body_file`, relative to the mocks file
+//!
+//! [[mock]]
+//! host = "flaky.example.com"
+//! reject = "connection refused"
+//! ```
+//!
+//! or in a YAML or JSON file whose name ends in `.yaml`, `.yml` or `.json`, holding the list of
+//! mocks.
+//!
+//! `--outcall-allow` and `--outcall-deny` restrict the outcalls made for real to hosts matching
+//! patterns, so that tests can't reach real APIs by accident, without firewall rules.
+//!
+//! `--outcall-proxy` routes the outcalls made for real through an HTTP or SOCKS proxy, such as a
+//! corporate proxy or a traffic inspector.
+//!
+//! `--record-outcalls` appends each outcall and its response to a file, one JSON object per line,
+//! and `--replay-outcalls` answers outcalls with the recorded responses instead, rejecting those
+//! that weren't recorded, for hermetic CI runs. Transforms run in the canister on the response, as
+//! they would for a real one, so only their input is recorded. Request headers aren't recorded, as
+//! they often carry API keys.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use pocket_ic::{
+    Time,
+    common::rest::{
+        CanisterHttpHeader, CanisterHttpMethod, CanisterHttpReject, CanisterHttpRejectCode,
+        CanisterHttpReply, CanisterHttpRequest, CanisterHttpResponse, MockCanisterHttpResponse,
+    },
+};
+use reqwest::{Url, header::CONTENT_LENGTH};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::control::Control;
+
+/// How long to wait before checking again while the network is paused for exceeding its quota.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long an outcall the launcher makes for real may take.
+const OUTCALL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Response size limit of outcalls that don't set one, as on mainnet.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+/// How the launcher answers outcalls.
+pub struct Rules {
+    pub mocks: Vec<Mock>,
+    /// Host patterns outcalls are restricted to, unless empty.
+    pub allow: Vec<String>,
+    /// Host patterns outcalls are rejected for.
+    pub deny: Vec<String>,
+    /// Where outcalls are recorded, if `--record-outcalls` is set.
+    pub recording: Option<File>,
+    /// Recorded outcalls to answer with, if `--replay-outcalls` is set.
+    pub replay: Option<Replay>,
+    /// Proxy the outcalls made for real go through, if `--outcall-proxy` is set.
+    pub proxy: Option<reqwest::Proxy>,
+}
+
+/// Parses a proxy URL, e.g. `http://proxy.corp:3128` or `socks5h://127.0.0.1:1080`.
+pub fn parse_proxy(s: &str) -> Result<reqwest::Proxy, String> {
+    if !["http://", "https://", "socks5://", "socks5h://"]
+        .iter()
+        .any(|scheme| s.starts_with(scheme))
+    {
+        return Err(format!(
+            "expected an http://, https://, socks5:// or socks5h:// URL, got `{s}`"
+        ));
+    }
+    reqwest::Proxy::all(s).map_err(|e| format!("invalid proxy `{s}`: {e}"))
+}
+
+/// An outcall and its response, as recorded. The outcall's headers are left out.
+#[derive(Serialize, Deserialize)]
+struct RecordedOutcall {
+    method: String,
+    url: String,
+    /// Hex-encoded.
+    body: String,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum RecordedResponse {
+    Reply {
+        status: u16,
+        headers: Vec<(String, String)>,
+        /// Hex-encoded.
+        body: String,
+    },
+    Reject {
+        code: u64,
+        message: String,
+    },
+}
+
+impl RecordedOutcall {
+    fn new(request: &CanisterHttpRequest, response: &CanisterHttpResponse) -> Self {
+        let headers = |headers: &[CanisterHttpHeader]| {
+            headers
+                .iter()
+                .map(|header| (header.name.clone(), header.value.clone()))
+                .collect()
+        };
+        Self {
+            method: method_name(&request.http_method).to_string(),
+            url: request.url.clone(),
+            body: hex::encode(&request.body),
+            response: match response {
+                CanisterHttpResponse::CanisterHttpReply(reply) => RecordedResponse::Reply {
+                    status: reply.status,
+                    headers: headers(&reply.headers),
+                    body: hex::encode(&reply.body),
+                },
+                CanisterHttpResponse::CanisterHttpReject(reject) => RecordedResponse::Reject {
+                    code: reject.reject_code,
+                    message: reject.message.clone(),
+                },
+            },
+        }
+    }
+
+    /// Whether this is a recording of `request`. Headers aren't recorded, so they aren't compared
+    /// either; they often carry values such as API keys or dates that differ between runs.
+    fn matches(&self, request: &CanisterHttpRequest) -> bool {
+        self.method == method_name(&request.http_method)
+            && self.url == request.url
+            && self.body == hex::encode(&request.body)
+    }
+
+    fn response(&self) -> CanisterHttpResponse {
+        let headers = |headers: &[(String, String)]| {
+            headers
+                .iter()
+                .map(|(name, value)| CanisterHttpHeader {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect()
+        };
+        match &self.response {
+            RecordedResponse::Reply {
+                status,
+                headers: reply_headers,
+                body,
+            } => CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+                status: *status,
+                headers: headers(reply_headers),
+                body: hex::decode(body).unwrap_or_default(),
+            }),
+            RecordedResponse::Reject { code, message } => {
+                CanisterHttpResponse::CanisterHttpReject(CanisterHttpReject {
+                    reject_code: *code,
+                    message: message.clone(),
+                })
+            }
+        }
+    }
+}
+
+/// Recorded outcalls, answered in the order they were recorded.
+pub struct Replay {
+    outcalls: Vec<RecordedOutcall>,
+    replayed: Vec<bool>,
+}
+
+impl Replay {
+    /// Reads the outcalls recorded at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let outcalls = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str::<RecordedOutcall>(line).with_context(|| {
+                    format!("failed to parse outcall {} of {}", i + 1, path.display())
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            replayed: vec![false; outcalls.len()],
+            outcalls,
+        })
+    }
+
+    /// Returns the response to the first recording of `request` not replayed yet, or to the last
+    /// one once all were, e.g. for a canister polling a price more often than when recorded.
+    fn respond(&mut self, request: &CanisterHttpRequest) -> Option<CanisterHttpResponse> {
+        let mut last = None;
+        for (i, outcall) in self.outcalls.iter().enumerate() {
+            if !outcall.matches(request) {
+                continue;
+            }
+            if !self.replayed[i] {
+                self.replayed[i] = true;
+                return Some(outcall.response());
+            }
+            last = Some(outcall);
+        }
+        last.map(RecordedOutcall::response)
+    }
+}
+
+impl Rules {
+    /// Whether an outcall to `host` may be made for real.
+    fn permits(&self, host: &str) -> bool {
+        let matches = |pattern: &String| glob_match(&pattern.to_ascii_lowercase(), host);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+/// Whether `host` matches `pattern`, where `*` matches any sequence of characters.
+pub fn glob_match(pattern: &str, host: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == host,
+        Some((prefix, rest)) => {
+            let Some(host) = host.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=host.len())
+                .filter(|&i| host.is_char_boundary(i))
+                .any(|i| glob_match(rest, &host[i..]))
+        }
+    }
+}
+
+/// A canned response, for the outcalls it matches.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mock {
+    host: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    body_file: Option<PathBuf>,
+    /// Rejects the outcall with this message instead of responding.
+    #[serde(default)]
+    reject: Option<String>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// The contents of a TOML mocks file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MocksFile {
+    #[serde(default)]
+    mock: Vec<Mock>,
+}
+
+impl Mock {
+    fn matches(&self, request: &CanisterHttpRequest, url: &Url) -> bool {
+        let method = method_name(&request.http_method);
+        url.host_str()
+            .is_some_and(|host| host.eq_ignore_ascii_case(&self.host))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| url.path().starts_with(path.as_str()))
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|expected| expected.eq_ignore_ascii_case(method))
+    }
+
+    fn response(&self) -> CanisterHttpResponse {
+        if let Some(message) = &self.reject {
+            return reject(CanisterHttpRejectCode::SysTransient, message.clone());
+        }
+        CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+            status: self.status,
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| CanisterHttpHeader {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            body: self.body.clone().unwrap_or_default().into_bytes(),
+        })
+    }
+}
+
+/// Reads the mocks at `path`, with the contents of their `body_file`s.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Mock>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut mocks: Vec<Mock> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        Some("yaml" | "yml") => serde_norway::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        _ => {
+            toml::from_str::<MocksFile>(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?
+                .mock
+        }
+    };
+    for mock in &mut mocks {
+        if let Some(body_file) = &mock.body_file {
+            let body_file = path.parent().unwrap_or(Path::new(".")).join(body_file);
+            mock.body = Some(
+                fs::read_to_string(&body_file)
+                    .with_context(|| format!("failed to read {}", body_file.display()))?,
+            );
+        }
+    }
+    Ok(mocks)
+}
+
+/// Executes rounds on the network of `control` while it runs, answering outcalls by `rules`.
+pub async fn run(control: Arc<Control>, mut rules: Rules) {
+    let mut client = reqwest::Client::builder().timeout(OUTCALL_TIMEOUT);
+    if let Some(proxy) = rules.proxy.take() {
+        client = client.proxy(proxy);
+    }
+    let client = client.build().expect("valid client configuration");
+    // outcalls being made for real, by subnet and request ID, which are answered once they complete
+    let mut fetching = BTreeSet::new();
+    let mut fetches = JoinSet::new();
+    loop {
+        {
+            // operations that pause rounds hold the lock until they resume them
+            let executing = control.progress.lock().await;
+            if control.quota_paused.load(Ordering::Relaxed) {
+                drop(executing);
+                tokio::time::sleep(PAUSED_POLL_INTERVAL).await;
+                continue;
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system clock should be after the unix epoch")
+                .as_nanos() as u64;
+            if now > control.pic.get_time().await.as_nanos_since_unix_epoch() {
+                control
+                    .pic
+                    .set_time(Time::from_nanos_since_unix_epoch(now))
+                    .await;
+            }
+            control.pic.tick().await;
+            let mut answers = vec![];
+            while let Some(fetched) = fetches.try_join_next() {
+                answers.extend(fetched.ok());
+            }
+            for request in control.pic.get_canister_http().await {
+                if fetching.contains(&(request.subnet_id, request.request_id)) {
+                    continue;
+                }
+                match respond(&mut rules, &request) {
+                    Answer::Respond(response) => answers.push((request, response)),
+                    Answer::Fetch(url) => {
+                        fetching.insert((request.subnet_id, request.request_id));
+                        let client = client.clone();
+                        fetches.spawn(async move {
+                            let response = match fetch(&client, &request, url).await {
+                                Ok(response) => response,
+                                Err(e) => {
+                                    reject(CanisterHttpRejectCode::SysTransient, format!("{e:#}"))
+                                }
+                            };
+                            (request, response)
+                        });
+                    }
+                }
+            }
+            for (request, response) in answers {
+                fetching.remove(&(request.subnet_id, request.request_id));
+                if let Some(recording) = &mut rules.recording {
+                    let outcall = RecordedOutcall::new(&request, &response);
+                    let line = serde_json::to_string(&outcall).expect("infallible serialization");
+                    if let Err(e) = writeln!(recording, "{line}") {
+                        eprintln!("Warning: failed to record an outcall: {e:#}");
+                    }
+                }
+                control
+                    .pic
+                    .mock_canister_http_response(MockCanisterHttpResponse {
+                        subnet_id: request.subnet_id,
+                        request_id: request.request_id,
+                        response,
+                        additional_responses: vec![],
+                    })
+                    .await;
+            }
+        }
+        let delay = control.artificial_delay_ms.lock().await.unwrap_or(0);
+        // sleeping even for no delay lets operations waiting for the lock take it
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+}
+
+/// How an outcall is answered.
+enum Answer {
+    /// Right away, with this response.
+    Respond(CanisterHttpResponse),
+    /// With the response of the outcall made for real to this URL.
+    Fetch(Url),
+}
+
+/// Returns how to answer `request`: with the first matching mock's response, or with the real one
+/// if the rules permit it.
+fn respond(rules: &mut Rules, request: &CanisterHttpRequest) -> Answer {
+    let url = match Url::parse(&request.url) {
+        Ok(url) => url,
+        Err(e) => {
+            return Answer::Respond(reject(
+                CanisterHttpRejectCode::DestinationInvalid,
+                format!("invalid URL {}: {e}", request.url),
+            ));
+        }
+    };
+    if let Some(mock) = rules.mocks.iter().find(|mock| mock.matches(request, &url)) {
+        return Answer::Respond(mock.response());
+    }
+    if let Some(replay) = &mut rules.replay {
+        return Answer::Respond(replay.respond(request).unwrap_or_else(|| {
+            reject(
+                CanisterHttpRejectCode::SysFatal,
+                format!("no recorded outcall to {} (--replay-outcalls)", request.url),
+            )
+        }));
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if !rules.permits(&host) {
+        return Answer::Respond(reject(
+            CanisterHttpRejectCode::SysFatal,
+            format!("outcalls to {host} are not allowed (--outcall-allow, --outcall-deny)"),
+        ));
+    }
+    Answer::Fetch(url)
+}
+
+/// Makes `request` to `url` for real.
+async fn fetch(
+    client: &reqwest::Client,
+    request: &CanisterHttpRequest,
+    url: Url,
+) -> anyhow::Result<CanisterHttpResponse> {
+    let method = match request.http_method {
+        CanisterHttpMethod::GET => reqwest::Method::GET,
+        CanisterHttpMethod::POST => reqwest::Method::POST,
+        CanisterHttpMethod::HEAD => reqwest::Method::HEAD,
+    };
+    let mut builder = client.request(method, url).body(request.body.clone());
+    for header in &request.headers {
+        builder = builder.header(&header.name, &header.value);
+    }
+    let mut response = builder
+        .send()
+        .await
+        .with_context(|| format!("failed to reach {}", request.url))?;
+    let max_response_bytes = request
+        .max_response_bytes
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES);
+    let too_large = || {
+        reject(
+            CanisterHttpRejectCode::SysFatal,
+            format!(
+                "response of {} exceeds {max_response_bytes} bytes",
+                request.url
+            ),
+        )
+    };
+    let content_length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_response_bytes) {
+        return Ok(too_large());
+    }
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| CanisterHttpHeader {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect();
+    // the body is read in chunks so that a response without a length can't exceed the limit either
+    let mut body = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("failed to read the response of {}", request.url))?
+    {
+        if (body.len() + chunk.len()) as u64 > max_response_bytes {
+            return Ok(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+        status,
+        headers,
+        body,
+    }))
+}
+
+fn method_name(method: &CanisterHttpMethod) -> &'static str {
+    match method {
+        CanisterHttpMethod::GET => "GET",
+        CanisterHttpMethod::POST => "POST",
+        CanisterHttpMethod::HEAD => "HEAD",
+    }
+}
+
+fn reject(code: CanisterHttpRejectCode, message: String) -> CanisterHttpResponse {
+    CanisterHttpResponse::CanisterHttpReject(CanisterHttpReject {
+        reject_code: code as u64,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_reads_toml_json_and_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_file = dir.path().join("mocks.toml");
+        fs::write(
+            &toml_file,
+            r#"
+            [[mock]]
+            host = "api.example.com"
+            path = "/v1/price"
+            headers = { content-type = "application/json" }
+            body = '{"price": 42}'
+
+            [[mock]]
+            host = "flaky.example.com"
+            reject = "connection refused"
+            "#,
+        )
+        .unwrap();
+        let mocks = load(&toml_file).unwrap();
+        assert_eq!(mocks.len(), 2);
+        assert_eq!(mocks[0].status, 200);
+        assert_eq!(mocks[1].reject.as_deref(), Some("connection refused"));
+        let json_file = dir.path().join("mocks.json");
+        fs::write(
+            &json_file,
+            r#"[{"host": "api.example.com", "status": 404}]"#,
+        )
+        .unwrap();
+        let mocks = load(&json_file).unwrap();
+        assert_eq!(mocks[0].status, 404);
+        let yaml_file = dir.path().join("mocks.yaml");
+        fs::write(
+            &yaml_file,
+            "- host: api.example.com\n  status: 503\n- host: flaky.example.com\n  reject: timeout\n",
+        )
+        .unwrap();
+        let mocks = load(&yaml_file).unwrap();
+        assert_eq!(mocks[0].status, 503);
+        assert_eq!(mocks[1].reject.as_deref(), Some("timeout"));
+    }
+}
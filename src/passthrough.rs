@@ -0,0 +1,149 @@
+//! Mainnet passthrough on the gateway (`--mainnet-passthrough`), so that a frontend can integrate
+//! with live third-party canisters, such as a ledger or Internet Identity, while the rest of the
+//! dapp runs locally.
+//!
+//! The launcher serves the gateway port in front of PocketIC's gateway (see [`crate::gateway`]) and
+//! sends the requests for the listed canisters to mainnet instead: API requests to the mainnet API
+//! and HTTP requests for their frontends to `<canister id>.icp0.io`. Responses to API requests are
+//! certified by mainnet's root key rather than the local network's, so agents talking to those
+//! canisters must be built with mainnet's root key.
+//!
+//! Only the headers that describe the content, such as `Content-Type` or `Accept`, are sent to
+//! mainnet, and the `access_token` query parameter is removed, so that cookies, credentials and
+//! the network's access token (see [`crate::access_token`]) stay on the machine.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::Principal;
+use hyper::{
+    Response, StatusCode,
+    header::{
+        ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, HeaderName,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, RANGE, USER_AGENT,
+    },
+    http::request,
+};
+
+use crate::{
+    access_token,
+    gateway::{self, GatewayBody},
+    local_assets,
+};
+
+/// Domain of mainnet's HTTP gateways.
+const MAINNET_GATEWAY_DOMAIN: &str = "icp0.io";
+/// Request headers sent on to mainnet.
+const FORWARDED_HEADERS: &[HeaderName] = &[
+    ACCEPT,
+    ACCEPT_ENCODING,
+    ACCEPT_LANGUAGE,
+    CACHE_CONTROL,
+    CONTENT_TYPE,
+    IF_MODIFIED_SINCE,
+    IF_NONE_MATCH,
+    IF_RANGE,
+    RANGE,
+    USER_AGENT,
+];
+
+/// The canisters requests are passed through to mainnet for.
+pub struct Passthrough {
+    canisters: BTreeSet<Principal>,
+    /// URL of the mainnet API.
+    api_url: String,
+}
+
+impl Passthrough {
+    pub fn new(canisters: Vec<Principal>, api_url: String) -> Option<Self> {
+        (!canisters.is_empty()).then(|| Self {
+            canisters: canisters.into_iter().collect(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Returns the mainnet URL the request described by `parts` goes to, if it is for a canister
+    /// passed through, by its API path, or its subdomain or alias in `aliases`.
+    pub fn target(
+        &self,
+        parts: &request::Parts,
+        aliases: &BTreeMap<String, Principal>,
+    ) -> Option<String> {
+        let path_and_query = access_token::path_and_query(&parts.uri);
+        if let ["", "api", _, "canister", canister_id, ..] =
+            parts.uri.path().split('/').collect::<Vec<_>>()[..]
+        {
+            let canister_id = Principal::from_text(canister_id).ok()?;
+            return self
+                .canisters
+                .contains(&canister_id)
+                .then(|| format!("{}{path_and_query}", self.api_url));
+        }
+        if parts.uri.path().starts_with("/api/") {
+            return None;
+        }
+        let canister_id = local_assets::canister_id(parts, aliases)?;
+        self.canisters
+            .contains(&canister_id)
+            .then(|| format!("https://{canister_id}.{MAINNET_GATEWAY_DOMAIN}{path_and_query}"))
+    }
+}
+
+/// Sends the request described by `parts` with `body` to `url` on mainnet, with only the
+/// headers in [`FORWARDED_HEADERS`], streaming the body both ways.
+pub async fn forward(
+    client: &reqwest::Client,
+    parts: &request::Parts,
+    body: GatewayBody,
+    url: String,
+) -> Response<GatewayBody> {
+    let mut request = client
+        .request(parts.method.clone(), url)
+        .body(reqwest::Body::wrap(body));
+    for name in FORWARDED_HEADERS {
+        for value in parts.headers.get_all(name) {
+            request = request.header(name, value);
+        }
+    }
+    match request.send().await {
+        Ok(response) => gateway::stream(response),
+        Err(e) => gateway::boxed(gateway::plain_response(
+            StatusCode::BAD_GATEWAY,
+            format!("failed to reach mainnet: {e}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+
+    use super::*;
+
+    #[test]
+    fn target_drops_the_access_token() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let passthrough =
+            Passthrough::new(vec![ledger], "https://icp-api.io/".to_string()).unwrap();
+        let (parts, ()) = Request::builder()
+            .uri("/logo.png?access_token=secret&size=2")
+            .header("host", format!("{ledger}.localhost:8000"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            passthrough.target(&parts, &BTreeMap::new()),
+            Some(format!("https://{ledger}.icp0.io/logo.png?size=2"))
+        );
+        let (parts, ()) = Request::builder()
+            .uri(format!(
+                "/api/v3/canister/{ledger}/call?access_token=secret"
+            ))
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            passthrough.target(&parts, &BTreeMap::new()),
+            Some(format!("https://icp-api.io/api/v3/canister/{ledger}/call"))
+        );
+    }
+}
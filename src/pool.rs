@@ -0,0 +1,79 @@
+//! Pools of isolated networks (`--pool`), so that test frameworks can shard end-to-end suites
+//! across networks started by a single launcher.
+//!
+//! All networks are instances of the same PocketIC server, each with its own gateway. Shard 0 is
+//! the launcher's usual network, set up by all the other flags and operated by the control API.
+//! The other shards have the same subnets and ICP features on fresh state, or on `--base-state`
+//! without persisting changes, but none of the launcher's own setup. The pool manifest,
+//! `pool.json` in the status directory, lists where to reach each shard.
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use ic_principal::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+
+const POOL_FILE: &str = "pool.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct PoolManifest {
+    pub v: String,
+    /// The shards, in order of their index.
+    pub shards: Vec<PoolShard>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PoolShard {
+    pub index: usize,
+    pub instance_id: usize,
+    pub gateway_port: u16,
+    pub root_key: String,
+    pub default_effective_canister_id: Principal,
+    /// Port of the control API, which only operates shard 0.
+    pub control_port: Option<u16>,
+}
+
+/// Describes `shard`, whose gateway listens on `gateway_port`, as shard number `index`.
+pub async fn describe(
+    index: usize,
+    shard: &PocketIc,
+    gateway_port: u16,
+    control_port: Option<u16>,
+) -> PoolShard {
+    let topology = shard.topology().await;
+    PoolShard {
+        index,
+        instance_id: shard.instance_id,
+        gateway_port,
+        root_key: hex::encode(
+            shard
+                .root_key()
+                .await
+                .expect("root key should be available if there is a root subnet"),
+        ),
+        default_effective_canister_id: Principal::from_slice(
+            &topology.default_effective_canister_id.canister_id,
+        ),
+        control_port,
+    }
+}
+
+/// Returns the port of the gateway of `shard`.
+pub fn gateway_port(shard: &PocketIc) -> u16 {
+    shard
+        .url()
+        .expect("gateway url set in builder")
+        .port_or_known_default()
+        .expect("gateway urls should have a known port")
+}
+
+/// Writes the pool manifest to `status_dir`.
+pub fn write(status_dir: &Path, shards: Vec<PoolShard>) -> anyhow::Result<()> {
+    let manifest = PoolManifest {
+        v: "1".to_string(),
+        shards,
+    };
+    let contents = serde_json::to_string_pretty(&manifest).expect("infallible serialization");
+    fs::write(status_dir.join(POOL_FILE), contents).context("failed to write pool manifest")
+}
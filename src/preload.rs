@@ -0,0 +1,190 @@
+//! Canisters installed at startup from a `canisters.toml` manifest (`--preload`).
+//!
+//! ```toml
+//! [ledger_accounts] # ICP minted on fresh networks, like `--ledger-account`
+//! "aaaaa-aa" = "100.5"
+//!
+//! [[test_neurons]] # neurons created on fresh networks, like `--test-neuron`
+//! controller = "2vxsx-fae"
+//! stake = "100"
+//! dissolve_delay = "365d"
+//!
+//! [canisters.backend]
+//! wasm = "target/wasm32-unknown-unknown/release/backend.wasm" # relative to the manifest
+//! init_arg = '(record { owner = principal "aaaaa-aa" })'     # Candid text, optional
+//! id = "bkyz2-fmaaa-aaaaa-qaaaq-cai"                         # optional
+//! controllers = ["aaaaa-aa"]                                 # optional
+//! cycles = 100_000_000_000_000                               # optional
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow};
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+
+use crate::{
+    canister, duration,
+    governance::TestNeuron,
+    ledger::{self, AccountId},
+};
+
+/// Cycles given to preloaded canisters that don't specify an amount.
+const DEFAULT_CYCLES: u128 = 100_000_000_000_000;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    canisters: BTreeMap<String, ManifestCanister>,
+    #[serde(default)]
+    ledger_accounts: BTreeMap<String, String>,
+    #[serde(default)]
+    test_neurons: Vec<ManifestNeuron>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestNeuron {
+    controller: Principal,
+    stake: String,
+    dissolve_delay: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestCanister {
+    wasm: PathBuf,
+    init_arg: Option<String>,
+    id: Option<Principal>,
+    controllers: Option<Vec<Principal>>,
+    cycles: Option<u128>,
+}
+
+/// A manifest whose Wasm modules and init args have been read and checked.
+pub struct LoadedManifest {
+    canisters: Vec<LoadedCanister>,
+    /// ICP balances to mint, in e8s.
+    pub ledger_accounts: Vec<(AccountId, u64)>,
+    pub test_neurons: Vec<TestNeuron>,
+}
+
+struct LoadedCanister {
+    name: String,
+    wasm_module: Vec<u8>,
+    init_arg: Vec<u8>,
+    id: Option<Principal>,
+    controllers: Option<Vec<Principal>>,
+    cycles: u128,
+}
+
+/// Reads the manifest at `path` along with everything it refers to, so that mistakes are reported
+/// before the network starts.
+pub fn load(path: &Path) -> anyhow::Result<LoadedManifest> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest: Manifest =
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut canisters = vec![];
+    for (name, canister) in manifest.canisters {
+        let wasm = base.join(&canister.wasm);
+        let wasm_module = fs::read(&wasm).with_context(|| {
+            format!("failed to read Wasm module of {name} ({})", wasm.display())
+        })?;
+        let init_arg = match &canister.init_arg {
+            Some(text) => candid_parser::parse_idl_args(text)
+                .with_context(|| format!("invalid init_arg for {name}"))?
+                .to_bytes()
+                .with_context(|| format!("invalid init_arg for {name}"))?,
+            None => Encode!()?,
+        };
+        canisters.push(LoadedCanister {
+            name,
+            wasm_module,
+            init_arg,
+            id: canister.id,
+            controllers: canister.controllers,
+            cycles: canister.cycles.unwrap_or(DEFAULT_CYCLES),
+        });
+    }
+    let mut ledger_accounts = vec![];
+    for (account, amount) in manifest.ledger_accounts {
+        let account = account.parse().map_err(anyhow::Error::msg)?;
+        let e8s = ledger::parse_icp(&amount).map_err(anyhow::Error::msg)?;
+        ledger_accounts.push((account, e8s));
+    }
+    let mut test_neurons = vec![];
+    for neuron in manifest.test_neurons {
+        test_neurons.push(TestNeuron {
+            controller: neuron.controller,
+            stake_e8s: ledger::parse_icp(&neuron.stake).map_err(anyhow::Error::msg)?,
+            dissolve_delay: duration::parse_duration(&neuron.dissolve_delay)
+                .map_err(anyhow::Error::msg)?,
+        });
+    }
+    Ok(LoadedManifest {
+        canisters,
+        ledger_accounts,
+        test_neurons,
+    })
+}
+
+/// Creates and installs the manifest's canisters, returning their IDs by name.
+///
+/// If the network resumed from existing state (`fresh` is false), canisters are assumed to have
+/// been preloaded already: those with a fixed ID are recorded if they exist, the rest are skipped.
+/// Canisters without controllers in the manifest get `extra_controllers` besides the anonymous
+/// principal.
+pub async fn install(
+    pic: &PocketIc,
+    manifest: LoadedManifest,
+    fresh: bool,
+    extra_controllers: &[Principal],
+) -> anyhow::Result<BTreeMap<String, Principal>> {
+    let mut installed = BTreeMap::new();
+    for canister in manifest.canisters {
+        let name = canister.name;
+        if !fresh {
+            if let Some(id) = canister.id
+                && pic.canister_exists(id).await
+            {
+                installed.insert(name, id);
+            }
+            continue;
+        }
+        let canister_id = match canister.id {
+            Some(id) => pic
+                .create_canister_with_id(None, None, id)
+                .await
+                .map_err(|e| anyhow!("failed to create canister {name} at {id}: {e}"))?,
+            None => pic.create_canister().await,
+        };
+        pic.add_cycles(canister_id, canister.cycles).await;
+        canister::install_code(
+            pic,
+            canister_id,
+            Principal::anonymous(),
+            canister.wasm_module,
+            canister.init_arg,
+        )
+        .await
+        .with_context(|| format!("failed to install {name}"))?;
+        if let Some(controllers) = canister.controllers {
+            pic.set_controllers(canister_id, None, controllers)
+                .await
+                .map_err(|e| anyhow!("failed to set controllers of {name}: {e}"))?;
+        } else if !extra_controllers.is_empty() {
+            canister::add_controllers(pic, canister_id, extra_controllers)
+                .await
+                .with_context(|| format!("failed to add controllers to {name}"))?;
+        }
+        installed.insert(name, canister_id);
+    }
+    Ok(installed)
+}
@@ -0,0 +1,82 @@
+//! Query statistics of the registry's canisters (`stats`, and the control API's `/status`), so
+//! that developers can see which canisters their frontend is hammering.
+//!
+//! The figures are the ones the replica keeps for `canister_status`: it aggregates queries per
+//! epoch, so they lag behind the latest calls, and it counts calls, instructions and payload bytes
+//! but not latencies. The instructions per call stand in for how expensive a canister's queries
+//! are.
+
+use std::collections::BTreeMap;
+
+use candid::Nat;
+use ic_principal::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+
+use crate::canister;
+
+#[derive(Serialize, Deserialize)]
+pub struct CanisterQueryStats {
+    pub canister_id: Principal,
+    pub calls: u64,
+    pub instructions: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+/// Returns the query statistics of `canisters`, by name, skipping those that don't exist or have
+/// no controller to ask on behalf of.
+pub async fn collect(
+    pic: &PocketIc,
+    canisters: &BTreeMap<String, Principal>,
+) -> BTreeMap<String, CanisterQueryStats> {
+    let mut stats = BTreeMap::new();
+    for (name, &canister_id) in canisters {
+        let Some(status) = canister::status(pic, canister_id).await else {
+            continue;
+        };
+        let query_stats = status.query_stats;
+        stats.insert(
+            name.clone(),
+            CanisterQueryStats {
+                canister_id,
+                calls: to_u64(&query_stats.num_calls_total),
+                instructions: to_u64(&query_stats.num_instructions_total),
+                request_bytes: to_u64(&query_stats.request_payload_bytes_total),
+                response_bytes: to_u64(&query_stats.response_payload_bytes_total),
+            },
+        );
+    }
+    stats
+}
+
+/// Converts `n`, saturating at `u64::MAX`.
+pub fn to_u64(n: &Nat) -> u64 {
+    u64::try_from(&n.0).unwrap_or(u64::MAX)
+}
+
+/// Prints `stats` as a table, busiest canisters first.
+pub fn print(stats: &BTreeMap<String, CanisterQueryStats>) {
+    let mut stats = stats.iter().collect::<Vec<_>>();
+    stats.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.calls));
+    let width = stats
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    println!(
+        "{:<width$}  {:>10}  {:>17}  {:>13}  {:>14}",
+        "CANISTER", "QUERIES", "INSTRUCTIONS/CALL", "REQUEST BYTES", "RESPONSE BYTES"
+    );
+    for (name, stats) in stats {
+        let per_call = match stats.calls {
+            0 => "-".to_string(),
+            calls => (stats.instructions / calls).to_string(),
+        };
+        println!(
+            "{:<width$}  {:>10}  {:>17}  {:>13}  {:>14}",
+            name, stats.calls, per_call, stats.request_bytes, stats.response_bytes
+        );
+    }
+}
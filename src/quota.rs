@@ -0,0 +1,165 @@
+//! Tracking of the state directory's size, and enforcement of `--state-quota`.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::control::Control;
+
+/// How often the state directory is measured.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Fraction of the quota, in percent, above which the launcher warns.
+const WARN_PERCENT: u64 = 90;
+
+/// Parses a size such as `500MB`, `20GiB` or `1048576`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|e| format!("invalid size `{s}`: {e}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "invalid size unit `{unit}` (expected e.g. MB, GiB)"
+            ));
+        }
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{s}` is too large"))
+}
+
+/// Formats a byte count for humans, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Returns the total size of the files in `dir`, in bytes.
+pub fn dir_size(dir: &Path) -> anyhow::Result<u64> {
+    let mut total = 0;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // pocket-ic creates and removes directories while it runs
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read {}", dir.display()))?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read {}", entry.path().display()));
+            }
+        };
+        if metadata.is_dir() {
+            total += dir_size(&entry.path())?;
+        } else {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Periodically measures `state_dir`, recording its size in `control`. With a quota, warns as the
+/// size approaches it, and pauses the network while it is exceeded.
+pub async fn monitor(control: Arc<Control>, state_dir: PathBuf, quota: Option<u64>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut warned = false;
+    loop {
+        interval.tick().await;
+        let size = tokio::task::spawn_blocking({
+            let state_dir = state_dir.clone();
+            move || dir_size(&state_dir)
+        })
+        .await
+        .expect("size task should not panic");
+        let size = match size {
+            Ok(size) => size,
+            Err(e) => {
+                eprintln!("Warning: failed to measure state directory: {e:#}");
+                continue;
+            }
+        };
+        control.state_size.store(size, Ordering::Relaxed);
+        let Some(quota) = quota else {
+            continue;
+        };
+        if size >= quota {
+            if !control.quota_paused.swap(true, Ordering::Relaxed) {
+                let _paused = control.progress.lock().await;
+                control.pic.stop_progress().await;
+                eprintln!(
+                    "Error: state directory has reached its quota ({} of {}); pausing the network",
+                    format_size(size),
+                    format_size(quota)
+                );
+            }
+            continue;
+        }
+        if control.quota_paused.swap(false, Ordering::Relaxed) {
+            let _paused = control.progress.lock().await;
+            match control.resume_progress().await {
+                Ok(()) => {
+                    eprintln!("state directory is below its quota again; resuming the network")
+                }
+                Err(e) => eprintln!("Warning: failed to resume the network: {e:#}"),
+            }
+        }
+        let near_quota = size >= quota / 100 * WARN_PERCENT;
+        if near_quota && !warned {
+            eprintln!(
+                "Warning: state directory is at {} of its {} quota",
+                format_size(size),
+                format_size(quota)
+            );
+        }
+        warned = near_quota;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_handles_units() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("500MB"), Ok(500_000_000));
+        assert_eq!(parse_size("20GiB"), Ok(20 << 30));
+        assert_eq!(parse_size(" 2 kb "), Ok(2_000));
+        for s in ["", "MB", "1.5GB", "10XB", "-1"] {
+            assert!(parse_size(s).is_err(), "`{s}` should be rejected");
+        }
+        assert!(parse_size("20000000TiB").is_err());
+    }
+}
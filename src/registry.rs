@@ -0,0 +1,95 @@
+//! The canister registry, `canisters.json` in the status directory, which maps human-readable
+//! names to the IDs and gateway URLs of well-known canisters on the network, and to the numbers of
+//! seeded Internet Identity anchors, test neurons, cycles wallets and test identities, and to the
+//! gateway URLs of the canisters announced as they appear.
+
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use anyhow::Context;
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+
+pub const REGISTRY_FILE: &str = "canisters.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct Registry {
+    pub v: String,
+    pub canisters: BTreeMap<String, RegistryEntry>,
+    /// Numbers of the anchors seeded by `--ii-anchor`, by name.
+    #[serde(default)]
+    pub ii_anchors: BTreeMap<String, u64>,
+    /// Neurons seeded by `--test-neuron`.
+    #[serde(default)]
+    pub test_neurons: Vec<RegistryNeuron>,
+    /// IDs of the cycles wallets created by `--cycles-wallet`, by owner.
+    #[serde(default)]
+    pub wallets: BTreeMap<Principal, Principal>,
+    /// Principals of the test identities provisioned by `--identity`, by name.
+    #[serde(default)]
+    pub identities: BTreeMap<String, Principal>,
+    /// Gateway URLs of the unnamed canisters announced by `--announce-canisters`, by ID.
+    #[serde(default)]
+    pub announced: BTreeMap<Principal, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub canister_id: Principal,
+    /// Gateway URL of the canister, as of the network's last start.
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl RegistryEntry {
+    /// Returns an entry for `canister_id`, whose URL is filled in by [`set_urls`].
+    pub fn new(canister_id: Principal) -> Self {
+        Self {
+            canister_id,
+            url: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegistryNeuron {
+    pub id: u64,
+    pub controller: Principal,
+}
+
+/// Reads the registry in `status_dir`, returning an empty registry if there is none.
+pub fn read(status_dir: &Path) -> anyhow::Result<Registry> {
+    let path = status_dir.join(REGISTRY_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            serde_json::from_str(&contents).context("failed to parse canister registry")
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Registry {
+            v: "1".to_string(),
+            canisters: BTreeMap::new(),
+            ii_anchors: BTreeMap::new(),
+            test_neurons: vec![],
+            wallets: BTreeMap::new(),
+            identities: BTreeMap::new(),
+            announced: BTreeMap::new(),
+        }),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Sets the URL of every canister in `registry` to its URL on the gateway at `gateway_port`.
+pub fn set_urls(registry: &mut Registry, gateway_port: u16) {
+    for entry in registry.canisters.values_mut() {
+        entry.url = Some(format!(
+            "http://{}.localhost:{gateway_port}",
+            entry.canister_id
+        ));
+    }
+}
+
+/// Writes `registry` to `status_dir`.
+pub fn write(status_dir: &Path, registry: &Registry) -> anyhow::Result<()> {
+    fs::create_dir_all(status_dir).context("failed to create status directory")?;
+    let mut contents = serde_json::to_string_pretty(registry).expect("infallible serialization");
+    contents.push('\n');
+    fs::write(status_dir.join(REGISTRY_FILE), contents).context("failed to write canister registry")
+}
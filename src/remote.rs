@@ -0,0 +1,153 @@
+//! Pushing snapshots to, and pulling them from, an HTTP object store.
+//!
+//! Snapshots are transferred as archives in the `export` format using plain PUT and GET requests,
+//! which works with generic HTTP stores as well as with S3-compatible stores through presigned
+//! URLs. A remote ending in `/` is treated as a prefix that `<name>.tar.zst` is appended to.
+
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use clap::{ArgAction, Args};
+use reqwest::{
+    Client, RequestBuilder,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    archive::{self, Manifest},
+    crypt::Passphrase,
+    snapshot,
+};
+
+/// Parses an HTTP header of the form `Name: value`.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected `Name: value`, got `{s}`"))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+fn object_url(remote: &str, name: &str) -> String {
+    if remote.ends_with('/') {
+        format!("{remote}{name}.tar.zst")
+    } else {
+        remote.to_string()
+    }
+}
+
+fn with_headers(mut request: RequestBuilder, headers: &[(String, String)]) -> RequestBuilder {
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// Uploads the snapshot of `state_dir` called `name`, encrypted with `passphrase` if given,
+/// returning the URL it was uploaded to.
+pub async fn push(
+    state_dir: &Path,
+    name: &str,
+    remote: &str,
+    headers: &[(String, String)],
+    passphrase: Option<Passphrase>,
+) -> anyhow::Result<String> {
+    let snapshot = snapshot::path(state_dir, name)?;
+    let archive = NamedTempFile::new().context("failed to create temporary file")?;
+    let archive_path = archive.path().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        archive::export(&snapshot, None, &archive_path, passphrase.as_ref())
+    })
+    .await
+    .expect("archive task should not panic")
+    .context("failed to archive snapshot")?;
+    let file = tokio::fs::File::open(archive.path())
+        .await
+        .context("failed to open snapshot archive")?;
+    let len = file
+        .metadata()
+        .await
+        .context("failed to read snapshot archive")?
+        .len();
+    let url = object_url(remote, name);
+    let request = Client::new()
+        .put(&url)
+        .header(CONTENT_TYPE, "application/zstd")
+        .header(CONTENT_LENGTH, len)
+        .body(file);
+    with_headers(request, headers)
+        .send()
+        .await
+        .with_context(|| format!("failed to upload snapshot to {url}"))?
+        .error_for_status()
+        .with_context(|| format!("failed to upload snapshot to {url}"))?;
+    Ok(url)
+}
+
+/// Downloads a snapshot pushed by [`push`] and stores it as the snapshot of `state_dir` called
+/// `name`, returning the manifest of its archive.
+pub async fn pull(
+    state_dir: &Path,
+    name: &str,
+    remote: &str,
+    headers: &[(String, String)],
+) -> anyhow::Result<Manifest> {
+    snapshot::validate_file_name(name).map_err(anyhow::Error::msg)?;
+    let dest = snapshot::snapshots_dir(state_dir)?.join(name);
+    if dest.exists() {
+        bail!("snapshot {name} already exists");
+    }
+    let url = object_url(remote, name);
+    let mut response = with_headers(Client::new().get(&url), headers)
+        .send()
+        .await
+        .with_context(|| format!("failed to download snapshot from {url}"))?
+        .error_for_status()
+        .with_context(|| format!("failed to download snapshot from {url}"))?;
+    let archive = NamedTempFile::new().context("failed to create temporary file")?;
+    let mut file = tokio::fs::File::create(archive.path())
+        .await
+        .context("failed to create temporary file")?;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("failed to download snapshot from {url}"))?
+    {
+        file.write_all(&chunk)
+            .await
+            .context("failed to write snapshot archive")?;
+    }
+    file.flush()
+        .await
+        .context("failed to write snapshot archive")?;
+    tokio::task::spawn_blocking(move || archive::import(archive.path(), &dest, false))
+        .await
+        .expect("archive task should not panic")
+        .context("failed to unpack snapshot")
+}
+
+#[derive(Args)]
+pub struct RemoteArgs {
+    /// URL of the snapshot object, or a URL prefix ending in `/` that `<name>.tar.zst` is appended
+    /// to. For S3-compatible stores, use a presigned URL.
+    #[arg(long, env = "ICP_CLI_NETWORK_LAUNCHER_SNAPSHOT_REMOTE")]
+    pub remote: String,
+    /// Extra header to send to the remote, e.g. `Authorization: Bearer ...`.
+    #[arg(long, value_name = "NAME: VALUE", value_parser = parse_header, action = ArgAction::Append)]
+    pub remote_header: Vec<(String, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header_trims_name_and_value() {
+        assert_eq!(
+            parse_header("Authorization: Bearer abc:def"),
+            Ok(("Authorization".to_string(), "Bearer abc:def".to_string()))
+        );
+        assert!(parse_header("Authorization").is_err());
+    }
+}
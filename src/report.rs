@@ -0,0 +1,114 @@
+//! Per-canister resource usage over a session (`report`, and `--usage-report` when the network
+//! stops), to catch cycle leaks early.
+//!
+//! The launcher measures the registry's canisters once the network is ready and compares them with
+//! their current state. The replica only counts instructions of queries (see
+//! [`crate::query_stats`]); those of updates show up in the cycles burned.
+
+use std::collections::BTreeMap;
+
+use ic_principal::Principal;
+use pocket_ic::nonblocking::PocketIc;
+use serde::{Deserialize, Serialize};
+
+use crate::{canister, query_stats::to_u64};
+
+/// Resources of a canister at one point in time.
+#[derive(Clone, Copy)]
+pub struct Usage {
+    pub cycles: u128,
+    pub memory_size: u64,
+    pub query_instructions: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CanisterReport {
+    pub canister_id: Principal,
+    pub cycles: u128,
+    /// Change of the cycle balance over the session, or `None` if the canister was created during
+    /// it.
+    pub cycles_delta: Option<i128>,
+    pub memory_size: u64,
+    /// Change of the memory size over the session, or `None` if the canister was created during it.
+    pub memory_delta: Option<i64>,
+    /// Instructions executed by queries during the session.
+    pub query_instructions: u64,
+}
+
+/// Measures `canisters`, skipping those that don't exist or have no controller to ask on behalf
+/// of.
+pub async fn measure(
+    pic: &PocketIc,
+    canisters: &BTreeMap<String, Principal>,
+) -> BTreeMap<Principal, Usage> {
+    let mut usage = BTreeMap::new();
+    for &canister_id in canisters.values() {
+        if let Some(status) = canister::status(pic, canister_id).await {
+            usage.insert(
+                canister_id,
+                Usage {
+                    cycles: u128::try_from(&status.cycles.0).unwrap_or(u128::MAX),
+                    memory_size: to_u64(&status.memory_size),
+                    query_instructions: to_u64(&status.query_stats.num_instructions_total),
+                },
+            );
+        }
+    }
+    usage
+}
+
+/// Compares the current usage of `canisters` with `baseline`, by name.
+pub async fn compare(
+    pic: &PocketIc,
+    canisters: &BTreeMap<String, Principal>,
+    baseline: &BTreeMap<Principal, Usage>,
+) -> BTreeMap<String, CanisterReport> {
+    let current = measure(pic, canisters).await;
+    canisters
+        .iter()
+        .filter_map(|(name, canister_id)| {
+            let now = current.get(canister_id)?;
+            let before = baseline.get(canister_id);
+            let report = CanisterReport {
+                canister_id: *canister_id,
+                cycles: now.cycles,
+                cycles_delta: before.map(|before| now.cycles as i128 - before.cycles as i128),
+                memory_size: now.memory_size,
+                memory_delta: before
+                    .map(|before| now.memory_size as i64 - before.memory_size as i64),
+                query_instructions: now
+                    .query_instructions
+                    .saturating_sub(before.map_or(0, |before| before.query_instructions)),
+            };
+            Some((name.clone(), report))
+        })
+        .collect()
+}
+
+/// Prints `report` as a table, biggest cycle consumers first.
+pub fn print(report: &BTreeMap<String, CanisterReport>) {
+    let mut report = report.iter().collect::<Vec<_>>();
+    report.sort_by_key(|(_, canister)| canister.cycles_delta.unwrap_or(0));
+    let width = report
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    println!(
+        "{:<width$}  {:>20}  {:>20}  {:>12}  {:>12}  {:>18}",
+        "CANISTER", "CYCLES", "CYCLES DELTA", "MEMORY", "MEMORY DELTA", "QUERY INSTRUCTIONS"
+    );
+    let delta = |delta: Option<i128>| delta.map_or_else(|| "new".to_string(), |d| format!("{d:+}"));
+    for (name, canister) in report {
+        println!(
+            "{:<width$}  {:>20}  {:>20}  {:>12}  {:>12}  {:>18}",
+            name,
+            canister.cycles,
+            delta(canister.cycles_delta),
+            canister.memory_size,
+            delta(canister.memory_delta.map(i128::from)),
+            canister.query_instructions
+        );
+    }
+}
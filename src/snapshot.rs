@@ -0,0 +1,285 @@
+//! Copies of the PocketIC state directory, stored next to it in `<state-dir>.snapshots`.
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use clap::Subcommand;
+
+use crate::{
+    archive,
+    crypt::Passphrase,
+    inspect,
+    remote::{self, RemoteArgs},
+};
+
+/// Name prefix of snapshots taken by `--snapshot-on-exit`.
+pub const EXIT_PREFIX: &str = "exit-";
+
+/// Returns the directory that snapshots of `state_dir` are stored in.
+pub fn snapshots_dir(state_dir: &Path) -> anyhow::Result<PathBuf> {
+    sibling_dir(state_dir, ".snapshots")
+}
+
+/// Returns the path next to `state_dir` named after it with `suffix` appended.
+pub fn sibling_dir(state_dir: &Path, suffix: &str) -> anyhow::Result<PathBuf> {
+    let state_dir = std::path::absolute(state_dir).context("failed to resolve state directory")?;
+    let Some(name) = state_dir.file_name() else {
+        bail!("state directory {} has no name", state_dir.display());
+    };
+    let mut name = name.to_os_string();
+    name.push(suffix);
+    Ok(state_dir.with_file_name(name))
+}
+
+/// Copies `state_dir` into a new snapshot called `name`, returning the snapshot's path.
+pub fn take(state_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let dir = snapshots_dir(state_dir)?;
+    fs::create_dir_all(&dir).context("failed to create snapshot directory")?;
+    let dest = dir.join(name);
+    if dest.exists() {
+        bail!("snapshot {} already exists", dest.display());
+    }
+    // copy to a hidden directory first so an interrupted copy never looks like a valid snapshot
+    let tmp = dir.join(format!(".{name}.partial"));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp).context("failed to remove stale partial snapshot")?;
+    }
+    copy_dir(state_dir, &tmp)
+        .with_context(|| format!("failed to copy {} to snapshot", state_dir.display()))?;
+    fs::rename(&tmp, &dest).context("failed to finalize snapshot")?;
+    Ok(dest)
+}
+
+/// Deletes all but the `keep` most recent snapshots whose names start with `prefix`.
+pub fn prune(state_dir: &Path, prefix: &str, keep: usize) -> anyhow::Result<()> {
+    let dir = snapshots_dir(state_dir)?;
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(&dir).context("failed to read snapshot directory")? {
+        let entry = entry.context("failed to read snapshot directory")?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(prefix) {
+            snapshots.push(name);
+        }
+    }
+    // names end in a unix timestamp, so lexicographic order is chronological
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for name in &snapshots[..excess] {
+        fs::remove_dir_all(dir.join(name))
+            .with_context(|| format!("failed to remove old snapshot {name}"))?;
+    }
+    Ok(())
+}
+
+/// Lists the names of all snapshots of `state_dir`, oldest first within each prefix.
+pub fn list(state_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let dir = snapshots_dir(state_dir)?;
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(&dir).context("failed to read snapshot directory")? {
+        let entry = entry.context("failed to read snapshot directory")?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with('.') {
+            snapshots.push(name);
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Returns the path of the existing snapshot of `state_dir` called `name`.
+pub fn path(state_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let snapshot = snapshots_dir(state_dir)?.join(name);
+    if !snapshot.is_dir() {
+        bail!("no snapshot named {name}");
+    }
+    Ok(snapshot)
+}
+
+/// Replaces the contents of `state_dir` with the snapshot called `name`.
+/// The network using `state_dir` must not be running.
+pub fn restore(state_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let snapshot = path(state_dir, name)?;
+    if state_dir.exists() {
+        fs::remove_dir_all(state_dir).context("failed to clear state directory")?;
+    }
+    copy_dir(&snapshot, state_dir)
+        .with_context(|| format!("failed to copy snapshot {name} to state directory"))
+}
+
+/// Checks that `name` is usable as the name of a snapshot directory.
+pub fn validate_file_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("invalid snapshot name `{name}`"));
+    }
+    Ok(())
+}
+
+/// Returns a snapshot name made of `prefix` and the current unix timestamp.
+pub fn timestamped_name(prefix: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock should be after the unix epoch")
+        .as_secs();
+    format!("{prefix}{secs}")
+}
+
+/// Parses a retention policy of the form `keep=N`.
+pub fn parse_retention(s: &str) -> Result<usize, String> {
+    let keep = s
+        .strip_prefix("keep=")
+        .ok_or_else(|| format!("expected `keep=N`, got `{s}`"))?;
+    match keep.parse::<usize>() {
+        Ok(0) => Err("retention must keep at least one snapshot".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("invalid snapshot count `{keep}`: {e}")),
+    }
+}
+
+/// Recursively copies `src` to `dst`. `fs::copy` clones files on filesystems with copy-on-write
+/// support (APFS, btrfs, XFS), so copies of large state directories are usually cheap. Symbolic
+/// links are copied as links rather than followed, so that no files from outside `src` end up in
+/// the copy; other special files are rejected.
+pub fn copy_dir(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(dst).with_context(|| format!("failed to create {}", dst.display()))?;
+    for entry in fs::read_dir(src).with_context(|| format!("failed to read {}", src.display()))? {
+        let entry = entry.with_context(|| format!("failed to read {}", src.display()))?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("failed to read the type of {}", from.display()))?;
+        if file_type.is_dir() {
+            copy_dir(&from, &to)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&from)
+                .with_context(|| format!("failed to read link {}", from.display()))?;
+            symlink(&target, &to)
+                .with_context(|| format!("failed to create link {}", to.display()))?;
+        } else if file_type.is_file() {
+            fs::copy(&from, &to).with_context(|| format!("failed to copy {}", from.display()))?;
+        } else {
+            bail!(
+                "cannot copy {}: not a file, directory or link",
+                from.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommand {
+    /// Lists the snapshots of `--state-dir`.
+    List,
+    /// Replaces the contents of `--state-dir` with a snapshot. The network must be stopped.
+    Restore {
+        /// Name of the snapshot to restore.
+        name: String,
+    },
+    /// Uploads a snapshot to an HTTP object store.
+    Push {
+        /// Name of the snapshot to upload.
+        name: String,
+        #[command(flatten)]
+        remote: RemoteArgs,
+    },
+    /// Downloads a snapshot uploaded by `snapshot push`.
+    Pull {
+        /// Name of the snapshot to download.
+        name: String,
+        #[command(flatten)]
+        remote: RemoteArgs,
+    },
+    /// Lists the subnets and canisters in a snapshot, with their sizes and module hashes.
+    Inspect {
+        /// Name of the snapshot to inspect.
+        name: String,
+    },
+    /// Lists the subnets and canisters that changed between two snapshots.
+    Diff {
+        /// Name of the earlier snapshot.
+        old: String,
+        /// Name of the later snapshot.
+        new: String,
+    },
+}
+
+/// Runs `command` on the snapshots of `state_dir`.
+pub async fn run_command(
+    state_dir: &Path,
+    passphrase: Option<Passphrase>,
+    command: SnapshotCommand,
+) -> anyhow::Result<()> {
+    match command {
+        SnapshotCommand::List => {
+            for name in list(state_dir)? {
+                println!("{name}");
+            }
+        }
+        SnapshotCommand::Restore { name } => restore(state_dir, &name)?,
+        SnapshotCommand::Push { name, remote } => {
+            let url = remote::push(
+                state_dir,
+                &name,
+                &remote.remote,
+                &remote.remote_header,
+                passphrase,
+            )
+            .await?;
+            println!("{url}");
+        }
+        SnapshotCommand::Pull { name, remote } => {
+            let manifest =
+                remote::pull(state_dir, &name, &remote.remote, &remote.remote_header).await?;
+            archive::warn_on_version_mismatch(&manifest);
+        }
+        SnapshotCommand::Inspect { name } => {
+            let inspection = inspect::inspect(&path(state_dir, &name)?)?;
+            inspect::print(&inspection);
+        }
+        SnapshotCommand::Diff { old, new } => {
+            let old = inspect::inspect(&path(state_dir, &old)?)?;
+            let new = inspect::inspect(&path(state_dir, &new)?)?;
+            inspect::print_diff(&old, &new);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_retention_requires_a_positive_count() {
+        assert_eq!(parse_retention("keep=3"), Ok(3));
+        for s in ["keep=0", "3", "keep=", "keep=-1", "keep=x", "retain=3"] {
+            assert!(parse_retention(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+
+    #[test]
+    fn copy_dir_copies_links_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/file"), "state").unwrap();
+        fs::write(dir.path().join("outside"), "secret").unwrap();
+        symlink("../outside", src.join("link")).unwrap();
+        let dst = dir.path().join("dst");
+        copy_dir(&src, &dst).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("sub/file")).unwrap(), "state");
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            Path::new("../outside")
+        );
+    }
+}
@@ -0,0 +1,766 @@
+//! Launching an SNS on the local network (`sns launch`), the local equivalent of SNS testflight,
+//! and installing the SNS aggregator (`--sns-aggregator`).
+//!
+//! An SNS is created the way it is on mainnet: a `CreateServiceNervousSystem` proposal is
+//! submitted and adopted by the neurons seeded with `--test-neuron`, which must hold a majority of
+//! the voting power. Once SNS-W has deployed the SNS, the network's time is moved forward to open
+//! the swap, the test participants buy in, and the swap is closed and finalized.
+//!
+//! The configuration is a TOML file, or YAML or JSON if its name ends in `.yaml`, `.yml` or
+//! `.json`; amounts are in tokens (or ICP) and durations like `30d`:
+//!
+//! ```toml
+//! name = "My Project"
+//! description = "A project governed by an SNS"
+//! url = "https://example.com"
+//! logo = "logo.png" # optional, relative to this file
+//! token_name = "My Token"
+//! token_symbol = "MYT"
+//! fallback_controllers = ["2vxsx-fae"]
+//! dapp_canisters = [] # their control is handed to SNS root
+//! developer_neurons = [{ controller = "2vxsx-fae", stake = "1000", dissolve_delay = "30d" }]
+//! treasury = "5000"
+//!
+//! [swap]
+//! tokens = "4000"
+//! min_participants = 1
+//! min_direct_participation_icp = "10"
+//! max_direct_participation_icp = "100"
+//! min_participant_icp = "1"
+//! max_participant_icp = "100"
+//! participants = [{ principal = "2vxsx-fae", icp = "100" }]
+//! ```
+//!
+//! `sns launch` reads the configuration and its logo, and sends them to the control API.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use candid::{CandidType, Decode, Encode, Principal, types::value::IDLValue};
+use clap::Subcommand;
+use pocket_ic::{Time, nonblocking::PocketIc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    control::{self, Control},
+    duration,
+    governance::{self, ProposalAction},
+    ledger::{self, AccountId},
+    read_status,
+    registry::{self, RegistryNeuron},
+};
+
+/// ID of the SNS-W canister.
+pub const SNS_WASM_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 10, 1, 1]);
+/// Mainnet ID of the SNS aggregator, where frontends expect to find it.
+pub const SNS_AGGREGATOR_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 0, 0, 134, 1, 1]);
+/// Cycles given to the SNS aggregator.
+const SNS_AGGREGATOR_CYCLES: u128 = 100_000_000_000_000;
+/// Used when the configuration has no logo.
+const DEFAULT_LOGO: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+/// How long to wait for each step of the launch.
+const STEP_TIMEOUT: Duration = Duration::from_secs(120);
+
+const LIFECYCLE_OPEN: i32 = 2;
+const LIFECYCLE_COMMITTED: i32 = 3;
+const LIFECYCLE_ABORTED: i32 = 4;
+
+/// An SNS configuration read by [`read_config`], with its logo.
+#[derive(Serialize, Deserialize)]
+pub struct LaunchConfig {
+    config: Config,
+    /// The PNG logo, in base64.
+    logo: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    name: String,
+    description: String,
+    url: String,
+    logo: Option<PathBuf>,
+    token_name: String,
+    token_symbol: String,
+    #[serde(default = "default_transaction_fee")]
+    transaction_fee: String,
+    fallback_controllers: Vec<Principal>,
+    #[serde(default)]
+    dapp_canisters: Vec<Principal>,
+    #[serde(default)]
+    developer_neurons: Vec<DeveloperNeuron>,
+    treasury: String,
+    swap: SwapConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeveloperNeuron {
+    controller: Principal,
+    stake: String,
+    dissolve_delay: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SwapConfig {
+    tokens: String,
+    #[serde(default = "default_min_participants")]
+    min_participants: u64,
+    min_direct_participation_icp: String,
+    max_direct_participation_icp: String,
+    min_participant_icp: String,
+    max_participant_icp: String,
+    #[serde(default = "default_swap_duration")]
+    duration: String,
+    #[serde(default = "default_basket_count")]
+    neuron_basket_count: u64,
+    #[serde(default = "default_basket_interval")]
+    neuron_basket_dissolve_delay_interval: String,
+    participants: Vec<Participant>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Participant {
+    principal: Principal,
+    icp: String,
+}
+
+fn default_transaction_fee() -> String {
+    "0.0001".to_string()
+}
+
+fn default_min_participants() -> u64 {
+    1
+}
+
+fn default_swap_duration() -> String {
+    "7d".to_string()
+}
+
+fn default_basket_count() -> u64 {
+    3
+}
+
+fn default_basket_interval() -> String {
+    "30d".to_string()
+}
+
+#[derive(CandidType)]
+pub struct CreateServiceNervousSystem {
+    name: Option<String>,
+    description: Option<String>,
+    url: Option<String>,
+    logo: Option<Image>,
+    fallback_controller_principal_ids: Vec<Principal>,
+    dapp_canisters: Vec<Canister>,
+    initial_token_distribution: Option<InitialTokenDistribution>,
+    swap_parameters: Option<SwapParameters>,
+    ledger_parameters: Option<LedgerParameters>,
+    governance_parameters: Option<GovernanceParameters>,
+}
+
+#[derive(CandidType)]
+struct Image {
+    base64_encoding: Option<String>,
+}
+
+#[derive(CandidType)]
+struct Canister {
+    id: Option<Principal>,
+}
+
+#[derive(CandidType)]
+struct Tokens {
+    e8s: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct Seconds {
+    seconds: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct Percentage {
+    basis_points: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct InitialTokenDistribution {
+    developer_distribution: Option<DeveloperDistribution>,
+    treasury_distribution: Option<SwapDistribution>,
+    swap_distribution: Option<SwapDistribution>,
+}
+
+#[derive(CandidType)]
+struct DeveloperDistribution {
+    developer_neurons: Vec<NeuronDistribution>,
+}
+
+#[derive(CandidType)]
+struct NeuronDistribution {
+    controller: Option<Principal>,
+    dissolve_delay: Option<Seconds>,
+    memo: Option<u64>,
+    stake: Option<Tokens>,
+    vesting_period: Option<Seconds>,
+}
+
+#[derive(CandidType)]
+struct SwapDistribution {
+    total: Option<Tokens>,
+}
+
+#[derive(CandidType)]
+struct SwapParameters {
+    minimum_participants: Option<u64>,
+    minimum_direct_participation_icp: Option<Tokens>,
+    maximum_direct_participation_icp: Option<Tokens>,
+    minimum_participant_icp: Option<Tokens>,
+    maximum_participant_icp: Option<Tokens>,
+    neuron_basket_construction_parameters: Option<NeuronBasketConstructionParameters>,
+    duration: Option<Seconds>,
+    neurons_fund_participation: Option<bool>,
+}
+
+#[derive(CandidType)]
+struct NeuronBasketConstructionParameters {
+    count: Option<u64>,
+    dissolve_delay_interval: Option<Seconds>,
+}
+
+#[derive(CandidType)]
+struct LedgerParameters {
+    transaction_fee: Option<Tokens>,
+    token_name: Option<String>,
+    token_symbol: Option<String>,
+    token_logo: Option<Image>,
+}
+
+#[derive(CandidType)]
+struct GovernanceParameters {
+    proposal_rejection_fee: Option<Tokens>,
+    proposal_initial_voting_period: Option<Seconds>,
+    proposal_wait_for_quiet_deadline_increase: Option<Seconds>,
+    neuron_minimum_stake: Option<Tokens>,
+    neuron_minimum_dissolve_delay_to_vote: Option<Seconds>,
+    neuron_maximum_dissolve_delay: Option<Seconds>,
+    neuron_maximum_dissolve_delay_bonus: Option<Percentage>,
+    neuron_maximum_age_for_age_bonus: Option<Seconds>,
+    neuron_maximum_age_bonus: Option<Percentage>,
+    voting_reward_parameters: Option<VotingRewardParameters>,
+}
+
+#[derive(CandidType)]
+struct VotingRewardParameters {
+    initial_reward_rate: Option<Percentage>,
+    final_reward_rate: Option<Percentage>,
+    reward_rate_transition_duration: Option<Seconds>,
+}
+
+/// IDs of the canisters of a launched SNS.
+#[derive(Serialize, Deserialize, CandidType)]
+pub struct SnsCanisters {
+    pub root_canister_id: Option<Principal>,
+    pub governance_canister_id: Option<Principal>,
+    pub ledger_canister_id: Option<Principal>,
+    pub swap_canister_id: Option<Principal>,
+    pub index_canister_id: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListDeployedSnsesResponse {
+    instances: Vec<SnsCanisters>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetLifecycleResponse {
+    lifecycle: Option<i32>,
+    decentralization_sale_open_timestamp_seconds: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetInitResponse {
+    init: Option<SwapInit>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SwapInit {
+    swap_due_timestamp_seconds: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct RefreshBuyerTokensRequest {
+    buyer: String,
+    confirmation_text: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct FinalizeSwapResponse {
+    error_message: Option<String>,
+}
+
+#[derive(CandidType)]
+struct Empty {}
+
+#[derive(CandidType)]
+struct AggregatorConfig {
+    update_interval_ms: u64,
+    fast_interval_ms: u64,
+}
+
+/// Installs the SNS aggregator Wasm module `wasm` at its mainnet ID, unless it is already
+/// installed. Its default configuration is kept.
+pub async fn install_aggregator(pic: &PocketIc, wasm: Vec<u8>) -> anyhow::Result<()> {
+    if pic.canister_exists(SNS_AGGREGATOR_CANISTER_ID).await {
+        return Ok(());
+    }
+    pic.create_canister_with_id(None, None, SNS_AGGREGATOR_CANISTER_ID)
+        .await
+        .map_err(|e| anyhow!("failed to create SNS aggregator: {e}"))?;
+    pic.add_cycles(SNS_AGGREGATOR_CANISTER_ID, SNS_AGGREGATOR_CYCLES)
+        .await;
+    pic.install_canister(
+        SNS_AGGREGATOR_CANISTER_ID,
+        wasm,
+        Encode!(&None::<AggregatorConfig>)?,
+        None,
+    )
+    .await;
+    Ok(())
+}
+
+fn tokens(amount: &str) -> anyhow::Result<Option<Tokens>> {
+    let e8s = ledger::parse_icp(amount).map_err(anyhow::Error::msg)?;
+    Ok(Some(Tokens { e8s: Some(e8s) }))
+}
+
+fn seconds(interval: &str) -> anyhow::Result<Option<Seconds>> {
+    let interval = duration::parse_duration(interval).map_err(anyhow::Error::msg)?;
+    Ok(Some(Seconds {
+        seconds: Some(interval.as_secs()),
+    }))
+}
+
+fn fixed_seconds(seconds: u64) -> Option<Seconds> {
+    Some(Seconds {
+        seconds: Some(seconds),
+    })
+}
+
+fn percentage(basis_points: u64) -> Option<Percentage> {
+    Some(Percentage {
+        basis_points: Some(basis_points),
+    })
+}
+
+/// Reads the configuration at `path`, with the logo it refers to.
+pub fn read_config(path: &Path) -> anyhow::Result<LaunchConfig> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        Some("yaml" | "yml") => serde_norway::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        _ => toml::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+    };
+    let logo = match &config.logo {
+        Some(logo) => {
+            let logo = path.parent().unwrap_or(Path::new(".")).join(logo);
+            STANDARD.encode(
+                fs::read(&logo).with_context(|| format!("failed to read {}", logo.display()))?,
+            )
+        }
+        None => DEFAULT_LOGO.to_string(),
+    };
+    Ok(LaunchConfig { config, logo })
+}
+
+/// Turns `config` into the proposal to create the SNS, and the test participants of its swap with
+/// their ICP in e8s.
+fn load(
+    LaunchConfig { config, logo }: LaunchConfig,
+) -> anyhow::Result<(CreateServiceNervousSystem, Vec<(Principal, u64)>)> {
+    let logo = format!("data:image/png;base64,{logo}");
+    let mut developer_neurons = vec![];
+    for (memo, neuron) in config.developer_neurons.iter().enumerate() {
+        developer_neurons.push(NeuronDistribution {
+            controller: Some(neuron.controller),
+            dissolve_delay: match &neuron.dissolve_delay {
+                Some(delay) => seconds(delay)?,
+                None => fixed_seconds(0),
+            },
+            memo: Some(memo as u64),
+            stake: tokens(&neuron.stake)?,
+            vesting_period: fixed_seconds(0),
+        });
+    }
+    let swap = &config.swap;
+    let mut participants = vec![];
+    for participant in &swap.participants {
+        let e8s = ledger::parse_icp(&participant.icp).map_err(anyhow::Error::msg)?;
+        participants.push((participant.principal, e8s));
+    }
+    // governance parameters follow the defaults of the SNS testing tools
+    let create = CreateServiceNervousSystem {
+        name: Some(config.name),
+        description: Some(config.description),
+        url: Some(config.url),
+        logo: Some(Image {
+            base64_encoding: Some(logo.clone()),
+        }),
+        fallback_controller_principal_ids: config.fallback_controllers,
+        dapp_canisters: config
+            .dapp_canisters
+            .into_iter()
+            .map(|id| Canister { id: Some(id) })
+            .collect(),
+        initial_token_distribution: Some(InitialTokenDistribution {
+            developer_distribution: Some(DeveloperDistribution { developer_neurons }),
+            treasury_distribution: Some(SwapDistribution {
+                total: tokens(&config.treasury)?,
+            }),
+            swap_distribution: Some(SwapDistribution {
+                total: tokens(&swap.tokens)?,
+            }),
+        }),
+        swap_parameters: Some(SwapParameters {
+            minimum_participants: Some(swap.min_participants),
+            minimum_direct_participation_icp: tokens(&swap.min_direct_participation_icp)?,
+            maximum_direct_participation_icp: tokens(&swap.max_direct_participation_icp)?,
+            minimum_participant_icp: tokens(&swap.min_participant_icp)?,
+            maximum_participant_icp: tokens(&swap.max_participant_icp)?,
+            neuron_basket_construction_parameters: Some(NeuronBasketConstructionParameters {
+                count: Some(swap.neuron_basket_count),
+                dissolve_delay_interval: seconds(&swap.neuron_basket_dissolve_delay_interval)?,
+            }),
+            duration: seconds(&swap.duration)?,
+            neurons_fund_participation: Some(false),
+        }),
+        ledger_parameters: Some(LedgerParameters {
+            transaction_fee: tokens(&config.transaction_fee)?,
+            token_name: Some(config.token_name),
+            token_symbol: Some(config.token_symbol),
+            token_logo: Some(Image {
+                base64_encoding: Some(logo),
+            }),
+        }),
+        governance_parameters: Some(GovernanceParameters {
+            proposal_rejection_fee: tokens("1")?,
+            proposal_initial_voting_period: fixed_seconds(4 * 24 * 60 * 60),
+            proposal_wait_for_quiet_deadline_increase: fixed_seconds(24 * 60 * 60),
+            neuron_minimum_stake: tokens("1")?,
+            neuron_minimum_dissolve_delay_to_vote: fixed_seconds(30 * 24 * 60 * 60),
+            neuron_maximum_dissolve_delay: fixed_seconds(8 * 365 * 24 * 60 * 60),
+            neuron_maximum_dissolve_delay_bonus: percentage(10_000),
+            neuron_maximum_age_for_age_bonus: fixed_seconds(4 * 365 * 24 * 60 * 60),
+            neuron_maximum_age_bonus: percentage(2_500),
+            voting_reward_parameters: Some(VotingRewardParameters {
+                initial_reward_rate: percentage(1_000),
+                final_reward_rate: percentage(225),
+                reward_rate_transition_duration: fixed_seconds(12 * 365 * 24 * 60 * 60),
+            }),
+        }),
+    };
+    Ok((create, participants))
+}
+
+/// Polls `check` until it returns a value, failing after [`STEP_TIMEOUT`].
+async fn wait_for<T, F: Future<Output = anyhow::Result<Option<T>>>>(
+    what: &str,
+    check: impl Fn() -> F,
+) -> anyhow::Result<T> {
+    let deadline = tokio::time::Instant::now() + STEP_TIMEOUT;
+    loop {
+        if let Some(value) = check().await? {
+            return Ok(value);
+        }
+        if tokio::time::Instant::now() > deadline {
+            bail!("timed out waiting for {what}");
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+async fn call<T: CandidType + DeserializeOwned>(
+    pic: &PocketIc,
+    canister_id: Principal,
+    method: &str,
+    arg: Vec<u8>,
+) -> anyhow::Result<T> {
+    let response = pic
+        .update_call(canister_id, Principal::anonymous(), method, arg)
+        .await
+        .map_err(|e| anyhow!("{method} failed: {e}"))?;
+    Ok(Decode!(&response, T)?)
+}
+
+async fn lifecycle(pic: &PocketIc, swap: Principal) -> anyhow::Result<GetLifecycleResponse> {
+    call(pic, swap, "get_lifecycle", Encode!(&Empty {})?).await
+}
+
+/// Launches the SNS configured in the file at `config` on the network of `control`. The proposal
+/// is submitted by `proposer` and voted for by `voters`.
+pub async fn launch(
+    control: &Control,
+    config: LaunchConfig,
+    proposer: &RegistryNeuron,
+    voters: &[RegistryNeuron],
+) -> anyhow::Result<SnsCanisters> {
+    let pic = &control.pic;
+    let (create, participants) = load(config)?;
+    let title = format!("Create {}", create.name.as_deref().unwrap_or("SNS"));
+    let proposal_id = governance::submit_proposal(
+        pic,
+        proposer.controller,
+        proposer.id,
+        title,
+        String::new(),
+        String::new(),
+        ProposalAction::CreateServiceNervousSystem(Box::new(create)),
+    )
+    .await?;
+    for voter in voters {
+        // with --nns-instant-execution, following neurons may already have decided it
+        if governance::proposal_status(pic, proposal_id).await? != "open" {
+            break;
+        }
+        if voter.id != proposer.id {
+            governance::vote_yes(pic, voter.controller, voter.id, proposal_id).await?;
+        }
+    }
+    wait_for("the SNS to be created", || async {
+        match governance::proposal_status(pic, proposal_id).await? {
+            "executed" => Ok(Some(())),
+            "open" | "adopted" => Ok(None),
+            status => bail!("SNS creation proposal {proposal_id} is {status}"),
+        }
+    })
+    .await?;
+    let sns = call::<ListDeployedSnsesResponse>(
+        pic,
+        SNS_WASM_CANISTER_ID,
+        "list_deployed_snses",
+        Encode!(&Empty {})?,
+    )
+    .await?
+    .instances
+    .pop()
+    .context("SNS-W lists no SNS")?;
+    let swap = sns.swap_canister_id.context("SNS has no swap canister")?;
+    // the swap opens at a time set by governance, typically a day later
+    let open_at = lifecycle(pic, swap)
+        .await?
+        .decentralization_sale_open_timestamp_seconds;
+    if let Some(open_at) = open_at {
+        control
+            .advance_time_to(Time::from_nanos_since_unix_epoch(
+                open_at.saturating_mul(1_000_000_000),
+            ))
+            .await?;
+    }
+    wait_for("the swap to open", || async {
+        let lifecycle = lifecycle(pic, swap).await?.lifecycle;
+        Ok((lifecycle == Some(LIFECYCLE_OPEN)).then_some(()))
+    })
+    .await?;
+    for (principal, e8s) in participants {
+        ledger::mint(
+            pic,
+            &[(AccountId::from_principal(principal), e8s + ledger::FEE_E8S)],
+        )
+        .await?;
+        ledger::transfer(
+            pic,
+            principal,
+            AccountId::new(swap, ledger::principal_subaccount(principal)),
+            e8s,
+            ledger::FEE_E8S,
+            0,
+        )
+        .await
+        .with_context(|| format!("failed to send ICP to the swap for {principal}"))?;
+        let _: IDLValue = call(
+            pic,
+            swap,
+            "refresh_buyer_tokens",
+            Encode!(&RefreshBuyerTokensRequest {
+                buyer: principal.to_text(),
+                confirmation_text: None,
+            })?,
+        )
+        .await
+        .with_context(|| format!("failed to participate in the swap for {principal}"))?;
+    }
+    // the swap closes early once the maximum is reached; otherwise skip ahead to its deadline
+    if lifecycle(pic, swap).await?.lifecycle == Some(LIFECYCLE_OPEN) {
+        let init: GetInitResponse = call(pic, swap, "get_init", Encode!(&Empty {})?).await?;
+        let due = init
+            .init
+            .and_then(|init| init.swap_due_timestamp_seconds)
+            .context("swap has no deadline")?;
+        control
+            .advance_time_to(Time::from_nanos_since_unix_epoch(
+                due.saturating_add(1).saturating_mul(1_000_000_000),
+            ))
+            .await?;
+    }
+    let committed = wait_for("the swap to close", || async {
+        match lifecycle(pic, swap).await?.lifecycle {
+            Some(LIFECYCLE_COMMITTED) => Ok(Some(true)),
+            Some(LIFECYCLE_ABORTED) => Ok(Some(false)),
+            _ => Ok(None),
+        }
+    })
+    .await?;
+    if !committed {
+        bail!("the swap was aborted because its participation minimums were not met");
+    }
+    let finalized: FinalizeSwapResponse =
+        call(pic, swap, "finalize_swap", Encode!(&Empty {})?).await?;
+    if let Some(error) = finalized.error_message {
+        bail!("failed to finalize the swap: {error}");
+    }
+    Ok(sns)
+}
+
+#[derive(Subcommand)]
+pub enum SnsCommand {
+    /// Creates an SNS through an NNS proposal adopted by the neurons seeded by `--test-neuron`,
+    /// runs its swap to completion with the configured test participants, and records its
+    /// canisters in the registry.
+    Launch {
+        /// Path of the SNS configuration file, in TOML, YAML or JSON.
+        #[arg(long)]
+        config: PathBuf,
+        /// Prefix of the SNS canisters' names in the registry, e.g. `sns-governance`.
+        #[arg(long, default_value = "sns")]
+        name: String,
+    },
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: SnsCommand) -> anyhow::Result<()> {
+    match command {
+        SnsCommand::Launch { config, name } => {
+            let config = read_config(&config)?;
+            let mut registry = registry::read(status_dir)?;
+            let proposer = registry
+                .test_neurons
+                .first()
+                .context("launching an SNS requires neurons seeded by --test-neuron")?
+                .clone();
+            let response: control::SnsLaunchResponse = control::request(
+                status_dir,
+                "/sns/launch",
+                &control::SnsLaunchRequest {
+                    config,
+                    proposer,
+                    voters: registry.test_neurons.clone(),
+                },
+            )
+            .await?;
+            let canisters = response.canisters;
+            for (suffix, id) in [
+                ("root", canisters.root_canister_id),
+                ("governance", canisters.governance_canister_id),
+                ("ledger", canisters.ledger_canister_id),
+                ("swap", canisters.swap_canister_id),
+                ("index", canisters.index_canister_id),
+            ] {
+                if let Some(canister_id) = id {
+                    println!("{name}-{suffix}: {canister_id}");
+                    registry.canisters.insert(
+                        format!("{name}-{suffix}"),
+                        registry::RegistryEntry::new(canister_id),
+                    );
+                }
+            }
+            registry::set_urls(&mut registry, read_status(status_dir)?.gateway_port);
+            registry::write(status_dir, &registry)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_parses_example_config() {
+        let config: Config = toml::from_str(
+            r#"
+            name = "My Project"
+            description = "A project governed by an SNS"
+            url = "https://example.com"
+            token_name = "My Token"
+            token_symbol = "MYT"
+            fallback_controllers = ["2vxsx-fae"]
+            developer_neurons = [{ controller = "2vxsx-fae", stake = "1000", dissolve_delay = "30d" }]
+            treasury = "5000"
+
+            [swap]
+            tokens = "4000"
+            min_direct_participation_icp = "10"
+            max_direct_participation_icp = "100"
+            min_participant_icp = "1"
+            max_participant_icp = "100"
+            participants = [{ principal = "2vxsx-fae", icp = "100" }]
+            "#,
+        )
+        .unwrap();
+        let (create, participants) = load(LaunchConfig {
+            config,
+            logo: DEFAULT_LOGO.to_string(),
+        })
+        .unwrap();
+        assert_eq!(create.name.as_deref(), Some("My Project"));
+        assert_eq!(participants, [(Principal::anonymous(), 100 * 100_000_000)]);
+    }
+
+    #[test]
+    fn load_parses_yaml_config() {
+        // amounts and counts are plain YAML numbers
+        let config: Config = serde_norway::from_str(
+            r#"
+            name: My Project
+            description: A project governed by an SNS
+            url: https://example.com
+            token_name: My Token
+            token_symbol: MYT
+            fallback_controllers: [2vxsx-fae]
+            developer_neurons:
+              - { controller: 2vxsx-fae, stake: 1000, dissolve_delay: 30d }
+            treasury: 5000
+            swap:
+              tokens: 4000
+              min_participants: 1
+              min_direct_participation_icp: 10
+              max_direct_participation_icp: 100
+              min_participant_icp: 0.5
+              max_participant_icp: 100
+              participants:
+                - { principal: 2vxsx-fae, icp: 100 }
+            "#,
+        )
+        .unwrap();
+        let (create, participants) = load(LaunchConfig {
+            config,
+            logo: DEFAULT_LOGO.to_string(),
+        })
+        .unwrap();
+        assert_eq!(create.name.as_deref(), Some("My Project"));
+        assert_eq!(participants, [(Principal::anonymous(), 100 * 100_000_000)]);
+    }
+}
@@ -0,0 +1,187 @@
+//! The SNS Wasm modules SNS-W deploys SNSes from (`--sns-wasms`).
+//!
+//! PocketIC installs SNS-W with the SNS Wasm modules of its own release, so SNSes created by
+//! `sns launch` or by proposals are reproducible for a given launcher version. A network can also
+//! start with the modules currently blessed on mainnet, or with modules from a directory, e.g. to
+//! test an SNS upgrade before it is released. Either set is added to SNS-W once, when the network
+//! starts from fresh state, and is part of the state from then on, so the network works offline.
+//!
+//! Modules are added the way NNS proposals add them, by calling `add_wasm` as NNS governance.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, anyhow, bail};
+use candid::{CandidType, Decode, Encode};
+use ic_agent::Agent;
+use pocket_ic::nonblocking::PocketIc;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{governance::GOVERNANCE_CANISTER_ID, sns::SNS_WASM_CANISTER_ID};
+
+/// SNS canister types as SNS-W numbers them, with their names in `get_latest_sns_version_pretty`
+/// and the file names of their modules in IC releases.
+const CANISTER_TYPES: &[(i32, &str, &str)] = &[
+    (1, "Root", "sns-root-canister.wasm.gz"),
+    (2, "Governance", "sns-governance-canister.wasm.gz"),
+    (3, "Ledger", "ic-icrc1-ledger.wasm.gz"),
+    (4, "Swap", "sns-swap-canister.wasm.gz"),
+    (5, "Ledger Archive", "ic-icrc1-archive.wasm.gz"),
+    (6, "Ledger Index", "ic-icrc1-index-ng.wasm.gz"),
+];
+
+/// Where the SNS Wasm modules come from.
+#[derive(Clone)]
+pub enum SnsWasmSource {
+    /// The modules PocketIC installs SNS-W with.
+    Pinned,
+    /// The latest modules of mainnet SNS-W.
+    Latest,
+    /// The modules in a directory, named as in IC releases.
+    Dir(PathBuf),
+}
+
+/// Parses `pinned`, `latest` or a directory.
+pub fn parse_source(s: &str) -> Result<SnsWasmSource, String> {
+    match s {
+        "pinned" => Ok(SnsWasmSource::Pinned),
+        "latest" => Ok(SnsWasmSource::Latest),
+        "" => Err("expected `pinned`, `latest` or a directory".to_string()),
+        dir => Ok(SnsWasmSource::Dir(PathBuf::from(dir))),
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct SnsWasm {
+    wasm: Vec<u8>,
+    proposal_id: Option<u64>,
+    canister_type: i32,
+}
+
+#[derive(CandidType)]
+struct GetWasmRequest {
+    hash: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetWasmResponse {
+    wasm: Option<SnsWasm>,
+}
+
+#[derive(CandidType)]
+struct AddWasmRequest {
+    hash: Vec<u8>,
+    wasm: Option<SnsWasm>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct AddWasmResponse {
+    result: Option<AddWasmResult>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum AddWasmResult {
+    Error(SnsWasmError),
+    Hash(Vec<u8>),
+}
+
+#[derive(CandidType, Deserialize)]
+struct SnsWasmError {
+    message: String,
+}
+
+/// Reads the modules of `source`, fetching the latest ones from the mainnet API at `mainnet_url`.
+/// The pinned set needs no modules.
+pub async fn load(source: &SnsWasmSource, mainnet_url: &str) -> anyhow::Result<Vec<SnsWasm>> {
+    match source {
+        SnsWasmSource::Pinned => Ok(vec![]),
+        SnsWasmSource::Latest => fetch_latest(mainnet_url)
+            .await
+            .context("failed to fetch the latest SNS Wasm modules from mainnet"),
+        SnsWasmSource::Dir(dir) => {
+            let mut wasms = vec![];
+            for &(canister_type, _, file_name) in CANISTER_TYPES {
+                let path = dir.join(file_name);
+                // missing modules keep the pinned version
+                if !path.exists() {
+                    continue;
+                }
+                let wasm = fs::read(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                wasms.push(SnsWasm {
+                    wasm,
+                    proposal_id: None,
+                    canister_type,
+                });
+            }
+            if wasms.is_empty() {
+                bail!("{} contains no SNS Wasm modules", dir.display());
+            }
+            Ok(wasms)
+        }
+    }
+}
+
+async fn fetch_latest(mainnet_url: &str) -> anyhow::Result<Vec<SnsWasm>> {
+    let agent = Agent::builder()
+        .with_url(mainnet_url)
+        .build()
+        .context("failed to create agent")?;
+    let response = agent
+        .query(&SNS_WASM_CANISTER_ID, "get_latest_sns_version_pretty")
+        .with_arg(Encode!(&())?)
+        .call()
+        .await
+        .context("get_latest_sns_version_pretty failed")?;
+    let version = Decode!(&response, Vec<(String, String)>)?;
+    let mut wasms = vec![];
+    for &(_, name, _) in CANISTER_TYPES {
+        let (_, hash) = version
+            .iter()
+            .find(|(n, _)| n == name)
+            .with_context(|| format!("mainnet SNS-W has no {name} module"))?;
+        let hash = hex::decode(hash).with_context(|| format!("invalid {name} module hash"))?;
+        let response = agent
+            .query(&SNS_WASM_CANISTER_ID, "get_wasm")
+            .with_arg(Encode!(&GetWasmRequest { hash })?)
+            .call()
+            .await
+            .with_context(|| format!("failed to fetch the {name} module"))?;
+        let wasm = Decode!(&response, GetWasmResponse)?
+            .wasm
+            .with_context(|| format!("mainnet SNS-W has no {name} module"))?;
+        wasms.push(wasm);
+    }
+    Ok(wasms)
+}
+
+/// Adds `wasms` to SNS-W, making them the latest SNS version.
+pub async fn add(pic: &PocketIc, wasms: Vec<SnsWasm>) -> anyhow::Result<()> {
+    for wasm in wasms {
+        let hash = Sha256::digest(&wasm.wasm).to_vec();
+        let name = CANISTER_TYPES
+            .iter()
+            .find(|&&(canister_type, _, _)| canister_type == wasm.canister_type)
+            .map_or("unknown", |&(_, name, _)| name);
+        let response = pic
+            .update_call(
+                SNS_WASM_CANISTER_ID,
+                GOVERNANCE_CANISTER_ID,
+                "add_wasm",
+                Encode!(&AddWasmRequest {
+                    hash,
+                    wasm: Some(wasm),
+                })?,
+            )
+            .await
+            .map_err(|e| anyhow!("add_wasm failed: {e}"))?;
+        match Decode!(&response, AddWasmResponse)?.result {
+            Some(AddWasmResult::Hash(_)) => {}
+            Some(AddWasmResult::Error(SnsWasmError { message })) => {
+                bail!("failed to add the SNS {name} module: {message}")
+            }
+            None => bail!("failed to add the SNS {name} module"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Custom builds of the system canisters installed by PocketIC's ICP features
+//! (`--system-canister-wasm`).
+//!
+//! PocketIC always bootstraps its bundled Wasm modules, so an override is applied by upgrading the
+//! canister to the custom module afterwards. This keeps the state the bootstrap set up, and is
+//! repeated on every start so that a rebuilt module takes effect when the network resumes.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, bail};
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::{canister, cycles, governance, ii, index, ledger, sns};
+
+/// Mainnet ID of the NNS registry canister.
+const REGISTRY_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+/// Mainnet ID of the NNS root canister.
+const ROOT_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 3, 1, 1]);
+/// Mainnet ID of the NNS lifeline canister.
+const LIFELINE_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 5, 1, 1]);
+/// Mainnet ID of the NNS dapp.
+const NNS_DAPP_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 8, 1, 1]);
+
+/// System canisters installed by the ICP features, by the names used on the command line and in
+/// the canister registry.
+pub const SYSTEM_CANISTERS: &[(&str, Principal)] = &[
+    ("registry", REGISTRY_CANISTER_ID),
+    ("governance", governance::GOVERNANCE_CANISTER_ID),
+    ("ledger", ledger::LEDGER_CANISTER_ID),
+    ("root", ROOT_CANISTER_ID),
+    ("cmc", cycles::CMC_CANISTER_ID),
+    ("lifeline", LIFELINE_CANISTER_ID),
+    ("ii", ii::II_CANISTER_ID),
+    ("nns-dapp", NNS_DAPP_CANISTER_ID),
+    ("sns-wasm", sns::SNS_WASM_CANISTER_ID),
+    ("cycles-ledger", index::CYCLES_LEDGER_CANISTER_ID),
+];
+
+/// Parses a `NAME=WASM` override, e.g. `ledger=/path/ledger.wasm`.
+pub fn parse_wasm_override(s: &str) -> Result<(String, PathBuf), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=WASM`, got `{s}`"))?;
+    if !SYSTEM_CANISTERS.iter().any(|&(known, _)| known == name) {
+        let names: Vec<_> = SYSTEM_CANISTERS.iter().map(|&(name, _)| name).collect();
+        return Err(format!(
+            "unknown system canister `{name}`, expected one of: {}",
+            names.join(", ")
+        ));
+    }
+    Ok((name.to_string(), PathBuf::from(path)))
+}
+
+/// A system canister's custom Wasm module.
+pub struct WasmOverride {
+    name: String,
+    canister_id: Principal,
+    wasm: Vec<u8>,
+}
+
+/// Reads the Wasm modules of `overrides`.
+pub fn load(overrides: Vec<(String, PathBuf)>) -> anyhow::Result<Vec<WasmOverride>> {
+    overrides
+        .into_iter()
+        .map(|(name, path)| {
+            let wasm =
+                fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            let &(_, canister_id) = SYSTEM_CANISTERS
+                .iter()
+                .find(|&&(known, _)| known == name)
+                .expect("names are checked when parsing");
+            Ok(WasmOverride {
+                name,
+                canister_id,
+                wasm,
+            })
+        })
+        .collect()
+}
+
+/// Upgrades each overridden system canister to its custom Wasm module, with no upgrade argument.
+pub async fn apply(pic: &PocketIc, overrides: Vec<WasmOverride>) -> anyhow::Result<()> {
+    for WasmOverride {
+        name,
+        canister_id,
+        wasm,
+    } in overrides
+    {
+        if !pic.canister_exists(canister_id).await {
+            bail!("cannot override the {name} canister's Wasm: its ICP feature is not enabled");
+        }
+        canister::upgrade_code(pic, canister_id, wasm, Encode!()?)
+            .await
+            .with_context(|| format!("failed to install the custom {name} canister"))?;
+    }
+    Ok(())
+}
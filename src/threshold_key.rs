@@ -0,0 +1,60 @@
+//! Threshold signing keys (`--ecdsa-key`, `--schnorr-key`), for canisters that call
+//! `sign_with_ecdsa` or `sign_with_schnorr`, such as chain fusion canisters, and vetKD master
+//! keys (`--vetkd`), for canisters that call `vetkd_derive_key`.
+//!
+//! PocketIC holds a fixed set of keys on the II subnet, under the names dfx and the IC use, so the
+//! flags add that subnet and check that the requested keys are among them. Requesting a key that
+//! does not exist locally fails at startup rather than on a canister's first signature.
+
+use std::fmt;
+
+/// Names of the keys PocketIC holds for every algorithm.
+pub const KEY_NAMES: &[&str] = &["dfx_test_key", "test_key_1", "key_1"];
+
+/// Algorithms of Schnorr keys, named as in the management canister's `SchnorrAlgorithm`.
+#[derive(Clone, Copy)]
+pub enum SchnorrAlgorithm {
+    Bip340Secp256k1,
+    Ed25519,
+}
+
+impl fmt::Display for SchnorrAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SchnorrAlgorithm::Bip340Secp256k1 => "bip340secp256k1",
+            SchnorrAlgorithm::Ed25519 => "ed25519",
+        })
+    }
+}
+
+fn check_name(name: &str) -> Result<String, String> {
+    if KEY_NAMES.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!(
+            "unknown key `{name}`; the local network has {}",
+            KEY_NAMES.join(", ")
+        ))
+    }
+}
+
+/// Parses the name of a secp256k1 ECDSA key.
+pub fn parse_ecdsa_key(s: &str) -> Result<String, String> {
+    check_name(s)
+}
+
+/// Parses a Schnorr key of the form `[ALGORITHM:]NAME`, where `ALGORITHM` is `bip340secp256k1`
+/// (the default) or `ed25519`.
+pub fn parse_schnorr_key(s: &str) -> Result<(SchnorrAlgorithm, String), String> {
+    let (algorithm, name) = match s.split_once(':') {
+        Some(("bip340secp256k1", name)) => (SchnorrAlgorithm::Bip340Secp256k1, name),
+        Some(("ed25519", name)) => (SchnorrAlgorithm::Ed25519, name),
+        Some((algorithm, _)) => {
+            return Err(format!(
+                "unknown algorithm `{algorithm}`, expected `bip340secp256k1` or `ed25519`"
+            ));
+        }
+        None => (SchnorrAlgorithm::Bip340Secp256k1, s),
+    };
+    Ok((algorithm, check_name(name)?))
+}
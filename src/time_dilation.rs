@@ -0,0 +1,67 @@
+//! Time dilation (`--time-dilation`): the network's time advances faster than the host's clock,
+//! so that timelines of days or weeks, such as neuron aging or swap durations, elapse in minutes.
+//!
+//! Auto-progress keeps the network's time in step with the host's clock, so the launcher moves it
+//! forward by the difference every [`NUDGE_INTERVAL`].
+
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use pocket_ic::Time;
+use tokio::time::Instant;
+
+use crate::control::Control;
+
+/// How often the network's time is moved forward.
+const NUDGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Parses a factor of at least 1, e.g. `60` for an hour per minute.
+pub fn parse_factor(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 1.0 => Ok(factor),
+        _ => Err(format!("expected a factor of at least 1, got `{s}`")),
+    }
+}
+
+/// Makes the network's time advance `factor` times as fast as the host's clock while it runs.
+pub async fn run(control: Arc<Control>, factor: f64) {
+    let mut ticks = tokio::time::interval(NUDGE_INTERVAL);
+    let mut last = Instant::now();
+    loop {
+        ticks.tick().await;
+        let now = Instant::now();
+        let elapsed = now - last;
+        last = now;
+        // time stands still while rounds are paused, e.g. for a snapshot or over the state quota
+        let Ok(_paused) = control.progress.try_lock() else {
+            continue;
+        };
+        if control.quota_paused.load(Ordering::Relaxed) {
+            continue;
+        }
+        let extra = elapsed.mul_f64(factor - 1.0).as_nanos() as u64;
+        let time = control.pic.get_time().await.as_nanos_since_unix_epoch();
+        control
+            .pic
+            .set_time(Time::from_nanos_since_unix_epoch(
+                time.saturating_add(extra),
+            ))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_factor_requires_at_least_1() {
+        assert_eq!(parse_factor("1"), Ok(1.0));
+        assert_eq!(parse_factor("60"), Ok(60.0));
+        for s in ["0.5", "0", "-2", "inf", "NaN", "x"] {
+            assert!(parse_factor(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Parsing of points in time given on the command line (`--initial-time`), and formatting of the
+//! network's timestamps for display (`logs`) and of the host's time for `--access-log`.
+
+/// Parses an RFC 3339 timestamp, e.g. `2025-01-01T00:00:00Z`, or a number of seconds since the
+/// Unix epoch, into nanoseconds since the Unix epoch.
+pub fn parse_timestamp(s: &str) -> Result<u64, String> {
+    let invalid = || format!("expected an RFC 3339 timestamp or Unix seconds, got `{s}`");
+    if let Ok(seconds) = s.parse::<u64>() {
+        return seconds.checked_mul(1_000_000_000).ok_or_else(invalid);
+    }
+    let (date, time) = s.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+    let number = |s: &str| -> Result<i64, String> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        s.parse().map_err(|_| invalid())
+    };
+    let [year, month, day] = date.split('-').collect::<Vec<_>>()[..] else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    // the offset follows the seconds and their fraction
+    let (time, offset_seconds) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (time, offset) = time.split_at(at);
+        let (sign, offset) = offset.split_at(1);
+        let (hours, minutes) = offset.split_once(':').ok_or_else(invalid)?;
+        let offset = number(hours)? * 3600 + number(minutes)? * 60;
+        (time, if sign == "-" { -offset } else { offset })
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let [hour, minute, second] = time.split(':').collect::<Vec<_>>()[..] else {
+        return Err(invalid());
+    };
+    let (hour, minute, second) = (number(hour)?, number(minute)?, number(second)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+        || fraction.len() > 9
+    {
+        return Err(invalid());
+    }
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        number(&format!("{fraction:0<9}"))?
+    };
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+        - offset_seconds;
+    u64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| seconds.checked_mul(1_000_000_000))
+        .and_then(|total| total.checked_add(nanos as u64))
+        .ok_or_else(|| format!("`{s}` is before the Unix epoch or too far in the future"))
+}
+
+/// Returns the number of days from 1970-01-01 to the given date of the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // years start in March, so that leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats nanoseconds since the Unix epoch as an RFC 3339 timestamp in UTC, with milliseconds.
+pub fn format_timestamp(nanos: u64) -> String {
+    let seconds = (nanos / 1_000_000_000) as i64;
+    let millis = nanos / 1_000_000 % 1000;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+/// Formats nanoseconds since the Unix epoch as a Common Log Format timestamp in UTC, e.g.
+/// `10/Oct/2000:13:55:36 +0000`.
+pub fn format_log_timestamp(nanos: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let seconds = (nanos / 1_000_000_000) as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    format!(
+        "{day:02}/{}/{year:04}:{:02}:{:02}:{:02} +0000",
+        MONTHS[(month - 1) as usize],
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+/// Returns the date of the proleptic Gregorian calendar that is `days` after 1970-01-01; the
+/// inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months start in March, as in `days_from_civil`
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEW_YEAR_2025: u64 = 1_735_689_600_000_000_000;
+
+    #[test]
+    fn parse_timestamp_accepts_rfc3339_and_unix_seconds() {
+        assert_eq!(parse_timestamp("1735689600"), Ok(NEW_YEAR_2025));
+        assert_eq!(parse_timestamp("2025-01-01T00:00:00Z"), Ok(NEW_YEAR_2025));
+        assert_eq!(
+            parse_timestamp("2025-01-01T01:00:00+01:00"),
+            Ok(NEW_YEAR_2025)
+        );
+        assert_eq!(
+            parse_timestamp("2025-01-01 00:00:00.5z"),
+            Ok(NEW_YEAR_2025 + 500_000_000)
+        );
+        assert_eq!(format_timestamp(NEW_YEAR_2025), "2025-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn parse_timestamp_rejects_invalid_timestamps() {
+        for s in [
+            "",
+            "2025-01-01",
+            "2025-13-01T00:00:00Z",
+            "2025-01-01T24:00:00Z",
+            "2025-01-01T00:00:00",
+            "1969-12-31T23:59:59Z",
+            "2025-01-01T00:00:00.1234567890Z",
+            "18446744073709551615",
+        ] {
+            assert!(parse_timestamp(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}
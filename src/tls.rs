@@ -0,0 +1,69 @@
+//! TLS on the gateway port (`--gateway-tls-cert` and `--gateway-tls-key`), so that clients which
+//! only negotiate HTTP/2 through ALPN, as browsers and gRPC-web tooling do, can talk to the local
+//! gateway the way they would to a boundary node.
+//!
+//! The launcher's proxy (see [`crate::gateway`]) tells TLS connections from plain ones by their
+//! first byte, so the same port keeps serving the `http://` URLs the launcher prints. With
+//! `--config-tls`, the admin API the launcher serves in front of PocketIC on the config port when
+//! it is exposed (see [`crate::access_token`]) accepts TLS with the same certificate, so that
+//! remote dev servers aren't managed over plain HTTP. The control API is only served on loopback,
+//! so it stays plain.
+
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+
+/// The first byte of a TLS handshake record.
+const HANDSHAKE: u8 = 0x16;
+
+/// A connection, over TLS or in the clear.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+/// Loads the PEM certificate chain at `cert` and private key at `key`, offering HTTP/2 and
+/// HTTP/1.1 to clients.
+pub fn acceptor(cert: &Path, key: &Path) -> anyhow::Result<TlsAcceptor> {
+    let chain = fs::read(cert).with_context(|| format!("failed to read {}", cert.display()))?;
+    let chain = CertificateDer::pem_slice_iter(&chain)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse certificates in {}", cert.display()))?;
+    let key_pem = fs::read(key).with_context(|| format!("failed to read {}", key.display()))?;
+    let key = PrivateKeyDer::from_pem_slice(&key_pem)
+        .with_context(|| format!("failed to parse the private key in {}", key.display()))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("failed to configure TLS")?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .context("the TLS certificate doesn't match its key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts `stream` over TLS if there is an `acceptor` and it starts with a TLS handshake, and in
+/// the clear otherwise. Returns `None` if the connection fails first.
+pub async fn accept(acceptor: Option<&TlsAcceptor>, stream: TcpStream) -> Option<Box<dyn Stream>> {
+    let Some(acceptor) = acceptor else {
+        return Some(Box::new(stream));
+    };
+    let mut first = [0];
+    stream.peek(&mut first).await.ok()?;
+    if first[0] == HANDSHAKE {
+        Some(Box::new(acceptor.accept(stream).await.ok()?))
+    } else {
+        Some(Box::new(stream))
+    }
+}
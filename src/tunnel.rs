@@ -0,0 +1,151 @@
+//! Public HTTPS tunnels to the gateway (`--tunnel`), so that a running dapp can be shared with
+//! teammates, and WebAuthn or OAuth flows that need a public origin can be tested locally.
+//!
+//! The launcher doesn't run a tunnel service of its own. It runs a provider's CLI, which must be
+//! installed (and, for ngrok, logged in), or a custom command, and takes the tunnel's URL from its
+//! output. The tunnel sends requests to the gateway with the Host header of the tunnel's canister,
+//! as the gateway can't tell canisters apart by the public host name.
+//!
+//! A tunnel publishes the whole gateway, including the API that deploys and calls canisters, so it
+//! requires the network's access token (see [`crate::access_token`]). The tunnel connects from
+//! loopback, so the token is required from loopback clients too; browsers get it once from the
+//! `access_token` query parameter and keep it as a cookie.
+
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::mpsc,
+};
+
+/// How long a tunnel may take to print its URL.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A tunnel provider with a known CLI.
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Provider {
+    /// Cloudflare quick tunnels, with `cloudflared`.
+    Cloudflared,
+    /// ngrok, with `ngrok`.
+    Ngrok,
+}
+
+/// How to open a tunnel.
+pub enum Opener {
+    Provider(Provider),
+    /// A shell command, with `{port}` and `{host}` replaced.
+    Command(String),
+}
+
+impl Opener {
+    fn command(&self, port: u16, host: &str) -> Command {
+        let target = format!("http://localhost:{port}");
+        match self {
+            Self::Provider(Provider::Cloudflared) => {
+                let mut command = Command::new("cloudflared");
+                command.args(["tunnel", "--no-autoupdate", "--url", &target]);
+                command.args(["--http-host-header", host]);
+                command
+            }
+            Self::Provider(Provider::Ngrok) => {
+                let mut command = Command::new("ngrok");
+                command.args(["http", &target, &format!("--host-header={host}")]);
+                command.args(["--log", "stdout", "--log-format", "json"]);
+                command
+            }
+            Self::Command(template) => {
+                let line = template
+                    .replace("{port}", &port.to_string())
+                    .replace("{host}", host);
+                let mut command = if cfg!(windows) {
+                    let mut command = Command::new("cmd");
+                    command.arg("/C");
+                    command
+                } else {
+                    let mut command = Command::new("sh");
+                    command.arg("-c");
+                    command
+                };
+                command.arg(line);
+                command
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Provider(Provider::Cloudflared) => "cloudflared",
+            Self::Provider(Provider::Ngrok) => "ngrok",
+            Self::Command(command) => command,
+        }
+    }
+
+    /// Whether `url`, printed by the tunnel, is the tunnel's rather than e.g. a documentation link.
+    fn is_tunnel_url(&self, url: &str) -> bool {
+        let host = url
+            .trim_start_matches("https://")
+            .split(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        match self {
+            Self::Provider(Provider::Cloudflared) => host.ends_with(".trycloudflare.com"),
+            Self::Provider(Provider::Ngrok) => host.contains(".ngrok"),
+            Self::Command(_) => true,
+        }
+    }
+}
+
+/// Opens a tunnel to the gateway on `port`, sending requests with `host` as their Host header.
+/// Returns the tunnel's process, which is killed when dropped, and its public URL.
+pub async fn open(opener: &Opener, port: u16, host: &str) -> anyhow::Result<(Child, String)> {
+    let mut child = opener
+        .command(port, host)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to run `{}`; is it installed?", opener.name()))?;
+    let (tx, mut rx) = mpsc::channel(64);
+    forward_lines(child.stdout.take().expect("stdout is piped"), tx.clone());
+    forward_lines(child.stderr.take().expect("stderr is piped"), tx);
+    let url = tokio::time::timeout(STARTUP_TIMEOUT, async {
+        while let Some(line) = rx.recv().await {
+            let url = line
+                .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '|' | '<' | '>'))
+                .filter(|token| token.starts_with("https://"))
+                .map(|token| token.trim_end_matches(['.', ',', ')', '/']))
+                .find(|url| opener.is_tunnel_url(url));
+            if let Some(url) = url {
+                return Some(url.to_string());
+            }
+        }
+        None
+    })
+    .await;
+    match url {
+        Ok(Some(url)) => Ok((child, url)),
+        Ok(None) => bail!("`{}` exited before printing a URL", opener.name()),
+        Err(_) => bail!(
+            "`{}` didn't print a URL within {}s",
+            opener.name(),
+            STARTUP_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+/// Sends the lines of `output` to `tx`, and keeps reading once they are no longer wanted, so that
+/// the tunnel never blocks on a full pipe.
+fn forward_lines(output: impl AsyncRead + Unpin + Send + 'static, tx: mpsc::Sender<String>) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if !tx.is_closed() {
+                _ = tx.send(line).await;
+            }
+        }
+    });
+}
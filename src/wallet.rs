@@ -0,0 +1,45 @@
+//! Cycles wallets (`--cycles-wallet`), for project scripts that expect each identity to have one,
+//! as dfx sets up on a local network.
+//!
+//! Like dfx, the launcher creates and installs each wallet on behalf of its owner, so the wallet's
+//! `init` makes the owner its custodian, and the owner is the canister's only controller.
+
+use anyhow::Context;
+use candid::{Encode, Principal};
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::canister;
+
+/// Cycles given to a wallet unless its `--cycles-wallet` says otherwise.
+const DEFAULT_WALLET_CYCLES: u128 = 100_000_000_000_000;
+
+/// Parses a `PRINCIPAL[=CYCLES]` wallet owner.
+pub fn parse_wallet(s: &str) -> Result<(Principal, u128), String> {
+    let (owner, cycles) = match s.split_once('=') {
+        Some((owner, cycles)) => (
+            owner,
+            cycles
+                .parse()
+                .map_err(|e| format!("invalid cycle amount: {e}"))?,
+        ),
+        None => (s, DEFAULT_WALLET_CYCLES),
+    };
+    let owner = Principal::from_text(owner).map_err(|e| format!("invalid principal: {e}"))?;
+    Ok((owner, cycles))
+}
+
+/// Creates a cycles wallet for `owner` holding `cycles`, from the Wasm module `wasm`, returning the
+/// wallet's ID.
+pub async fn create(
+    pic: &PocketIc,
+    owner: Principal,
+    cycles: u128,
+    wasm: Vec<u8>,
+) -> anyhow::Result<Principal> {
+    let canister_id = pic.create_canister_with_settings(Some(owner), None).await;
+    pic.add_cycles(canister_id, cycles).await;
+    canister::install_code(pic, canister_id, owner, wasm, Encode!()?)
+        .await
+        .with_context(|| format!("failed to install cycles wallet of {owner}"))?;
+    Ok(canister_id)
+}
@@ -0,0 +1,82 @@
+//! WebSocket proxying on the gateway (`--websocket-upstream`), so that ic-websocket gateways and
+//! dev tooling can be reached on the gateway port like everything else.
+//!
+//! PocketIC's gateway doesn't serve WebSockets, so the launcher serves the gateway port in front of
+//! it (see [`crate::gateway`]) and forwards upgrade requests to the given upstream instead. Once the
+//! upstream accepts the upgrade, the bytes of the connection are copied both ways until either side
+//! closes it. Upgrade requests are logged, and need the access token and count towards the rate
+//! limit, like any other request.
+
+use std::net::SocketAddr;
+
+use anyhow::{Context, anyhow};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{Request, Response, StatusCode, body::Bytes, client::conn::http1, header::UPGRADE};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+
+/// Whether `req` asks to upgrade its connection to a WebSocket.
+pub fn is_upgrade<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Forwards the upgrade request `req` to `upstream`, and once both sides have upgraded, copies the
+/// connection both ways in the background. Returns the upstream's response.
+pub async fn proxy<B>(
+    upstream: SocketAddr,
+    mut req: Request<B>,
+) -> anyhow::Result<Response<Full<Bytes>>> {
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    let stream = TcpStream::connect(upstream)
+        .await
+        .with_context(|| format!("failed to connect to {upstream}"))?;
+    let (mut sender, connection) = http1::handshake(TokioIo::new(stream))
+        .await
+        .context("failed to open a connection to the WebSocket upstream")?;
+    tokio::spawn(connection.with_upgrades());
+    let mut upstream_req = Request::builder()
+        .method(req.method())
+        .uri(req.uri().path_and_query().map_or("/", |p| p.as_str()));
+    for (name, value) in req.headers() {
+        upstream_req = upstream_req.header(name, value);
+    }
+    let upstream_req = upstream_req
+        .body(Empty::<Bytes>::new())
+        .expect("valid request");
+    let mut response = sender
+        .send_request(upstream_req)
+        .await
+        .context("failed to send the upgrade request to the WebSocket upstream")?;
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let (parts, body) = response.into_parts();
+        let body = body
+            .collect()
+            .await
+            .context("failed to read the WebSocket upstream's response")?
+            .to_bytes();
+        return Ok(Response::from_parts(parts, Full::new(body)));
+    }
+    let upstream_upgrade = hyper::upgrade::on(&mut response);
+    tokio::spawn(async move {
+        let upgraded = async {
+            let client = client_upgrade.await.map_err(|e| anyhow!("{e}"))?;
+            let upstream = upstream_upgrade.await.map_err(|e| anyhow!("{e}"))?;
+            anyhow::Ok((client, upstream))
+        };
+        match upgraded.await {
+            Ok((client, upstream)) => {
+                _ = tokio::io::copy_bidirectional(
+                    &mut TokioIo::new(client),
+                    &mut TokioIo::new(upstream),
+                )
+                .await;
+            }
+            Err(e) => eprintln!("Warning: failed to upgrade a WebSocket connection: {e:#}"),
+        }
+    });
+    let (parts, _) = response.into_parts();
+    Ok(Response::from_parts(parts, Full::new(Bytes::new())))
+}
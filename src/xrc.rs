@@ -0,0 +1,474 @@
+//! A mock exchange rate canister (`--xrc-mock`) at the XRC's mainnet ID, answering
+//! `get_exchange_rate` with fixed rates.
+//!
+//! The launcher generates the mock's Wasm module itself: its data holds the Candid-encoded reply
+//! for each configured pair, and `get_exchange_rate` picks the reply whose quote and base symbols
+//! appear, in that order, in the Candid-encoded request. (Candid orders record fields by hash, so
+//! `quote_asset` is encoded before `base_asset`.) Changing the rates reinstalls the mock with a
+//! new module.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, anyhow};
+use candid::{CandidType, Encode, Principal};
+use clap::Subcommand;
+use pocket_ic::nonblocking::PocketIc;
+
+use crate::{canister, control};
+
+/// Mainnet ID of the exchange rate canister.
+pub const XRC_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 2, 16, 0, 1, 1, 1]);
+/// Cycles given to the mock.
+const XRC_CYCLES: u128 = 100_000_000_000_000;
+/// Number of decimals of the rates in replies, as used by the XRC.
+const DECIMALS: u32 = 9;
+/// Symbols the XRC classifies as fiat currencies; all others are cryptocurrencies.
+const FIAT_SYMBOLS: &[&str] = &[
+    "AUD", "CAD", "CHF", "CNY", "CXDR", "EUR", "GBP", "JPY", "USD", "XDR",
+];
+
+/// Exchange rates by `(base, quote)` symbol pair, with [`DECIMALS`] decimal places.
+pub type Rates = BTreeMap<(String, String), u64>;
+
+/// Parses a rate of the form `BASE/QUOTE=RATE`, e.g. `ICP/USD=12.5`.
+pub fn parse_rate(s: &str) -> Result<((String, String), u64), String> {
+    let (pair, rate) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `BASE/QUOTE=RATE`, got `{s}`"))?;
+    Ok((parse_pair(pair)?, parse_amount(rate)?))
+}
+
+fn parse_pair(s: &str) -> Result<(String, String), String> {
+    let (base, quote) = s
+        .split_once('/')
+        .filter(|(base, quote)| !base.is_empty() && !quote.is_empty())
+        .ok_or_else(|| format!("expected `BASE/QUOTE`, got `{s}`"))?;
+    Ok((base.to_uppercase(), quote.to_uppercase()))
+}
+
+fn parse_amount(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid rate `{s}`");
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() || fraction.len() > DECIMALS as usize {
+        return Err(invalid());
+    }
+    let whole: u64 = whole.parse().map_err(|_| invalid())?;
+    let fraction: u64 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{fraction:0<9}").parse().map_err(|_| invalid())?
+    };
+    whole
+        .checked_mul(10u64.pow(DECIMALS))
+        .and_then(|rate| rate.checked_add(fraction))
+        .filter(|&rate| rate > 0)
+        .ok_or_else(invalid)
+}
+
+/// Parses rates given as a map from `BASE/QUOTE` to a rate.
+pub fn parse_rates(rates: &BTreeMap<String, String>) -> Result<Rates, String> {
+    rates
+        .iter()
+        .map(|(pair, rate)| Ok((parse_pair(pair)?, parse_amount(rate)?)))
+        .collect()
+}
+
+/// Formats `rates` as a map from `BASE/QUOTE` to a rate, the inverse of [`parse_rates`].
+pub fn format_rates(rates: &Rates) -> BTreeMap<String, String> {
+    rates
+        .iter()
+        .map(|((base, quote), rate)| {
+            let divisor = 10u64.pow(DECIMALS);
+            let fraction = format!("{:09}", rate % divisor);
+            let fraction = fraction.trim_end_matches('0');
+            let rate = if fraction.is_empty() {
+                format!("{}", rate / divisor)
+            } else {
+                format!("{}.{fraction}", rate / divisor)
+            };
+            (format!("{base}/{quote}"), rate)
+        })
+        .collect()
+}
+
+/// Reads rates from a JSON file mapping `BASE/QUOTE` to a rate, e.g. `{"ICP/USD": 12.5}`.
+pub fn load_rates(path: &Path) -> anyhow::Result<Rates> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let rates: BTreeMap<String, serde_json::Value> = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let rates = rates
+        .into_iter()
+        .map(|(pair, rate)| match rate {
+            serde_json::Value::String(rate) => (pair, rate),
+            rate => (pair, rate.to_string()),
+        })
+        .collect();
+    parse_rates(&rates).map_err(|e| anyhow!("invalid rate in {}: {e}", path.display()))
+}
+
+#[derive(CandidType)]
+enum GetExchangeRateResult {
+    Ok(ExchangeRate),
+    Err(ExchangeRateError),
+}
+
+#[derive(CandidType)]
+struct ExchangeRate {
+    base_asset: Asset,
+    quote_asset: Asset,
+    timestamp: u64,
+    rate: u64,
+    metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType)]
+struct Asset {
+    symbol: String,
+    class: AssetClass,
+}
+
+#[derive(CandidType)]
+enum AssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType)]
+struct ExchangeRateMetadata {
+    decimals: u32,
+    base_asset_num_queried_sources: u64,
+    base_asset_num_received_rates: u64,
+    quote_asset_num_queried_sources: u64,
+    quote_asset_num_received_rates: u64,
+    standard_deviation: u64,
+    forex_timestamp: Option<u64>,
+}
+
+#[derive(CandidType)]
+enum ExchangeRateError {
+    CryptoBaseAssetNotFound,
+}
+
+fn asset(symbol: &str) -> Asset {
+    Asset {
+        symbol: symbol.to_string(),
+        class: if FIAT_SYMBOLS.contains(&symbol) {
+            AssetClass::FiatCurrency
+        } else {
+            AssetClass::Cryptocurrency
+        },
+    }
+}
+
+/// Returns the Candid encoding of `symbol` as a text value, which is how it appears in requests.
+fn encoded_symbol(symbol: &str) -> Vec<u8> {
+    let mut encoded = vec![];
+    leb128(&mut encoded, symbol.len() as u32);
+    encoded.extend_from_slice(symbol.as_bytes());
+    encoded
+}
+
+/// Builds the mock's Wasm module for `rates`, whose replies carry the timestamp `now` (in
+/// seconds). Pairs are also answered in reverse, unless configured both ways.
+fn wasm_module(rates: &Rates, now: u64) -> anyhow::Result<Vec<u8>> {
+    let mut replies = BTreeMap::new();
+    for ((base, quote), &rate) in rates {
+        let inverse = 10u128.pow(2 * DECIMALS) / u128::from(rate);
+        replies
+            .entry((quote.clone(), base.clone()))
+            .or_insert(u64::try_from(inverse).unwrap_or(u64::MAX).max(1));
+    }
+    for (pair, &rate) in rates {
+        replies.insert(pair.clone(), rate);
+    }
+    // data layout: for each pair, the quote and base symbols and the reply; then the error reply
+    let mut data = vec![];
+    let mut entries = vec![];
+    for ((base, quote), rate) in replies {
+        let reply = Encode!(&GetExchangeRateResult::Ok(ExchangeRate {
+            base_asset: asset(&base),
+            quote_asset: asset(&quote),
+            timestamp: now - now % 60,
+            rate,
+            metadata: ExchangeRateMetadata {
+                decimals: DECIMALS,
+                base_asset_num_queried_sources: 1,
+                base_asset_num_received_rates: 1,
+                quote_asset_num_queried_sources: 1,
+                quote_asset_num_received_rates: 1,
+                standard_deviation: 0,
+                forex_timestamp: None,
+            },
+        }))?;
+        let mut entry = [(0, 0); 3];
+        for (i, bytes) in [encoded_symbol(&quote), encoded_symbol(&base), reply]
+            .into_iter()
+            .enumerate()
+        {
+            entry[i] = (data.len() as u32, bytes.len() as u32);
+            data.extend(bytes);
+        }
+        entries.push(entry);
+    }
+    let error = Encode!(&GetExchangeRateResult::Err(
+        ExchangeRateError::CryptoBaseAssetNotFound
+    ))?;
+    let not_found = (data.len() as u32, error.len() as u32);
+    data.extend(error);
+    // the request is copied to the page after the data, and may take up one more page
+    let arg_offset = (data.len() as u32).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let pages = arg_offset / PAGE_SIZE + 1;
+
+    let mut main = vec![];
+    // size = msg_arg_data_size(); msg_arg_data_copy(arg_offset, 0, size)
+    main.extend([CALL, ARG_DATA_SIZE, LOCAL_SET, 0]);
+    i32_const(&mut main, arg_offset);
+    main.extend([I32_CONST, 0, LOCAL_GET, 0, CALL, ARG_DATA_COPY]);
+    for [
+        (quote_offset, quote_len),
+        (base_offset, base_len),
+        (reply_offset, reply_len),
+    ] in entries
+    {
+        // p = find(request, quote); if p >= 0 && find(request after p, base) >= 0 then reply
+        i32_const(&mut main, arg_offset);
+        main.extend([LOCAL_GET, 0]);
+        i32_const(&mut main, quote_offset);
+        i32_const(&mut main, quote_len);
+        main.extend([CALL, FIND, LOCAL_TEE, 1, I32_CONST, 0, I32_GE_S, IF, EMPTY]);
+        i32_const(&mut main, arg_offset);
+        main.extend([LOCAL_GET, 1, I32_ADD]);
+        i32_const(&mut main, quote_len);
+        main.extend([I32_ADD, LOCAL_GET, 0, LOCAL_GET, 1, I32_SUB]);
+        i32_const(&mut main, quote_len);
+        main.push(I32_SUB);
+        i32_const(&mut main, base_offset);
+        i32_const(&mut main, base_len);
+        main.extend([CALL, FIND, I32_CONST, 0, I32_GE_S, IF, EMPTY]);
+        i32_const(&mut main, reply_offset);
+        i32_const(&mut main, reply_len);
+        main.extend([CALL, REPLY_DATA_APPEND, CALL, REPLY, RETURN, END, END]);
+    }
+    i32_const(&mut main, not_found.0);
+    i32_const(&mut main, not_found.1);
+    main.extend([CALL, REPLY_DATA_APPEND, CALL, REPLY, END]);
+
+    // find(haystack, haystack_len, needle, needle_len): offset of needle in haystack, or -1
+    #[rustfmt::skip]
+    let find = [
+        BLOCK, EMPTY, LOOP, EMPTY,
+        // if i + needle_len > haystack_len, not found
+        LOCAL_GET, 4, LOCAL_GET, 3, I32_ADD, LOCAL_GET, 1, I32_GT_U, BR_IF, 1,
+        I32_CONST, 0, LOCAL_SET, 5,
+        BLOCK, EMPTY, LOOP, EMPTY,
+        // if j >= needle_len, found at i
+        LOCAL_GET, 5, LOCAL_GET, 3, I32_GE_U, IF, EMPTY, LOCAL_GET, 4, RETURN, END,
+        // if haystack[i + j] != needle[j], try the next i
+        LOCAL_GET, 0, LOCAL_GET, 4, I32_ADD, LOCAL_GET, 5, I32_ADD, I32_LOAD8_U, 0, 0,
+        LOCAL_GET, 2, LOCAL_GET, 5, I32_ADD, I32_LOAD8_U, 0, 0,
+        I32_NE, BR_IF, 1,
+        LOCAL_GET, 5, I32_CONST, 1, I32_ADD, LOCAL_SET, 5, BR, 0,
+        END, END,
+        LOCAL_GET, 4, I32_CONST, 1, I32_ADD, LOCAL_SET, 4, BR, 0,
+        END, END,
+        I32_CONST, 0x7f, END,
+    ];
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    #[rustfmt::skip]
+    section(&mut module, 1, 5, &[
+        0x60, 0, 0,
+        0x60, 0, 1, I32,
+        0x60, 3, I32, I32, I32, 0,
+        0x60, 2, I32, I32, 0,
+        0x60, 4, I32, I32, I32, I32, 1, I32,
+    ]);
+    let mut imports = vec![];
+    for (name, ty) in [
+        ("msg_arg_data_size", 1),
+        ("msg_arg_data_copy", 2),
+        ("msg_reply_data_append", 3),
+        ("msg_reply", 0),
+    ] {
+        name_bytes(&mut imports, "ic0");
+        name_bytes(&mut imports, name);
+        imports.extend([0, ty]);
+    }
+    section(&mut module, 2, 4, &imports);
+    section(&mut module, 3, 2, &[4, 0]);
+    let mut memory = vec![0];
+    leb128(&mut memory, pages);
+    section(&mut module, 5, 1, &memory);
+    let mut exports = vec![];
+    name_bytes(&mut exports, "canister_update get_exchange_rate");
+    exports.extend([0, MAIN]);
+    section(&mut module, 7, 1, &exports);
+    let mut code = vec![];
+    for (locals, body) in [(2, &find[..]), (2, &main[..])] {
+        let mut function = vec![1, locals, I32];
+        function.extend_from_slice(body);
+        leb128(&mut code, function.len() as u32);
+        code.extend(function);
+    }
+    section(&mut module, 10, 2, &code);
+    let mut segment = vec![0, I32_CONST, 0, END];
+    leb128(&mut segment, data.len() as u32);
+    segment.extend(data);
+    section(&mut module, 11, 1, &segment);
+    Ok(module)
+}
+
+const PAGE_SIZE: u32 = 65536;
+const I32: u8 = 0x7f;
+const EMPTY: u8 = 0x40;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_LOAD8_U: u8 = 0x2d;
+const I32_CONST: u8 = 0x41;
+const I32_NE: u8 = 0x47;
+const I32_GE_S: u8 = 0x4e;
+const I32_GT_U: u8 = 0x4b;
+const I32_GE_U: u8 = 0x4f;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+// function indices: the imports, then `find` and the exported method
+const ARG_DATA_SIZE: u8 = 0;
+const ARG_DATA_COPY: u8 = 1;
+const REPLY_DATA_APPEND: u8 = 2;
+const REPLY: u8 = 3;
+const FIND: u8 = 4;
+const MAIN: u8 = 5;
+
+fn leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Appends `i32.const value`, for values below 2^31.
+fn i32_const(out: &mut Vec<u8>, value: u32) {
+    out.push(I32_CONST);
+    // signed LEB128: the last byte's sign bit must be clear for non-negative values
+    let mut value = value;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 && byte & 0x40 == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name_bytes(out: &mut Vec<u8>, name: &str) {
+    leb128(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(module: &mut Vec<u8>, id: u8, count: u32, contents: &[u8]) {
+    let mut body = vec![];
+    leb128(&mut body, count);
+    body.extend_from_slice(contents);
+    module.push(id);
+    leb128(module, body.len() as u32);
+    module.extend(body);
+}
+
+/// Installs the mock with `rates`, or replaces the rates of an installed mock.
+pub async fn install(pic: &PocketIc, rates: &Rates) -> anyhow::Result<()> {
+    let now = pic.get_time().await.as_nanos_since_unix_epoch() / 1_000_000_000;
+    let wasm = wasm_module(rates, now)?;
+    if pic.canister_exists(XRC_CANISTER_ID).await {
+        return canister::reinstall_code(
+            pic,
+            XRC_CANISTER_ID,
+            Principal::anonymous(),
+            wasm,
+            vec![],
+        )
+        .await
+        .context("failed to update exchange rate mock");
+    }
+    pic.create_canister_with_id(None, None, XRC_CANISTER_ID)
+        .await
+        .map_err(|e| anyhow!("failed to create exchange rate mock: {e}"))?;
+    pic.add_cycles(XRC_CANISTER_ID, XRC_CYCLES).await;
+    canister::install_code(pic, XRC_CANISTER_ID, Principal::anonymous(), wasm, vec![])
+        .await
+        .context("failed to install exchange rate mock")
+}
+
+#[derive(Subcommand)]
+pub enum XrcCommand {
+    /// Sets rates of the exchange rate mock, printing all its rates.
+    SetRate {
+        /// Rate to set, as `BASE/QUOTE=RATE`, e.g. `ICP/USD=12.5`.
+        #[arg(required = true)]
+        rates: Vec<String>,
+    },
+}
+
+/// Runs `command` against the network whose status files are in `status_dir`.
+pub async fn run_command(status_dir: &Path, command: XrcCommand) -> anyhow::Result<()> {
+    match command {
+        XrcCommand::SetRate { rates } => {
+            let mut request = BTreeMap::new();
+            for rate in rates {
+                let (pair, rate) = rate
+                    .split_once('=')
+                    .with_context(|| format!("expected `BASE/QUOTE=RATE`, got `{rate}`"))?;
+                request.insert(pair.to_string(), rate.to_string());
+            }
+            let response: control::XrcRatesResponse = control::request(
+                status_dir,
+                "/xrc/rates",
+                &control::XrcRatesRequest { rates: request },
+            )
+            .await?;
+            for (pair, rate) in response.rates {
+                println!("{pair}: {rate}");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate_scales_to_decimals() {
+        assert_eq!(
+            parse_rate("icp/usd=12.5"),
+            Ok((("ICP".to_string(), "USD".to_string()), 12_500_000_000))
+        );
+        for s in [
+            "ICP/USD",
+            "ICP=1",
+            "/USD=1",
+            "ICP/USD=0",
+            "ICP/USD=0.0000000001",
+            "ICP/USD=x",
+        ] {
+            assert!(parse_rate(s).is_err(), "`{s}` should be rejected");
+        }
+    }
+}